@@ -0,0 +1,191 @@
+//! Procedural macros for jetski.
+//! These are re-exported by the `jetski` crate and should be used from there.
+
+extern crate proc_macro;
+
+#[macro_use]
+extern crate pest_derive;
+
+use grammar::{R7rsGrammar, Rule};
+use pest::{iterators::Pair, Parser};
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+// The derived `Rule` enum is public, and a proc-macro crate must not export anything but macros.
+mod grammar {
+    #[derive(Parser)]
+    #[grammar = "../../jetski/src/r7rs.pest"]
+    pub struct R7rsGrammar;
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Parse a Scheme datum at compile time and expand to the code that constructs it as `Object`.
+///
+/// Syntax errors in the datum are reported by the compiler, pointing at the string literal.
+#[proc_macro]
+pub fn datum(input: TokenStream) -> TokenStream {
+    let source = parse_macro_input!(input as LitStr);
+    match parse_datum(&source.value()) {
+        Ok(tokens) => tokens.into(),
+        Err(msg) => syn::Error::new(source.span(), msg)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn parse_datum(input: &str) -> Result<TokenStream2> {
+    let mut datum = R7rsGrammar::parse(Rule::datum, input)
+        .map_err(|e| format!("invalid Scheme datum\n{}", e))?;
+    walk_datum(datum.next().unwrap())
+}
+
+fn walk_datum(pair: Pair<Rule>) -> Result<TokenStream2> {
+    match pair.as_rule() {
+        Rule::list => walk_list(pair),
+        Rule::vector => walk_vector(pair),
+        Rule::boolean => walk_boolean(pair),
+        Rule::number => walk_number(pair),
+        Rule::symbol => walk_symbol(pair),
+        Rule::string_content => walk_string(pair),
+        Rule::abbreviation => walk_abbreviation(pair),
+        _ => Err(error_at(&pair, "unsupported datum")),
+    }
+}
+
+fn walk_list(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let mut parse_list = pair.into_inner();
+    let mut items = vec![];
+    let mut tail = quote!(::jetski::Object::nil());
+    while let Some(list_item) = parse_list.next() {
+        if list_item.as_rule() == Rule::dot {
+            tail = walk_datum(parse_list.next().unwrap())?;
+        } else {
+            items.push(walk_datum(list_item)?);
+        }
+    }
+    Ok(items
+        .into_iter()
+        .rev()
+        .fold(tail, |cdr, car| quote!(::jetski::Object::cons(#car, #cdr))))
+}
+
+fn walk_vector(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let items = pair
+        .into_inner()
+        .map(walk_datum)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote!(::jetski::Object::vector(vec![#(#items),*])))
+}
+
+fn walk_boolean(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let value = pair.as_str().starts_with("#t");
+    Ok(quote!(::jetski::Object::boolean(#value)))
+}
+
+fn walk_number(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let number = pair.into_inner().next().unwrap();
+    match number.as_rule() {
+        Rule::num_2 => walk_num_with_radix(number, 2),
+        Rule::num_8 => walk_num_with_radix(number, 8),
+        Rule::num_10 => walk_num_with_radix(number, 10),
+        Rule::num_16 => walk_num_with_radix(number, 16),
+        _ => unreachable!(),
+    }
+}
+
+fn walk_num_with_radix(pair: Pair<Rule>, radix: u32) -> Result<TokenStream2> {
+    let mut inner = pair.clone().into_inner();
+    let exactness = inner.next().unwrap();
+    let value = inner.next().unwrap();
+    let integer_result = i64::from_str_radix(value.as_str(), radix);
+    let invalid = || error_at(&pair, "invalid numeric constant");
+    match (exactness.as_rule(), integer_result) {
+        (Rule::exact, Ok(i)) | (Rule::empty, Ok(i)) => Ok(quote!(::jetski::Object::integer(#i))),
+        (Rule::exact, Err(_)) => Err(invalid()),
+        (Rule::inexact, Ok(i)) => Ok(make_float(i as f64)),
+        (Rule::inexact, Err(_)) | (Rule::empty, Err(_)) => value
+            .as_str()
+            .parse::<f64>()
+            .map(make_float)
+            .map_err(|_| invalid()),
+        _ => unreachable!(),
+    }
+}
+
+fn make_float(x: f64) -> TokenStream2 {
+    // Going through the bit pattern reproduces the parsed value exactly, including non-finite values.
+    let bits = x.to_bits();
+    quote!(::jetski::Object::float(f64::from_bits(#bits)))
+}
+
+fn walk_symbol(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let identifier = pair.into_inner().next().unwrap();
+    match identifier.as_rule() {
+        Rule::delimited_identifier | Rule::normal_identifier | Rule::peculiar_identifier => {
+            let name = identifier.as_str();
+            Ok(quote!(::jetski::Object::symbol(#name)))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn walk_string(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let content = pair.as_str();
+    Ok(quote!(::jetski::Object::string(String::from(#content))))
+}
+
+fn walk_abbreviation(pair: Pair<Rule>) -> Result<TokenStream2> {
+    let mut inner = pair.clone().into_inner();
+    let prefix = inner.next().unwrap();
+    let datum = inner.next().unwrap();
+
+    match prefix.as_str() {
+        "'" => {
+            let datum = walk_datum(datum)?;
+            Ok(quote!(::jetski::Object::cons(
+                ::jetski::Object::symbol("quote"),
+                ::jetski::Object::cons(#datum, ::jetski::Object::nil())
+            )))
+        }
+        _ => Err(error_at(&pair, "unsupported abbreviation")),
+    }
+}
+
+fn error_at(pair: &Pair<Rule>, msg: &str) -> String {
+    let (line, col) = pair.as_span().start_pos().line_col();
+    format!("{} at {}:{}: `{}`", msg, line, col, pair.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        assert!(parse_datum("(1 2").is_err());
+        assert!(parse_datum(")").is_err());
+    }
+
+    #[test]
+    fn unsupported_datums_are_reported() {
+        let err = parse_datum("(1 #\\a)").unwrap_err();
+        assert!(err.starts_with("unsupported datum at 1:4"), "{}", err);
+    }
+
+    #[test]
+    fn booleans_and_vectors_are_supported() {
+        let tokens = parse_datum("#(#t #false)").unwrap().to_string();
+        assert!(tokens.contains("vector"), "{}", tokens);
+        assert!(tokens.contains("boolean (true)"), "{}", tokens);
+        assert!(tokens.contains("boolean (false)"), "{}", tokens);
+    }
+
+    #[test]
+    fn invalid_numbers_are_reported() {
+        let err = parse_datum("#e1.5").unwrap_err();
+        assert!(err.starts_with("invalid numeric constant"), "{}", err);
+    }
+}
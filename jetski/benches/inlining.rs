@@ -0,0 +1,122 @@
+//! Compare recursive benchmarks with and without inlining.
+//!
+//! The programs run on the tree-walking interpreter and on the JIT. The interpreter allocates
+//! an environment frame for every `let`, so the `let`s that inlining introduces are expensive
+//! there. The JIT compiles them to plain variables, so its numbers show what inlining saves on
+//! calls in compiled code. The JIT compiles the output of the front end, and only the call
+//! is measured, not compilation.
+
+#[macro_use]
+extern crate criterion;
+
+use criterion::Criterion;
+use jetski::core_scheme::inlining::Inliner;
+use jetski::core_scheme::Expression;
+use jetski::differential::{to_datum, Compiled};
+use jetski::eval::{Interpreter, Value};
+use jetski::jit::Compiler;
+use jetski::parser::parse_datum;
+use jetski::Object;
+use std::convert::TryFrom;
+
+const FIB: &[&str] = &[
+    "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))",
+    "(fib 20)",
+];
+
+const TAK: &[&str] = &[
+    "(define (tak x y z) (if (< y x) (tak (tak (- x 1) y z) (tak (- y 1) z x) (tak (- z 1) x y)) z))",
+    "(tak 18 12 6)",
+];
+
+fn parse_program(sources: &[&str]) -> Vec<Expression> {
+    sources
+        .iter()
+        .map(|source| Expression::try_from(parse_datum(source).unwrap()).unwrap())
+        .collect()
+}
+
+fn bench_program(c: &mut Criterion, name: &str, sources: &[&str]) {
+    let program = parse_program(sources);
+    let inlined = Inliner::new().inline_program(program.clone());
+    assert_eq!(run(&program), run(&inlined));
+
+    c.bench_function(&format!("{} without inlining", name), move |b| {
+        b.iter(|| run(&program))
+    });
+    c.bench_function(&format!("{} with inlining", name), move |b| {
+        b.iter(|| run(&inlined))
+    });
+}
+
+fn bench_jit(c: &mut Criterion, name: &str, sources: &[&str]) {
+    let source: Vec<_> = sources.iter().map(|s| parse_datum(s).unwrap()).collect();
+    let front_end = Compiled::new();
+    let inlining = Compiled::new().with_pass("inlining", |p| Ok(Inliner::new().inline_program(p)));
+    let program = jit_source(&front_end, &source);
+    let inlined = jit_source(&inlining, &source);
+    assert_eq!(run_jit(&program), run_jit(&inlined));
+
+    c.bench_function(&format!("{} without inlining (JIT)", name), move |b| {
+        bench_jit_call(b, &program)
+    });
+    c.bench_function(&format!("{} with inlining (JIT)", name), move |b| {
+        bench_jit_call(b, &inlined)
+    });
+}
+
+fn benchmarks(c: &mut Criterion) {
+    bench_program(c, "fib", FIB);
+    bench_program(c, "tak", TAK);
+    bench_jit(c, "fib", FIB);
+    bench_jit(c, "tak", TAK);
+}
+
+// Each sample runs the programs a growing number of times, so keep the number of samples low.
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = benchmarks
+}
+criterion_main!(benches);
+
+/// Run a program and return the value of its last expression, which must be an integer.
+fn run(program: &[Expression]) -> i64 {
+    match Interpreter::new().eval_program(program).unwrap() {
+        Value::Integer(i) => i,
+        value => panic!("not an integer: {}", value),
+    }
+}
+
+/// Compile a program with the front end and the given passes to the source the JIT compiles.
+fn jit_source(passes: &Compiled, source: &[Object]) -> Vec<Object> {
+    passes
+        .compile(source)
+        .unwrap()
+        .iter()
+        .map(|expr| to_datum(expr).unwrap())
+        .collect()
+}
+
+/// Run the definitions of a program and compile its last form, which is left to `run`.
+fn compile_jit<'c>(
+    compiler: &'c mut Compiler,
+    program: &[Object],
+) -> jetski::jit::CompiledForm<'c> {
+    let (call, definitions) = program.split_last().unwrap();
+    for definition in definitions {
+        compiler.eval(definition).unwrap();
+    }
+    compiler.compile(call).unwrap()
+}
+
+fn bench_jit_call(b: &mut criterion::Bencher, program: &[Object]) {
+    let mut compiler = Compiler::new();
+    let call = compile_jit(&mut compiler, program);
+    b.iter(|| call.call().unwrap())
+}
+
+/// Compile and run a program on the JIT and return the value of its last form.
+fn run_jit(program: &[Object]) -> Object {
+    compile_jit(&mut Compiler::new(), program).eval().unwrap()
+}
@@ -0,0 +1,246 @@
+//! Assignment conversion
+//! Local variables that are assigned with `set!` and captured by a lambda are stored in boxes,
+//! so that the binding procedure and all closures share one location. All other variables,
+//! including assigned variables that are never captured, are left alone and can live in
+//! registers.
+//!
+//! The input must be alphatized, with definitions only at toplevel. This pass must run before lambda lifting and closure
+//! conversion, which copy the values of captured variables.
+
+use super::closure_conversion::free_variables;
+use super::{check_toplevel_definitions, Expression};
+use crate::error::Result;
+use crate::runtime::{GensymCounter, Symbol};
+use std::collections::HashSet;
+
+#[derive(Debug, Default)]
+pub struct AssignmentConversion {
+    gensym: GensymCounter,
+}
+
+impl AssignmentConversion {
+    pub fn new() -> Self {
+        AssignmentConversion {
+            gensym: GensymCounter::new(),
+        }
+    }
+
+    pub fn convert_program(&mut self, program: Vec<Expression>) -> Result<Vec<Expression>> {
+        program.iter().map(|expr| self.convert(expr)).collect()
+    }
+
+    /// Convert a toplevel expression or definition.
+    pub fn convert(&mut self, expr: &Expression) -> Result<Expression> {
+        check_toplevel_definitions(expr)?;
+        Ok(self.convert_in(expr, &boxed_variables(expr)))
+    }
+
+    fn convert_in(&self, expr: &Expression, boxed: &HashSet<Symbol>) -> Expression {
+        use Expression::*;
+        let convert = |x: &Expression| Box::new(self.convert_in(x, boxed));
+        match expr {
+            Variable(var) if boxed.contains(var) => BoxRef(Box::new(expr.clone())),
+            Set(var, value) if boxed.contains(var) => {
+                BoxSet(Box::new(Variable(*var)), convert(value))
+            }
+            Set(var, value) => Set(*var, convert(value)),
+            Lambda(params, body) => {
+                let (params, body) = self.convert_procedure(params, body, boxed);
+                Lambda(params, Box::new(body))
+            }
+            DeFunc(name, params, body) => {
+                let (params, body) = self.convert_procedure(params, body, boxed);
+                DeFunc(*name, params, Box::new(body))
+            }
+            Let(var, init, body) if boxed.contains(var) => {
+                let init = self.convert_in(init, boxed);
+                let body = convert(body);
+                if init.is_atomic() {
+                    Let(*var, Box::new(MakeBox(Box::new(init))), body)
+                } else {
+                    // keep the program in A-normal form
+                    let t = Symbol::gensym(var.name(), &self.gensym);
+                    let boxing = Let(*var, Box::new(MakeBox(Box::new(Variable(t)))), body);
+                    Let(t, Box::new(init), Box::new(boxing))
+                }
+            }
+            Let(var, init, body) => Let(*var, convert(init), convert(body)),
+            If(cond, yes, no) => If(convert(cond), convert(yes), convert(no)),
+            Apply(proc, args) => Apply(
+                convert(proc),
+                args.iter().map(|x| self.convert_in(x, boxed)).collect(),
+            ),
+            Begin(exprs) => Begin(exprs.iter().map(|x| self.convert_in(x, boxed)).collect()),
+            DefVar(name, value) => DefVar(*name, convert(value)),
+            Closure(code, captured) => Closure(
+                convert(code),
+                captured.iter().map(|x| self.convert_in(x, boxed)).collect(),
+            ),
+            ClosureCode(closure) => ClosureCode(convert(closure)),
+            EnvRef(env, idx) => EnvRef(convert(env), *idx),
+            MakeBox(value) => MakeBox(convert(value)),
+            BoxRef(b) => BoxRef(convert(b)),
+            BoxSet(b, value) => BoxSet(convert(b), convert(value)),
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => expr.clone(),
+        }
+    }
+
+    /// Boxed parameters are renamed, and the body starts by putting their values in boxes
+    /// under the original names.
+    fn convert_procedure(
+        &self,
+        params: &[Symbol],
+        body: &Expression,
+        boxed: &HashSet<Symbol>,
+    ) -> (Vec<Symbol>, Expression) {
+        let mut body = self.convert_in(body, boxed);
+        let mut new_params = vec![];
+        for param in params {
+            if boxed.contains(param) {
+                let t = Symbol::gensym(param.name(), &self.gensym);
+                let boxing = Expression::MakeBox(Box::new(Expression::Variable(t)));
+                body = Expression::Let(*param, Box::new(boxing), Box::new(body));
+                new_params.push(t);
+            } else {
+                new_params.push(*param);
+            }
+        }
+        (new_params, body)
+    }
+}
+
+/// The local variables in `expr` that are both assigned and captured by a lambda.
+pub fn boxed_variables(expr: &Expression) -> HashSet<Symbol> {
+    let mut analysis = Analysis::default();
+    analysis.visit(expr);
+    let Analysis {
+        bound,
+        assigned,
+        captured,
+    } = analysis;
+    assigned
+        .into_iter()
+        .filter(|var| bound.contains(var) && captured.contains(var))
+        .collect()
+}
+
+#[derive(Default)]
+struct Analysis {
+    bound: HashSet<Symbol>,
+    assigned: HashSet<Symbol>,
+    captured: HashSet<Symbol>,
+}
+
+impl Analysis {
+    fn visit(&mut self, expr: &Expression) {
+        use Expression::*;
+        match expr {
+            Lambda(params, body) | DeFunc(_, params, body) => {
+                self.captured.extend(free_variables(expr));
+                self.bound.extend(params);
+                self.visit(body);
+            }
+            Let(var, init, body) => {
+                self.bound.insert(*var);
+                self.visit(init);
+                self.visit(body);
+            }
+            Set(var, value) => {
+                self.assigned.insert(*var);
+                self.visit(value);
+            }
+            If(cond, yes, no) => {
+                self.visit(cond);
+                self.visit(yes);
+                self.visit(no);
+            }
+            Apply(proc, args) => {
+                self.visit(proc);
+                args.iter().for_each(|x| self.visit(x));
+            }
+            Begin(exprs) => exprs.iter().for_each(|x| self.visit(x)),
+            Closure(_, exprs) => exprs.iter().for_each(|x| self.visit(x)),
+            DefVar(_, x) | ClosureCode(x) | EnvRef(x, _) | MakeBox(x) | BoxRef(x) => self.visit(x),
+            BoxSet(b, value) => {
+                self.visit(b);
+                self.visit(value);
+            }
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_scheme::closure_conversion::ClosureConversion;
+    use crate::parser::parse_datum;
+    use std::convert::TryFrom;
+
+    fn expression(source: &str) -> Expression {
+        Expression::try_from(parse_datum(source).unwrap()).unwrap()
+    }
+
+    fn convert(source: &str) -> String {
+        format!(
+            "{:?}",
+            AssignmentConversion::new()
+                .convert(&expression(source))
+                .unwrap()
+        )
+    }
+
+    #[test]
+    fn captured_assigned_parameters_are_boxed() {
+        assert_eq!(
+            convert("(lambda (n) (lambda () (set! n (+ n 1)) n))"),
+            "(lambda (#:n.0) (let (n (make-box #:n.0)) (lambda () (begin (box-set! n (+ (box-ref n) 1)) (box-ref n)))))"
+        );
+    }
+
+    #[test]
+    fn let_bound_variables_are_boxed() {
+        assert_eq!(
+            convert("(lambda () (let ((x 0)) (lambda () (set! x 1))))"),
+            "(lambda () (let (x (make-box 0)) (lambda () (box-set! x 1))))"
+        );
+        assert_eq!(
+            convert("(lambda () (let ((x (f))) (lambda () (set! x 1))))"),
+            "(lambda () (let (#:x.0 (f)) (let (x (make-box #:x.0)) (lambda () (box-set! x 1)))))"
+        );
+    }
+
+    #[test]
+    fn other_variables_are_not_boxed() {
+        let uncaptured = "(lambda (x) (set! x 1) x)";
+        assert_eq!(convert(uncaptured), format!("{:?}", expression(uncaptured)));
+        let unassigned = "(lambda (x) (lambda () x))";
+        assert_eq!(convert(unassigned), format!("{:?}", expression(unassigned)));
+        let global = "(lambda () (lambda () (set! x 1)))";
+        assert_eq!(convert(global), format!("{:?}", expression(global)));
+    }
+
+    #[test]
+    fn closures_capture_boxes() {
+        let expr = AssignmentConversion::new()
+            .convert(&expression("(lambda (n) (lambda () (set! n 0)))"))
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", ClosureConversion::new().convert(&expr).unwrap()),
+            "(make-closure (lambda (#:env.0 #:n.0) (let (n (make-box #:n.0)) (make-closure (lambda (#:env.1) (box-set! (env-ref #:env.1 0) 0)) n))))"
+        );
+    }
+
+    #[test]
+    fn nested_definitions_are_errors() {
+        let x = Symbol::new("x");
+        let nested = Expression::Lambda(
+            vec![],
+            Box::new(Expression::DefVar(x, Box::new(Expression::Integer(1)))),
+        );
+        assert!(AssignmentConversion::new().convert(&nested).is_err());
+        assert!(ClosureConversion::new().convert(&nested).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+//! Closure conversion
+//! Every lambda becomes an explicit closure record that pairs closed code with the values of
+//! the local variables it captures. The code takes the record as an additional first
+//! parameter and loads captured variables from it, and every application passes the record
+//! on to the code of the called closure. Global variables are not captured.
+//!
+//! Known functions, such as those created by lambda lifting, are exempt: they are defined as
+//! plain toplevel functions and called directly.
+//!
+//! Definitions must be at toplevel; the front end turns internal definitions into local
+//! variables.
+//!
+//! Captured variables must not be assigned, because closures hold copies of their values.
+//! Assignment conversion puts such variables into boxes beforehand; an assignment to a captured
+//! variable is an error.
+
+use super::{check_toplevel_definitions, Expression};
+use crate::error::{ErrorKind, Result};
+use crate::runtime::{GensymCounter, Symbol};
+use std::collections::{HashMap, HashSet};
+
+/// How closures store the variables they capture.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ClosureRepresentation {
+    /// Every closure holds a copy of each variable it captures.
+    #[default]
+    Flat,
+    /// Closures only hold the variables bound by the enclosing procedure, and reach the
+    /// variables of procedures further out through the enclosing procedure's closure. If
+    /// needed, that closure is stored in the first slot.
+    Linked,
+}
+
+#[derive(Debug, Default)]
+pub struct ClosureConversion {
+    representation: ClosureRepresentation,
+    gensym: GensymCounter,
+    known_functions: HashSet<Symbol>,
+}
+
+/// Variables visible in the procedure being converted, mapped to the expression that loads
+/// them. Global variables are not in scope.
+#[derive(Clone, Default)]
+struct Scope {
+    vars: HashMap<Symbol, Expression>,
+    env: Option<Symbol>,
+}
+
+impl Scope {
+    /// Variables bound in the current procedure are not loaded from the environment.
+    fn is_local(&self, var: Symbol) -> bool {
+        match self.vars.get(&var) {
+            Some(Expression::Variable(v)) => *v == var,
+            _ => false,
+        }
+    }
+}
+
+impl ClosureConversion {
+    pub fn new() -> Self {
+        ClosureConversion::with_representation(ClosureRepresentation::Flat)
+    }
+
+    pub fn with_representation(representation: ClosureRepresentation) -> Self {
+        ClosureConversion {
+            representation,
+            gensym: GensymCounter::new(),
+            known_functions: HashSet::new(),
+        }
+    }
+
+    /// Declare a toplevel function that is never reassigned and never used as a value.
+    pub fn declare_known_function(&mut self, name: Symbol) {
+        self.known_functions.insert(name);
+    }
+
+    pub fn convert_program(&mut self, program: Vec<Expression>) -> Result<Vec<Expression>> {
+        program.iter().map(|expr| self.convert(expr)).collect()
+    }
+
+    /// Convert a toplevel expression or definition.
+    pub fn convert(&mut self, expr: &Expression) -> Result<Expression> {
+        check_toplevel_definitions(expr)?;
+        self.convert_in(expr, &Scope::default())
+    }
+
+    fn convert_in(&self, expr: &Expression, scope: &Scope) -> Result<Expression> {
+        use Expression::*;
+        let convert = |x: &Expression| self.convert_in(x, scope).map(Box::new);
+        let convert_all = |xs: &[Expression]| {
+            xs.iter()
+                .map(|x| self.convert_in(x, scope))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(match expr {
+            Variable(var) => scope.vars.get(var).cloned().unwrap_or(Variable(*var)),
+            Lambda(params, body) => self.convert_lambda(params, body, scope)?,
+            Let(var, init, body) => {
+                let mut body_scope = scope.clone();
+                body_scope.vars.insert(*var, Variable(*var));
+                Let(
+                    *var,
+                    convert(init)?,
+                    Box::new(self.convert_in(body, &body_scope)?),
+                )
+            }
+            If(cond, yes, no) => If(convert(cond)?, convert(yes)?, convert(no)?),
+            Apply(proc, args) => self.convert_application(proc, args, scope)?,
+            Begin(exprs) => Begin(convert_all(exprs)?),
+            DefVar(name, value) => DefVar(*name, convert(value)?),
+            DeFunc(name, params, body) if self.known_functions.contains(name) => {
+                let mut body_scope = scope.clone();
+                for p in params {
+                    body_scope.vars.insert(*p, Variable(*p));
+                }
+                DeFunc(
+                    *name,
+                    params.clone(),
+                    Box::new(self.convert_in(body, &body_scope)?),
+                )
+            }
+            DeFunc(name, params, body) => {
+                DefVar(*name, Box::new(self.convert_lambda(params, body, scope)?))
+            }
+            Closure(code, captured) => Closure(code.clone(), convert_all(captured)?),
+            ClosureCode(closure) => ClosureCode(convert(closure)?),
+            EnvRef(env, idx) => EnvRef(convert(env)?, *idx),
+            Set(var, value) => {
+                if scope.vars.contains_key(var) && !scope.is_local(*var) {
+                    return Err(ErrorKind::ValidationError(format!(
+                        "assignment to captured variable {}; run assignment conversion first",
+                        var
+                    ))
+                    .into());
+                }
+                Set(*var, convert(value)?)
+            }
+            MakeBox(value) => MakeBox(convert(value)?),
+            BoxRef(b) => BoxRef(convert(b)?),
+            BoxSet(b, value) => BoxSet(convert(b)?, convert(value)?),
+            Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => expr.clone(),
+        })
+    }
+
+    fn convert_lambda(
+        &self,
+        params: &[Symbol],
+        body: &Expression,
+        scope: &Scope,
+    ) -> Result<Expression> {
+        let env = Symbol::gensym("env", &self.gensym);
+        let env_var = Expression::Variable(env);
+
+        let mut inner = Scope {
+            vars: HashMap::new(),
+            env: Some(env),
+        };
+        let mut captured = vec![];
+
+        let free_vars =
+            free_variables(&Expression::Lambda(params.to_vec(), Box::new(body.clone())));
+        let (local, outer): (Vec<_>, Vec<_>) = free_vars
+            .into_iter()
+            .filter(|var| scope.vars.contains_key(var))
+            .partition(|var| {
+                self.representation == ClosureRepresentation::Flat || scope.is_local(*var)
+            });
+
+        if !outer.is_empty() {
+            // linked closures reach outer variables through the enclosing closure
+            let outer_env = scope.env.ok_or_else(|| {
+                ErrorKind::ValidationError(format!(
+                    "captured variables outside of a procedure: {:?}",
+                    outer
+                ))
+            })?;
+            captured.push(Expression::Variable(outer_env));
+            let link = Expression::EnvRef(Box::new(env_var.clone()), 0);
+            for var in outer {
+                inner.vars.insert(var, rebase(&scope.vars[&var], &link));
+            }
+        }
+
+        for var in local {
+            let idx = captured.len();
+            captured.push(scope.vars[&var].clone());
+            inner
+                .vars
+                .insert(var, Expression::EnvRef(Box::new(env_var.clone()), idx));
+        }
+
+        for p in params {
+            inner.vars.insert(*p, Expression::Variable(*p));
+        }
+
+        let mut code_params = vec![env];
+        code_params.extend(params);
+        let code = Expression::Lambda(code_params, Box::new(self.convert_in(body, &inner)?));
+        Ok(Expression::Closure(Box::new(code), captured))
+    }
+
+    fn convert_application(
+        &self,
+        proc: &Expression,
+        args: &[Expression],
+        scope: &Scope,
+    ) -> Result<Expression> {
+        let args = args
+            .iter()
+            .map(|x| self.convert_in(x, scope))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match self.convert_in(proc, scope)? {
+            Expression::Variable(f) if self.known_functions.contains(&f) => {
+                Expression::Apply(Box::new(Expression::Variable(f)), args)
+            }
+            Expression::Primitive => Expression::Apply(Box::new(Expression::Primitive), args),
+            proc @ Expression::Variable(_) => {
+                let code = Expression::ClosureCode(Box::new(proc.clone()));
+                Expression::Apply(Box::new(code), std::iter::once(proc).chain(args).collect())
+            }
+            // the closure is used twice, so it must be evaluated only once
+            proc => {
+                let f = Symbol::gensym("f", &self.gensym);
+                let f_var = Expression::Variable(f);
+                let code = Expression::ClosureCode(Box::new(f_var.clone()));
+                let call =
+                    Expression::Apply(Box::new(code), std::iter::once(f_var).chain(args).collect());
+                Expression::Let(f, Box::new(proc), Box::new(call))
+            }
+        })
+    }
+}
+
+/// Replace the environment variable at the root of a chain of environment loads.
+fn rebase(access: &Expression, root: &Expression) -> Expression {
+    match access {
+        Expression::EnvRef(env, idx) => Expression::EnvRef(Box::new(rebase(env, root)), *idx),
+        _ => root.clone(),
+    }
+}
+
+/// The variables referenced but not bound in `expr`, in order of their first occurrence.
+pub fn free_variables(expr: &Expression) -> Vec<Symbol> {
+    let mut free = vec![];
+    collect_free_variables(expr, &mut vec![], &mut free);
+    free
+}
+
+fn collect_free_variables(expr: &Expression, bound: &mut Vec<Symbol>, free: &mut Vec<Symbol>) {
+    use Expression::*;
+    match expr {
+        Variable(var) => {
+            if !bound.contains(var) && !free.contains(var) {
+                free.push(*var)
+            }
+        }
+        Lambda(params, body) | DeFunc(_, params, body) => {
+            let n = bound.len();
+            bound.extend(params);
+            collect_free_variables(body, bound, free);
+            bound.truncate(n);
+        }
+        Let(var, init, body) => {
+            collect_free_variables(init, bound, free);
+            bound.push(*var);
+            collect_free_variables(body, bound, free);
+            bound.pop();
+        }
+        If(cond, yes, no) => {
+            collect_free_variables(cond, bound, free);
+            collect_free_variables(yes, bound, free);
+            collect_free_variables(no, bound, free);
+        }
+        Apply(proc, args) => {
+            collect_free_variables(proc, bound, free);
+            args.iter()
+                .for_each(|x| collect_free_variables(x, bound, free));
+        }
+        Begin(exprs) => exprs
+            .iter()
+            .for_each(|x| collect_free_variables(x, bound, free)),
+        DefVar(_, value) => collect_free_variables(value, bound, free),
+        // the code of a closure record is closed
+        Closure(_, captured) => captured
+            .iter()
+            .for_each(|x| collect_free_variables(x, bound, free)),
+        ClosureCode(closure) => collect_free_variables(closure, bound, free),
+        EnvRef(env, _) => collect_free_variables(env, bound, free),
+        Set(var, value) => {
+            collect_free_variables(&Variable(*var), bound, free);
+            collect_free_variables(value, bound, free);
+        }
+        MakeBox(x) | BoxRef(x) => collect_free_variables(x, bound, free),
+        BoxSet(b, value) => {
+            collect_free_variables(b, bound, free);
+            collect_free_variables(value, bound, free);
+        }
+        Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_scheme::assignment_conversion::AssignmentConversion;
+    use crate::parser::parse_datum;
+    use std::convert::TryFrom;
+
+    fn expression(source: &str) -> Expression {
+        Expression::try_from(parse_datum(source).unwrap()).unwrap()
+    }
+
+    fn convert(representation: ClosureRepresentation, source: &str) -> String {
+        let mut conversion = ClosureConversion::with_representation(representation);
+        format!("{:?}", conversion.convert(&expression(source)).unwrap())
+    }
+
+    #[test]
+    fn free_variables_in_order_of_occurrence() {
+        let free = free_variables(&expression(
+            "(lambda (x) (let ((y (f x z))) (g y (lambda (z) (h z w)) z)))",
+        ));
+        let names: Vec<_> = free.iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec!["f", "z", "g", "h", "w"]);
+    }
+
+    #[test]
+    fn global_variables_are_not_captured() {
+        assert_eq!(
+            convert(ClosureRepresentation::Flat, "(define (f x) (g x))"),
+            "(define f (make-closure (lambda (#:env.0 x) ((closure-code g) g x))))"
+        );
+    }
+
+    #[test]
+    fn flat_closures_copy_captured_variables() {
+        assert_eq!(
+            convert(
+                ClosureRepresentation::Flat,
+                "(lambda (x) (lambda (y) (lambda (z) (list x y z))))"
+            ),
+            "(make-closure (lambda (#:env.0 x) (make-closure (lambda (#:env.1 y) (make-closure (lambda (#:env.2 z) ((closure-code list) list (env-ref #:env.2 0) (env-ref #:env.2 1) z)) (env-ref #:env.1 0) y)) x)))"
+        );
+    }
+
+    #[test]
+    fn linked_closures_reach_outer_variables_through_the_enclosing_closure() {
+        assert_eq!(
+            convert(
+                ClosureRepresentation::Linked,
+                "(lambda (x) (lambda (y) (lambda (z) (list x y z))))"
+            ),
+            "(make-closure (lambda (#:env.0 x) (make-closure (lambda (#:env.1 y) (make-closure (lambda (#:env.2 z) ((closure-code list) list (env-ref (env-ref #:env.2 0) 0) (env-ref #:env.2 1) z)) #:env.1 y)) x)))"
+        );
+    }
+
+    #[test]
+    fn let_bound_variables_are_captured() {
+        assert_eq!(
+            convert(ClosureRepresentation::Flat, "(let ((n 1)) (lambda () n))"),
+            "(let (n 1) (make-closure (lambda (#:env.0) (env-ref #:env.0 0)) n))"
+        );
+    }
+
+    #[test]
+    fn assignments_to_captured_variables_are_errors() {
+        let expr = expression("(lambda (x) (lambda () (set! x 1)))");
+        match ClosureConversion::new().convert(&expr).unwrap_err().kind() {
+            ErrorKind::ValidationError(msg) => {
+                assert!(msg.starts_with("assignment to captured variable x"))
+            }
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn closures_in_operator_position_are_evaluated_once() {
+        assert_eq!(
+            convert(ClosureRepresentation::Flat, "(lambda (x) ((lambda (y) x) 1))"),
+            "(make-closure (lambda (#:env.0 x) (let (#:f.2 (make-closure (lambda (#:env.1 y) (env-ref #:env.1 0)) x)) ((closure-code #:f.2) #:f.2 1))))"
+        );
+    }
+
+    #[test]
+    fn closures_capture_internal_definitions() {
+        let expr = AssignmentConversion::new()
+            .convert(&expression("(lambda (y) (define x y) (lambda () x))"))
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", ClosureConversion::new().convert(&expr).unwrap()),
+            "(make-closure (lambda (#:env.0 y) (let (x (make-box <undefined>)) (begin (box-set! x y) (make-closure (lambda (#:env.1) (box-ref (env-ref #:env.1 0))) x)))))"
+        );
+    }
+}
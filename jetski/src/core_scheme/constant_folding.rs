@@ -0,0 +1,440 @@
+//! Constant folding and propagation
+//! Arithmetic and comparisons on literal numbers are evaluated at compile time, constants bound
+//! by `let` are substituted into the body, and `if` with a known condition is reduced to one
+//! branch.
+//!
+//! Folding never changes the meaning of a program. Exact integer arithmetic is only folded if it
+//! does not overflow and the result is an integer, so `(/ 7 2)` and `(/ 1 0)` are left to the
+//! runtime. Mixing exact and inexact operands gives an inexact result, as in R7RS.
+//! Operators that are shadowed by local variables or redefined by the program are not folded.
+
+use super::{body_definitions, Expression};
+use crate::object::TaggedValue;
+use crate::runtime::{compare_integer_float, Symbol};
+use crate::Object;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default)]
+pub struct ConstantFolding {
+    redefined: HashSet<Symbol>,
+}
+
+#[derive(Clone, Default)]
+struct Scope {
+    constants: HashMap<Symbol, Expression>,
+    locals: HashSet<Symbol>,
+}
+
+impl Scope {
+    fn bind(&self, vars: &[Symbol]) -> Scope {
+        let mut scope = self.clone();
+        for var in vars {
+            scope.constants.remove(var);
+            scope.locals.insert(*var);
+        }
+        scope
+    }
+
+    /// The scope of a body, where internal definitions shadow outer variables too.
+    fn bind_body(&self, vars: &[Symbol], body: &Expression) -> Scope {
+        self.bind(vars).bind(&body_definitions(body))
+    }
+}
+
+impl ConstantFolding {
+    pub fn new() -> Self {
+        ConstantFolding {
+            redefined: HashSet::new(),
+        }
+    }
+
+    /// Global variables that are defined or assigned by the program no longer refer to the
+    /// builtin operators.
+    pub fn declare_redefined(&mut self, name: Symbol) {
+        self.redefined.insert(name);
+    }
+
+    pub fn fold_program(&mut self, program: Vec<Expression>) -> Vec<Expression> {
+        for expr in &program {
+            match expr {
+                Expression::DefVar(name, _) | Expression::DeFunc(name, _, _) => {
+                    self.declare_redefined(*name)
+                }
+                _ => {}
+            }
+            for var in assigned_variables(expr) {
+                self.declare_redefined(var);
+            }
+        }
+        program.iter().map(|expr| self.fold(expr)).collect()
+    }
+
+    /// Fold a toplevel expression or definition.
+    pub fn fold(&self, expr: &Expression) -> Expression {
+        self.fold_in(expr, &Scope::default())
+    }
+
+    fn fold_in(&self, expr: &Expression, scope: &Scope) -> Expression {
+        use Expression::*;
+        let fold = |x: &Expression| Box::new(self.fold_in(x, scope));
+        match expr {
+            Variable(var) => scope
+                .constants
+                .get(var)
+                .cloned()
+                .unwrap_or_else(|| expr.clone()),
+            Let(var, init, body) => {
+                let init = self.fold_in(init, scope);
+                if is_constant(&init) && !assigned_variables(body).contains(var) {
+                    let mut body_scope = scope.bind(&[*var]);
+                    body_scope.constants.insert(*var, init);
+                    self.fold_in(body, &body_scope.bind(&body_definitions(body)))
+                } else {
+                    Let(
+                        *var,
+                        Box::new(init),
+                        Box::new(self.fold_in(body, &scope.bind_body(&[*var], body))),
+                    )
+                }
+            }
+            If(cond, yes, no) => {
+                let cond = self.fold_in(cond, scope);
+                match truth_value(&cond) {
+                    Some(true) => self.fold_in(yes, scope),
+                    Some(false) => self.fold_in(no, scope),
+                    None => If(Box::new(cond), fold(yes), fold(no)),
+                }
+            }
+            Apply(proc, args) => {
+                let args: Vec<_> = args.iter().map(|x| self.fold_in(x, scope)).collect();
+                let folded = match &**proc {
+                    Variable(op) if !scope.locals.contains(op) && !self.redefined.contains(op) => {
+                        fold_operation(op.name(), &args)
+                    }
+                    _ => None,
+                };
+                folded.unwrap_or_else(|| Apply(fold(proc), args))
+            }
+            Lambda(params, body) => Lambda(
+                params.clone(),
+                Box::new(self.fold_in(body, &scope.bind_body(params, body))),
+            ),
+            DeFunc(name, params, body) => DeFunc(
+                *name,
+                params.clone(),
+                Box::new(self.fold_in(body, &scope.bind_body(params, body))),
+            ),
+            Set(var, value) => Set(*var, fold(value)),
+            Begin(exprs) => Begin(exprs.iter().map(|x| self.fold_in(x, scope)).collect()),
+            DefVar(name, value) => DefVar(*name, fold(value)),
+            Closure(code, captured) => Closure(
+                fold(code),
+                captured.iter().map(|x| self.fold_in(x, scope)).collect(),
+            ),
+            ClosureCode(closure) => ClosureCode(fold(closure)),
+            EnvRef(env, idx) => EnvRef(fold(env), *idx),
+            MakeBox(value) => MakeBox(fold(value)),
+            BoxRef(b) => BoxRef(fold(b)),
+            BoxSet(b, value) => BoxSet(fold(b), fold(value)),
+            Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => expr.clone(),
+        }
+    }
+}
+
+/// Constants that can be substituted for variables. Strings and quoted lists are excluded,
+/// because copies of them would not be `eq?` to each other.
+pub fn is_constant(expr: &Expression) -> bool {
+    match expr {
+        Expression::Integer(_) | Expression::Float(_) | Expression::Nil => true,
+        Expression::Quote(obj) => matches!(
+            obj.as_value(),
+            TaggedValue::Boolean(_) | TaggedValue::Symbol(_)
+        ),
+        _ => false,
+    }
+}
+
+/// Whether a condition is known to be true or false. Only `#f` is false.
+pub fn truth_value(expr: &Expression) -> Option<bool> {
+    match expr {
+        Expression::Quote(obj) => Some(*obj != Object::boolean(false)),
+        Expression::Integer(_)
+        | Expression::Float(_)
+        | Expression::String(_)
+        | Expression::Nil
+        | Expression::Lambda(_, _) => Some(true),
+        _ => None,
+    }
+}
+
+/// The variables assigned with `set!` anywhere in `expr`.
+pub fn assigned_variables(expr: &Expression) -> HashSet<Symbol> {
+    use Expression::*;
+    let mut vars = HashSet::new();
+    let mut todo = vec![expr];
+    while let Some(expr) = todo.pop() {
+        match expr {
+            Set(var, value) => {
+                vars.insert(*var);
+                todo.push(value);
+            }
+            Lambda(_, x)
+            | DeFunc(_, _, x)
+            | DefVar(_, x)
+            | ClosureCode(x)
+            | EnvRef(x, _)
+            | MakeBox(x)
+            | BoxRef(x) => todo.push(x),
+            Let(_, a, b) | BoxSet(a, b) => todo.extend(&[&**a, &**b]),
+            If(cond, yes, no) => todo.extend(&[&**cond, &**yes, &**no]),
+            Apply(x, xs) | Closure(x, xs) => {
+                todo.push(x);
+                todo.extend(xs);
+            }
+            Begin(xs) => todo.extend(xs),
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => {}
+        }
+    }
+    vars
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Exact(i64),
+    Inexact(f64),
+}
+
+impl Number {
+    fn from_expression(expr: &Expression) -> Option<Number> {
+        match expr {
+            Expression::Integer(i) => Some(Number::Exact(*i)),
+            Expression::Float(f) => Some(Number::Inexact(*f)),
+            _ => None,
+        }
+    }
+
+    fn into_expression(self) -> Expression {
+        match self {
+            Number::Exact(i) => Expression::Integer(i),
+            Number::Inexact(f) => Expression::Float(f),
+        }
+    }
+
+    fn is_exact_zero(self) -> bool {
+        matches!(self, Number::Exact(0))
+    }
+
+    fn to_float(self) -> f64 {
+        match self {
+            Number::Exact(i) => i as f64,
+            Number::Inexact(f) => f,
+        }
+    }
+
+    /// Apply an arithmetic operation; exact operations return `None` if they have no exact
+    /// integer result.
+    fn arithmetic(
+        self,
+        other: Number,
+        exact: fn(i64, i64) -> Option<i64>,
+        inexact: fn(f64, f64) -> f64,
+    ) -> Option<Number> {
+        match (self, other) {
+            (Number::Exact(a), Number::Exact(b)) => exact(a, b).map(Number::Exact),
+            (a, b) => Some(Number::Inexact(inexact(a.to_float(), b.to_float()))),
+        }
+    }
+
+    /// `None` if the numbers are unordered (NaN).
+    fn compare(self, other: Number) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Number::Exact(a), Number::Exact(b)) => Some(a.cmp(&b)),
+            (Number::Exact(a), Number::Inexact(b)) => compare_integer_float(a, b),
+            (Number::Inexact(a), Number::Exact(b)) => {
+                compare_integer_float(b, a).map(std::cmp::Ordering::reverse)
+            }
+            (Number::Inexact(a), Number::Inexact(b)) => a.partial_cmp(&b),
+        }
+    }
+}
+
+fn exact_division(a: i64, b: i64) -> Option<i64> {
+    if b != 0 && a.checked_rem(b) == Some(0) {
+        a.checked_div(b)
+    } else {
+        None
+    }
+}
+
+/// Evaluate a builtin operator on literal operands.
+fn fold_operation(op: &str, args: &[Expression]) -> Option<Expression> {
+    use std::cmp::Ordering::*;
+
+    let args = args
+        .iter()
+        .map(Number::from_expression)
+        .collect::<Option<Vec<_>>>()?;
+
+    let arithmetic = |exact: fn(i64, i64) -> Option<i64>, inexact: fn(f64, f64) -> f64| {
+        let (first, rest) = args.split_first()?;
+        rest.iter()
+            .try_fold(*first, |acc, &x| acc.arithmetic(x, exact, inexact))
+            .map(Number::into_expression)
+    };
+
+    let comparison = |test: fn(std::cmp::Ordering) -> bool| {
+        if args.len() < 2 {
+            return None;
+        }
+        let mut result = true;
+        for pair in args.windows(2) {
+            result &= test(pair[0].compare(pair[1])?);
+        }
+        Some(Expression::Quote(Object::boolean(result)))
+    };
+
+    match (op, args.as_slice()) {
+        ("+", []) => Some(Expression::Integer(0)),
+        ("*", []) => Some(Expression::Integer(1)),
+        ("+", [x]) | ("*", [x]) => Some(x.into_expression()),
+        ("-", [Number::Exact(x)]) => x.checked_neg().map(Expression::Integer),
+        ("-", [Number::Inexact(x)]) => Some(Expression::Float(-x)),
+        ("/", [x]) => Number::Exact(1)
+            .arithmetic(*x, exact_division, |a, b| a / b)
+            .filter(|_| !x.is_exact_zero())
+            .map(Number::into_expression),
+        ("+", _) => arithmetic(i64::checked_add, |a, b| a + b),
+        ("*", _) => arithmetic(i64::checked_mul, |a, b| a * b),
+        ("-", _) => arithmetic(i64::checked_sub, |a, b| a - b),
+        // division by exact zero is an error, even with inexact operands
+        ("/", _) if args[1..].iter().any(|x| x.is_exact_zero()) => None,
+        ("/", _) => arithmetic(exact_division, |a, b| a / b),
+        ("=", _) => comparison(|o| o == Equal),
+        ("<", _) => comparison(|o| o == Less),
+        (">", _) => comparison(|o| o == Greater),
+        ("<=", _) => comparison(|o| o != Greater),
+        (">=", _) => comparison(|o| o != Less),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_scheme::AnormalTransform;
+    use crate::parser::parse_datum;
+    use std::convert::TryFrom;
+
+    fn expression(source: &str) -> Expression {
+        Expression::try_from(parse_datum(source).unwrap()).unwrap()
+    }
+
+    fn fold(source: &str) -> String {
+        format!("{:?}", ConstantFolding::new().fold(&expression(source)))
+    }
+
+    fn unchanged(source: &str) -> bool {
+        fold(source) == format!("{:?}", expression(source))
+    }
+
+    #[test]
+    fn internal_definitions_shadow_operators_and_constants() {
+        assert_eq!(
+            fold("(lambda () (define + -) (+ 5 2))"),
+            "(lambda () (let (+ <undefined>) (begin (set! + -) (+ 5 2))))"
+        );
+        assert_eq!(
+            fold("(let ((x 1)) (define x 2) x)"),
+            "(let (x 1) (let (x <undefined>) (begin (set! x 2) x)))"
+        );
+
+        // definitions in hand-built bodies are local as well
+        let (x, plus) = (Symbol::new("x"), Symbol::new("+"));
+        let body = Expression::Begin(vec![
+            Expression::DefVar(x, Box::new(Expression::Integer(2))),
+            Expression::DefVar(plus, Box::new(Expression::Variable(Symbol::new("-")))),
+            Expression::Apply(
+                Box::new(Expression::Variable(plus)),
+                vec![Expression::Variable(x), Expression::Integer(1)],
+            ),
+        ]);
+        let expr = Expression::Let(x, Box::new(Expression::Integer(1)), Box::new(body));
+        assert_eq!(
+            format!("{:?}", ConstantFolding::new().fold(&expr)),
+            "(begin (define x 2) (define + -) (+ x 1))"
+        );
+    }
+
+    #[test]
+    fn arithmetic_is_folded() {
+        assert_eq!(fold("(define (f) (* 2 (+ 3 4)))"), "(define (f ) 14)");
+        assert_eq!(fold("(- 5)"), "-5");
+        assert_eq!(fold("(- 10 1 2)"), "7");
+        assert_eq!(fold("(/ 12 2 3)"), "2");
+        assert_eq!(fold("(+)"), "0");
+    }
+
+    #[test]
+    fn exactness_is_respected() {
+        assert_eq!(fold("(+ 1 2.5)"), "3.5");
+        assert_eq!(fold("(/ 1.0 4)"), "0.25");
+        assert!(unchanged("(/ 7 2)"));
+        assert!(unchanged("(/ 1 0)"));
+        assert!(unchanged("(/ 1.0 0)"));
+        assert!(unchanged("(/ 0)"));
+    }
+
+    #[test]
+    fn overflow_is_not_folded() {
+        assert!(unchanged("(* 4611686018427387904 2)"));
+        assert!(unchanged("(- -9223372036854775807 2)"));
+    }
+
+    #[test]
+    fn comparisons_are_folded() {
+        assert_eq!(fold("(< 1 2 3)"), "'#t");
+        assert_eq!(fold("(= 1 1.0)"), "'#t");
+        assert_eq!(fold("(>= 2 3)"), "'#f");
+        assert_eq!(fold("(< 9007199254740993 9007199254740992.0)"), "'#f");
+        assert_eq!(fold("(< 9223372036854775807 9223372036854775808.0)"), "'#t");
+        assert_eq!(fold("(= 9223372036854775807 9223372036854775808.0)"), "'#f");
+    }
+
+    #[test]
+    fn let_bound_constants_are_propagated() {
+        assert_eq!(fold("(let ((x 2)) (let ((y (* x 3))) (+ x y)))"), "8");
+        assert_eq!(fold("(let ((x 2)) (f x))"), "(f 2)");
+        assert!(unchanged("(let ((x 1)) (set! x 2) x)"));
+        assert!(unchanged("(let ((s \"abc\")) (eq? s s))"));
+    }
+
+    #[test]
+    fn conditionals_with_known_conditions_are_reduced() {
+        assert_eq!(fold("(if (< 1 2) (f) (g))"), "(f)");
+        assert_eq!(fold("(if (> 1 2) (f) (g))"), "(g)");
+        assert_eq!(fold("(if '() (f) (g))"), "(f)");
+        assert_eq!(fold("(if #f (f))"), "<undefined>");
+    }
+
+    #[test]
+    fn normalized_programs_are_folded() {
+        let expr = AnormalTransform::new()
+            .transform(&parse_datum("(define (f) (* 2 (+ 3 4)))").unwrap())
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", ConstantFolding::new().fold(&expr)),
+            "(define f (lambda () 14))"
+        );
+    }
+
+    #[test]
+    fn shadowed_and_redefined_operators_are_not_folded() {
+        assert!(unchanged("(lambda (+) (+ 1 2))"));
+        let program = vec![expression("(define (+ a b) a)"), expression("(+ 1 2)")];
+        assert_eq!(
+            format!("{:?}", ConstantFolding::new().fold_program(program)),
+            "[(define (+ a b) a), (+ 1 2)]"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! Dead code elimination
+//! Removes `let` bindings that are never used and whose initializer has no side effects, pure
+//! expressions whose values are discarded in a `begin`, branches of `if` that can never be taken,
+//! and toplevel definitions that cannot be reached from the exported names or from the toplevel
+//! expressions of the program.
+//!
+//! The effect analysis knows which primitives are pure. Applying a primitive to arguments of
+//! the wrong type "is an error" in R7RS, which programs cannot rely on being signalled, so such
+//! applications count as pure, too.
+
+use super::closure_conversion::free_variables;
+use super::constant_folding::{assigned_variables, truth_value};
+use super::{body_definitions, Expression};
+use crate::runtime::Symbol;
+use std::collections::HashSet;
+
+/// Primitives without side effects.
+const PURE_PRIMITIVES: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "not",
+    "eq?",
+    "eqv?",
+    "equal?",
+    "cons",
+    "car",
+    "cdr",
+    "list",
+    "vector",
+    "vector-ref",
+    "vector-length",
+    "null?",
+    "pair?",
+    "number?",
+    "integer?",
+    "symbol?",
+    "string?",
+    "boolean?",
+    "procedure?",
+    "vector?",
+];
+
+#[derive(Debug, Default)]
+pub struct DeadCodeElimination {
+    exported: HashSet<Symbol>,
+    redefined: HashSet<Symbol>,
+}
+
+impl DeadCodeElimination {
+    pub fn new() -> Self {
+        DeadCodeElimination {
+            exported: HashSet::new(),
+            redefined: HashSet::new(),
+        }
+    }
+
+    /// Definitions of exported names are never removed.
+    pub fn export(&mut self, name: Symbol) {
+        self.exported.insert(name);
+    }
+
+    /// Global variables that are defined or assigned by the program no longer refer to the
+    /// builtin primitives.
+    pub fn declare_redefined(&mut self, name: Symbol) {
+        self.redefined.insert(name);
+    }
+
+    pub fn eliminate_program(&mut self, program: Vec<Expression>) -> Vec<Expression> {
+        for expr in &program {
+            if let Some(name) = definition_name(expr) {
+                self.declare_redefined(name);
+            }
+            for var in assigned_variables(expr) {
+                self.declare_redefined(var);
+            }
+        }
+
+        let program: Vec<_> = program.iter().map(|expr| self.eliminate(expr)).collect();
+
+        // Toplevel expressions and definitions with side effects are always executed, so they
+        // are roots, too.
+        let mut reachable = self.exported.clone();
+        let mut todo: Vec<_> = program
+            .iter()
+            .filter(|expr| match expr {
+                Expression::DefVar(_, value) => !self.is_pure(value),
+                Expression::DeFunc(_, _, _) => false,
+                _ => true,
+            })
+            .collect();
+        let mut todo_names: Vec<_> = reachable.iter().cloned().collect();
+        loop {
+            for expr in todo.drain(..) {
+                for name in references(expr) {
+                    if reachable.insert(name) {
+                        todo_names.push(name);
+                    }
+                }
+            }
+            match todo_names.pop() {
+                Some(name) => todo.extend(
+                    program
+                        .iter()
+                        .filter(|expr| definition_name(expr) == Some(name)),
+                ),
+                None => break,
+            }
+        }
+
+        program
+            .into_iter()
+            .filter(|expr| match expr {
+                Expression::DefVar(name, value) => reachable.contains(name) || !self.is_pure(value),
+                Expression::DeFunc(name, _, _) => reachable.contains(name),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Eliminate dead code within a toplevel expression or definition.
+    pub fn eliminate(&self, expr: &Expression) -> Expression {
+        self.eliminate_in(expr, &HashSet::new())
+    }
+
+    fn eliminate_in(&self, expr: &Expression, locals: &HashSet<Symbol>) -> Expression {
+        use Expression::*;
+        let eliminate = |x: &Expression| Box::new(self.eliminate_in(x, locals));
+        match expr {
+            Let(var, init, body) => {
+                let init = self.eliminate_in(init, locals);
+                let body = self.eliminate_in(body, &bind_body(locals, &[*var], body));
+                if !free_variables(&body).contains(var) && self.is_pure_in(&init, locals) {
+                    body
+                } else {
+                    Let(*var, Box::new(init), Box::new(body))
+                }
+            }
+            If(cond, yes, no) => {
+                let cond = self.eliminate_in(cond, locals);
+                match truth_value(&cond) {
+                    Some(true) => self.eliminate_in(yes, locals),
+                    Some(false) => self.eliminate_in(no, locals),
+                    None => If(Box::new(cond), eliminate(yes), eliminate(no)),
+                }
+            }
+            Begin(exprs) => {
+                let (last, init) = exprs.split_last().expect("empty begin");
+                let mut exprs: Vec<_> = init
+                    .iter()
+                    .map(|x| self.eliminate_in(x, locals))
+                    .filter(|x| !self.is_pure_in(x, locals))
+                    .collect();
+                exprs.push(self.eliminate_in(last, locals));
+                if exprs.len() == 1 {
+                    exprs.pop().unwrap()
+                } else {
+                    Begin(exprs)
+                }
+            }
+            Lambda(params, body) => Lambda(
+                params.clone(),
+                Box::new(self.eliminate_in(body, &bind_body(locals, params, body))),
+            ),
+            DeFunc(name, params, body) => DeFunc(
+                *name,
+                params.clone(),
+                Box::new(self.eliminate_in(body, &bind_body(locals, params, body))),
+            ),
+            Apply(proc, args) => Apply(
+                eliminate(proc),
+                args.iter().map(|x| self.eliminate_in(x, locals)).collect(),
+            ),
+            Set(var, value) => Set(*var, eliminate(value)),
+            DefVar(name, value) => DefVar(*name, eliminate(value)),
+            Closure(code, captured) => Closure(
+                eliminate(code),
+                captured
+                    .iter()
+                    .map(|x| self.eliminate_in(x, locals))
+                    .collect(),
+            ),
+            ClosureCode(closure) => ClosureCode(eliminate(closure)),
+            EnvRef(env, idx) => EnvRef(eliminate(env), *idx),
+            MakeBox(value) => MakeBox(eliminate(value)),
+            BoxRef(b) => BoxRef(eliminate(b)),
+            BoxSet(b, value) => BoxSet(eliminate(b), eliminate(value)),
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => expr.clone(),
+        }
+    }
+
+    /// Effect analysis: can `expr` be removed if its value is not used?
+    pub fn is_pure(&self, expr: &Expression) -> bool {
+        self.is_pure_in(expr, &HashSet::new())
+    }
+
+    fn is_pure_in(&self, expr: &Expression, locals: &HashSet<Symbol>) -> bool {
+        use Expression::*;
+        let pure = |x: &Expression| self.is_pure_in(x, locals);
+        match expr {
+            Undef
+            | Nil
+            | Integer(_)
+            | Float(_)
+            | String(_)
+            | Quote(_)
+            | Primitive
+            | Variable(_)
+            | Lambda(_, _) => true,
+            Let(var, init, body) => {
+                pure(init) && self.is_pure_in(body, &bind_body(locals, &[*var], body))
+            }
+            If(cond, yes, no) => pure(cond) && pure(yes) && pure(no),
+            Begin(exprs) => exprs.iter().all(pure),
+            Apply(proc, args) => match &**proc {
+                Variable(op) => self.is_pure_primitive(*op, locals) && args.iter().all(pure),
+                _ => false,
+            },
+            Closure(code, captured) => pure(code) && captured.iter().all(pure),
+            ClosureCode(x) | EnvRef(x, _) | MakeBox(x) | BoxRef(x) => pure(x),
+            Set(_, _) | BoxSet(_, _) | DefVar(_, _) | DeFunc(_, _, _) => false,
+        }
+    }
+
+    fn is_pure_primitive(&self, op: Symbol, locals: &HashSet<Symbol>) -> bool {
+        !locals.contains(&op)
+            && !self.redefined.contains(&op)
+            && PURE_PRIMITIVES.contains(&op.name())
+    }
+}
+
+fn bind(locals: &HashSet<Symbol>, vars: &[Symbol]) -> HashSet<Symbol> {
+    let mut locals = locals.clone();
+    locals.extend(vars);
+    locals
+}
+
+/// The locals of a body include its internal definitions.
+fn bind_body(locals: &HashSet<Symbol>, vars: &[Symbol], body: &Expression) -> HashSet<Symbol> {
+    let mut locals = bind(locals, vars);
+    locals.extend(body_definitions(body));
+    locals
+}
+
+fn definition_name(expr: &Expression) -> Option<Symbol> {
+    match expr {
+        Expression::DefVar(name, _) | Expression::DeFunc(name, _, _) => Some(*name),
+        _ => None,
+    }
+}
+
+/// All variables referenced or assigned anywhere in `expr`, including the code of closures.
+/// Local variables are included, which keeps globals of the same name alive; that is merely
+/// conservative.
+fn references(expr: &Expression) -> HashSet<Symbol> {
+    use Expression::*;
+    let mut vars = HashSet::new();
+    let mut todo = vec![expr];
+    while let Some(expr) = todo.pop() {
+        match expr {
+            Variable(var) => {
+                vars.insert(*var);
+            }
+            Set(var, value) => {
+                vars.insert(*var);
+                todo.push(value);
+            }
+            Lambda(_, x)
+            | DeFunc(_, _, x)
+            | DefVar(_, x)
+            | ClosureCode(x)
+            | EnvRef(x, _)
+            | MakeBox(x)
+            | BoxRef(x) => todo.push(x),
+            Let(_, a, b) | BoxSet(a, b) => todo.extend(&[&**a, &**b]),
+            If(cond, yes, no) => todo.extend(&[&**cond, &**yes, &**no]),
+            Apply(x, xs) | Closure(x, xs) => {
+                todo.push(x);
+                todo.extend(xs);
+            }
+            Begin(xs) => todo.extend(xs),
+            Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => {}
+        }
+    }
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_scheme::AnormalTransform;
+    use crate::parser::parse_datum;
+    use std::convert::TryFrom;
+
+    fn expression(source: &str) -> Expression {
+        Expression::try_from(parse_datum(source).unwrap()).unwrap()
+    }
+
+    fn eliminate(source: &str) -> String {
+        format!(
+            "{:?}",
+            DeadCodeElimination::new().eliminate(&expression(source))
+        )
+    }
+
+    fn unchanged(source: &str) -> bool {
+        eliminate(source) == format!("{:?}", expression(source))
+    }
+
+    #[test]
+    fn internal_definitions_shadow_primitives() {
+        assert!(unchanged(
+            "(lambda () (define car (lambda (x) (display x))) (car 1) 2)"
+        ));
+
+        // definitions in hand-built bodies are local as well
+        let car = Symbol::new("car");
+        let body = Expression::Begin(vec![
+            Expression::DefVar(car, Box::new(Expression::Variable(Symbol::new("display")))),
+            Expression::Apply(
+                Box::new(Expression::Variable(car)),
+                vec![Expression::Integer(1)],
+            ),
+            Expression::Integer(2),
+        ]);
+        let expr = Expression::Lambda(vec![], Box::new(body));
+        assert_eq!(
+            format!("{:?}", DeadCodeElimination::new().eliminate(&expr)),
+            format!("{:?}", expr)
+        );
+    }
+
+    #[test]
+    fn unused_pure_bindings_are_removed() {
+        assert_eq!(eliminate("(let ((x (cons 1 2))) 3)"), "3");
+        assert_eq!(
+            eliminate("(lambda (y) (let ((x (+ y 1))) (let ((z (car x))) y)))"),
+            "(lambda (y) y)"
+        );
+    }
+
+    #[test]
+    fn bindings_with_effects_are_kept() {
+        assert!(unchanged("(let ((x (display 1))) 3)"));
+        assert!(unchanged("(let ((x (f 1))) 3)"));
+        assert!(unchanged("(let ((x 1)) (set! x 2))"));
+        assert!(unchanged("(lambda (car) (let ((x (car 1))) 2))"));
+    }
+
+    #[test]
+    fn normalized_sequences_are_cleaned_up() {
+        let expr = AnormalTransform::new()
+            .transform(&parse_datum("(lambda (x) (+ x 1) (* x 2) (f x))").unwrap())
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", DeadCodeElimination::new().eliminate(&expr)),
+            "(lambda (x) (f x))"
+        );
+    }
+
+    #[test]
+    fn pure_expressions_in_sequences_are_removed() {
+        assert_eq!(eliminate("(begin 1 (cons a b) (f) 2)"), "(begin (f) 2)");
+        assert_eq!(eliminate("(begin x y)"), "y");
+    }
+
+    #[test]
+    fn unreachable_branches_are_removed() {
+        assert_eq!(eliminate("(if #t (f) (g))"), "(f)");
+        assert_eq!(eliminate("(if #f (f) (g))"), "(g)");
+    }
+
+    #[test]
+    fn unreachable_definitions_are_removed() {
+        let mut dce = DeadCodeElimination::new();
+        dce.export(Symbol::new("main"));
+        let program = vec![
+            expression("(define (helper x) x)"),
+            expression("(define (unused) (helper 2))"),
+            expression("(define (main) (helper 1))"),
+            expression("(define side-effect (display 1))"),
+            expression("(define constant 42)"),
+            expression("(define (called) 0)"),
+            expression("(called)"),
+        ];
+        assert_eq!(format!("{:?}", dce.eliminate_program(program)), "[(define (helper x) x), (define (main ) (helper 1)), (define side-effect (display 1)), (define (called ) 0), (called)]");
+    }
+}
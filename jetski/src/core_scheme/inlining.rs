@@ -0,0 +1,498 @@
+//! Inlining
+//! Calls of known procedures are replaced by their bodies. A procedure is known if it is a lambda
+//! in operator position, a lambda bound by `let`, or a toplevel function that is defined once
+//! and never reassigned. Lambdas in operator position are always reduced; other procedures are
+//! inlined if their body does not exceed the size budget. Recursive functions are unrolled once.
+//!
+//! Constant arguments and local variables that are never assigned are substituted for the
+//! parameters. All other arguments, including global variables, are bound by `let`, so they are
+//! still evaluated exactly once and a procedure called from the inlined body can't change the
+//! value of a parameter.
+//!
+//! The input must be alphatized. Every inlined copy gets fresh names for the variables it binds,
+//! so the output is alphatized, too.
+
+use super::constant_folding::{assigned_variables, is_constant};
+use super::Expression;
+use crate::runtime::{GensymCounter, Symbol};
+use std::collections::{HashMap, HashSet};
+
+/// Default maximum size of an inlined procedure body, in expression nodes.
+pub const DEFAULT_BUDGET: usize = 32;
+
+/// Maximum nesting of inlined calls within inlined calls.
+const MAX_DEPTH: usize = 4;
+
+#[derive(Debug)]
+pub struct Inliner {
+    budget: usize,
+    gensym: GensymCounter,
+    functions: HashMap<Symbol, Procedure>,
+    /// The local variables of the current toplevel expression that are never assigned.
+    unassigned_locals: HashSet<Symbol>,
+}
+
+#[derive(Debug, Clone)]
+struct Procedure {
+    params: Vec<Symbol>,
+    body: Expression,
+}
+
+impl Default for Inliner {
+    fn default() -> Self {
+        Inliner::new()
+    }
+}
+
+impl Inliner {
+    pub fn new() -> Self {
+        Inliner {
+            budget: DEFAULT_BUDGET,
+            gensym: GensymCounter::new(),
+            functions: HashMap::new(),
+            unassigned_locals: HashSet::new(),
+        }
+    }
+
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Inline toplevel functions into a whole program. Only functions whose definition is
+    /// the only binding of their name in the program are candidates.
+    pub fn inline_program(&mut self, program: Vec<Expression>) -> Vec<Expression> {
+        let mut definitions = HashMap::new();
+        let mut assigned = HashSet::new();
+        for expr in &program {
+            match expr {
+                Expression::DeFunc(name, params, body) => definitions
+                    .entry(*name)
+                    .or_insert_with(Vec::new)
+                    .push(Some(Procedure {
+                        params: params.clone(),
+                        body: (**body).clone(),
+                    })),
+                Expression::DefVar(name, value) => {
+                    let procedure = match &**value {
+                        Expression::Lambda(params, body) => Some(Procedure {
+                            params: params.clone(),
+                            body: (**body).clone(),
+                        }),
+                        _ => None,
+                    };
+                    definitions
+                        .entry(*name)
+                        .or_insert_with(Vec::new)
+                        .push(procedure)
+                }
+                _ => {}
+            }
+            assigned.extend(assigned_variables(expr));
+        }
+
+        self.functions = definitions
+            .into_iter()
+            .filter(|(name, _)| !assigned.contains(name))
+            .filter_map(|(name, mut defs)| match defs.len() {
+                1 => defs.pop().unwrap().map(|procedure| (name, procedure)),
+                _ => None,
+            })
+            .collect();
+
+        program.iter().map(|expr| self.inline(expr)).collect()
+    }
+
+    /// Inline known procedures into a toplevel expression or definition.
+    pub fn inline(&mut self, expr: &Expression) -> Expression {
+        // local variables can only be assigned within their scope, which is part of `expr`
+        let assigned = assigned_variables(expr);
+        self.unassigned_locals = bound_variables(expr)
+            .into_iter()
+            .filter(|var| !assigned.contains(var))
+            .collect();
+        self.inline_in(expr, &HashMap::new(), &mut vec![])
+    }
+
+    fn inline_in(
+        &self,
+        expr: &Expression,
+        locals: &HashMap<Symbol, Procedure>,
+        stack: &mut Vec<Symbol>,
+    ) -> Expression {
+        use Expression::*;
+        match expr {
+            Apply(proc, args) => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|x| self.inline_in(x, locals, stack))
+                    .collect();
+                match &**proc {
+                    Lambda(params, body) if params.len() == args.len() => {
+                        self.beta_reduce(params, body, args, locals, stack)
+                    }
+                    Variable(f) => match locals.get(f).or_else(|| self.functions.get(f)) {
+                        Some(procedure) if self.should_inline(*f, procedure, &args, stack) => {
+                            stack.push(*f);
+                            let expr = self.beta_reduce(
+                                &procedure.params,
+                                &procedure.body,
+                                args,
+                                locals,
+                                stack,
+                            );
+                            stack.pop();
+                            expr
+                        }
+                        _ => Apply(proc.clone(), args),
+                    },
+                    _ => Apply(Box::new(self.inline_in(proc, locals, stack)), args),
+                }
+            }
+            Let(var, init, body) => {
+                let init = self.inline_in(init, locals, stack);
+                let body = match &init {
+                    Lambda(params, func_body) if !assigned_variables(body).contains(var) => {
+                        let mut locals = locals.clone();
+                        let procedure = Procedure {
+                            params: params.clone(),
+                            body: (**func_body).clone(),
+                        };
+                        locals.insert(*var, procedure);
+                        self.inline_in(body, &locals, stack)
+                    }
+                    _ => self.inline_in(body, locals, stack),
+                };
+                Let(*var, Box::new(init), Box::new(body))
+            }
+            Lambda(params, body) => Lambda(
+                params.clone(),
+                Box::new(self.inline_in(body, locals, stack)),
+            ),
+            DeFunc(name, params, body) => DeFunc(
+                *name,
+                params.clone(),
+                Box::new(self.inline_in(body, locals, stack)),
+            ),
+            If(cond, yes, no) => If(
+                Box::new(self.inline_in(cond, locals, stack)),
+                Box::new(self.inline_in(yes, locals, stack)),
+                Box::new(self.inline_in(no, locals, stack)),
+            ),
+            Set(var, value) => Set(*var, Box::new(self.inline_in(value, locals, stack))),
+            Begin(exprs) => Begin(
+                exprs
+                    .iter()
+                    .map(|x| self.inline_in(x, locals, stack))
+                    .collect(),
+            ),
+            DefVar(name, value) => DefVar(*name, Box::new(self.inline_in(value, locals, stack))),
+            Closure(code, captured) => Closure(
+                Box::new(self.inline_in(code, locals, stack)),
+                captured
+                    .iter()
+                    .map(|x| self.inline_in(x, locals, stack))
+                    .collect(),
+            ),
+            ClosureCode(closure) => ClosureCode(Box::new(self.inline_in(closure, locals, stack))),
+            EnvRef(env, idx) => EnvRef(Box::new(self.inline_in(env, locals, stack)), *idx),
+            MakeBox(value) => MakeBox(Box::new(self.inline_in(value, locals, stack))),
+            BoxRef(b) => BoxRef(Box::new(self.inline_in(b, locals, stack))),
+            BoxSet(b, value) => BoxSet(
+                Box::new(self.inline_in(b, locals, stack)),
+                Box::new(self.inline_in(value, locals, stack)),
+            ),
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => expr.clone(),
+        }
+    }
+
+    fn should_inline(
+        &self,
+        name: Symbol,
+        procedure: &Procedure,
+        args: &[Expression],
+        stack: &[Symbol],
+    ) -> bool {
+        procedure.params.len() == args.len()
+            && size(&procedure.body) <= self.budget
+            && stack.len() < MAX_DEPTH
+            && !stack.contains(&name)
+    }
+
+    /// Replace the application of a procedure with a copy of its body.
+    fn beta_reduce(
+        &self,
+        params: &[Symbol],
+        body: &Expression,
+        args: Vec<Expression>,
+        locals: &HashMap<Symbol, Procedure>,
+        stack: &mut Vec<Symbol>,
+    ) -> Expression {
+        let assigned = assigned_variables(body);
+        let mut renames = HashMap::new();
+        let mut bindings = vec![];
+        for (param, arg) in params.iter().zip(args) {
+            let substitutable = match &arg {
+                Expression::Variable(var) => self.unassigned_locals.contains(var),
+                arg => is_constant(arg),
+            };
+            if substitutable && !assigned.contains(param) {
+                renames.insert(*param, arg);
+            } else {
+                let var = Symbol::gensym(param.name(), &self.gensym);
+                renames.insert(*param, Expression::Variable(var));
+                bindings.push((var, arg));
+            }
+        }
+
+        let body = self.inline_in(&self.copy(body, &renames), locals, stack);
+        bindings.into_iter().rev().fold(body, |body, (var, init)| {
+            Expression::Let(var, Box::new(init), Box::new(body))
+        })
+    }
+
+    /// Copy an expression, substituting variables according to `renames` and giving fresh names
+    /// to all variables bound within it.
+    fn copy(&self, expr: &Expression, renames: &HashMap<Symbol, Expression>) -> Expression {
+        use Expression::*;
+        let copy = |x: &Expression| Box::new(self.copy(x, renames));
+        let rename_var = |var: &Symbol| match renames.get(var) {
+            Some(Variable(new_var)) => *new_var,
+            _ => *var,
+        };
+        match expr {
+            Variable(var) => renames.get(var).cloned().unwrap_or_else(|| expr.clone()),
+            Set(var, value) => Set(rename_var(var), copy(value)),
+            Lambda(params, body) => {
+                let (params, renames) = self.fresh_names(params, renames);
+                Lambda(params, Box::new(self.copy(body, &renames)))
+            }
+            DeFunc(name, params, body) => {
+                let (params, renames) = self.fresh_names(params, renames);
+                DeFunc(*name, params, Box::new(self.copy(body, &renames)))
+            }
+            Let(var, init, body) => {
+                let init = copy(init);
+                let (vars, renames) = self.fresh_names(&[*var], renames);
+                Let(vars[0], init, Box::new(self.copy(body, &renames)))
+            }
+            If(cond, yes, no) => If(copy(cond), copy(yes), copy(no)),
+            Apply(proc, args) => Apply(
+                copy(proc),
+                args.iter().map(|x| self.copy(x, renames)).collect(),
+            ),
+            Begin(exprs) => Begin(exprs.iter().map(|x| self.copy(x, renames)).collect()),
+            DefVar(name, value) => DefVar(*name, copy(value)),
+            Closure(code, captured) => Closure(
+                copy(code),
+                captured.iter().map(|x| self.copy(x, renames)).collect(),
+            ),
+            ClosureCode(closure) => ClosureCode(copy(closure)),
+            EnvRef(env, idx) => EnvRef(copy(env), *idx),
+            MakeBox(value) => MakeBox(copy(value)),
+            BoxRef(b) => BoxRef(copy(b)),
+            BoxSet(b, value) => BoxSet(copy(b), copy(value)),
+            Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => expr.clone(),
+        }
+    }
+
+    fn fresh_names(
+        &self,
+        vars: &[Symbol],
+        renames: &HashMap<Symbol, Expression>,
+    ) -> (Vec<Symbol>, HashMap<Symbol, Expression>) {
+        let mut renames = renames.clone();
+        let vars = vars
+            .iter()
+            .map(|var| {
+                let new_var = Symbol::gensym(var.name(), &self.gensym);
+                renames.insert(*var, Expression::Variable(new_var));
+                new_var
+            })
+            .collect();
+        (vars, renames)
+    }
+}
+
+/// The variables bound by lambdas and `let`s within `expr`.
+fn bound_variables(expr: &Expression) -> HashSet<Symbol> {
+    use Expression::*;
+    let mut vars = HashSet::new();
+    let mut todo = vec![expr];
+    while let Some(expr) = todo.pop() {
+        match expr {
+            Lambda(params, x) | DeFunc(_, params, x) => {
+                vars.extend(params);
+                todo.push(x);
+            }
+            Let(var, init, body) => {
+                vars.insert(*var);
+                todo.extend(&[&**init, &**body]);
+            }
+            DefVar(_, x) | Set(_, x) | ClosureCode(x) | EnvRef(x, _) | MakeBox(x) | BoxRef(x) => {
+                todo.push(x)
+            }
+            BoxSet(a, b) => todo.extend(&[&**a, &**b]),
+            If(cond, yes, no) => todo.extend(&[&**cond, &**yes, &**no]),
+            Apply(x, xs) | Closure(x, xs) => {
+                todo.push(x);
+                todo.extend(xs);
+            }
+            Begin(xs) => todo.extend(xs),
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => {}
+        }
+    }
+    vars
+}
+
+/// The number of expression nodes in `expr`.
+fn size(expr: &Expression) -> usize {
+    use Expression::*;
+    1 + match expr {
+        Lambda(_, x)
+        | DeFunc(_, _, x)
+        | DefVar(_, x)
+        | Set(_, x)
+        | ClosureCode(x)
+        | EnvRef(x, _)
+        | MakeBox(x)
+        | BoxRef(x) => size(x),
+        Let(_, a, b) | BoxSet(a, b) => size(a) + size(b),
+        If(cond, yes, no) => size(cond) + size(yes) + size(no),
+        Apply(x, xs) | Closure(x, xs) => size(x) + xs.iter().map(size).sum::<usize>(),
+        Begin(xs) => xs.iter().map(size).sum(),
+        Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+    use std::convert::TryFrom;
+
+    fn expression(source: &str) -> Expression {
+        Expression::try_from(parse_datum(source).unwrap()).unwrap()
+    }
+
+    fn inline_program(inliner: &mut Inliner, sources: &[&str]) -> String {
+        let program = sources.iter().map(|source| expression(source)).collect();
+        format!("{:?}", inliner.inline_program(program))
+    }
+
+    #[test]
+    fn lambdas_in_operator_position_are_reduced() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                Inliner::new().inline(&expression("((lambda (x y) (+ x y)) 1 (f 2))"))
+            ),
+            "(let (#:y.0 (f 2)) (+ 1 #:y.0))"
+        );
+    }
+
+    #[test]
+    fn small_toplevel_functions_are_inlined() {
+        assert_eq!(
+            inline_program(
+                &mut Inliner::new(),
+                &["(define (sq x) (* x x))", "(define (f y) (+ (sq y) 1))"]
+            ),
+            "[(define (sq x) (* x x)), (define (f y) (+ (* y y) 1))]"
+        );
+    }
+
+    #[test]
+    fn let_bound_lambdas_are_inlined() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                Inliner::new().inline(&expression(
+                    "(lambda (a) (let ((f (lambda (x) (+ x a)))) (f 1)))"
+                ))
+            ),
+            "(lambda (a) (let (f (lambda (x) (+ x a))) (+ 1 a)))"
+        );
+    }
+
+    #[test]
+    fn recursive_functions_are_unrolled_once() {
+        assert_eq!(
+            inline_program(
+                &mut Inliner::new(),
+                &["(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))"]
+            ),
+            "[(define (fib n) (if (< n 2) n (+ (let (#:n.0 (- n 1)) (if (< #:n.0 2) #:n.0 (+ (fib (- #:n.0 1)) (fib (- #:n.0 2))))) (let (#:n.1 (- n 2)) (if (< #:n.1 2) #:n.1 (+ (fib (- #:n.1 1)) (fib (- #:n.1 2))))))))]"
+        );
+    }
+
+    #[test]
+    fn inlined_copies_bind_fresh_names() {
+        assert_eq!(
+            inline_program(
+                &mut Inliner::new(),
+                &["(define (g x) (let ((y (* x 2))) y))", "(+ (g 1) (g 2))"]
+            ),
+            "[(define (g x) (let (y (* x 2)) y)), (+ (let (#:y.0 (* 1 2)) #:y.0) (let (#:y.1 (* 2 2)) #:y.1))]"
+        );
+    }
+
+    #[test]
+    fn arguments_are_evaluated_once() {
+        assert_eq!(
+            inline_program(
+                &mut Inliner::new(),
+                &["(define (twice x) (+ x x))", "(twice (read))"]
+            ),
+            "[(define (twice x) (+ x x)), (let (#:x.0 (read)) (+ #:x.0 #:x.0))]"
+        );
+    }
+
+    #[test]
+    fn globals_and_assigned_locals_are_bound_by_let() {
+        assert_eq!(
+            inline_program(
+                &mut Inliner::new(),
+                &[
+                    "(define x 1)",
+                    "(define (bump) (set! x 5))",
+                    "(define (f y) (bump) y)",
+                    "(f x)"
+                ]
+            ),
+            "[(define x 1), (define (bump ) (set! x 5)), (define (f y) (begin (set! x 5) y)), (let (#:y.0 x) (begin (set! x 5) #:y.0))]"
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                Inliner::new().inline(&expression(
+                    "(lambda (a b) (set! a 2) ((lambda (x y) (+ x y)) a b))"
+                ))
+            ),
+            "(lambda (a b) (begin (set! a 2) (let (#:x.0 a) (+ #:x.0 b))))"
+        );
+    }
+
+    #[test]
+    fn reassigned_and_large_functions_are_not_inlined() {
+        let sources = &["(define (g) 1)", "(set! g h)", "(g)"];
+        assert_eq!(
+            inline_program(&mut Inliner::new(), sources),
+            format!(
+                "{:?}",
+                sources.iter().map(|s| expression(s)).collect::<Vec<_>>()
+            )
+        );
+
+        let sources = &["(define (g x) (+ x 1))", "(g 1)"];
+        assert_eq!(
+            inline_program(&mut Inliner::new().with_budget(3), sources),
+            format!(
+                "{:?}",
+                sources.iter().map(|s| expression(s)).collect::<Vec<_>>()
+            )
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Lambda lifting
+//! Local functions that never escape, i.e. that are bound by `let` and only ever called, are
+//! turned into toplevel functions. The local variables they reference become additional
+//! leading parameters, and every call passes them along. Calls to lifted functions can be
+//! compiled as direct calls, and no closure needs to be allocated for them.
+//!
+//! The input must be alphatized, so that variables are never shadowed. Assignment conversion
+//! must run first: the extra parameters receive copies of the captured variables, so an
+//! assignment in a lifted function would not be seen by its caller. Such input is rejected.
+
+use super::assignment_conversion::boxed_variables;
+use super::closure_conversion::free_variables;
+use super::{check_toplevel_definitions, Expression};
+use crate::error::{ErrorKind, Result};
+use crate::runtime::{GensymCounter, Symbol};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct LambdaLifting {
+    gensym: GensymCounter,
+    lifted: Vec<Symbol>,
+}
+
+/// A lifted function and the variables that are passed to it in addition to the arguments.
+type Lifted = (Symbol, Vec<Symbol>);
+
+#[derive(Clone, Default)]
+struct Scope {
+    locals: Vec<Symbol>,
+    lifted: HashMap<Symbol, Lifted>,
+}
+
+impl Scope {
+    fn bind(&self, vars: &[Symbol]) -> Scope {
+        let mut scope = self.clone();
+        for var in vars {
+            scope.locals.push(*var);
+            scope.lifted.remove(var);
+        }
+        scope
+    }
+}
+
+impl LambdaLifting {
+    pub fn new() -> Self {
+        LambdaLifting {
+            gensym: GensymCounter::new(),
+            lifted: vec![],
+        }
+    }
+
+    /// The names of all functions lifted so far. They are never reassigned, so calls to them
+    /// are known calls.
+    pub fn lifted_functions(&self) -> &[Symbol] {
+        &self.lifted
+    }
+
+    pub fn lift_program(&mut self, program: Vec<Expression>) -> Result<Vec<Expression>> {
+        let mut lifted = vec![];
+        for expr in &program {
+            lifted.extend(self.lift(expr)?);
+        }
+        Ok(lifted)
+    }
+
+    /// Lift the local functions out of a toplevel expression or definition. The lifted
+    /// function definitions come before the transformed expression.
+    pub fn lift(&mut self, expr: &Expression) -> Result<Vec<Expression>> {
+        check_toplevel_definitions(expr)?;
+        if let Some(var) = boxed_variables(expr).into_iter().next() {
+            return Err(ErrorKind::ValidationError(format!(
+                "lambda lifting requires assignment conversion: {} is assigned and captured",
+                var
+            ))
+            .into());
+        }
+        let mut definitions = vec![];
+        let expr = self.lift_in(expr, &Scope::default(), &mut definitions);
+        definitions.push(expr);
+        Ok(definitions)
+    }
+
+    fn lift_in(
+        &mut self,
+        expr: &Expression,
+        scope: &Scope,
+        definitions: &mut Vec<Expression>,
+    ) -> Expression {
+        use Expression::*;
+        match expr {
+            Lambda(params, body) => Lambda(
+                params.clone(),
+                Box::new(self.lift_in(body, &scope.bind(params), definitions)),
+            ),
+            DeFunc(name, params, body) => DeFunc(
+                *name,
+                params.clone(),
+                Box::new(self.lift_in(body, &scope.bind(params), definitions)),
+            ),
+            Let(var, init, body) => match &**init {
+                Lambda(params, func_body) if !escapes(*var, params.len(), body) => {
+                    let func_body = self.lift_in(func_body, &scope.bind(params), definitions);
+                    let lambda = Lambda(params.clone(), Box::new(func_body.clone()));
+                    let extra_params: Vec<_> = free_variables(&lambda)
+                        .into_iter()
+                        .filter(|v| scope.locals.contains(v))
+                        .collect();
+
+                    let name = Symbol::gensym(var.name(), &self.gensym);
+                    let mut all_params = extra_params.clone();
+                    all_params.extend(params);
+                    definitions.push(DeFunc(name, all_params, Box::new(func_body)));
+                    self.lifted.push(name);
+
+                    let mut body_scope = scope.clone();
+                    body_scope.lifted.insert(*var, (name, extra_params));
+                    self.lift_in(body, &body_scope, definitions)
+                }
+                _ => Let(
+                    *var,
+                    Box::new(self.lift_in(init, scope, definitions)),
+                    Box::new(self.lift_in(body, &scope.bind(&[*var]), definitions)),
+                ),
+            },
+            Apply(proc, args) => {
+                let mut args: Vec<_> = args
+                    .iter()
+                    .map(|x| self.lift_in(x, scope, definitions))
+                    .collect();
+                match &**proc {
+                    Variable(f) if scope.lifted.contains_key(f) => {
+                        let (name, extra_params) = &scope.lifted[f];
+                        let mut all_args: Vec<_> =
+                            extra_params.iter().map(|v| Variable(*v)).collect();
+                        all_args.append(&mut args);
+                        Apply(Box::new(Variable(*name)), all_args)
+                    }
+                    _ => Apply(Box::new(self.lift_in(proc, scope, definitions)), args),
+                }
+            }
+            If(cond, yes, no) => If(
+                Box::new(self.lift_in(cond, scope, definitions)),
+                Box::new(self.lift_in(yes, scope, definitions)),
+                Box::new(self.lift_in(no, scope, definitions)),
+            ),
+            Begin(exprs) => Begin(
+                exprs
+                    .iter()
+                    .map(|x| self.lift_in(x, scope, definitions))
+                    .collect(),
+            ),
+            DefVar(name, value) => DefVar(*name, Box::new(self.lift_in(value, scope, definitions))),
+            Closure(code, captured) => Closure(
+                code.clone(),
+                captured
+                    .iter()
+                    .map(|x| self.lift_in(x, scope, definitions))
+                    .collect(),
+            ),
+            ClosureCode(closure) => {
+                ClosureCode(Box::new(self.lift_in(closure, scope, definitions)))
+            }
+            EnvRef(env, idx) => EnvRef(Box::new(self.lift_in(env, scope, definitions)), *idx),
+            Set(var, value) => Set(*var, Box::new(self.lift_in(value, scope, definitions))),
+            MakeBox(value) => MakeBox(Box::new(self.lift_in(value, scope, definitions))),
+            BoxRef(b) => BoxRef(Box::new(self.lift_in(b, scope, definitions))),
+            BoxSet(b, value) => BoxSet(
+                Box::new(self.lift_in(b, scope, definitions)),
+                Box::new(self.lift_in(value, scope, definitions)),
+            ),
+            Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_)
+            | Primitive => expr.clone(),
+        }
+    }
+}
+
+/// Escape analysis: does the function bound to `var` escape from `expr`? Only calls with the
+/// expected number of arguments do not let a function escape.
+pub fn escapes(var: Symbol, arity: usize, expr: &Expression) -> bool {
+    use Expression::*;
+    let escape = |x: &Expression| escapes(var, arity, x);
+    match expr {
+        Variable(v) => *v == var,
+        Apply(proc, args) => {
+            let escapes_as_operator = match &**proc {
+                Variable(v) if *v == var => args.len() != arity,
+                proc => escape(proc),
+            };
+            escapes_as_operator || args.iter().any(escape)
+        }
+        Lambda(params, body) | DeFunc(_, params, body) => !params.contains(&var) && escape(body),
+        Let(v, init, body) => escape(init) || (*v != var && escape(body)),
+        If(cond, yes, no) => escape(cond) || escape(yes) || escape(no),
+        Begin(exprs) => exprs.iter().any(escape),
+        DefVar(_, value) => escape(value),
+        Closure(_, captured) => captured.iter().any(escape),
+        ClosureCode(x) | EnvRef(x, _) | MakeBox(x) | BoxRef(x) => escape(x),
+        // a function that is reassigned is not known at its call sites
+        Set(v, value) => *v == var || escape(value),
+        BoxSet(b, value) => escape(b) || escape(value),
+        Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_scheme::assignment_conversion::AssignmentConversion;
+    use crate::core_scheme::closure_conversion::ClosureConversion;
+    use crate::parser::parse_datum;
+    use std::convert::TryFrom;
+
+    fn expression(source: &str) -> Expression {
+        Expression::try_from(parse_datum(source).unwrap()).unwrap()
+    }
+
+    fn lift(source: &str) -> String {
+        format!(
+            "{:?}",
+            LambdaLifting::new().lift(&expression(source)).unwrap()
+        )
+    }
+
+    #[test]
+    fn escape_analysis() {
+        let f = Symbol::new("f");
+        assert!(!escapes(f, 1, &expression("(begin (f 1) (g (f 2)))")));
+        assert!(!escapes(f, 1, &expression("(lambda (x) (f x))")));
+        assert!(escapes(f, 1, &expression("(g f)")));
+        assert!(escapes(f, 1, &expression("(f 1 2)")));
+        assert!(escapes(f, 1, &expression("(lambda (x) f)")));
+        assert!(!escapes(f, 1, &expression("(lambda (f) f)")));
+    }
+
+    #[test]
+    fn local_functions_are_lifted() {
+        assert_eq!(
+            lift("(lambda (x) (let ((add (lambda (y) (+ x y)))) (add 1)))"),
+            "[(define (#:add.0 x y) (+ x y)), (lambda (x) (#:add.0 x 1))]"
+        );
+    }
+
+    #[test]
+    fn escaping_functions_are_not_lifted() {
+        assert_eq!(
+            lift("(lambda (x) (let ((f (lambda (y) x))) (g f)))"),
+            "[(lambda (x) (let (f (lambda (y) x)) (g f)))]"
+        );
+        assert_eq!(
+            lift("(lambda (x) (let ((f (lambda (y) x))) (f 1 2)))"),
+            "[(lambda (x) (let (f (lambda (y) x)) (f 1 2)))]"
+        );
+    }
+
+    #[test]
+    fn lifted_functions_pass_on_extra_arguments() {
+        assert_eq!(
+            lift("(lambda (x) (let ((f (lambda (y) (+ x y)))) (let ((g (lambda (z) (f z)))) (g 1))))"),
+            "[(define (#:f.0 x y) (+ x y)), (define (#:g.1 x z) (#:f.0 x z)), (lambda (x) (#:g.1 x 1))]"
+        );
+    }
+
+    #[test]
+    fn calls_from_nested_lambdas() {
+        assert_eq!(
+            lift("(lambda (x) (let ((f (lambda () x))) (lambda () (f))))"),
+            "[(define (#:f.0 x) x), (lambda (x) (lambda () (#:f.0 x)))]"
+        );
+    }
+
+    #[test]
+    fn lifted_functions_are_called_directly_after_closure_conversion() {
+        let mut lifting = LambdaLifting::new();
+        let program = lifting
+            .lift(&expression(
+                "(define (f x) (let ((g (lambda (y) (* x y)))) (g 2)))",
+            ))
+            .unwrap();
+        let mut conversion = ClosureConversion::new();
+        for &name in lifting.lifted_functions() {
+            conversion.declare_known_function(name);
+        }
+        assert_eq!(format!("{:?}", conversion.convert_program(program).unwrap()), "[(define (#:g.0 x y) ((closure-code *) * x y)), (define f (make-closure (lambda (#:env.0 x) (#:g.0 x 2))))]");
+    }
+
+    #[test]
+    fn assigned_captured_variables_must_be_boxed_first() {
+        let expr = expression("(lambda (x) (let ((f (lambda () (set! x 1)))) (f) x))");
+        match LambdaLifting::new().lift(&expr).unwrap_err().kind() {
+            ErrorKind::ValidationError(msg) => assert_eq!(
+                msg,
+                "lambda lifting requires assignment conversion: x is assigned and captured"
+            ),
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+        let boxed = AssignmentConversion::new().convert(&expr).unwrap();
+        assert!(LambdaLifting::new().lift(&boxed).is_ok());
+    }
+}
@@ -0,0 +1,743 @@
+use crate::error::{Error, ErrorKind, Result};
+use crate::object::TaggedValue;
+use crate::runtime::{GensymCounter, Symbol};
+use crate::Object;
+use crate::SchemeExpression;
+use std::convert::{TryFrom, TryInto};
+
+pub mod assignment_conversion;
+pub mod closure_conversion;
+pub mod constant_folding;
+pub mod dead_code;
+pub mod inlining;
+pub mod lambda_lifting;
+
+#[derive(Clone)]
+pub enum Expression {
+    Undef,
+    Nil,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Quote(Object),
+    Variable(Symbol),
+    Lambda(Vec<Symbol>, Box<Expression>),
+    Primitive,
+
+    Let(Symbol, Box<Expression>, Box<Expression>),
+    If(Box<Expression>, Box<Expression>, Box<Expression>),
+    Apply(Box<Expression>, Vec<Expression>),
+    Set(Symbol, Box<Expression>),
+
+    Begin(Vec<Expression>),
+    DefVar(Symbol, Box<Expression>),
+    DeFunc(Symbol, Vec<Symbol>, Box<Expression>),
+
+    // closure conversion makes environments explicit with these forms
+    /// A closure record: the code, which takes the record as its first argument, and the
+    /// captured values.
+    Closure(Box<Expression>, Vec<Expression>),
+    ClosureCode(Box<Expression>),
+    EnvRef(Box<Expression>, usize),
+
+    // assignment conversion stores variables that are mutated and captured in boxes
+    MakeBox(Box<Expression>),
+    BoxRef(Box<Expression>),
+    BoxSet(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    pub fn is_atomic(&self) -> bool {
+        matches!(
+            self,
+            Expression::Undef
+                | Expression::Nil
+                | Expression::Integer(_)
+                | Expression::Float(_)
+                | Expression::String(_)
+                | Expression::Quote(_)
+                | Expression::Variable(_)
+                | Expression::Lambda(_, _)
+                | Expression::Primitive
+                | Expression::Closure(_, _)
+                | Expression::ClosureCode(_)
+                | Expression::EnvRef(_, _)
+                | Expression::BoxRef(_)
+        )
+    }
+}
+
+impl std::fmt::Debug for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Undef => write!(f, "<undefined>"),
+            Expression::Nil => write!(f, "'()"),
+            Expression::Integer(x) => write!(f, "{}", x),
+            Expression::Float(x) => write!(f, "{}", x),
+            Expression::String(x) => write!(f, "{:?}", x),
+            Expression::Quote(x) => write!(f, "'{}", x),
+            Expression::Variable(x) => write!(f, "{}", x),
+            Expression::Lambda(params, body) => write!(
+                f,
+                "(lambda ({}) {:?})",
+                params
+                    .iter()
+                    .map(|a| format!("{:?}", a))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                body
+            ),
+            Expression::Primitive => write!(f, "<primitive>"),
+            Expression::Let(var, init, body) => write!(f, "(let ({} {:?}) {:?})", var, init, body),
+            Expression::If(cond, yes, no) => write!(f, "(if {:?} {:?} {:?})", cond, yes, no),
+            Expression::Apply(proc, args) => {
+                write!(f, "({:?}", proc)?;
+                for a in args {
+                    write!(f, " {:?}", a)?;
+                }
+                write!(f, ")")
+            }
+            Expression::Begin(exprs) => {
+                write!(f, "(begin")?;
+                for x in exprs {
+                    write!(f, " {:?}", x)?;
+                }
+                write!(f, ")")
+            }
+            Expression::DeFunc(name, params, body) => write!(
+                f,
+                "(define ({} {}) {:?})",
+                name,
+                params
+                    .iter()
+                    .map(|a| format!("{:?}", a))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                body
+            ),
+            Expression::DefVar(name, expr) => write!(f, "(define {} {:?})", name, expr),
+            Expression::Closure(code, captured) => {
+                write!(f, "(make-closure {:?}", code)?;
+                for x in captured {
+                    write!(f, " {:?}", x)?;
+                }
+                write!(f, ")")
+            }
+            Expression::ClosureCode(closure) => write!(f, "(closure-code {:?})", closure),
+            Expression::EnvRef(env, idx) => write!(f, "(env-ref {:?} {})", env, idx),
+            Expression::Set(var, value) => write!(f, "(set! {} {:?})", var, value),
+            Expression::MakeBox(value) => write!(f, "(make-box {:?})", value),
+            Expression::BoxRef(b) => write!(f, "(box-ref {:?})", b),
+            Expression::BoxSet(b, value) => write!(f, "(box-set! {:?} {:?})", b, value),
+        }
+    }
+}
+
+impl TryFrom<Object> for Expression {
+    type Error = Error;
+    fn try_from(obj: Object) -> Result<Self> {
+        Expression::try_from(&obj)
+    }
+}
+
+impl TryFrom<&Object> for Expression {
+    type Error = Error;
+    fn try_from(obj: &Object) -> Result<Self> {
+        match obj.as_value() {
+            TaggedValue::Undef => Ok(Expression::Undef),
+            TaggedValue::Nil => Ok(Expression::Nil),
+            TaggedValue::Integer(x) => Ok(Expression::Integer(*x)),
+            TaggedValue::Float(x) => Ok(Expression::Float(*x)),
+            TaggedValue::Symbol(s) => Ok(Expression::Variable(*s)),
+            TaggedValue::String(s) => Ok(Expression::String(s.clone())),
+            TaggedValue::Boolean(_) | TaggedValue::Vector(_) => Ok(Expression::Quote(obj.clone())),
+            TaggedValue::Function(_) => Err(ErrorKind::SyntaxError(format!(
+                "compiled procedure in source code: {:?}",
+                obj
+            ))
+            .into()),
+            TaggedValue::Pair(_, _) => match obj.car().and_then(Object::symbol_name) {
+                Some("quote") => try_switch! {obj,
+                    [(_ ?datum)] => Ok(Expression::Quote(datum.clone())),
+                },
+                Some("set!") => try_switch! {obj,
+                    [(_ ?var ?value)] => Ok(Expression::Set(to_symbol(var)?,
+                                                            Box::new(value.try_into()?))),
+                },
+                Some("define") => try_switch! {obj,
+                    [(_ (?f ?params ...) . ?body)] => Ok(Expression::DeFunc(to_symbol(f)?,
+                                                                           to_symbols(&params)?,
+                                                                           Box::new(to_local_body(body)?))),
+                    [(_ (?f . ?params) . ?body)] => Err(parameter_error(params)),
+                    [(_ ?var ?exp)] => Ok(Expression::DefVar(to_symbol(var)?,
+                                                             Box::new(exp.try_into()?))),
+                },
+                Some("lambda") => try_switch! {obj,
+                    [(_ (?params ...) . ?body)] => Ok(Expression::Lambda(to_symbols(&params)?,
+                                                                         Box::new(to_local_body(body)?))),
+                    [(_ ?params . ?body)] => Err(parameter_error(params)),
+                },
+                Some("if") => try_switch! {obj,
+                    [(_ ?cond ?yes ?no)] => Ok(Expression::If(Box::new(cond.try_into()?),
+                                                              Box::new(yes.try_into()?),
+                                                              Box::new(no.try_into()?))),
+                    [(_ ?cond ?yes)] => Ok(Expression::If(Box::new(cond.try_into()?),
+                                                          Box::new(yes.try_into()?),
+                                                          Box::new(Expression::Undef))),
+                },
+                // a single binding maps directly to `Let`; multiple bindings must not see each
+                // other, so they become the application of a lambda.
+                Some("let") => try_switch! {obj,
+                    [(_ ((?var ?init)) . ?body)] => Ok(Expression::Let(to_symbol(var)?,
+                                                                       Box::new(init.try_into()?),
+                                                                       Box::new(to_local_body(body)?))),
+                    [(_ ((?vars ?inits) ...) . ?body)] => Ok(Expression::Apply(
+                        Box::new(Expression::Lambda(to_symbols(&vars)?, Box::new(to_local_body(body)?))),
+                        inits.into_iter().map(TryFrom::try_from).collect::<Result<_>>()?)),
+                },
+                Some("begin") => try_switch! {obj,
+                    [(_ . ?body)] => to_body(body),
+                },
+                _ => try_switch! {obj,
+                    // procedure application
+                    [(?proc ?args ...)] => Ok(Expression::Apply(Box::new(proc.try_into()?),
+                                                                args.into_iter()
+                                                                    .map(TryFrom::try_from)
+                                                                    .collect::<Result<_>>()?)),
+                },
+            },
+        }
+    }
+}
+
+/// Explain why a parameter list is not a proper list of variables.
+fn parameter_error(params: &Object) -> Error {
+    let message = if params.list_parts().1.is_symbol() {
+        "variadic lambda is not supported"
+    } else {
+        "invalid parameter list"
+    };
+    ErrorKind::SyntaxError(format!("{}: {:?}", message, params)).into()
+}
+
+/// Convert a sequence of expressions, such as a lambda body.
+fn to_body(body: &Object) -> Result<Expression> {
+    let (exprs, tail) = body.list_parts();
+    if !tail.is_nil() {
+        return Err(ErrorKind::SyntaxError(format!("improper body: {:?}", body)).into());
+    }
+    let mut exprs = exprs
+        .into_iter()
+        .map(Expression::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    match exprs.len() {
+        0 => Err(ErrorKind::SyntaxError("empty body".to_string()).into()),
+        1 => Ok(exprs.pop().unwrap()),
+        _ => Ok(Expression::Begin(exprs)),
+    }
+}
+
+/// Convert the body of a procedure or `let`. Internal definitions have `letrec*` semantics:
+/// the defined variables are bound, but unassigned, when the body starts, and each definition
+/// assigns its variable. Later passes only see definitions at toplevel.
+fn to_local_body(body: &Object) -> Result<Expression> {
+    let mut names = vec![];
+    let body = assign_definitions(to_body(body)?, &mut names);
+    Ok(names.into_iter().rev().fold(body, |body, name| {
+        Expression::Let(name, Box::new(Expression::Undef), Box::new(body))
+    }))
+}
+
+fn assign_definitions(expr: Expression, names: &mut Vec<Symbol>) -> Expression {
+    let mut assign = |name, value| {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+        Expression::Set(name, Box::new(value))
+    };
+    match expr {
+        Expression::DefVar(name, value) => assign(name, *value),
+        Expression::DeFunc(name, params, body) => assign(name, Expression::Lambda(params, body)),
+        Expression::Begin(exprs) => Expression::Begin(
+            exprs
+                .into_iter()
+                .map(|x| assign_definitions(x, names))
+                .collect(),
+        ),
+        expr => expr,
+    }
+}
+
+fn to_symbol(obj: &Object) -> Result<Symbol> {
+    obj.as_symbol()
+        .ok_or_else(|| ErrorKind::SyntaxError(format!("expected symbol: {:?}", obj)).into())
+}
+
+fn to_symbols(objs: &[&Object]) -> Result<Vec<Symbol>> {
+    objs.iter().map(|obj| to_symbol(obj)).collect()
+}
+
+/// The variables defined at the level of a body, which are local to it. `Expression::try_from`
+/// turns such definitions into local variables, but hand-built expressions may contain them.
+pub fn body_definitions(body: &Expression) -> Vec<Symbol> {
+    match body {
+        Expression::DefVar(name, _) | Expression::DeFunc(name, _, _) => vec![*name],
+        Expression::Begin(exprs) => exprs.iter().flat_map(body_definitions).collect(),
+        _ => vec![],
+    }
+}
+
+/// `Expression::try_from` turns internal definitions into local variables, so the passes after
+/// the front end only accept definitions at toplevel, possibly inside `begin`.
+pub fn check_toplevel_definitions(expr: &Expression) -> Result<()> {
+    match expr {
+        Expression::Begin(exprs) => exprs.iter().try_for_each(check_toplevel_definitions),
+        Expression::DefVar(_, value) => check_no_definitions(value),
+        Expression::DeFunc(_, _, body) => check_no_definitions(body),
+        expr => check_no_definitions(expr),
+    }
+}
+
+fn check_no_definitions(expr: &Expression) -> Result<()> {
+    use Expression::*;
+    match expr {
+        DefVar(name, _) | DeFunc(name, _, _) => Err(ErrorKind::ValidationError(format!(
+            "definition of {} is not at toplevel",
+            name
+        ))
+        .into()),
+        Lambda(_, x) | Set(_, x) | ClosureCode(x) | EnvRef(x, _) | MakeBox(x) | BoxRef(x) => {
+            check_no_definitions(x)
+        }
+        Let(_, a, b) | BoxSet(a, b) => {
+            check_no_definitions(a)?;
+            check_no_definitions(b)
+        }
+        If(cond, yes, no) => {
+            check_no_definitions(cond)?;
+            check_no_definitions(yes)?;
+            check_no_definitions(no)
+        }
+        Apply(proc, args) => {
+            check_no_definitions(proc)?;
+            args.iter().try_for_each(check_no_definitions)
+        }
+        Begin(exprs) => exprs.iter().try_for_each(check_no_definitions),
+        Closure(code, captured) => {
+            check_no_definitions(code)?;
+            captured.iter().try_for_each(check_no_definitions)
+        }
+        Variable(_) | Undef | Nil | Integer(_) | Float(_) | String(_) | Quote(_) | Primitive => {
+            Ok(())
+        }
+    }
+}
+
+/// Converts toplevel forms to A-normal form, where the operands of every application and the
+/// condition of every `if` are atomic. Intermediate results are bound to generated variables,
+/// which are numbered across all forms transformed by the same instance.
+#[derive(Debug, Default)]
+pub struct AnormalTransform {
+    gensym: GensymCounter,
+}
+
+impl AnormalTransform {
+    pub fn new() -> Self {
+        AnormalTransform {
+            gensym: GensymCounter::new(),
+        }
+    }
+
+    /// Parse and normalize a toplevel form.
+    pub fn transform(&mut self, source: &Object) -> Result<Expression> {
+        Expression::try_from(source).map(|expr| self.normalize_toplevel(expr))
+    }
+
+    pub fn normalize_toplevel(&mut self, expr: Expression) -> Expression {
+        match expr {
+            Expression::DeFunc(name, params, body) => {
+                normalize_define(name, Expression::Lambda(params, body), &self.gensym)
+            }
+            Expression::DefVar(name, value) => normalize_define(name, *value, &self.gensym),
+            expr => normalize_term(expr, &self.gensym),
+        }
+    }
+}
+
+pub fn normalize_program(decs: Vec<Expression>) -> Vec<Expression> {
+    let mut anf = AnormalTransform::new();
+    decs.into_iter()
+        .map(|dec| anf.normalize_toplevel(dec))
+        .collect()
+}
+
+fn normalize_term(expr: Expression, gensym: &GensymCounter) -> Expression {
+    normalize(expr, gensym, Box::new(|x| x))
+}
+
+/// Function definitions become variable definitions of lambdas.
+fn normalize_define(name: Symbol, value: Expression, gensym: &GensymCounter) -> Expression {
+    //Begin(flatten_top(normalize_term(value), name))
+    Expression::DefVar(name, Box::new(normalize_term(value, gensym)))
+}
+
+fn normalize<'a>(
+    expr: Expression,
+    gensym: &'a GensymCounter,
+    k: Box<dyn FnOnce(Expression) -> Expression + 'a>,
+) -> Expression {
+    use Expression::*;
+    match expr {
+        Lambda(params, body) => k(Lambda(params, Box::new(normalize_term(*body, gensym)))),
+        Let(var, init, body) => normalize(
+            *init,
+            gensym,
+            Box::new(move |n| Let(var, Box::new(n), Box::new(normalize(*body, gensym, k)))),
+        ),
+        Set(var, value) => {
+            normalize_name(*value, gensym, Box::new(move |t| k(Set(var, Box::new(t)))))
+        }
+        MakeBox(value) => {
+            normalize_name(*value, gensym, Box::new(move |t| k(MakeBox(Box::new(t)))))
+        }
+        BoxRef(b) => normalize_name(*b, gensym, Box::new(move |t| k(BoxRef(Box::new(t))))),
+        BoxSet(b, value) => normalize_name(
+            *b,
+            gensym,
+            Box::new(move |tb| {
+                normalize_name(
+                    *value,
+                    gensym,
+                    Box::new(move |tv| k(BoxSet(Box::new(tb), Box::new(tv)))),
+                )
+            }),
+        ),
+        If(cond, yes, no) => normalize_name(
+            *cond,
+            gensym,
+            Box::new(move |t| {
+                k(Expression::If(
+                    Box::new(t),
+                    Box::new(normalize_term(*yes, gensym)),
+                    Box::new(normalize_term(*no, gensym)),
+                ))
+            }),
+        ),
+        // the values of all but the last expression are discarded, so only non-atomic
+        // expressions need to be evaluated.
+        Begin(mut exprs) => {
+            if exprs.is_empty() {
+                return k(Undef);
+            }
+            let first = exprs.remove(0);
+            if exprs.is_empty() {
+                return normalize(first, gensym, k);
+            }
+            normalize(
+                first,
+                gensym,
+                Box::new(move |n| {
+                    if n.is_atomic() {
+                        normalize(Begin(exprs), gensym, k)
+                    } else if let DefVar(_, _) = n {
+                        // definitions are statements, not values
+                        match normalize(Begin(exprs), gensym, k) {
+                            Begin(mut rest) => {
+                                rest.insert(0, n);
+                                Begin(rest)
+                            }
+                            rest => Begin(vec![n, rest]),
+                        }
+                    } else {
+                        let t = Symbol::gensym("ignore", gensym);
+                        Let(t, Box::new(n), Box::new(normalize(Begin(exprs), gensym, k)))
+                    }
+                }),
+            )
+        }
+        Apply(proc, args) => {
+            if let Primitive = *proc {
+                normalize_names(
+                    args,
+                    gensym,
+                    Box::new(move |ts| k(Expression::Apply(proc.clone(), ts))),
+                )
+            } else {
+                normalize_name(
+                    *proc,
+                    gensym,
+                    Box::new(move |t| {
+                        normalize_names(
+                            args,
+                            gensym,
+                            Box::new(move |ts| k(Expression::Apply(Box::new(t), ts))),
+                        )
+                    }),
+                )
+            }
+        }
+        DefVar(name, value) => k(normalize_define(name, *value, gensym)),
+        DeFunc(name, params, body) => k(normalize_define(name, Lambda(params, body), gensym)),
+        _ => k(expr),
+    }
+}
+
+fn normalize_names<'a>(
+    exprs: Vec<Expression>,
+    gensym: &'a GensymCounter,
+    k: Box<dyn FnOnce(Vec<Expression>) -> Expression + 'a>,
+) -> Expression {
+    if exprs.is_empty() {
+        k(vec![])
+    } else {
+        normalize_name(
+            exprs[0].clone(),
+            gensym,
+            Box::new(move |t| {
+                normalize_names(
+                    exprs[1..].to_vec(),
+                    gensym,
+                    Box::new(move |mut ts| {
+                        ts.insert(0, t);
+                        k(ts)
+                    }),
+                )
+            }),
+        )
+    }
+}
+
+fn normalize_name<'a>(
+    expr: Expression,
+    gensym: &'a GensymCounter,
+    k: Box<dyn FnOnce(Expression) -> Expression + 'a>,
+) -> Expression {
+    normalize(
+        expr,
+        gensym,
+        Box::new(move |n| {
+            if n.is_atomic() {
+                k(n)
+            } else {
+                let t = Symbol::gensym("newvar", gensym);
+                Expression::Let(t, Box::new(n), Box::new(k(Expression::Variable(t))))
+            }
+        }),
+    )
+}
+
+// The implementation by Matt Might uses this function to convert nested let expressions into a
+// sequence of defines at the top level. I'm not sure why it is necessary to put variables into
+// the global environment that were not explicitly put there by the programmer.
+// I just disabled this behavior for now to see how things turn out...
+/*fn flatten_top(expr: Expression, v: Symbol) -> Vec<Expression> {
+    use Expression::*;
+    match expr {
+        Let(x, cexp, body) => {
+            let mut defs = vec![DefVar(x, cexp)];
+            defs.extend(flatten_top(*body, v));
+            defs
+        }
+        _ => vec![DefVar(v, Box::new(expr))],
+    }
+}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+
+    fn syntax_error(source: &str) -> String {
+        match Expression::try_from(parse_datum(source).unwrap()) {
+            Err(e) => match e.kind() {
+                ErrorKind::SyntaxError(msg) => msg.clone(),
+                kind => panic!("unexpected error: {:?}", kind),
+            },
+            Ok(x) => panic!("expected syntax error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn malformed_expressions_are_syntax_errors() {
+        assert_eq!(syntax_error("(lambda (x 1) x)"), "expected symbol: 1");
+        assert!(syntax_error("(f . 3)").starts_with("(f . 3) does not match any of:"));
+        assert!(syntax_error("(f x . y)").starts_with("(f x . y) does not match any of:"));
+        for source in &[
+            "(if)",
+            "(if 1 2 3 4)",
+            "(quote)",
+            "(quote 1 2)",
+            "(define x)",
+            "(set! x)",
+        ] {
+            assert!(
+                syntax_error(source).starts_with(&format!("{} does not match any of:", source)),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn variadic_lambdas_are_not_supported() {
+        assert_eq!(
+            syntax_error("(lambda x x)"),
+            "variadic lambda is not supported: x"
+        );
+        assert_eq!(
+            syntax_error("(lambda (a . b) a)"),
+            "variadic lambda is not supported: (a . b)"
+        );
+        assert_eq!(
+            syntax_error("(define (f . args) args)"),
+            "variadic lambda is not supported: args"
+        );
+        assert_eq!(syntax_error("(lambda 1 x)"), "invalid parameter list: 1");
+    }
+
+    fn anf(source: &str) -> String {
+        let mut anf = AnormalTransform::new();
+        format!(
+            "{:?}",
+            anf.transform(&parse_datum(source).unwrap()).unwrap()
+        )
+    }
+
+    #[test]
+    fn nested_applications_are_named() {
+        let gensym = GensymCounter::new();
+        let n = Symbol::new("n");
+        let expr = normalize_term(
+            Expression::Lambda(
+                vec![n],
+                Box::new(Expression::If(
+                    Box::new(Expression::Apply(
+                        Box::new(Expression::Primitive),
+                        vec![Expression::Variable(n), Expression::Integer(0)],
+                    )),
+                    Box::new(Expression::Integer(1)),
+                    Box::new(Expression::Apply(
+                        Box::new(Expression::Primitive),
+                        vec![
+                            Expression::Variable(n),
+                            Expression::Apply(
+                                Box::new(Expression::Variable(Symbol::new("fact"))),
+                                vec![Expression::Apply(
+                                    Box::new(Expression::Primitive),
+                                    vec![Expression::Variable(n), Expression::Integer(1)],
+                                )],
+                            ),
+                        ],
+                    )),
+                )),
+            ),
+            &gensym,
+        );
+        assert_eq!(format!("{:?}", expr), "(lambda (n) (let (#:newvar.0 (<primitive> n 0)) (if #:newvar.0 1 (let (#:newvar.1 (<primitive> n 1)) (let (#:newvar.2 (fact #:newvar.1)) (<primitive> n #:newvar.2))))))");
+    }
+
+    #[test]
+    fn let_initializers_are_normalized() {
+        assert_eq!(
+            anf("(let ((x (if c1 (f 1) (f 2)))) (let ((y (if c1 (f x) (g x)))) y))"),
+            "(let (x (if c1 (f 1) (f 2))) (let (y (if c1 (f x) (g x))) y))"
+        );
+    }
+
+    #[test]
+    fn variable_definitions() {
+        assert_eq!(
+            anf("(define z (let ((x (f (g 1)))) x))"),
+            "(define z (let (#:newvar.0 (g 1)) (let (x (f #:newvar.0)) x)))"
+        );
+    }
+
+    #[test]
+    fn function_definitions() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                normalize_program(vec![parse_datum(
+                    "(define (sillyfunc x) (+ x (sqr (- x ref))))"
+                )
+                .unwrap()
+                .try_into()
+                .unwrap()])
+            ),
+            "[(define sillyfunc (lambda (x) (let (#:newvar.0 (- x ref)) (let (#:newvar.1 (sqr #:newvar.0)) (+ x #:newvar.1)))))]"
+        );
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(
+            anf("(lambda (x) (f x) x (g (h x)))"),
+            "(lambda (x) (let (#:ignore.0 (f x)) (let (#:newvar.1 (h x)) (g #:newvar.1))))"
+        );
+        assert_eq!(
+            anf("(begin (f (g 1)) (h 2))"),
+            "(let (#:newvar.0 (g 1)) (let (#:ignore.1 (f #:newvar.0)) (h 2)))"
+        );
+        assert_eq!(
+            anf("(define (f) (g) 1)"),
+            "(define f (lambda () (let (#:ignore.0 (g)) 1)))"
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(
+            anf(r#"(f "text" 'sym '(1 2) #(3))"#),
+            r#"(f "text" 'sym '(1 2) '#(3))"#
+        );
+    }
+
+    #[test]
+    fn let_with_multiple_bindings() {
+        assert_eq!(
+            anf("(let ((x (f 1)) (y 2)) (g x y))"),
+            "(let (#:newvar.0 (f 1)) ((lambda (x y) (g x y)) #:newvar.0 2))"
+        );
+    }
+
+    #[test]
+    fn one_armed_if() {
+        assert_eq!(
+            anf("(if (f x) (g x))"),
+            "(let (#:newvar.0 (f x)) (if #:newvar.0 (g x) <undefined>))"
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            anf("(set! x (f y))"),
+            "(let (#:newvar.0 (f y)) (set! x #:newvar.0))"
+        );
+    }
+
+    #[test]
+    fn internal_definitions_bind_local_variables() {
+        assert_eq!(
+            anf("(lambda () (define x (f (g 1))) x)"),
+            "(lambda () (let (x <undefined>) (let (#:newvar.0 (g 1)) (let (#:newvar.1 (f #:newvar.0)) (let (#:ignore.2 (set! x #:newvar.1)) x)))))"
+        );
+        assert_eq!(
+            anf("(define (f) (define (g) (h)) (define y (g)) y)"),
+            "(define f (lambda () (let (g <undefined>) (let (y <undefined>) (let (#:ignore.0 (set! g (lambda () (h)))) (let (#:newvar.1 (g)) (let (#:ignore.2 (set! y #:newvar.1)) y)))))))"
+        );
+    }
+
+    #[test]
+    fn toplevel_definitions_stay_statements() {
+        assert_eq!(
+            anf("(begin (define x (f (g 1))) (define (h) x) (h))"),
+            "(begin (define x (let (#:newvar.0 (g 1)) (f #:newvar.0))) (define h (lambda () x)) (h))"
+        );
+    }
+
+    #[test]
+    fn empty_bodies_are_syntax_errors() {
+        assert_eq!(syntax_error("(lambda (x))"), "empty body");
+    }
+}
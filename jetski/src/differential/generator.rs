@@ -0,0 +1,512 @@
+//! Random programs
+//! Generated programs are type correct and always terminate without errors: arithmetic stays
+//! far from overflow, `car` and `cdr` are guarded by `null?`, loops are bounded, and toplevel
+//! functions only call functions defined before them. Effects are confined to places where the
+//! order of evaluation is specified, so every correct compiler must agree on the outcome.
+//!
+//! Variable names are drawn from a small pool to provoke shadowing.
+//!
+//! Programs for the JIT are restricted to what it compiles: integers and booleans, integer
+//! arithmetic and comparison, and procedures that don't capture local variables. They don't
+//! print either.
+
+use crate::object::{ListBuilder, Object};
+
+/// Names of local variables.
+const NAMES: &[&str] = &["a", "b", "n", "x", "y"];
+
+/// Literals stay small, and multiplication is only by small factors, so that the nesting depth
+/// bounds the magnitude of all integers.
+const MAX_LITERAL: i64 = 50;
+const MAX_FACTOR: i64 = 9;
+const MAX_LOOP: i64 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Type {
+    Integer,
+    Boolean,
+    List,
+}
+
+const TYPES: &[Type] = &[Type::Integer, Type::Boolean, Type::List];
+const JIT_TYPES: &[Type] = &[Type::Integer, Type::Boolean];
+
+/// A xorshift generator, so that programs can be reproduced from the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + self.below((high - low + 1) as usize) as i64
+    }
+
+    fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+struct Function {
+    name: String,
+    params: Vec<Type>,
+    result: Type,
+}
+
+/// Variables in scope, innermost last.
+type Scope = Vec<(String, Type)>;
+
+pub struct ProgramGenerator {
+    rng: Rng,
+    max_depth: usize,
+    functions: Vec<Function>,
+    globals: Scope,
+    jit_subset: bool,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64) -> Self {
+        ProgramGenerator {
+            rng: Rng::new(seed),
+            max_depth: 4,
+            functions: vec![],
+            globals: vec![],
+            jit_subset: false,
+        }
+    }
+
+    /// Generate programs that the JIT can run.
+    pub fn jit_subset(mut self) -> Self {
+        self.jit_subset = true;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Generate a program: some toplevel functions and variables, some output, and a final
+    /// expression whose value is the result of the program.
+    pub fn generate(&mut self) -> Vec<Object> {
+        self.functions.clear();
+        self.globals.clear();
+        let mut program = vec![];
+
+        for i in 0..self.rng.below(4) {
+            program.push(self.function_definition(format!("f{}", i)));
+        }
+        for i in 0..self.rng.below(3) {
+            let ty = self.any_type();
+            let value = self.expression(ty, self.max_depth, &vec![]);
+            program.push(call("define", vec![symbol(&format!("g{}", i)), value]));
+            self.globals.push((format!("g{}", i), ty));
+        }
+        for _ in 0..self.rng.below(4) {
+            program.extend(self.statement());
+        }
+        let ty = self.any_type();
+        program.push(self.expression(ty, self.max_depth, &vec![]));
+        program
+    }
+
+    fn function_definition(&mut self, name: String) -> Object {
+        let arity = self.rng.below(4);
+        let mut names = NAMES.to_vec();
+        let mut scope = vec![];
+        for _ in 0..arity {
+            let param = names.remove(self.rng.below(names.len()));
+            let ty = self.any_type();
+            scope.push((param.to_string(), ty));
+        }
+        let result = self.any_type();
+        let body = self.expression(result, self.max_depth, &scope);
+        let mut signature = vec![symbol(&name)];
+        signature.extend(scope.iter().map(|(param, _)| symbol(param)));
+        self.functions.push(Function {
+            name,
+            params: scope.iter().map(|(_, ty)| *ty).collect(),
+            result,
+        });
+        call("define", vec![list(signature), body])
+    }
+
+    /// A toplevel form that prints or assigns a global variable. Programs for the JIT only
+    /// assign, so there may be nothing to do.
+    fn statement(&mut self) -> Option<Object> {
+        match self.rng.below(3) {
+            0 if !self.globals.is_empty() => {
+                let (name, ty) = self.globals[self.rng.below(self.globals.len())].clone();
+                let value = self.expression(ty, self.max_depth, &vec![]);
+                Some(call("set!", vec![symbol(&name), value]))
+            }
+            _ if self.jit_subset => None,
+            1 => Some(call("newline", vec![])),
+            _ => {
+                let ty = self.any_type();
+                let value = self.expression(ty, self.max_depth, &vec![]);
+                Some(call(
+                    self.rng.choose::<&str>(&["display", "write"]),
+                    vec![value],
+                ))
+            }
+        }
+    }
+
+    fn any_type(&mut self) -> Type {
+        if self.jit_subset {
+            *self.rng.choose(JIT_TYPES)
+        } else {
+            *self.rng.choose(TYPES)
+        }
+    }
+
+    fn expression(&mut self, ty: Type, depth: usize, scope: &Scope) -> Object {
+        match ty {
+            Type::Integer => self.integer(depth, scope),
+            Type::Boolean => self.boolean(depth, scope),
+            Type::List => self.list(depth, scope),
+        }
+    }
+
+    /// A variable of the given type, or a fresh expression if there is none.
+    fn variable(&mut self, ty: Type, depth: usize, scope: &Scope) -> Object {
+        let visible: Vec<_> = visible(scope, &self.globals)
+            .into_iter()
+            .filter(|(_, t)| *t == ty)
+            .collect();
+        if visible.is_empty() {
+            self.expression(ty, depth.saturating_sub(1), scope)
+        } else {
+            symbol(&self.rng.choose(&visible).0)
+        }
+    }
+
+    fn integer(&mut self, depth: usize, scope: &Scope) -> Object {
+        if depth == 0 {
+            return match self.rng.below(2) {
+                0 => Object::integer(self.rng.range(-MAX_LITERAL, MAX_LITERAL)),
+                _ => self.variable(Type::Integer, 0, scope),
+            };
+        }
+        let d = depth - 1;
+        // no lists, closures, or loops, which need closures as well
+        let case = if self.jit_subset {
+            *self.rng.choose(&[0, 1, 2, 3, 4, 5, 6, 10, 13])
+        } else {
+            self.rng.below(14)
+        };
+        match case {
+            0 => Object::integer(self.rng.range(-MAX_LITERAL, MAX_LITERAL)),
+            1 => self.variable(Type::Integer, depth, scope),
+            2 => call("+", vec![self.integer(d, scope), self.integer(d, scope)]),
+            3 => call("-", vec![self.integer(d, scope), self.integer(d, scope)]),
+            4 => call(
+                "*",
+                vec![
+                    self.integer(d, scope),
+                    Object::integer(self.rng.range(-MAX_FACTOR, MAX_FACTOR)),
+                ],
+            ),
+            5 => self.conditional(Type::Integer, d, scope),
+            6 => self.binding(Type::Integer, d, scope),
+            7 => call("length", vec![self.list(d, scope)]),
+            8 => {
+                // (let ((l list)) (if (null? l) default (car l)))
+                let (var, inner) = self.bind(Type::List, scope);
+                let list = self.list(d, scope);
+                let default = self.integer(d, &inner);
+                let guarded = call(
+                    "if",
+                    vec![
+                        call("null?", vec![symbol(&var)]),
+                        default,
+                        call("car", vec![symbol(&var)]),
+                    ],
+                );
+                make_let(vec![(var, list)], guarded)
+            }
+            9 => self.application(Type::Integer, d, scope),
+            10 => self.function_call(Type::Integer, d, scope),
+            11 => {
+                // a counter that is captured and assigned:
+                // (let ((v init)) (let ((g (lambda () (set! v (+ v k)) v))) (+ (g) (g))))
+                let (var, inner) = self.bind(Type::Integer, scope);
+                let init = self.integer(d, scope);
+                let (counter, _) = self.bind(Type::Integer, &inner);
+                let step = Object::integer(self.rng.range(1, MAX_FACTOR));
+                let increment = call(
+                    "set!",
+                    vec![symbol(&var), call("+", vec![symbol(&var), step])],
+                );
+                let lambda = call("lambda", vec![Object::nil(), increment, symbol(&var)]);
+                let uses = call("+", vec![call(&counter, vec![]), call(&counter, vec![])]);
+                make_let(vec![(var, init)], make_let(vec![(counter, lambda)], uses))
+            }
+            12 => {
+                // (let loop ((i 0) (acc init)) (if (< i k) (loop (+ i 1) (+ acc i)) acc))
+                let mut names = NAMES.to_vec();
+                let mut pick = |rng: &mut Rng| names.remove(rng.below(names.len()));
+                let (name, i, acc) = (
+                    pick(&mut self.rng),
+                    pick(&mut self.rng),
+                    pick(&mut self.rng),
+                );
+                let init = self.integer(d, scope);
+                let bound = Object::integer(self.rng.range(0, MAX_LOOP));
+                let body = call(
+                    "if",
+                    vec![
+                        call("<", vec![symbol(i), bound]),
+                        call(
+                            name,
+                            vec![
+                                call("+", vec![symbol(i), Object::integer(1)]),
+                                call("+", vec![symbol(acc), symbol(i)]),
+                            ],
+                        ),
+                        symbol(acc),
+                    ],
+                );
+                let bindings = list(vec![
+                    list(vec![symbol(i), Object::integer(0)]),
+                    list(vec![symbol(acc), init]),
+                ]);
+                list(vec![symbol("let"), symbol(name), bindings, body])
+            }
+            _ => {
+                // (cond (test value) ... (else value))
+                let mut clauses = vec![symbol("cond")];
+                for _ in 0..self.rng.range(1, 2) {
+                    clauses.push(list(vec![self.boolean(d, scope), self.integer(d, scope)]));
+                }
+                clauses.push(list(vec![symbol("else"), self.integer(d, scope)]));
+                list(clauses)
+            }
+        }
+    }
+
+    fn boolean(&mut self, depth: usize, scope: &Scope) -> Object {
+        if depth == 0 {
+            return match self.rng.below(2) {
+                0 => Object::boolean(self.rng.below(2) == 0),
+                _ => self.variable(Type::Boolean, 0, scope),
+            };
+        }
+        let d = depth - 1;
+        // no lists, and only the comparisons the JIT compiles inline
+        let (case, comparisons): (_, &[&str]) = if self.jit_subset {
+            (*self.rng.choose(&[0, 1, 2, 5, 8, 9, 11]), &["<", "=", ">"])
+        } else {
+            (self.rng.below(12), &["<", "=", ">="])
+        };
+        match case {
+            0 => Object::boolean(self.rng.below(2) == 0),
+            1 => self.variable(Type::Boolean, depth, scope),
+            2 => call(
+                self.rng.choose::<&str>(comparisons),
+                vec![self.integer(d, scope), self.integer(d, scope)],
+            ),
+            3 => call("not", vec![self.boolean(d, scope)]),
+            4 => call(
+                self.rng.choose::<&str>(&["null?", "pair?"]),
+                vec![self.list(d, scope)],
+            ),
+            5 => call(
+                self.rng.choose::<&str>(&["and", "or"]),
+                vec![self.boolean(d, scope), self.boolean(d, scope)],
+            ),
+            6 => call("even?", vec![self.integer(d, scope)]),
+            7 => call("equal?", vec![self.list(d, scope), self.list(d, scope)]),
+            8 => self.conditional(Type::Boolean, d, scope),
+            9 => self.binding(Type::Boolean, d, scope),
+            10 => self.application(Type::Boolean, d, scope),
+            _ => self.function_call(Type::Boolean, d, scope),
+        }
+    }
+
+    fn list(&mut self, depth: usize, scope: &Scope) -> Object {
+        if depth == 0 {
+            return match self.rng.below(3) {
+                0 => Object::nil(),
+                1 => self.quoted_list(),
+                _ => self.variable(Type::List, 0, scope),
+            };
+        }
+        let d = depth - 1;
+        match self.rng.below(12) {
+            0 => self.quoted_list(),
+            1 => self.variable(Type::List, depth, scope),
+            2 => {
+                let items = (0..self.rng.below(4))
+                    .map(|_| self.integer(d, scope))
+                    .collect();
+                call("list", items)
+            }
+            3 => call("cons", vec![self.integer(d, scope), self.list(d, scope)]),
+            4 => {
+                // (let ((l list)) (if (null? l) l (cdr l)))
+                let (var, _) = self.bind(Type::List, scope);
+                let list = self.list(d, scope);
+                let guarded = call(
+                    "if",
+                    vec![
+                        call("null?", vec![symbol(&var)]),
+                        symbol(&var),
+                        call("cdr", vec![symbol(&var)]),
+                    ],
+                );
+                make_let(vec![(var, list)], guarded)
+            }
+            5 => call("append", vec![self.list(d, scope), self.list(d, scope)]),
+            6 => call("reverse", vec![self.list(d, scope)]),
+            7 => self.conditional(Type::List, d, scope),
+            8 => self.binding(Type::List, d, scope),
+            9 => self.application(Type::List, d, scope),
+            _ => self.function_call(Type::List, d, scope),
+        }
+    }
+
+    fn quoted_list(&mut self) -> Object {
+        let items = (0..self.rng.below(4))
+            .map(|_| Object::integer(self.rng.range(-MAX_LITERAL, MAX_LITERAL)))
+            .collect();
+        call("quote", vec![list(items)])
+    }
+
+    fn conditional(&mut self, ty: Type, depth: usize, scope: &Scope) -> Object {
+        let test = self.boolean(depth, scope);
+        let yes = self.expression(ty, depth, scope);
+        let no = self.expression(ty, depth, scope);
+        call("if", vec![test, yes, no])
+    }
+
+    /// A `let` or `let*` with one or two bindings of random types.
+    fn binding(&mut self, ty: Type, depth: usize, scope: &Scope) -> Object {
+        let sequential = self.rng.below(2) == 0;
+        let mut inner = scope.clone();
+        let mut bindings: Vec<(String, Object)> = vec![];
+        for _ in 0..self.rng.range(1, 2) {
+            let var_type = self.any_type();
+            let init_scope = if sequential { &inner } else { scope };
+            let init = self.expression(var_type, depth, &init_scope.clone());
+            let bound: Vec<_> = bindings.iter().map(|(var, _)| var.clone()).collect();
+            let var = self.fresh_name(&bound);
+            inner.push((var.clone(), var_type));
+            bindings.push((var, init));
+        }
+        let body = self.expression(ty, depth, &inner);
+        if sequential {
+            make_binding_form("let*", bindings, body)
+        } else {
+            make_let(bindings, body)
+        }
+    }
+
+    /// `((lambda (params) body) args)`
+    fn application(&mut self, ty: Type, depth: usize, scope: &Scope) -> Object {
+        let mut inner = scope.clone();
+        let mut params = vec![];
+        let mut args = vec![];
+        for _ in 0..self.rng.below(3) {
+            let param_type = self.any_type();
+            args.push(self.expression(param_type, depth, scope));
+            let param = self.fresh_name(&params);
+            inner.push((param.clone(), param_type));
+            params.push(param);
+        }
+        let body = self.expression(ty, depth, &inner);
+        let params = list(params.iter().map(|param| symbol(param)).collect());
+        let mut application = vec![call("lambda", vec![params, body])];
+        application.extend(args);
+        list(application)
+    }
+
+    /// A call of a toplevel function with the given result type, if there is one.
+    fn function_call(&mut self, ty: Type, depth: usize, scope: &Scope) -> Object {
+        let candidates: Vec<_> = (0..self.functions.len())
+            .filter(|&i| self.functions[i].result == ty)
+            .collect();
+        if candidates.is_empty() {
+            return self.expression(ty, depth, scope);
+        }
+        let index = *self.rng.choose(&candidates);
+        let params = self.functions[index].params.clone();
+        let name = self.functions[index].name.clone();
+        let args = params
+            .into_iter()
+            .map(|param| self.expression(param, depth, scope))
+            .collect();
+        call(&name, args)
+    }
+
+    /// Choose a name for a new variable of the given type.
+    fn bind(&mut self, ty: Type, scope: &Scope) -> (String, Scope) {
+        let name = self.rng.choose(NAMES).to_string();
+        let mut inner = scope.clone();
+        inner.push((name.clone(), ty));
+        (name, inner)
+    }
+
+    /// A name that is not bound yet in the same binding form.
+    fn fresh_name(&mut self, bound: &[String]) -> String {
+        let free: Vec<_> = NAMES
+            .iter()
+            .filter(|name| bound.iter().all(|b| b != *name))
+            .collect();
+        self.rng.choose(&free).to_string()
+    }
+}
+
+/// The variables that are not shadowed, with their types.
+fn visible(scope: &Scope, globals: &Scope) -> Vec<(String, Type)> {
+    let mut vars: Vec<(String, Type)> = vec![];
+    for (name, ty) in scope.iter().rev().chain(globals.iter().rev()) {
+        if vars.iter().all(|(v, _)| v != name) {
+            vars.push((name.clone(), *ty));
+        }
+    }
+    vars
+}
+
+fn symbol(name: &str) -> Object {
+    Object::symbol(name)
+}
+
+fn list(items: Vec<Object>) -> Object {
+    let mut list = ListBuilder::new();
+    for item in items {
+        list.append(item);
+    }
+    list.build()
+}
+
+fn call(op: &str, args: Vec<Object>) -> Object {
+    let mut items = vec![symbol(op)];
+    items.extend(args);
+    list(items)
+}
+
+fn make_binding_form(keyword: &str, bindings: Vec<(String, Object)>, body: Object) -> Object {
+    let bindings = bindings
+        .into_iter()
+        .map(|(var, init)| list(vec![symbol(&var), init]))
+        .collect();
+    call(keyword, vec![list(bindings), body])
+}
+
+fn make_let(bindings: Vec<(String, Object)>, body: Object) -> Object {
+    make_binding_form("let", bindings, body)
+}
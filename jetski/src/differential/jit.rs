@@ -0,0 +1,205 @@
+//! The JIT backend
+//! Programs run through the front end and are compiled by `jit::Compiler`. The compiler only
+//! supports a small subset of Scheme: integers and booleans, integer arithmetic and
+//! comparison, and procedures that don't capture local variables. Programs outside this subset
+//! are skipped, so the backend only checks the programs it can run. `ProgramGenerator` has a
+//! mode that generates programs in this subset.
+
+use super::{Backend, Compiled, Outcome};
+use crate::core_scheme::Expression;
+use crate::error::{ErrorKind, Result};
+use crate::jit::Compiler;
+use crate::object::{ListBuilder, Object, TaggedValue};
+use crate::runtime::Symbol;
+use std::collections::HashSet;
+
+/// The primitive operations the JIT compiles inline. `/` is missing on purpose: the JIT
+/// truncates, while the interpreter produces exact quotients.
+const OPERATORS: &[&str] = &["+", "-", "*", "=", "<", ">"];
+
+/// Compiles programs in core Scheme to machine code and runs them.
+#[derive(Debug, Default)]
+pub struct Jit;
+
+impl Backend for Jit {
+    fn name(&self) -> String {
+        "front end + JIT".to_string()
+    }
+
+    fn supports(&self, program: &[Object]) -> bool {
+        match Compiled::new().compile(program) {
+            Ok(core) => is_supported(&core),
+            // the error is compared with the reference
+            Err(_) => true,
+        }
+    }
+
+    fn run(&mut self, program: &[Object]) -> Result<Outcome> {
+        let mut compiler = Compiler::new();
+        let mut value = Object::undef();
+        for expr in Compiled::new().compile(program)? {
+            value = compiler.eval(&to_datum(&expr)?)?;
+        }
+        Ok(Outcome::new(Some(value), String::new()))
+    }
+}
+
+/// Convert an expression in core Scheme to the source code the JIT compiles.
+pub fn to_datum(expr: &Expression) -> Result<Object> {
+    use Expression::*;
+    let datums = |exprs: &[Expression]| exprs.iter().map(to_datum).collect::<Result<Vec<_>>>();
+    let params = |params: &[Symbol]| list(params.iter().map(|&p| Object::from(p)).collect());
+    Ok(match expr {
+        Undef => list(vec![
+            symbol("if"),
+            Object::boolean(false),
+            Object::boolean(false),
+        ]),
+        Integer(i) => Object::integer(*i),
+        Quote(datum) if datum.is_symbol() => list(vec![symbol("quote"), datum.clone()]),
+        Quote(datum) if is_boolean(datum) => datum.clone(),
+        Variable(name) => Object::from(*name),
+        Lambda(ps, body) => list(vec![symbol("lambda"), params(ps), to_datum(body)?]),
+        Let(var, init, body) => list(vec![
+            symbol("let"),
+            list(vec![list(vec![Object::from(*var), to_datum(init)?])]),
+            to_datum(body)?,
+        ]),
+        // `let` is desugared to an application of a lambda, which the JIT would compile to a
+        // procedure that captures the variables around it. The arguments are atomic after
+        // A-normalization and the parameters are unique after alphatization, so the
+        // parameters can be bound one after the other.
+        Apply(proc, args) if is_let(proc, args) => match &**proc {
+            Lambda(ps, body) => ps.iter().zip(args).rev().try_fold(
+                to_datum(body)?,
+                |body, (var, init)| -> Result<Object> {
+                    Ok(list(vec![
+                        symbol("let"),
+                        list(vec![list(vec![Object::from(*var), to_datum(init)?])]),
+                        body,
+                    ]))
+                },
+            )?,
+            _ => unreachable!(),
+        },
+        If(cond, yes, no) => list(vec![
+            symbol("if"),
+            to_datum(cond)?,
+            to_datum(yes)?,
+            to_datum(no)?,
+        ]),
+        Apply(proc, args) => {
+            let mut items = vec![to_datum(proc)?];
+            items.extend(datums(args)?);
+            list(items)
+        }
+        Set(var, value) => list(vec![symbol("set!"), Object::from(*var), to_datum(value)?]),
+        Begin(exprs) => {
+            let mut items = vec![symbol("begin")];
+            items.extend(datums(exprs)?);
+            list(items)
+        }
+        DefVar(name, value) => list(vec![
+            symbol("define"),
+            Object::from(*name),
+            to_datum(value)?,
+        ]),
+        DeFunc(name, ps, body) => list(vec![
+            symbol("define"),
+            Object::from(*name),
+            list(vec![symbol("lambda"), params(ps), to_datum(body)?]),
+        ]),
+        _ => {
+            return Err(
+                ErrorKind::CodegenError(format!("not supported by the JIT: {:?}", expr)).into(),
+            )
+        }
+    })
+}
+
+fn symbol(name: &str) -> Object {
+    Object::symbol(name)
+}
+
+fn list(items: Vec<Object>) -> Object {
+    let mut list = ListBuilder::new();
+    for item in items {
+        list.append(item);
+    }
+    list.build()
+}
+
+/// Whether an application is that of a lambda expression to the right number of arguments.
+fn is_let(proc: &Expression, args: &[Expression]) -> bool {
+    matches!(proc, Expression::Lambda(params, _) if params.len() == args.len())
+}
+
+fn is_boolean(datum: &Object) -> bool {
+    matches!(datum.as_value(), TaggedValue::Boolean(_))
+}
+
+/// Whether the JIT compiles the program to code that behaves like the interpreter.
+fn is_supported(program: &[Expression]) -> bool {
+    let mut globals = HashSet::new();
+    for expr in program {
+        if let Expression::DefVar(name, _) | Expression::DeFunc(name, _, _) = expr {
+            globals.insert(*name);
+        }
+    }
+    let mut checker = SubsetChecker {
+        globals,
+        locals: HashSet::new(),
+    };
+    program
+        .iter()
+        .all(|expr| to_datum(expr).is_ok() && checker.check(expr))
+}
+
+/// Checks that every variable is a global, a primitive operation, or a local of the procedure
+/// that references it.
+struct SubsetChecker {
+    globals: HashSet<Symbol>,
+    /// The locals of the innermost procedure. Variables are unique after alphatization, so
+    /// there is no need to track shadowing.
+    locals: HashSet<Symbol>,
+}
+
+impl SubsetChecker {
+    fn check(&mut self, expr: &Expression) -> bool {
+        use Expression::*;
+        match expr {
+            Variable(name) => self.is_visible(*name),
+            Set(name, value) => self.is_visible(*name) && self.check(value),
+            Lambda(params, body) | DeFunc(_, params, body) => {
+                let outer = std::mem::replace(&mut self.locals, params.iter().cloned().collect());
+                let supported = self.check(body);
+                self.locals = outer;
+                supported
+            }
+            Let(var, init, body) => {
+                let supported = self.check(init);
+                self.locals.insert(*var);
+                supported && self.check(body)
+            }
+            Apply(proc, args) if is_let(proc, args) => match &**proc {
+                Lambda(params, body) => {
+                    let supported = args.iter().all(|arg| self.check(arg));
+                    self.locals.extend(params.iter().cloned());
+                    supported && self.check(body)
+                }
+                _ => unreachable!(),
+            },
+            If(cond, yes, no) => self.check(cond) && self.check(yes) && self.check(no),
+            Apply(proc, args) => self.check(proc) && args.iter().all(|arg| self.check(arg)),
+            Begin(exprs) => exprs.iter().all(|expr| self.check(expr)),
+            DefVar(_, value) => self.check(value),
+            _ => true,
+        }
+    }
+
+    fn is_visible(&self, name: Symbol) -> bool {
+        self.locals.contains(&name)
+            || self.globals.contains(&name)
+            || OPERATORS.contains(&name.name())
+    }
+}
@@ -0,0 +1,484 @@
+//! Differential testing
+//! Programs are run on several backends, and the values and printed output must agree. The
+//! reference interpreter runs the source program and defines the expected outcome. Compiled
+//! backends run the program through the compiler pipeline first, so any disagreement points
+//! to a bug in one of the passes.
+//!
+//! Programs come from a corpus or from `ProgramGenerator`, which generates random programs
+//! that are guaranteed to run without errors. Programs that are supposed to fail are compared
+//! with `compare_failing`; everywhere else, an error of the reference is a failure too.
+//!
+//! The reference interpreter shares part of the front end with the compiled backends:
+//! `Interpreter::eval_datum` desugars with `Desugar` and parses the result with
+//! `Expression::try_from`. Bugs in those two stages affect the reference and the backends alike,
+//! so they never show up as mismatches. Only the alphatizer and the passes after parsing are
+//! checked against an independent implementation.
+
+mod generator;
+mod jit;
+
+pub use generator::ProgramGenerator;
+pub use jit::{to_datum, Jit};
+
+use crate::core_scheme::assignment_conversion::AssignmentConversion;
+use crate::core_scheme::closure_conversion::ClosureConversion;
+use crate::core_scheme::constant_folding::ConstantFolding;
+use crate::core_scheme::dead_code::DeadCodeElimination;
+use crate::core_scheme::inlining::Inliner;
+use crate::core_scheme::lambda_lifting::LambdaLifting;
+use crate::core_scheme::{AnormalTransform, Expression};
+use crate::error::Result;
+use crate::eval::{Interpreter, Value};
+use crate::object::{Object, TaggedValue};
+use crate::transformations::alphatize::Alphatizer;
+use crate::transformations::desugar::Desugar;
+use crate::transformations::SourceTransformer;
+use std::fmt;
+use std::rc::Rc;
+
+/// What running a program produced: the value of its last form and everything it printed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// The value as data; `None` for procedures and other values that are not data.
+    pub value: Option<Object>,
+    pub output: String,
+}
+
+impl Outcome {
+    pub fn new(value: Option<Object>, output: String) -> Self {
+        // compiled code represents procedures as function pointers, which can't be compared
+        let value = value.filter(|obj| !matches!(obj.as_value(), TaggedValue::Function(_)));
+        Outcome { value, output }
+    }
+
+    fn from_interpreter(value: Value, interp: &mut Interpreter) -> Self {
+        Outcome::new(value.to_object(), interp.take_output())
+    }
+}
+
+/// A way to run programs. Every program runs in a fresh global environment.
+pub trait Backend {
+    fn name(&self) -> String;
+
+    /// Whether the backend can run the program at all; `compare` skips it otherwise.
+    fn supports(&self, _program: &[Object]) -> bool {
+        true
+    }
+
+    fn run(&mut self, program: &[Object]) -> Result<Outcome>;
+}
+
+/// The reference interpreter, which runs the source program.
+#[derive(Debug, Default)]
+pub struct Reference;
+
+impl Backend for Reference {
+    fn name(&self) -> String {
+        "reference".to_string()
+    }
+
+    fn run(&mut self, program: &[Object]) -> Result<Outcome> {
+        let mut interp = Interpreter::new();
+        let mut value = Value::Unspecified;
+        for form in program {
+            value = interp.eval_datum(form)?;
+        }
+        Ok(Outcome::from_interpreter(value, &mut interp))
+    }
+}
+
+/// A pass over a whole program in core Scheme.
+pub type ProgramPass = Rc<dyn Fn(Vec<Expression>) -> Result<Vec<Expression>>>;
+
+/// Runs programs through the front end (alphatization, desugaring and A-normalization) and
+/// the given passes over core Scheme. The resulting program is run by the interpreter, which
+/// can run the output of every pass. After closure conversion, procedures run in an empty
+/// environment, so that they only get at captured variables through their closure record.
+#[derive(Default)]
+pub struct Compiled {
+    passes: Vec<Pass>,
+}
+
+#[derive(Clone)]
+struct Pass {
+    name: String,
+    run: ProgramPass,
+    /// Whether procedures no longer refer to the local variables around them after the pass.
+    closes_procedures: bool,
+}
+
+impl Compiled {
+    pub fn new() -> Self {
+        Compiled { passes: vec![] }
+    }
+
+    /// The optimizations followed by the conversions that prepare code generation.
+    pub fn all_passes() -> Self {
+        Compiled::new()
+            .with_pass("constant folding", |p| {
+                Ok(ConstantFolding::new().fold_program(p))
+            })
+            .with_pass("inlining", |p| Ok(Inliner::new().inline_program(p)))
+            .with_pass("dead code elimination", |p| {
+                Ok(DeadCodeElimination::new().eliminate_program(p))
+            })
+            .with_pass("assignment conversion", |p| {
+                AssignmentConversion::new().convert_program(p)
+            })
+            .with_closing_pass("closure conversion", |p| {
+                let mut lifting = LambdaLifting::new();
+                let program = lifting.lift_program(p)?;
+                let mut conversion = ClosureConversion::new();
+                for &name in lifting.lifted_functions() {
+                    conversion.declare_known_function(name);
+                }
+                conversion.convert_program(program)
+            })
+    }
+
+    pub fn with_pass<F>(self, name: &str, pass: F) -> Self
+    where
+        F: Fn(Vec<Expression>) -> Result<Vec<Expression>> + 'static,
+    {
+        self.add_pass(name, Rc::new(pass), false)
+    }
+
+    /// Add a pass after which procedures don't refer to the local variables around them.
+    pub fn with_closing_pass<F>(self, name: &str, pass: F) -> Self
+    where
+        F: Fn(Vec<Expression>) -> Result<Vec<Expression>> + 'static,
+    {
+        self.add_pass(name, Rc::new(pass), true)
+    }
+
+    fn add_pass(mut self, name: &str, run: ProgramPass, closes_procedures: bool) -> Self {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            run,
+            closes_procedures,
+        });
+        self
+    }
+
+    /// Compiled backends with increasingly many of the passes, starting with none. The first
+    /// one that disagrees with the reference points to the culprit.
+    pub fn prefixes(&self) -> Vec<Compiled> {
+        (0..=self.passes.len())
+            .map(|n| Compiled {
+                passes: self.passes[..n].to_vec(),
+            })
+            .collect()
+    }
+
+    /// Compile a program to core Scheme.
+    pub fn compile(&self, program: &[Object]) -> Result<Vec<Expression>> {
+        let mut alphatizer = Alphatizer::new();
+        let mut desugar = Desugar::new();
+        let mut anf = AnormalTransform::new();
+        let mut core = vec![];
+        for form in program {
+            let form = desugar.transform(&alphatizer.transform(form)?)?;
+            core.push(anf.transform(&form)?);
+        }
+        for pass in &self.passes {
+            core = (pass.run)(core)?;
+        }
+        Ok(core)
+    }
+}
+
+impl Backend for Compiled {
+    fn name(&self) -> String {
+        let names: Vec<_> = self.passes.iter().map(|pass| pass.name.as_str()).collect();
+        format!("front end + [{}]", names.join(", "))
+    }
+
+    fn run(&mut self, program: &[Object]) -> Result<Outcome> {
+        let core = self.compile(program)?;
+        let mut interp = Interpreter::new();
+        if self.passes.iter().any(|pass| pass.closes_procedures) {
+            interp = interp.with_closed_procedures();
+        }
+        let value = interp.eval_program(&core)?;
+        Ok(Outcome::from_interpreter(value, &mut interp))
+    }
+}
+
+/// A backend that disagreed with the reference.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub backend: String,
+    pub program: Vec<Object>,
+    /// What the reference produced, or `None` if the reference itself failed when it should
+    /// have succeeded, or the other way around.
+    pub expected: Option<std::result::Result<Outcome, String>>,
+    pub actual: std::result::Result<Outcome, String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.expected, &self.actual) {
+            (Some(_), _) => writeln!(
+                f,
+                "backend {} disagrees with the reference on",
+                self.backend
+            )?,
+            (None, Ok(_)) => writeln!(f, "the reference does not fail on")?,
+            (None, Err(_)) => writeln!(f, "the reference fails on")?,
+        }
+        for form in &self.program {
+            writeln!(f, "    {}", form)?;
+        }
+        if let Some(expected) = &self.expected {
+            writeln!(f, "expected: {:?}", expected)?;
+        }
+        write!(f, "actual:   {:?}", self.actual)
+    }
+}
+
+/// Run a program that is expected to succeed on the reference and on each backend.
+pub fn compare(
+    program: &[Object],
+    backends: &mut [Box<dyn Backend>],
+) -> std::result::Result<(), Box<Mismatch>> {
+    compare_outcomes(program, false, backends)
+}
+
+/// Run a program that is expected to fail with an error on the reference and on each backend.
+/// The error messages may differ.
+pub fn compare_failing(
+    program: &[Object],
+    backends: &mut [Box<dyn Backend>],
+) -> std::result::Result<(), Box<Mismatch>> {
+    compare_outcomes(program, true, backends)
+}
+
+fn compare_outcomes(
+    program: &[Object],
+    expect_error: bool,
+    backends: &mut [Box<dyn Backend>],
+) -> std::result::Result<(), Box<Mismatch>> {
+    let run = |backend: &mut dyn Backend| {
+        backend
+            .run(program)
+            .map_err(|err| format!("{:?}", err.kind()))
+    };
+    let expected = run(&mut Reference);
+    if expected.is_err() != expect_error {
+        return Err(Box::new(Mismatch {
+            backend: Reference.name(),
+            program: program.to_vec(),
+            expected: None,
+            actual: expected,
+        }));
+    }
+    for backend in backends {
+        if !backend.supports(program) {
+            continue;
+        }
+        let actual = run(backend.as_mut());
+        let agree = match (&expected, &actual) {
+            (Ok(expected), Ok(actual)) => expected == actual,
+            (Err(_), Err(_)) => true,
+            _ => false,
+        };
+        if !agree {
+            return Err(Box::new(Mismatch {
+                backend: backend.name(),
+                program: program.to_vec(),
+                expected: Some(expected),
+                actual,
+            }));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+
+    const CORPUS: &[&[&str]] = &[
+        &["(+ 1 2)"],
+        &["(define (square x) (* x x))", "(display (square 7))", "(square 3)"],
+        &[
+            "(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))",
+            "(fact 10)",
+        ],
+        &[
+            "(define (make-counter) (let ((n 0)) (lambda () (set! n (+ n 1)) n)))",
+            "(define c (make-counter))",
+            "(c)",
+            "(c)",
+        ],
+        &["(let loop ((i 0) (acc '())) (if (= i 5) (reverse acc) (loop (+ i 1) (cons (* i i) acc))))"],
+        &["(let ((x 1)) (let ((f (lambda (y) (+ x y)))) (let ((x 10)) (f x))))"],
+        &[
+            "(define x 1)",
+            "(define (get-x) x)",
+            "(set! x 2)",
+            "(list x (get-x))",
+        ],
+        &[
+            "(define n 0)",
+            "(define (count-down i) (if (< i 1) n (begin (set! n (+ n i)) (count-down (- i 1)))))",
+            "(count-down 100)",
+        ],
+        &["(define (between? a x b) (< a x b))", "(if (between? 1 2 3) 'yes 'no)"],
+        &[
+            "(define x 1)",
+            "(define (bump) (set! x 5))",
+            "(define (f y) (bump) y)",
+            "(f x)",
+        ],
+        &["(do ((i 0 (+ i 1)) (s 0 (+ s i))) ((= i 4) s) (display i))"],
+        &["(let* ((x 2) (y (* x 3))) (cond ((> x y) 'bigger) ((= x y) 'same) (else (list x y))))"],
+        &["((lambda (a) (case 'a ((a) 1) (else 2))) 0)"],
+        &["((lambda (x) (quasiquote (x (unquote x)))) 1)"],
+        &["(case (+ 1 2) ((1 2) 'low) ((3 4) 'mid) (else 'high))"],
+        &["(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1))))) (odd? (lambda (n) (if (= n 0) #f (even? (- n 1)))))) (even? 1001))"],
+        &["(define (compose f g) (lambda (x) (f (g x))))", "((compose car cdr) '(1 2 3))"],
+        &["(begin (write \"a\") (newline) (display \"b\") 'done)"],
+        &["(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))"],
+        &["(let ((p (delay (begin (display 'once) 42)))) (+ (force p) (force p)))"],
+        &["((lambda (a) (let-values (((a b) (values 2 a))) (list a b))) 1)"],
+    ];
+
+    /// Programs that fail with an error.
+    const FAILING_CORPUS: &[&[&str]] = &[
+        &["(car '())"],
+        &["(display 1)", "(undefined-variable)"],
+        &["(define (f x) x)", "(f 1 2)"],
+        &["(define (f x) x)", "(define (g) (f))", "(g)"],
+        &["(define (g) (5))", "(g)"],
+        &["(raise 'oops)"],
+    ];
+
+    fn parse_program(sources: &[&str]) -> Vec<Object> {
+        sources.iter().map(|s| parse_datum(s).unwrap()).collect()
+    }
+
+    fn backends() -> Vec<Box<dyn Backend>> {
+        let mut backends: Vec<Box<dyn Backend>> = Compiled::all_passes()
+            .prefixes()
+            .into_iter()
+            .map(|backend| Box::new(backend) as Box<dyn Backend>)
+            .collect();
+        // Cranelift 0.30 only generates code for x86 here
+        #[cfg(target_arch = "x86_64")]
+        backends.push(Box::new(Jit));
+        backends
+    }
+
+    #[test]
+    fn corpus_agrees() {
+        let mut backends = backends();
+        for sources in CORPUS {
+            if let Err(mismatch) = compare(&parse_program(sources), &mut backends) {
+                panic!("{}", mismatch);
+            }
+        }
+    }
+
+    #[test]
+    fn failing_corpus_agrees() {
+        let mut backends = backends();
+        for sources in FAILING_CORPUS {
+            if let Err(mismatch) = compare_failing(&parse_program(sources), &mut backends) {
+                panic!("{}", mismatch);
+            }
+        }
+    }
+
+    #[test]
+    fn generated_programs_agree() {
+        let mut backends = backends();
+        for seed in 0..300 {
+            let program = ProgramGenerator::new(seed).generate();
+            if let Err(mismatch) = compare(&program, &mut backends) {
+                panic!("seed {}: {}", seed, mismatch);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn jit_runs_part_of_the_corpus() {
+        let supported: Vec<_> = CORPUS
+            .iter()
+            .map(|sources| parse_program(sources))
+            .filter(|program| Jit.supports(program))
+            .collect();
+        assert!(supported.len() >= 4);
+        assert!(!Jit.supports(&parse_program(&["(car '())"])));
+        assert_eq!(
+            Jit.run(&parse_program(CORPUS[2])).unwrap().value,
+            Some(Object::integer(3628800))
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn generated_programs_agree_with_the_jit() {
+        let mut backends: Vec<Box<dyn Backend>> = vec![Box::new(Jit)];
+        for seed in 0..100 {
+            let program = ProgramGenerator::new(seed).jit_subset().generate();
+            assert!(Jit.supports(&program), "seed {}", seed);
+            if let Err(mismatch) = compare(&program, &mut backends) {
+                panic!("seed {}: {}", seed, mismatch);
+            }
+        }
+    }
+
+    #[test]
+    fn generated_programs_run_without_errors() {
+        for seed in 0..100 {
+            let program = ProgramGenerator::new(seed).generate();
+            if let Err(err) = Reference.run(&program) {
+                let forms: Vec<_> = program.iter().map(Object::to_string).collect();
+                panic!("seed {}: {:?}\n{}", seed, err, forms.join("\n"));
+            }
+        }
+    }
+
+    #[test]
+    fn generated_programs_are_reproducible() {
+        assert_eq!(
+            ProgramGenerator::new(42).generate(),
+            ProgramGenerator::new(42).generate()
+        );
+        assert_ne!(
+            ProgramGenerator::new(1).generate(),
+            ProgramGenerator::new(2).generate()
+        );
+    }
+
+    #[test]
+    fn mismatches_are_detected() {
+        let mut backends: Vec<Box<dyn Backend>> =
+            vec![Box::new(Compiled::new().with_pass("broken", |program| {
+                Ok(program
+                    .into_iter()
+                    .map(|_| Expression::Integer(0))
+                    .collect())
+            }))];
+        let mismatch = compare(&parse_program(&["(+ 1 2)"]), &mut backends).unwrap_err();
+        assert_eq!(mismatch.backend, "front end + [broken]");
+        assert_eq!(
+            mismatch.expected.unwrap().unwrap().value,
+            Some(Object::integer(3))
+        );
+        assert_eq!(mismatch.actual.unwrap().value, Some(Object::integer(0)));
+    }
+
+    #[test]
+    fn unexpected_errors_of_the_reference_are_detected() {
+        let mismatch = compare(&parse_program(&["(car '())"]), &mut []).unwrap_err();
+        assert_eq!(mismatch.backend, "reference");
+        assert!(mismatch.expected.is_none());
+        assert!(mismatch.actual.is_err());
+        let mismatch = compare_failing(&parse_program(&["(+ 1 2)"]), &mut []).unwrap_err();
+        assert!(mismatch.actual.is_ok());
+    }
+}
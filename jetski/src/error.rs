@@ -17,6 +17,16 @@ pub enum ErrorKind {
 
     NotAPair(Object),
     SyntaxError(String),
+    ValidationError(String),
+    RuntimeError(String),
+    CodegenError(String),
+    IoError(std::io::Error),
+}
+
+impl Error {
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -30,3 +40,9 @@ impl From<PestError<Rule>> for Error {
         ErrorKind::PestError(pe).into()
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        ErrorKind::IoError(e).into()
+    }
+}
@@ -0,0 +1,666 @@
+//! Reference interpreter
+//! Evaluates `core_scheme::Expression` directly. It defines the meaning of programs when
+//! testing the compiler, and runs them on hosts where the JIT is not available.
+//!
+//! Expressions from any stage of the pipeline can be evaluated, including the closure records
+//! and boxes introduced by closure conversion and assignment conversion. Calls in tail position
+//! do not grow the stack; other calls nest at most `MAX_DEPTH` deep, on a stack that is extended
+//! on the heap as needed. Continuations are escape-only: they can only be invoked while the
+//! `call/cc` that captured them is still active.
+
+mod primitives;
+mod value;
+
+pub use primitives::{is_equal, is_eqv, Primitive, PRIMITIVES};
+pub use value::{ClosureRecord, ErrorObject, NativeFn, NativeProcedure, Pair, Procedure, Value};
+
+use crate::core_scheme::Expression;
+use crate::error::{Error, ErrorKind, Result};
+use crate::object::Object;
+use crate::runtime::Symbol;
+use crate::transformations::desugar::Desugar;
+use crate::transformations::SourceTransformer;
+use primitives::make_error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Local variables: a chain of frames. Variables not found in any frame are global.
+type Env = Option<Rc<Frame>>;
+
+struct Frame {
+    vars: RefCell<Vec<(Symbol, Value)>>,
+    parent: Env,
+}
+
+fn extend(env: &Env, vars: Vec<(Symbol, Value)>) -> Env {
+    Some(Rc::new(Frame {
+        vars: RefCell::new(vars),
+        parent: env.clone(),
+    }))
+}
+
+/// Ways in which evaluation is aborted.
+enum Condition {
+    /// An error signalled by a primitive, which is yet to be passed to the exception handler.
+    Error(Value),
+    /// A raised object that no handler dealt with.
+    Raise(Value),
+    /// A continuation was invoked; unwind to the `call/cc` that captured it.
+    Escape(usize, Value),
+}
+
+type Eval<T = Value> = std::result::Result<T, Condition>;
+
+/// Maximum nesting of expressions that are not in tail position.
+const MAX_DEPTH: usize = 10_000;
+
+/// Evaluation continues on a new stack segment of `STACK_SEGMENT` bytes when less than
+/// `RED_ZONE` bytes are left. A nested evaluation takes about 10K of stack in debug builds.
+const RED_ZONE: usize = 64 * 1024;
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Expressions are evaluated up to the call in tail position, which is left to the caller.
+enum Step {
+    Return(Value),
+    Call(Value, Vec<Value>),
+}
+
+pub struct Interpreter {
+    globals: HashMap<Symbol, Value>,
+    handlers: Vec<Value>,
+    output: String,
+    next_continuation: usize,
+    desugar: Desugar,
+    depth: usize,
+    /// Procedures don't capture the local variables around them, as in closure-converted code.
+    closed_procedures: bool,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the primitive library in its global environment.
+    pub fn new() -> Self {
+        Interpreter {
+            globals: PRIMITIVES
+                .iter()
+                .map(|p| (Symbol::new(p.name), Value::Primitive(p)))
+                .collect(),
+            handlers: vec![],
+            output: String::new(),
+            next_continuation: 0,
+            desugar: Desugar::new(),
+            depth: 0,
+            closed_procedures: false,
+        }
+    }
+
+    /// An interpreter for closure-converted code, where procedures get at the variables they
+    /// capture through their closure record. Procedures don't close over the local variables
+    /// around them, so references to variables that closure conversion missed are errors.
+    pub fn with_closed_procedures(mut self) -> Self {
+        self.closed_procedures = true;
+        self
+    }
+
+    /// Evaluate a toplevel expression or definition.
+    pub fn eval(&mut self, expr: &Expression) -> Result<Value> {
+        let result = self.eval_in(expr, &None);
+        result.map_err(uncaught)
+    }
+
+    /// Call a procedure from Rust.
+    pub fn apply_procedure(&mut self, proc: Value, args: Vec<Value>) -> Result<Value> {
+        let result = self.apply(proc, args);
+        result.map_err(uncaught)
+    }
+
+    /// Evaluate the toplevel forms of a program in order, returning the value of the last.
+    pub fn eval_program(&mut self, program: &[Expression]) -> Result<Value> {
+        let mut result = Value::Unspecified;
+        for expr in program {
+            result = self.eval(expr)?;
+        }
+        Ok(result)
+    }
+
+    /// Evaluate a toplevel form of the full language, which is desugared first.
+    pub fn eval_datum(&mut self, datum: &Object) -> Result<Value> {
+        let core = self.desugar.transform(datum)?;
+        self.eval(&Expression::try_from(&core)?)
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.globals.insert(name, value);
+    }
+
+    pub fn global(&self, name: Symbol) -> Option<&Value> {
+        self.globals.get(&name)
+    }
+
+    /// Everything written by `display`, `write` and `newline` since the last call.
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Evaluate a nested expression. All recursion of the interpreter passes through here.
+    fn eval_in(&mut self, expr: &Expression, env: &Env) -> Eval {
+        if self.depth >= MAX_DEPTH {
+            return self.error("recursion too deep", vec![]);
+        }
+        self.depth += 1;
+        let result = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+            match self.eval_tail(expr, env)? {
+                Step::Return(value) => Ok(value),
+                Step::Call(proc, args) => self.apply(proc, args),
+            }
+        });
+        self.depth -= 1;
+        result
+    }
+
+    /// Evaluate expressions in tail position in a loop, without recursion.
+    fn eval_tail(&mut self, mut expr: &Expression, env: &Env) -> Eval<Step> {
+        use Expression::*;
+        let mut env = env.clone();
+        loop {
+            let value = match expr {
+                Undef => Value::Unspecified,
+                Nil => Value::Nil,
+                Integer(i) => Value::Integer(*i),
+                Float(x) => Value::Float(*x),
+                String(s) => Value::string(s),
+                Quote(datum) => Value::from(datum),
+                Variable(var) => self.lookup(*var, &env)?,
+                Lambda(params, body) => make_procedure(params, body, self.closure_env(&env)),
+                Primitive => return self.error("cannot evaluate primitive", vec![]),
+
+                Let(var, init, body) => {
+                    let value = self.eval_in(init, &env)?;
+                    env = extend(&env, vec![(*var, value)]);
+                    expr = body;
+                    continue;
+                }
+                If(cond, yes, no) => {
+                    expr = if self.eval_in(cond, &env)?.is_true() {
+                        yes
+                    } else {
+                        no
+                    };
+                    continue;
+                }
+                Begin(exprs) => {
+                    let (last, init) = exprs.split_last().expect("empty begin");
+                    for x in init {
+                        self.eval_in(x, &env)?;
+                    }
+                    expr = last;
+                    continue;
+                }
+                Apply(proc, args) => {
+                    let (proc, skip) = match &**proc {
+                        // Closure conversion treats global procedures like closure records,
+                        // which they are not. They are called without the record instead.
+                        ClosureCode(closure) => match self.eval_in(closure, &env)? {
+                            Value::Closure(record) => (record.code.clone(), 0),
+                            value if value.is_procedure() => (value, 1),
+                            value => return self.error("not a closure", vec![value]),
+                        },
+                        proc => (self.eval_in(proc, &env)?, 0),
+                    };
+                    let args = args
+                        .iter()
+                        .skip(skip)
+                        .map(|x| self.eval_in(x, &env))
+                        .collect::<Eval<_>>()?;
+                    return Ok(Step::Call(proc, args));
+                }
+                Set(var, value) => {
+                    let value = self.eval_in(value, &env)?;
+                    self.assign(*var, value, &env)?;
+                    Value::Unspecified
+                }
+
+                DefVar(name, value) => {
+                    let value = self.eval_in(value, &env)?;
+                    self.bind(*name, value, &env);
+                    Value::Unspecified
+                }
+                DeFunc(name, params, body) => {
+                    let value = make_procedure(params, body, self.closure_env(&env));
+                    self.bind(*name, value, &env);
+                    Value::Unspecified
+                }
+
+                Closure(code, captured) => Value::Closure(Rc::new(ClosureRecord {
+                    code: self.eval_in(code, &env)?,
+                    captured: captured
+                        .iter()
+                        .map(|x| self.eval_in(x, &env))
+                        .collect::<Eval<_>>()?,
+                })),
+                ClosureCode(closure) => match self.eval_in(closure, &env)? {
+                    Value::Closure(record) => record.code.clone(),
+                    value => return self.error("not a closure", vec![value]),
+                },
+                EnvRef(closure, idx) => match self.eval_in(closure, &env)? {
+                    Value::Closure(ref record) if *idx < record.captured.len() => {
+                        record.captured[*idx].clone()
+                    }
+                    value => {
+                        let idx = Value::Integer(*idx as i64);
+                        return self.error("invalid closure slot", vec![value, idx]);
+                    }
+                },
+
+                MakeBox(value) => Value::Box(Rc::new(RefCell::new(self.eval_in(value, &env)?))),
+                BoxRef(b) => match self.eval_in(b, &env)? {
+                    Value::Box(b) => b.borrow().clone(),
+                    value => return self.error("not a box", vec![value]),
+                },
+                BoxSet(b, value) => {
+                    let b = self.eval_in(b, &env)?;
+                    let value = self.eval_in(value, &env)?;
+                    match b {
+                        Value::Box(b) => *b.borrow_mut() = value,
+                        b => return self.error("not a box", vec![b]),
+                    }
+                    Value::Unspecified
+                }
+            };
+            return Ok(Step::Return(value));
+        }
+    }
+
+    /// The environment that new procedures close over.
+    fn closure_env<'a>(&self, env: &'a Env) -> &'a Env {
+        if self.closed_procedures {
+            &None
+        } else {
+            env
+        }
+    }
+
+    /// Apply a procedure. Calls in tail position of procedure bodies are performed here, in a
+    /// loop, which keeps the stack from growing.
+    fn apply(&mut self, mut proc: Value, mut args: Vec<Value>) -> Eval {
+        loop {
+            match proc {
+                Value::Procedure(p) => {
+                    if p.params.len() != args.len() {
+                        let irritants = vec![Value::Procedure(p), Value::list(args)];
+                        return self.error("wrong number of arguments", irritants);
+                    }
+                    let env = extend(&p.env, p.params.iter().cloned().zip(args).collect());
+                    match self.eval_tail(&p.body, &env)? {
+                        Step::Return(value) => return Ok(value),
+                        Step::Call(next, next_args) => {
+                            proc = next;
+                            args = next_args;
+                        }
+                    }
+                }
+                // closure records are passed to their code
+                Value::Closure(record) => {
+                    proc = record.code.clone();
+                    args.insert(0, Value::Closure(record));
+                }
+                Value::Primitive(p) => {
+                    return match p.call(self, args) {
+                        Err(Condition::Error(obj)) => Err(self.raise(obj)),
+                        result => result,
+                    };
+                }
+                Value::Native(p) => {
+                    return match (p.func)(&args) {
+                        Ok(value) => Ok(value),
+                        Err(message) => self.error(&format!("{}: {}", p.name, message), args),
+                    };
+                }
+                Value::Continuation(id) => {
+                    return Err(Condition::Escape(id, primitives::values(args)));
+                }
+                Value::Parameter(param) => return self.apply_parameter(&param, args),
+                value => return self.error("not a procedure", vec![value]),
+            }
+        }
+    }
+
+    /// Parameter objects follow the protocol expected by the desugaring of `parameterize`.
+    fn apply_parameter(&mut self, param: &RefCell<(Value, Value)>, args: Vec<Value>) -> Eval {
+        match args.as_slice() {
+            [] => Ok(param.borrow().0.clone()),
+            [Value::Symbol(s)] if s.name() == "<param-convert>" => Ok(param.borrow().1.clone()),
+            [Value::Symbol(s), value] if s.name() == "<param-set!>" => {
+                param.borrow_mut().0 = value.clone();
+                Ok(Value::Unspecified)
+            }
+            _ => self.error("invalid parameter application", args),
+        }
+    }
+
+    /// Pass a raised object to the current handler, which runs with the outer handlers
+    /// installed. If the handler returns, a secondary exception is raised.
+    fn raise(&mut self, obj: Value) -> Condition {
+        match self.handlers.pop() {
+            None => Condition::Raise(obj),
+            Some(handler) => {
+                let condition = match self.apply(handler.clone(), vec![obj.clone()]) {
+                    Ok(_) => self.raise(make_error("exception handler returned", vec![obj])),
+                    Err(condition) => condition,
+                };
+                self.handlers.push(handler);
+                condition
+            }
+        }
+    }
+
+    fn raise_continuable(&mut self, obj: Value) -> Eval {
+        match self.handlers.pop() {
+            None => Err(Condition::Raise(obj)),
+            Some(handler) => {
+                let result = self.apply(handler.clone(), vec![obj]);
+                self.handlers.push(handler);
+                result
+            }
+        }
+    }
+
+    fn error<T>(&mut self, message: &str, irritants: Vec<Value>) -> Eval<T> {
+        Err(self.raise(make_error(message, irritants)))
+    }
+
+    fn lookup(&mut self, var: Symbol, env: &Env) -> Eval {
+        let mut frame = env.as_ref();
+        while let Some(f) = frame {
+            if let Some((_, value)) = f.vars.borrow().iter().find(|(v, _)| *v == var) {
+                return Ok(value.clone());
+            }
+            frame = f.parent.as_ref();
+        }
+        match self.globals.get(&var) {
+            Some(value) => Ok(value.clone()),
+            None => self.error("unbound variable", vec![Value::Symbol(var)]),
+        }
+    }
+
+    fn assign(&mut self, var: Symbol, value: Value, env: &Env) -> Eval<()> {
+        let mut frame = env.as_ref();
+        while let Some(f) = frame {
+            if let Some(entry) = f.vars.borrow_mut().iter_mut().find(|(v, _)| *v == var) {
+                entry.1 = value;
+                return Ok(());
+            }
+            frame = f.parent.as_ref();
+        }
+        match self.globals.get_mut(&var) {
+            Some(entry) => {
+                *entry = value;
+                Ok(())
+            }
+            None => self.error("unbound variable", vec![Value::Symbol(var)]),
+        }
+    }
+
+    /// Definitions in a body are local to the innermost frame.
+    fn bind(&mut self, var: Symbol, value: Value, env: &Env) {
+        match env {
+            None => self.define(var, value),
+            Some(frame) => {
+                let mut vars = frame.vars.borrow_mut();
+                match vars.iter_mut().find(|(v, _)| *v == var) {
+                    Some(entry) => entry.1 = value,
+                    None => vars.push((var, value)),
+                }
+            }
+        }
+    }
+}
+
+/// The error for a condition that reached the toplevel.
+fn uncaught(condition: Condition) -> Error {
+    let message = match condition {
+        Condition::Error(obj) | Condition::Raise(obj) => match obj {
+            Value::Error(_) => obj.to_string(),
+            _ => format!("uncaught exception: {}", obj),
+        },
+        Condition::Escape(_, _) => "continuation invoked outside of its dynamic extent".to_string(),
+    };
+    ErrorKind::RuntimeError(message).into()
+}
+
+fn make_procedure(params: &[Symbol], body: &Expression, env: &Env) -> Value {
+    Value::Procedure(Rc::new(Procedure {
+        params: params.to_vec(),
+        body: body.clone(),
+        env: env.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+
+    fn run(sources: &[&str]) -> Result<Value> {
+        let mut interp = Interpreter::new();
+        let mut result = Value::Unspecified;
+        for source in sources {
+            result = interp.eval_datum(&parse_datum(source).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    fn eval(source: &str) -> String {
+        run(&[source]).unwrap().to_string()
+    }
+
+    fn error_message(sources: &[&str]) -> String {
+        match run(sources).unwrap_err().kind() {
+            ErrorKind::RuntimeError(msg) => msg.clone(),
+            kind => panic!("not a runtime error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn constants_and_primitives() {
+        assert_eq!(eval("42"), "42");
+        assert_eq!(eval("\"text\""), "\"text\"");
+        assert_eq!(eval("'(1 (2 . 3) #(4 x))"), "(1 (2 . 3) #(4 x))");
+        assert_eq!(eval("(+ 1 2 (* 3 4))"), "15");
+        assert_eq!(eval("(- 10 1.5)"), "8.5");
+        assert_eq!(eval("(/ 12 4)"), "3");
+        assert_eq!(eval("(< 1 2 3)"), "#t");
+        assert_eq!(eval("(modulo -7 2)"), "1");
+        assert_eq!(eval("(append '(1 2) '(3) '() 4)"), "(1 2 3 . 4)");
+        assert_eq!(eval("(assv 2 '((1 . a) (2 . b)))"), "(2 . b)");
+        assert_eq!(eval("(equal? (list 1 \"a\") (list 1 \"a\"))"), "#t");
+        assert_eq!(eval("(eq? (list 1) (list 1))"), "#f");
+    }
+
+    #[test]
+    fn mixed_comparisons_are_exact() {
+        assert_eq!(eval("(< 9223372036854775807 9223372036854775808.0)"), "#t");
+        assert_eq!(eval("(= 9223372036854775807 9223372036854775808.0)"), "#f");
+        assert_eq!(eval("(> 9007199254740993 9007199254740992.0)"), "#t");
+        assert_eq!(eval("(= 1 1.0)"), "#t");
+    }
+
+    #[test]
+    fn closures_capture_their_environment() {
+        assert_eq!(eval("(((lambda (x) (lambda (y) (+ x y))) 1) 2)"), "3");
+        let counter = [
+            "(define (make-counter) (let ((n 0)) (lambda () (set! n (+ n 1)) n)))",
+            "(define c (make-counter))",
+            "(c)",
+            "(c)",
+        ];
+        assert_eq!(run(&counter).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn derived_expressions() {
+        assert_eq!(
+            eval("(let loop ((i 0) (acc '())) (if (= i 3) acc (loop (+ i 1) (cons i acc))))"),
+            "(2 1 0)"
+        );
+        assert_eq!(
+            eval("(do ((vec (make-vector 3)) (i 0 (+ i 1))) ((= i 3) vec) (vector-set! vec i i))"),
+            "#(0 1 2)"
+        );
+        assert_eq!(
+            eval("(case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite))"),
+            "composite"
+        );
+        assert_eq!(
+            eval("(let* ((x 1) (y (+ x 1))) (and (< x y) (or #f y)))"),
+            "2"
+        );
+        assert_eq!(
+            eval("(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1))))) (odd? (lambda (n) (if (= n 0) #f (even? (- n 1)))))) (even? 100))"),
+            "#t"
+        );
+        assert_eq!(eval("(force (delay (+ 1 2)))"), "3");
+        assert_eq!(
+            eval("(let ((p (make-parameter 1))) (list (parameterize ((p 2)) (p)) (p)))"),
+            "(2 1)"
+        );
+    }
+
+    #[test]
+    fn internal_definitions_are_local() {
+        let program = [
+            "(define x 1)",
+            "(define (f) (define x 2) (define (g) x) (g))",
+            "(list (f) x)",
+        ];
+        assert_eq!(run(&program).unwrap().to_string(), "(2 1)");
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_space() {
+        let program = [
+            "(define (count n) (if (= n 0) 'done (count (- n 1))))",
+            "(count 1000000)",
+        ];
+        assert_eq!(run(&program).unwrap().to_string(), "done");
+    }
+
+    #[test]
+    fn deep_recursion_is_an_error() {
+        let program = [
+            "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))",
+            "(f 1000)",
+        ];
+        assert_eq!(run(&program).unwrap().to_string(), "1000");
+        assert_eq!(
+            error_message(&[program[0], "(f 1000000)"]),
+            "recursion too deep"
+        );
+    }
+
+    #[test]
+    fn output_is_collected() {
+        let mut interp = Interpreter::new();
+        let program = "(begin (display \"x:\") (write \"y\") (newline) (display '(1 \"z\")))";
+        interp.eval_datum(&parse_datum(program).unwrap()).unwrap();
+        assert_eq!(interp.take_output(), "x:\"y\"\n(1 z)");
+        assert_eq!(interp.take_output(), "");
+    }
+
+    #[test]
+    fn escaping_continuations() {
+        assert_eq!(eval("(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))"), "3");
+        assert_eq!(
+            eval("(call-with-values (lambda () (values 1 2)) (lambda (a b) (list a b)))"),
+            "(1 2)"
+        );
+        let program = [
+            "(define out '())",
+            "(call/cc (lambda (k) (dynamic-wind (lambda () (set! out (cons 'in out))) (lambda () (k 0)) (lambda () (set! out (cons 'out out))))))",
+            "out",
+        ];
+        assert_eq!(run(&program).unwrap().to_string(), "(out in)");
+        let program = [
+            "(define k2 #f)",
+            "(call/cc (lambda (k) (set! k2 k)))",
+            "(k2 1)",
+        ];
+        assert_eq!(
+            error_message(&program),
+            "continuation invoked outside of its dynamic extent"
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        assert_eq!(
+            eval("(with-exception-handler (lambda (e) 10) (lambda () (+ 1 (raise-continuable 'oops))))"),
+            "11"
+        );
+        assert_eq!(
+            eval("(call/cc (lambda (k) (with-exception-handler (lambda (e) (k (error-object-message e))) (lambda () (car 1)))))"),
+            "\"car: not a pair\""
+        );
+        assert_eq!(
+            error_message(&["(raise 'oops)"]),
+            "uncaught exception: oops"
+        );
+        assert_eq!(
+            error_message(&["(error \"bad thing:\" 1 \"x\")"]),
+            "bad thing: 1 \"x\""
+        );
+        assert_eq!(
+            error_message(&["(undefined-variable)"]),
+            "unbound variable undefined-variable"
+        );
+        assert_eq!(error_message(&["(/ 1 0)"]), "/: division by zero");
+        assert_eq!(
+            error_message(&["(with-exception-handler (lambda (e) 0) (lambda () (raise 'oops)))"]),
+            "exception handler returned oops"
+        );
+    }
+
+    #[test]
+    fn closure_converted_code() {
+        use crate::core_scheme::closure_conversion::ClosureConversion;
+        let expr = Expression::try_from(
+            parse_datum("(((lambda (x) (lambda (y) (cons x y))) 1) 2)").unwrap(),
+        )
+        .unwrap();
+        let converted = ClosureConversion::new().convert(&expr).unwrap();
+        let mut interp = Interpreter::new().with_closed_procedures();
+        assert_eq!(interp.eval(&converted).unwrap().to_string(), "(1 . 2)");
+        // without closure conversion, the inner procedure can't get at x
+        let mut interp = Interpreter::new().with_closed_procedures();
+        match interp.eval(&expr).unwrap_err().kind() {
+            ErrorKind::RuntimeError(msg) => assert_eq!(msg, "unbound variable x"),
+            kind => panic!("not a runtime error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn native_procedures() {
+        let mut interp = Interpreter::new();
+        let double = Value::native("double", |args| match args {
+            [Value::Integer(i)] => Ok(Value::Integer(2 * i)),
+            _ => Err("expected an integer".to_string()),
+        });
+        interp.define(Symbol::new("double"), double.clone());
+        let value = interp.eval_datum(&parse_datum("(double 21)").unwrap());
+        assert_eq!(value.unwrap().to_string(), "42");
+
+        let value = interp.apply_procedure(double.clone(), vec![Value::Integer(4)]);
+        assert_eq!(value.unwrap().to_string(), "8");
+        match interp.apply_procedure(double, vec![]).unwrap_err().kind() {
+            ErrorKind::RuntimeError(msg) => assert_eq!(msg, "double: expected an integer"),
+            kind => panic!("not a runtime error: {:?}", kind),
+        }
+    }
+}
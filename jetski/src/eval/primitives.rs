@@ -0,0 +1,683 @@
+//! The primitive library
+//! Errors signalled here are passed to the current exception handler by the interpreter, so
+//! the primitives need not know about handlers.
+
+use super::value::{ErrorObject, Pair, Value};
+use super::{Condition, Eval, Interpreter};
+use crate::runtime::{compare_integer_float, Symbol};
+use std::cmp::Ordering;
+use std::rc::Rc;
+use Arity::*;
+
+pub struct Primitive {
+    pub name: &'static str,
+    arity: Arity,
+    func: fn(&mut Interpreter, Vec<Value>) -> Eval,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Primitive {
+    pub(super) fn call(&self, interp: &mut Interpreter, args: Vec<Value>) -> Eval {
+        let accepted = match self.arity {
+            Exactly(n) => args.len() == n,
+            AtLeast(n) => args.len() >= n,
+            Between(min, max) => args.len() >= min && args.len() <= max,
+        };
+        if accepted {
+            (self.func)(interp, args)
+        } else {
+            Err(error(
+                &format!("{}: wrong number of arguments", self.name),
+                args,
+            ))
+        }
+    }
+}
+
+macro_rules! primitive_table {
+    ($($name:expr, $arity:expr, $func:expr;)*) => {
+        &[$(Primitive { name: $name, arity: $arity, func: $func }),*]
+    };
+}
+
+pub static PRIMITIVES: &[Primitive] = primitive_table! {
+    "+", AtLeast(0), add;
+    "-", AtLeast(1), sub;
+    "*", AtLeast(0), mul;
+    "/", AtLeast(1), div;
+    "=", AtLeast(1), |_, args| compare("=", &args, |ord| ord == Ordering::Equal);
+    "<", AtLeast(1), |_, args| compare("<", &args, |ord| ord == Ordering::Less);
+    ">", AtLeast(1), |_, args| compare(">", &args, |ord| ord == Ordering::Greater);
+    "<=", AtLeast(1), |_, args| compare("<=", &args, |ord| ord != Ordering::Greater);
+    ">=", AtLeast(1), |_, args| compare(">=", &args, |ord| ord != Ordering::Less);
+    "quotient", Exactly(2), |_, args| integer_division("quotient", &args, quotient);
+    "remainder", Exactly(2), |_, args| integer_division("remainder", &args, i64::checked_rem);
+    "modulo", Exactly(2), |_, args| integer_division("modulo", &args, modulo);
+    "abs", Exactly(1), abs;
+    "min", AtLeast(1), |_, args| extremum("min", &args, Ordering::Less);
+    "max", AtLeast(1), |_, args| extremum("max", &args, Ordering::Greater);
+    "number?", Exactly(1), |_, args| Ok(Value::Boolean(is_number(&args[0])));
+    "integer?", Exactly(1), |_, args| Ok(Value::Boolean(is_integer(&args[0])));
+    "zero?", Exactly(1), |_, args| sign("zero?", &args[0], |ord| ord == Ordering::Equal);
+    "positive?", Exactly(1), |_, args| sign("positive?", &args[0], |ord| ord == Ordering::Greater);
+    "negative?", Exactly(1), |_, args| sign("negative?", &args[0], |ord| ord == Ordering::Less);
+    "even?", Exactly(1), |_, args| Ok(Value::Boolean(integer("even?", &args[0])? % 2 == 0));
+    "odd?", Exactly(1), |_, args| Ok(Value::Boolean(integer("odd?", &args[0])? % 2 != 0));
+    "exact", Exactly(1), exact;
+    "inexact", Exactly(1), inexact;
+    "exact->inexact", Exactly(1), inexact;
+    "inexact->exact", Exactly(1), exact;
+    "number->string", Exactly(1), number_to_string;
+
+    "not", Exactly(1), |_, args| Ok(Value::Boolean(!args[0].is_true()));
+    "eq?", Exactly(2), |_, args| Ok(Value::Boolean(is_eqv(&args[0], &args[1])));
+    "eqv?", Exactly(2), |_, args| Ok(Value::Boolean(is_eqv(&args[0], &args[1])));
+    "equal?", Exactly(2), |_, args| Ok(Value::Boolean(is_equal(&args[0], &args[1])));
+    "boolean?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Boolean(_))));
+    "symbol?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Symbol(_))));
+    "string?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::String(_))));
+    "procedure?", Exactly(1), |_, args| Ok(Value::Boolean(args[0].is_procedure()));
+
+    "cons", Exactly(2), |_, mut args| { let cdr = args.pop().unwrap(); Ok(Value::cons(args.pop().unwrap(), cdr)) };
+    "car", Exactly(1), |_, args| Ok(pair("car", &args[0])?.car.borrow().clone());
+    "cdr", Exactly(1), |_, args| Ok(pair("cdr", &args[0])?.cdr.borrow().clone());
+    "set-car!", Exactly(2), |_, args| { *pair("set-car!", &args[0])?.car.borrow_mut() = args[1].clone(); Ok(Value::Unspecified) };
+    "set-cdr!", Exactly(2), |_, args| { *pair("set-cdr!", &args[0])?.cdr.borrow_mut() = args[1].clone(); Ok(Value::Unspecified) };
+    "caar", Exactly(1), |_, args| Ok(pair("caar", &pair("caar", &args[0])?.car.borrow())?.car.borrow().clone());
+    "cadr", Exactly(1), |_, args| Ok(pair("cadr", &pair("cadr", &args[0])?.cdr.borrow())?.car.borrow().clone());
+    "cdar", Exactly(1), |_, args| Ok(pair("cdar", &pair("cdar", &args[0])?.car.borrow())?.cdr.borrow().clone());
+    "cddr", Exactly(1), |_, args| Ok(pair("cddr", &pair("cddr", &args[0])?.cdr.borrow())?.cdr.borrow().clone());
+    "null?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Nil)));
+    "pair?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Pair(_))));
+    "list?", Exactly(1), |_, args| Ok(Value::Boolean(args[0].list_to_vec().is_some()));
+    "list", AtLeast(0), |_, args| Ok(Value::list(args));
+    "length", Exactly(1), |_, args| Ok(Value::Integer(list("length", &args[0])?.len() as i64));
+    "append", AtLeast(0), append;
+    "reverse", Exactly(1), |_, args| Ok(Value::list(list("reverse", &args[0])?.into_iter().rev().collect()));
+    "list-tail", Exactly(2), list_tail;
+    "list-ref", Exactly(2), |interp, args| Ok(pair("list-ref", &list_tail(interp, args)?)?.car.borrow().clone());
+    "memq", Exactly(2), |_, args| member("memq", &args, is_eqv);
+    "memv", Exactly(2), |_, args| member("memv", &args, is_eqv);
+    "member", Exactly(2), |_, args| member("member", &args, is_equal);
+    "assq", Exactly(2), |_, args| assoc("assq", &args, is_eqv);
+    "assv", Exactly(2), |_, args| assoc("assv", &args, is_eqv);
+    "assoc", Exactly(2), |_, args| assoc("assoc", &args, is_equal);
+
+    "symbol->string", Exactly(1), |_, args| Ok(Value::string(symbol("symbol->string", &args[0])?.name()));
+    "string->symbol", Exactly(1), |_, args| Ok(Value::Symbol(Symbol::new(&*string("string->symbol", &args[0])?)));
+    "string-length", Exactly(1), |_, args| Ok(Value::Integer(string("string-length", &args[0])?.chars().count() as i64));
+    "string-append", AtLeast(0), string_append;
+    "string=?", AtLeast(1), string_equal;
+
+    "vector", AtLeast(0), |_, args| Ok(Value::vector(args));
+    "make-vector", Between(1, 2), make_vector;
+    "vector?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Vector(_))));
+    "vector-length", Exactly(1), |_, args| Ok(Value::Integer(vector("vector-length", &args[0])?.borrow().len() as i64));
+    "vector-ref", Exactly(2), vector_ref;
+    "vector-set!", Exactly(3), vector_set;
+    "vector->list", Exactly(1), |_, args| Ok(Value::list(vector("vector->list", &args[0])?.borrow().clone()));
+    "list->vector", Exactly(1), |_, args| Ok(Value::vector(list("list->vector", &args[0])?));
+
+    "display", Exactly(1), |interp, args| { interp.output.push_str(&args[0].to_display_string()); Ok(Value::Unspecified) };
+    "write", Exactly(1), |interp, args| { interp.output.push_str(&args[0].to_string()); Ok(Value::Unspecified) };
+    "newline", Exactly(0), |interp, _| { interp.output.push('\n'); Ok(Value::Unspecified) };
+
+    "apply", AtLeast(2), apply;
+    "values", AtLeast(0), |_, args| Ok(values(args));
+    "call-with-values", Exactly(2), call_with_values;
+    "call/cc", Exactly(1), call_cc;
+    "call-with-current-continuation", Exactly(1), call_cc;
+    "dynamic-wind", Exactly(3), dynamic_wind;
+    "with-exception-handler", Exactly(2), with_exception_handler;
+    "raise", Exactly(1), |interp, mut args| Err(interp.raise(args.pop().unwrap()));
+    "raise-continuable", Exactly(1), |interp, mut args| interp.raise_continuable(args.pop().unwrap());
+    "error", AtLeast(1), raise_error;
+    "error-object?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Error(_))));
+    "error-object-message", Exactly(1), |_, args| Ok(Value::string(&error_object("error-object-message", &args[0])?.message));
+    "error-object-irritants", Exactly(1), |_, args| Ok(Value::list(error_object("error-object-irritants", &args[0])?.irritants.clone()));
+    "make-promise", Between(1, 2), make_promise;
+    "promise?", Exactly(1), |_, args| Ok(Value::Boolean(matches!(args[0], Value::Promise(_))));
+    "force", Exactly(1), force;
+    "make-parameter", Between(1, 2), make_parameter;
+};
+
+/// Look up a primitive by name.
+pub fn primitive(name: &str) -> Option<&'static Primitive> {
+    PRIMITIVES.iter().find(|p| p.name == name)
+}
+
+/// Signal an error with an error object.
+pub fn error(message: &str, irritants: Vec<Value>) -> Condition {
+    Condition::Error(make_error(message, irritants))
+}
+
+pub fn make_error(message: &str, irritants: Vec<Value>) -> Value {
+    Value::Error(Rc::new(ErrorObject {
+        message: message.to_string(),
+        irritants,
+    }))
+}
+
+/// Wrap results for `values`: a single value stands for itself.
+pub fn values(mut args: Vec<Value>) -> Value {
+    if args.len() == 1 {
+        args.pop().unwrap()
+    } else {
+        Value::Values(Rc::new(args))
+    }
+}
+
+/// `eq?` and `eqv?` coincide, because numbers and characters are not boxed.
+pub fn is_eqv(a: &Value, b: &Value) -> bool {
+    use Value::*;
+    match (a, b) {
+        (Unspecified, Unspecified) | (Nil, Nil) => true,
+        (Boolean(a), Boolean(b)) => a == b,
+        (Integer(a), Integer(b)) => a == b,
+        (Float(a), Float(b)) => a.to_bits() == b.to_bits(),
+        (Symbol(a), Symbol(b)) => a == b,
+        (String(a), String(b)) => Rc::ptr_eq(a, b),
+        (Pair(a), Pair(b)) => Rc::ptr_eq(a, b),
+        (Vector(a), Vector(b)) => Rc::ptr_eq(a, b),
+        (Procedure(a), Procedure(b)) => Rc::ptr_eq(a, b),
+        (Primitive(a), Primitive(b)) => std::ptr::eq(*a, *b),
+        (Native(a), Native(b)) => Rc::ptr_eq(a, b),
+        (Continuation(a), Continuation(b)) => a == b,
+        (Values(a), Values(b)) => Rc::ptr_eq(a, b),
+        (Error(a), Error(b)) => Rc::ptr_eq(a, b),
+        (Promise(a), Promise(b)) => Rc::ptr_eq(a, b),
+        (Parameter(a), Parameter(b)) => Rc::ptr_eq(a, b),
+        (Closure(a), Closure(b)) => Rc::ptr_eq(a, b),
+        (Box(a), Box(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+pub fn is_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Pair(a), Value::Pair(b)) => {
+            is_equal(&a.car.borrow(), &b.car.borrow()) && is_equal(&a.cdr.borrow(), &b.cdr.borrow())
+        }
+        (Value::Vector(a), Value::Vector(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| is_equal(a, b))
+        }
+        (Value::String(a), Value::String(b)) => a == b,
+        _ => is_eqv(a, b),
+    }
+}
+
+// argument types
+
+fn wrong_type(name: &str, expected: &str, value: &Value) -> Condition {
+    error(&format!("{}: not {}", name, expected), vec![value.clone()])
+}
+
+fn pair<'a>(name: &str, value: &'a Value) -> Eval<&'a Rc<Pair>> {
+    match value {
+        Value::Pair(pair) => Ok(pair),
+        _ => Err(wrong_type(name, "a pair", value)),
+    }
+}
+
+fn list(name: &str, value: &Value) -> Eval<Vec<Value>> {
+    value
+        .list_to_vec()
+        .ok_or_else(|| wrong_type(name, "a list", value))
+}
+
+fn integer(name: &str, value: &Value) -> Eval<i64> {
+    match value {
+        Value::Integer(i) => Ok(*i),
+        _ => Err(wrong_type(name, "an exact integer", value)),
+    }
+}
+
+fn symbol(name: &str, value: &Value) -> Eval<Symbol> {
+    match value {
+        Value::Symbol(s) => Ok(*s),
+        _ => Err(wrong_type(name, "a symbol", value)),
+    }
+}
+
+fn string(name: &str, value: &Value) -> Eval<Rc<str>> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        _ => Err(wrong_type(name, "a string", value)),
+    }
+}
+
+fn vector<'a>(name: &str, value: &'a Value) -> Eval<&'a Rc<std::cell::RefCell<Vec<Value>>>> {
+    match value {
+        Value::Vector(items) => Ok(items),
+        _ => Err(wrong_type(name, "a vector", value)),
+    }
+}
+
+fn error_object<'a>(name: &str, value: &'a Value) -> Eval<&'a ErrorObject> {
+    match value {
+        Value::Error(err) => Ok(err),
+        _ => Err(wrong_type(name, "an error object", value)),
+    }
+}
+
+fn index(name: &str, value: &Value, len: usize) -> Eval<usize> {
+    match integer(name, value)? {
+        i if i >= 0 && (i as usize) < len => Ok(i as usize),
+        _ => Err(error(
+            &format!("{}: index out of range", name),
+            vec![value.clone()],
+        )),
+    }
+}
+
+// numbers
+
+#[derive(Debug, Copy, Clone)]
+enum Number {
+    Exact(i64),
+    Inexact(f64),
+}
+
+impl Number {
+    fn to_f64(self) -> f64 {
+        match self {
+            Number::Exact(i) => i as f64,
+            Number::Inexact(x) => x,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Number::Exact(i) => Value::Integer(i),
+            Number::Inexact(x) => Value::Float(x),
+        }
+    }
+
+    fn compare(self, other: Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Exact(a), Number::Exact(b)) => Some(a.cmp(&b)),
+            (Number::Exact(a), Number::Inexact(b)) => compare_integer_float(a, b),
+            (Number::Inexact(a), Number::Exact(b)) => compare_integer_float(b, a).map(Ordering::reverse),
+            (Number::Inexact(a), Number::Inexact(b)) => a.partial_cmp(&b),
+        }
+    }
+}
+
+fn number(name: &str, value: &Value) -> Eval<Number> {
+    match value {
+        Value::Integer(i) => Ok(Number::Exact(*i)),
+        Value::Float(x) => Ok(Number::Inexact(*x)),
+        _ => Err(wrong_type(name, "a number", value)),
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    matches!(value, Value::Integer(_) | Value::Float(_))
+}
+
+fn is_integer(value: &Value) -> bool {
+    match value {
+        Value::Integer(_) => true,
+        Value::Float(x) => x.is_finite() && x.fract() == 0.0,
+        _ => false,
+    }
+}
+
+/// Apply a binary operation from left to right. Results are exact if all arguments are.
+fn arithmetic(
+    name: &str,
+    init: Number,
+    args: &[Value],
+    op: impl Fn(Number, Number) -> Eval<Number>,
+) -> Eval {
+    let mut acc = init;
+    for arg in args {
+        acc = op(acc, number(name, arg)?)?;
+    }
+    Ok(acc.into_value())
+}
+
+fn overflow(name: &str) -> Condition {
+    error(&format!("{}: integer overflow", name), vec![])
+}
+
+fn add(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    arithmetic("+", Number::Exact(0), &args, |a, b| match (a, b) {
+        (Number::Exact(a), Number::Exact(b)) => a
+            .checked_add(b)
+            .map(Number::Exact)
+            .ok_or_else(|| overflow("+")),
+        (a, b) => Ok(Number::Inexact(a.to_f64() + b.to_f64())),
+    })
+}
+
+fn mul(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    arithmetic("*", Number::Exact(1), &args, |a, b| match (a, b) {
+        (Number::Exact(a), Number::Exact(b)) => a
+            .checked_mul(b)
+            .map(Number::Exact)
+            .ok_or_else(|| overflow("*")),
+        (a, b) => Ok(Number::Inexact(a.to_f64() * b.to_f64())),
+    })
+}
+
+/// With a single argument, `-` negates and `/` takes the reciprocal.
+fn first_operand(name: &str, args: &[Value], unit: i64) -> Eval<(Number, usize)> {
+    if args.len() == 1 {
+        Ok((Number::Exact(unit), 0))
+    } else {
+        Ok((number(name, &args[0])?, 1))
+    }
+}
+
+fn sub(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let (init, skip) = first_operand("-", &args, 0)?;
+    arithmetic("-", init, &args[skip..], |a, b| match (a, b) {
+        (Number::Exact(a), Number::Exact(b)) => a
+            .checked_sub(b)
+            .map(Number::Exact)
+            .ok_or_else(|| overflow("-")),
+        (a, b) => Ok(Number::Inexact(a.to_f64() - b.to_f64())),
+    })
+}
+
+/// Exact division must have an integer result, because there are no exact rationals.
+fn div(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let (init, skip) = first_operand("/", &args, 1)?;
+    arithmetic("/", init, &args[skip..], |a, b| match (a, b) {
+        (Number::Exact(_), Number::Exact(0)) => Err(error("/: division by zero", vec![])),
+        (Number::Exact(a), Number::Exact(b)) if a % b == 0 => a
+            .checked_div(b)
+            .map(Number::Exact)
+            .ok_or_else(|| overflow("/")),
+        (Number::Exact(a), Number::Exact(b)) => Err(error(
+            "/: exact rational numbers are not supported",
+            vec![Value::Integer(a), Value::Integer(b)],
+        )),
+        (a, b) => Ok(Number::Inexact(a.to_f64() / b.to_f64())),
+    })
+}
+
+fn compare(name: &str, args: &[Value], pred: fn(Ordering) -> bool) -> Eval {
+    let numbers = args
+        .iter()
+        .map(|arg| number(name, arg))
+        .collect::<Eval<Vec<_>>>()?;
+    Ok(Value::Boolean(
+        numbers
+            .windows(2)
+            .all(|w| w[0].compare(w[1]).is_some_and(pred)),
+    ))
+}
+
+fn sign(name: &str, value: &Value, pred: fn(Ordering) -> bool) -> Eval {
+    let ord = number(name, value)?.compare(Number::Exact(0));
+    Ok(Value::Boolean(ord.is_some_and(pred)))
+}
+
+/// The result is inexact if any argument is.
+fn extremum(name: &str, args: &[Value], wanted: Ordering) -> Eval {
+    let mut result = number(name, &args[0])?;
+    let mut exact = true;
+    for arg in args {
+        let x = number(name, arg)?;
+        if let Number::Inexact(_) = x {
+            exact = false;
+        }
+        if x.compare(result) == Some(wanted) {
+            result = x;
+        }
+    }
+    Ok(if exact {
+        result.into_value()
+    } else {
+        Value::Float(result.to_f64())
+    })
+}
+
+fn quotient(a: i64, b: i64) -> Option<i64> {
+    a.checked_div(b)
+}
+
+fn modulo(a: i64, b: i64) -> Option<i64> {
+    let r = a.checked_rem(b)?;
+    if r != 0 && (r < 0) != (b < 0) {
+        Some(r + b)
+    } else {
+        Some(r)
+    }
+}
+
+fn integer_division(name: &str, args: &[Value], op: fn(i64, i64) -> Option<i64>) -> Eval {
+    let (a, b) = (integer(name, &args[0])?, integer(name, &args[1])?);
+    if b == 0 {
+        return Err(error(&format!("{}: division by zero", name), vec![]));
+    }
+    op(a, b).map(Value::Integer).ok_or_else(|| overflow(name))
+}
+
+fn abs(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    match number("abs", &args[0])? {
+        Number::Exact(i) => i
+            .checked_abs()
+            .map(Value::Integer)
+            .ok_or_else(|| overflow("abs")),
+        Number::Inexact(x) => Ok(Value::Float(x.abs())),
+    }
+}
+
+fn exact(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    match number("exact", &args[0])? {
+        Number::Exact(i) => Ok(Value::Integer(i)),
+        Number::Inexact(x) if x.fract() == 0.0 && x >= i64::MIN as f64 && x < i64::MAX as f64 => {
+            Ok(Value::Integer(x as i64))
+        }
+        Number::Inexact(_) => Err(error(
+            "exact: no exact representation",
+            vec![args[0].clone()],
+        )),
+    }
+}
+
+fn inexact(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    Ok(Value::Float(number("inexact", &args[0])?.to_f64()))
+}
+
+fn number_to_string(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    number("number->string", &args[0])?;
+    Ok(Value::string(args[0].to_string()))
+}
+
+// lists
+
+fn append(_: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let mut result = args.pop().unwrap_or(Value::Nil);
+    for arg in args.iter().rev() {
+        for item in list("append", arg)?.into_iter().rev() {
+            result = Value::cons(item, result);
+        }
+    }
+    Ok(result)
+}
+
+fn list_tail(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let mut list = args[0].clone();
+    for _ in 0..integer("list-tail", &args[1])? {
+        let next = pair("list-tail", &list)?.cdr.borrow().clone();
+        list = next;
+    }
+    Ok(list)
+}
+
+/// The first sublist whose car matches.
+fn member(name: &str, args: &[Value], same: fn(&Value, &Value) -> bool) -> Eval {
+    let mut list = args[1].clone();
+    loop {
+        let next = match &list {
+            Value::Pair(pair) if same(&args[0], &pair.car.borrow()) => return Ok(list.clone()),
+            Value::Pair(pair) => pair.cdr.borrow().clone(),
+            Value::Nil => return Ok(Value::Boolean(false)),
+            _ => return Err(wrong_type(name, "a list", &args[1])),
+        };
+        list = next;
+    }
+}
+
+/// The first pair in an association list whose car matches.
+fn assoc(name: &str, args: &[Value], same: fn(&Value, &Value) -> bool) -> Eval {
+    for entry in list(name, &args[1])? {
+        if same(&args[0], &pair(name, &entry)?.car.borrow()) {
+            return Ok(entry);
+        }
+    }
+    Ok(Value::Boolean(false))
+}
+
+// strings and vectors
+
+fn string_append(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let mut result = String::new();
+    for arg in &args {
+        result.push_str(&string("string-append", arg)?);
+    }
+    Ok(Value::string(result))
+}
+
+fn string_equal(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let strings = args
+        .iter()
+        .map(|arg| string("string=?", arg))
+        .collect::<Eval<Vec<_>>>()?;
+    Ok(Value::Boolean(strings.windows(2).all(|w| w[0] == w[1])))
+}
+
+fn make_vector(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let len = integer("make-vector", &args[0])?;
+    if len < 0 {
+        return Err(wrong_type("make-vector", "a valid length", &args[0]));
+    }
+    let fill = args.get(1).cloned().unwrap_or(Value::Unspecified);
+    Ok(Value::vector(vec![fill; len as usize]))
+}
+
+fn vector_ref(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let items = vector("vector-ref", &args[0])?.borrow();
+    Ok(items[index("vector-ref", &args[1], items.len())?].clone())
+}
+
+fn vector_set(_: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let mut items = vector("vector-set!", &args[0])?.borrow_mut();
+    let i = index("vector-set!", &args[1], items.len())?;
+    items[i] = args[2].clone();
+    Ok(Value::Unspecified)
+}
+
+// control
+
+fn apply(interp: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let last = list("apply", &args.pop().unwrap())?;
+    let proc = args.remove(0);
+    args.extend(last);
+    interp.apply(proc, args)
+}
+
+fn call_with_values(interp: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let consumer = args.pop().unwrap();
+    let args = match interp.apply(args.pop().unwrap(), vec![])? {
+        Value::Values(values) => (*values).clone(),
+        value => vec![value],
+    };
+    interp.apply(consumer, args)
+}
+
+/// Continuations escape by unwinding the Rust stack up to the `call/cc` that created them.
+fn call_cc(interp: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let id = interp.next_continuation;
+    interp.next_continuation += 1;
+    match interp.apply(args.pop().unwrap(), vec![Value::Continuation(id)]) {
+        Err(Condition::Escape(k, value)) if k == id => Ok(value),
+        result => result,
+    }
+}
+
+fn dynamic_wind(interp: &mut Interpreter, args: Vec<Value>) -> Eval {
+    interp.apply(args[0].clone(), vec![])?;
+    let result = interp.apply(args[1].clone(), vec![]);
+    interp.apply(args[2].clone(), vec![])?;
+    result
+}
+
+fn with_exception_handler(interp: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let thunk = args.pop().unwrap();
+    let handler = args.pop().unwrap();
+    if !handler.is_procedure() {
+        return Err(wrong_type(
+            "with-exception-handler",
+            "a procedure",
+            &handler,
+        ));
+    }
+    let depth = interp.handlers.len();
+    interp.handlers.push(handler);
+    let result = interp.apply(thunk, vec![]);
+    interp.handlers.truncate(depth);
+    result
+}
+
+fn raise_error(interp: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let message = args.remove(0).to_display_string();
+    Err(interp.raise(make_error(&message, args)))
+}
+
+/// `(make-promise done? value)` is used by the desugaring of `delay`; `(make-promise obj)` is
+/// the procedure from R7RS.
+fn make_promise(_: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let value = args.pop().unwrap();
+    match args.pop() {
+        Some(done) => Ok(Value::Promise(Rc::new((done.is_true(), value).into()))),
+        None => match value {
+            Value::Promise(_) => Ok(value),
+            _ => Ok(Value::Promise(Rc::new((true, value).into()))),
+        },
+    }
+}
+
+/// Forcing follows the iterative algorithm of the R7RS sample implementation, so chains of
+/// `delay-force` run in constant space.
+fn force(interp: &mut Interpreter, args: Vec<Value>) -> Eval {
+    let promise = match &args[0] {
+        Value::Promise(promise) => promise.clone(),
+        value => return Ok(value.clone()),
+    };
+    loop {
+        let (done, value) = promise.borrow().clone();
+        if done {
+            return Ok(value);
+        }
+        let next = interp.apply(value, vec![])?;
+        if !promise.borrow().0 {
+            let state = match next {
+                Value::Promise(next) => next.borrow().clone(),
+                value => (true, value),
+            };
+            *promise.borrow_mut() = state;
+        }
+    }
+}
+
+fn make_parameter(interp: &mut Interpreter, mut args: Vec<Value>) -> Eval {
+    let converter = if args.len() == 2 {
+        args.pop().unwrap()
+    } else {
+        Value::Primitive(primitive("values").unwrap())
+    };
+    let value = interp.apply(converter.clone(), args)?;
+    Ok(Value::Parameter(Rc::new((value, converter).into())))
+}
@@ -0,0 +1,276 @@
+use super::primitives::Primitive;
+use super::Env;
+use crate::core_scheme::Expression;
+use crate::object::{Object, TaggedValue};
+use crate::runtime::Symbol;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Run time values of the interpreter.
+/// Unlike `Object`, values of compound types are shared and can be mutated.
+#[derive(Clone)]
+pub enum Value {
+    Unspecified,
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Symbol(Symbol),
+    String(Rc<str>),
+    Pair(Rc<Pair>),
+    Vector(Rc<RefCell<Vec<Value>>>),
+
+    Procedure(Rc<Procedure>),
+    Primitive(&'static Primitive),
+    /// A procedure the host implements in Rust and passes to Scheme code.
+    Native(Rc<NativeProcedure>),
+    /// An escape-only continuation, identified by the `call/cc` that created it.
+    Continuation(usize),
+    /// Multiple values, as returned by `values`.
+    Values(Rc<Vec<Value>>),
+    /// An error object, as raised by `error`.
+    Error(Rc<ErrorObject>),
+    /// A promise: whether it is done, and its value or the thunk that computes it.
+    Promise(Rc<RefCell<(bool, Value)>>),
+    /// A parameter object: its value and its converter.
+    Parameter(Rc<RefCell<(Value, Value)>>),
+
+    // the explicit environments introduced by closure conversion and assignment conversion
+    Closure(Rc<ClosureRecord>),
+    Box(Rc<RefCell<Value>>),
+}
+
+pub struct Pair {
+    pub car: RefCell<Value>,
+    pub cdr: RefCell<Value>,
+}
+
+/// A procedure created by evaluating a lambda expression.
+pub struct Procedure {
+    pub params: Vec<Symbol>,
+    pub body: Expression,
+    pub(super) env: Env,
+}
+
+/// A procedure implemented in Rust, which returns its value or an error message.
+pub type NativeFn = dyn Fn(&[Value]) -> std::result::Result<Value, String>;
+
+pub struct NativeProcedure {
+    pub name: String,
+    pub(super) func: Box<NativeFn>,
+}
+
+pub struct ErrorObject {
+    pub message: String,
+    pub irritants: Vec<Value>,
+}
+
+pub struct ClosureRecord {
+    pub code: Value,
+    pub captured: Vec<Value>,
+}
+
+impl Value {
+    pub fn cons(car: Value, cdr: Value) -> Self {
+        Value::Pair(Rc::new(Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+        }))
+    }
+
+    pub fn list(items: Vec<Value>) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |list, item| Value::cons(item, list))
+    }
+
+    pub fn string<T: AsRef<str>>(s: T) -> Self {
+        Value::String(Rc::from(s.as_ref()))
+    }
+
+    pub fn vector(items: Vec<Value>) -> Self {
+        Value::Vector(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn native<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(&[Value]) -> std::result::Result<Value, String> + 'static,
+    {
+        Value::Native(Rc::new(NativeProcedure {
+            name: name.to_string(),
+            func: Box::new(func),
+        }))
+    }
+
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Boolean(false))
+    }
+
+    pub fn is_procedure(&self) -> bool {
+        matches!(
+            self,
+            Value::Procedure(_)
+                | Value::Primitive(_)
+                | Value::Native(_)
+                | Value::Continuation(_)
+                | Value::Parameter(_)
+                | Value::Closure(_)
+        )
+    }
+
+    /// The elements of a proper list.
+    pub fn list_to_vec(&self) -> Option<Vec<Value>> {
+        let mut items = vec![];
+        let mut cursor = self.clone();
+        loop {
+            cursor = match cursor {
+                Value::Nil => return Some(items),
+                Value::Pair(pair) => {
+                    items.push(pair.car.borrow().clone());
+                    let cdr = pair.cdr.borrow().clone();
+                    cdr
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Convert data to an `Object`. Procedures and other values that have no counterpart are
+    /// not converted.
+    pub fn to_object(&self) -> Option<Object> {
+        Some(match self {
+            Value::Unspecified => Object::undef(),
+            Value::Nil => Object::nil(),
+            Value::Boolean(b) => Object::boolean(*b),
+            Value::Integer(i) => Object::integer(*i),
+            Value::Float(x) => Object::float(*x),
+            Value::Symbol(s) => Object::from(*s),
+            Value::String(s) => Object::string(s.to_string()),
+            Value::Pair(pair) => Object::cons(
+                pair.car.borrow().to_object()?,
+                pair.cdr.borrow().to_object()?,
+            ),
+            Value::Vector(items) => Object::vector(
+                items
+                    .borrow()
+                    .iter()
+                    .map(Value::to_object)
+                    .collect::<Option<_>>()?,
+            ),
+            _ => return None,
+        })
+    }
+
+    /// The external representation used by `display`, which prints strings without quotes.
+    pub fn to_display_string(&self) -> String {
+        let mut s = String::new();
+        self.write(&mut s, false).unwrap();
+        s
+    }
+
+    fn write(&self, f: &mut impl fmt::Write, quote_strings: bool) -> fmt::Result {
+        match self {
+            Value::Unspecified => write!(f, "#<unspecified>"),
+            Value::Nil => write!(f, "()"),
+            Value::Boolean(true) => write!(f, "#t"),
+            Value::Boolean(false) => write!(f, "#f"),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) if x.is_nan() => write!(f, "+nan.0"),
+            Value::Float(x) if x.is_infinite() && *x > 0.0 => write!(f, "+inf.0"),
+            Value::Float(x) if x.is_infinite() => write!(f, "-inf.0"),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Symbol(s) => write!(f, "{}", s),
+            Value::String(s) if quote_strings => write!(f, "{:?}", s),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Pair(pair) => {
+                write!(f, "(")?;
+                pair.car.borrow().write(f, quote_strings)?;
+                let mut cdr = pair.cdr.borrow().clone();
+                while let Value::Pair(pair) = cdr {
+                    write!(f, " ")?;
+                    pair.car.borrow().write(f, quote_strings)?;
+                    cdr = pair.cdr.borrow().clone();
+                }
+                if let Value::Nil = cdr {
+                } else {
+                    write!(f, " . ")?;
+                    cdr.write(f, quote_strings)?;
+                }
+                write!(f, ")")
+            }
+            Value::Vector(items) => {
+                write!(f, "#(")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    item.write(f, quote_strings)?;
+                }
+                write!(f, ")")
+            }
+            Value::Procedure(_) | Value::Closure(_) => write!(f, "#<procedure>"),
+            Value::Primitive(p) => write!(f, "#<primitive {}>", p.name),
+            Value::Native(p) => write!(f, "#<native {}>", p.name),
+            Value::Continuation(_) => write!(f, "#<continuation>"),
+            Value::Values(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    value.write(f, quote_strings)?;
+                }
+                Ok(())
+            }
+            Value::Error(err) => {
+                write!(f, "{}", err.message)?;
+                for irritant in &err.irritants {
+                    write!(f, " ")?;
+                    irritant.write(f, true)?;
+                }
+                Ok(())
+            }
+            Value::Promise(_) => write!(f, "#<promise>"),
+            Value::Parameter(_) => write!(f, "#<parameter>"),
+            Value::Box(_) => write!(f, "#<box>"),
+        }
+    }
+}
+
+/// Values print in the external representation used by `write`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, true)
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, true)
+    }
+}
+
+/// Quoted data are copied, so evaluating a literal yields fresh pairs and vectors.
+impl From<&Object> for Value {
+    fn from(obj: &Object) -> Self {
+        match obj.as_value() {
+            TaggedValue::Undef => Value::Unspecified,
+            TaggedValue::Nil => Value::Nil,
+            TaggedValue::Boolean(b) => Value::Boolean(*b),
+            TaggedValue::Integer(i) => Value::Integer(*i),
+            TaggedValue::Float(x) => Value::Float(*x),
+            TaggedValue::Symbol(s) => Value::Symbol(*s),
+            TaggedValue::String(s) => Value::string(s),
+            TaggedValue::Pair(car, cdr) => Value::cons(Value::from(&**car), Value::from(&**cdr)),
+            TaggedValue::Vector(items) => Value::vector(items.iter().map(Value::from).collect()),
+            TaggedValue::Function(_) => unimplemented!("compiled functions in quoted data"),
+        }
+    }
+}
+
+impl From<Object> for Value {
+    fn from(obj: Object) -> Self {
+        Value::from(&obj)
+    }
+}
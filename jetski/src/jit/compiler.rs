@@ -0,0 +1,1280 @@
+//! Just-in-time compilation of toplevel forms
+//! A `Compiler` is a long-lived compilation session. It owns everything that compiled code
+//! needs at run time: a single module with the machine code of all forms compiled so far, and
+//! the global environment. Compiling a toplevel form yields a `CompiledForm`, which runs the code
+//! and returns a tagged value that converts to an `Object`.
+//!
+//! Compiled procedures take the environment as first argument, followed by a tag and a value
+//! per argument, and return a tag and a value.
+//!
+//! Every global variable lives in a cell of the `GlobalEnvironment`, which compiled code reads and
+//! writes directly. Code compiled later links against earlier definitions through their cells,
+//! and redefining a global updates the cell, so existing code sees the new value.
+//!
+//! Reading an unbound variable, arithmetic on non-integers, division by zero, and calling a
+//! value that is not a procedure or with the wrong number of arguments are errors.
+//! Errors are returned as `Tag::Error`, and every call site passes them on to its caller, so they
+//! end up as the result of the toplevel form. The payload of an error is the index of its message,
+//! which the compiler records when it generates the check.
+
+use super::environment::{unbound_variable, GlobalEnvironment, CELL_TAG_OFFSET, CELL_VALUE_OFFSET};
+use super::trampoline::{push_tail_call_argument, trampoline, MAX_TAIL_CALL_ARITY};
+use super::{Tag, Tagged};
+use crate::error::{ErrorKind, Result};
+use crate::object::TaggedValue;
+use crate::runtime::Symbol;
+use crate::{Object, SchemeExpression};
+use cranelift::codegen::write_function;
+use cranelift::prelude::*;
+use cranelift_module::{FuncId, Linkage, Module};
+use cranelift_simplejit::SimpleJITBackend;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+type TopLevelFunction = extern "C" fn(&GlobalEnvironment) -> Tagged;
+
+/// Function values keep the number of parameters in the bits above the address.
+const ARITY_SHIFT: i64 = 48;
+const ADDRESS_MASK: i64 = (1 << ARITY_SHIFT) - 1;
+
+/// A JIT context.
+pub struct Compiler {
+    module: Module<SimpleJITBackend>,
+    // boxed, so the address passed to compiled code stays the same
+    environment: Box<GlobalEnvironment>,
+    /// The messages of the errors compiled code can return, indexed by the error's payload.
+    errors: Vec<String>,
+    function_count: usize,
+    print_ir: bool,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        let mut jb = super::host_jit_builder();
+        jb.symbol("trampoline", trampoline as *const _);
+        jb.symbol(
+            "push_tail_call_argument",
+            push_tail_call_argument as *const _,
+        );
+
+        Compiler {
+            module: Module::new(jb),
+            environment: Box::new(GlobalEnvironment::new()),
+            errors: vec![],
+            function_count: 0,
+            print_ir: false,
+        }
+    }
+
+    /// Print the Cranelift IR of every compiled function to stderr.
+    pub fn print_ir(mut self) -> Self {
+        self.print_ir = true;
+        self
+    }
+
+    /// Compile a toplevel form. Definitions made by the form become visible to later forms when
+    /// it runs.
+    pub fn compile(&mut self, expr: &Object) -> Result<CompiledForm<'_>> {
+        let fn_id = self.compile_function(&Object::nil(), &[expr], "main")?;
+        let fn_code = self.module.get_finalized_function(fn_id);
+
+        Ok(CompiledForm {
+            code: unsafe { std::mem::transmute::<*const u8, TopLevelFunction>(fn_code) },
+            environment: &self.environment,
+            errors: &self.errors,
+        })
+    }
+
+    /// Compile and run a toplevel form.
+    pub fn eval(&mut self, expr: &Object) -> Result<Object> {
+        self.compile(expr)?.eval()
+    }
+
+    /// The global variables, which can be inspected and modified from Rust.
+    pub fn globals(&self) -> &GlobalEnvironment {
+        &self.environment
+    }
+
+    pub fn globals_mut(&mut self) -> &mut GlobalEnvironment {
+        &mut self.environment
+    }
+
+    /// The payload of an error with the given message.
+    fn error_index(&mut self, message: String) -> i64 {
+        let index = match self.errors.iter().position(|m| *m == message) {
+            Some(index) => index,
+            None => {
+                self.errors.push(message);
+                self.errors.len() - 1
+            }
+        };
+        index as i64
+    }
+
+    /// Functions can't be removed from the module, so every function gets a name of its own,
+    /// such as `main#3` or `square#4`.
+    fn unique_name(&mut self, base: &str) -> String {
+        self.function_count += 1;
+        format!("{}#{}", base, self.function_count)
+    }
+
+    fn compile_function(
+        &mut self,
+        params: &Object,
+        body: &[&Object],
+        name: &str,
+    ) -> Result<FuncId> {
+        let mut ctx = self.module.make_context();
+        let mut func_ctx = FunctionBuilderContext::new();
+
+        let (params, rest) = params.list_parts();
+        if !rest.is_nil() {
+            return Err(ErrorKind::SyntaxError(format!(
+                "variadic lambdas are not supported: {}",
+                rest
+            ))
+            .into());
+        }
+        let signature = make_dynamic_signature(&mut self.module, params.len());
+
+        let name = self.unique_name(name);
+        let func = self
+            .module
+            .declare_function(&name, Linkage::Local, &signature)
+            .map_err(codegen_error)?;
+
+        ctx.func.signature = signature;
+        ctx.func.name = ExternalName::user(0, func.as_u32());
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let ebb = bcx.create_ebb();
+
+            bcx.switch_to_block(ebb);
+            bcx.append_ebb_params_for_function_params(ebb);
+
+            let env = bcx.ebb_params(ebb)[0];
+            let args = bcx.ebb_params(ebb)[1..].to_vec();
+            let mut compiler = FunctionCompiler::new(self, &mut bcx);
+
+            compiler.new_variable("env", types::I64, Some(env));
+            for (param, arg) in params.iter().zip(args.chunks(2)) {
+                let name = param.as_symbol().ok_or_else(|| {
+                    ErrorKind::SyntaxError(format!("invalid parameter: {}", param))
+                })?;
+                compiler.new_local(name, arg[0], arg[1]);
+            }
+
+            let (tag, val) = compiler.compile_body(body, true)?;
+            let error_exit = compiler.error_exit;
+
+            bcx.ins().return_(&[tag, val]);
+
+            if let Some(ebb) = error_exit {
+                bcx.switch_to_block(ebb);
+                let error = bcx.ebb_params(ebb).to_vec();
+                bcx.ins().return_(&error);
+            }
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        if self.print_ir {
+            let mut s = String::new();
+            write_function(&mut s, &ctx.func, None).unwrap();
+            eprintln!("{}", s);
+        }
+
+        self.module
+            .define_function(func, &mut ctx)
+            .map_err(codegen_error)?;
+        self.module.clear_context(&mut ctx);
+
+        self.module.finalize_definitions();
+
+        Ok(func)
+    }
+}
+
+/// A compiled toplevel form. It can be run any number of times.
+pub struct CompiledForm<'c> {
+    code: TopLevelFunction,
+    environment: &'c GlobalEnvironment,
+    errors: &'c [String],
+}
+
+impl<'c> CompiledForm<'c> {
+    /// Run the form and return the tagged value it evaluates to.
+    pub fn call(&self) -> Result<Tagged> {
+        let result = (self.code)(self.environment);
+        let env = self.environment as *const _ as i64;
+        match trampoline(env, result.tag as i8, result.val) {
+            Tagged {
+                tag: Tag::Error,
+                val: index,
+            } => Err(ErrorKind::RuntimeError(self.errors[index as usize].clone()).into()),
+            result => Ok(result),
+        }
+    }
+
+    /// Run the form and convert the result to an `Object`.
+    pub fn eval(&self) -> Result<Object> {
+        self.call().and_then(Object::try_from)
+    }
+}
+
+fn codegen_error(err: impl std::fmt::Display) -> ErrorKind {
+    ErrorKind::CodegenError(err.to_string())
+}
+
+/// Generates the body of a single function.
+struct FunctionCompiler<'a, 'b> {
+    jit: &'a mut Compiler,
+    builder: &'a mut FunctionBuilder<'b>,
+    variables: HashMap<&'static str, Variable>,
+    /// The parameters and `let` variables in scope, each a variable for the tag and one for
+    /// the value.
+    locals: HashMap<Symbol, (Variable, Variable)>,
+    variable_count: usize,
+    /// Returns an error from the function; created when it is first needed.
+    error_exit: Option<Ebb>,
+}
+
+impl<'a, 'b> FunctionCompiler<'a, 'b> {
+    fn new(jit: &'a mut Compiler, builder: &'a mut FunctionBuilder<'b>) -> Self {
+        FunctionCompiler {
+            jit,
+            builder,
+            variables: HashMap::new(),
+            locals: HashMap::new(),
+            variable_count: 0,
+            error_exit: None,
+        }
+    }
+
+    fn new_variable(&mut self, name: &'static str, typ: Type, init: Option<Value>) {
+        let var = self.next_variable();
+        self.builder.declare_var(var, typ);
+        if let Some(val) = init {
+            self.builder.def_var(var, val);
+        }
+        self.variables.insert(name, var);
+    }
+
+    fn use_variable(&mut self, name: &'static str) -> Value {
+        self.builder.use_var(self.variables[name])
+    }
+
+    fn next_variable(&mut self) -> Variable {
+        self.variable_count += 1;
+        Variable::new(self.variable_count - 1)
+    }
+
+    /// Bind a local variable, returning the binding it shadows.
+    fn new_local(&mut self, name: Symbol, tag: Value, val: Value) -> Option<(Variable, Variable)> {
+        let tag_var = self.next_variable();
+        let val_var = self.next_variable();
+        self.builder.declare_var(tag_var, types::I8);
+        self.builder.declare_var(val_var, types::I64);
+        self.builder.def_var(tag_var, tag);
+        self.builder.def_var(val_var, val);
+        self.locals.insert(name, (tag_var, val_var))
+    }
+
+    fn compile_expression(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        if is_self_evaluating(expr) {
+            return self.compile_self_evaluating(expr);
+        }
+        if is_variable(expr) {
+            return self.compile_variable(expr);
+        }
+        match self.special_form(expr) {
+            Some("quote") => self.compile_quote(expr),
+            Some("+") | Some("-") | Some("*") | Some("/") | Some("=") | Some("<") | Some(">") => {
+                self.compile_hardcoded(expr)
+            }
+            Some("define") => self.compile_definition(expr),
+            Some("set!") => self.compile_assignment(expr),
+            Some("lambda") => self.compile_lambda(expr, "lambda"),
+            Some("if") => self.compile_if(expr, false),
+            Some("let") => self.compile_let(expr, false),
+            Some("begin") => self.compile_begin(expr, false),
+            _ if expr.is_list() => self.compile_application(expr),
+            _ => Err(ErrorKind::UnknownExpressionType(expr.clone()).into()),
+        }
+    }
+
+    /// The body of a function is in tail position. Tail calls are returned to the caller's
+    /// trampoline, so they do not grow the stack.
+    fn compile_tail_expression(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        match self.special_form(expr) {
+            Some("if") => return self.compile_if(expr, true),
+            Some("let") => return self.compile_let(expr, true),
+            Some("begin") => return self.compile_begin(expr, true),
+            _ => {}
+        }
+        if expr.is_list() && self.special_form(expr).is_none() {
+            self.compile_tail_call(expr)
+        } else {
+            self.compile_expression(expr)
+        }
+    }
+
+    /// The keyword of a special form or primitive operation, such as `define` in `(define x 1)`.
+    /// Parameters shadow keywords, so `(lambda (+) (+ 1 2))` calls its argument.
+    fn special_form<'e>(&self, expr: &'e Object) -> Option<&'e str> {
+        scheme_match!(expr, { op.as_symbol() }, (?op:symbol . _))
+            .unwrap_or(None)
+            .filter(|op| op.is_interned() && !self.locals.contains_key(op))
+            .map(|op| op.name())
+            .filter(|name| SPECIAL_FORMS.contains(name))
+    }
+
+    fn compile_self_evaluating(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        if let TaggedValue::Boolean(b) = expr.as_value() {
+            Ok(self.make_boolean(*b))
+        } else if expr.is_integer() {
+            Ok(self.make_integer(expr.try_as_integer().unwrap()))
+        } else if expr.is_float() {
+            Ok(self.make_float(expr.try_as_float().unwrap()))
+        } else {
+            Err(ErrorKind::UnknownExpressionType(expr.clone()).into())
+        }
+    }
+
+    fn compile_variable(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        let name = expr.as_symbol().unwrap();
+        if let Some(&(tag_var, val_var)) = self.locals.get(&name) {
+            let tag = self.builder.use_var(tag_var);
+            let val = self.builder.use_var(val_var);
+            return Ok((tag, val));
+        }
+
+        let cell = self.bound_global_cell(expr.as_symbol().unwrap());
+        let flags = MemFlags::new();
+        let tag = self
+            .builder
+            .ins()
+            .load(types::I8, flags, cell, CELL_TAG_OFFSET);
+        let val = self
+            .builder
+            .ins()
+            .load(types::I64, flags, cell, CELL_VALUE_OFFSET);
+        Ok((tag, val))
+    }
+
+    /// The address of the cell of a global variable.
+    fn global_cell(&mut self, name: Symbol) -> Value {
+        let cell = self.jit.environment.cell(name).address();
+        self.builder.ins().iconst(types::I64, cell)
+    }
+
+    /// The address of the cell of a global variable, which must be bound when the code runs.
+    fn bound_global_cell(&mut self, name: Symbol) -> Value {
+        let cell = self.global_cell(name);
+        let flags = MemFlags::new();
+        let tag = self
+            .builder
+            .ins()
+            .load(types::I8, flags, cell, CELL_TAG_OFFSET);
+        let is_unbound = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, tag, Tag::Unbound as i64);
+        self.fail_if(is_unbound, unbound_variable(name));
+        cell
+    }
+
+    /// Return an error with the given message from the function if `condition` holds.
+    fn fail_if(&mut self, condition: Value, message: String) {
+        let index = self.jit.error_index(message);
+        let tag = self.builder.ins().iconst(types::I8, Tag::Error as i64);
+        let val = self.builder.ins().iconst(types::I64, index);
+        self.return_if(condition, tag, val);
+    }
+
+    /// Pass errors returned by a call on to the caller.
+    fn propagate_error(&mut self, tag: Value, val: Value) {
+        let is_error = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, tag, Tag::Error as i64);
+        self.return_if(is_error, tag, val);
+    }
+
+    /// Return `(tag, val)` from the function if `condition` holds.
+    fn return_if(&mut self, condition: Value, tag: Value, val: Value) {
+        let exit = match self.error_exit {
+            Some(ebb) => ebb,
+            None => {
+                let ebb = self.builder.create_ebb();
+                self.builder.append_ebb_param(ebb, types::I8);
+                self.builder.append_ebb_param(ebb, types::I64);
+                self.error_exit = Some(ebb);
+                ebb
+            }
+        };
+        self.builder.ins().brnz(condition, exit, &[tag, val]);
+
+        let next = self.builder.create_ebb();
+        self.builder.ins().jump(next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn compile_quote(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        try_switch! {expr,
+            [(_ ?name:symbol)] => Ok(self.make_symbol(name.as_symbol().unwrap())),
+        }
+    }
+
+    /// Only `#f` is false; the branches of an `if` in tail position are in tail position, too.
+    fn compile_if(&mut self, expr: &Object, tail: bool) -> Result<(Value, Value)> {
+        let (test, consequence, alternative) = try_switch! {expr,
+            [(_ ?test ?consequence ?alternative)] => Ok((test, consequence, Some(alternative))),
+            [(_ ?test ?consequence)] => Ok((test, consequence, None)),
+        }?;
+
+        let (tag, val) = self.compile_expression(test)?;
+        let is_boolean = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, tag, Tag::Boolean as i64);
+        let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, val, 0);
+        let is_false = self.builder.ins().band(is_boolean, is_zero);
+
+        let then_ebb = self.builder.create_ebb();
+        let else_ebb = self.builder.create_ebb();
+        let merge_ebb = self.builder.create_ebb();
+        self.builder.append_ebb_param(merge_ebb, types::I8);
+        self.builder.append_ebb_param(merge_ebb, types::I64);
+
+        self.builder.ins().brnz(is_false, else_ebb, &[]);
+        self.builder.ins().jump(then_ebb, &[]);
+
+        self.builder.switch_to_block(then_ebb);
+        let (tag, val) = self.compile_branch(consequence, tail)?;
+        self.builder.ins().jump(merge_ebb, &[tag, val]);
+
+        self.builder.switch_to_block(else_ebb);
+        let (tag, val) = match alternative {
+            Some(alternative) => self.compile_branch(alternative, tail)?,
+            None => self.make_undef(),
+        };
+        self.builder.ins().jump(merge_ebb, &[tag, val]);
+
+        self.builder.switch_to_block(merge_ebb);
+        let result = self.builder.ebb_params(merge_ebb);
+        Ok((result[0], result[1]))
+    }
+
+    fn compile_branch(&mut self, expr: &Object, tail: bool) -> Result<(Value, Value)> {
+        if tail {
+            self.compile_tail_expression(expr)
+        } else {
+            self.compile_expression(expr)
+        }
+    }
+
+    /// Evaluate a sequence of expressions; the last one may be in tail position.
+    fn compile_body(&mut self, body: &[&Object], tail: bool) -> Result<(Value, Value)> {
+        let (last, init) = body.split_last().unwrap();
+        for expr in init {
+            self.compile_expression(expr)?;
+        }
+        self.compile_branch(last, tail)
+    }
+
+    fn compile_begin(&mut self, expr: &Object, tail: bool) -> Result<(Value, Value)> {
+        try_switch! {expr,
+            [(_ ?body ...)] if !body.is_empty() => self.compile_body(&body, tail),
+        }
+    }
+
+    /// The variables of a `let` are locals of the enclosing function.
+    fn compile_let(&mut self, expr: &Object, tail: bool) -> Result<(Value, Value)> {
+        let (names, inits, body) = try_switch! {expr,
+            [(_ ((?names:symbol ?inits) ...) ?body ...)] if !body.is_empty() => {
+                Ok((names, inits, body))
+            },
+        }?;
+
+        let mut values = vec![];
+        for init in inits {
+            values.push(self.compile_expression(init)?);
+        }
+        let mut shadowed = vec![];
+        for (name, (tag, val)) in names.iter().zip(values) {
+            let name = name.as_symbol().unwrap();
+            shadowed.push((name, self.new_local(name, tag, val)));
+        }
+
+        let result = self.compile_body(&body, tail);
+
+        for (name, binding) in shadowed.into_iter().rev() {
+            match binding {
+                Some(binding) => self.locals.insert(name, binding),
+                None => self.locals.remove(&name),
+            };
+        }
+        result
+    }
+
+    /// Integer arithmetic and comparison. Like in Scheme, `+` and `*` take any number of
+    /// operands, and `-` and `/` with a single operand negate or invert it.
+    fn compile_hardcoded(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        let (op, operands) = try_switch! {expr,
+            [(?op:symbol ?operands ...)] => Ok((op.symbol_name().unwrap(), operands)),
+        }?;
+        let mut values = vec![];
+        for operand in operands {
+            let (tag, val) = self.compile_expression(operand)?;
+            let not_integer =
+                self.builder
+                    .ins()
+                    .icmp_imm(IntCC::NotEqual, tag, Tag::Integer as i64);
+            self.fail_if(
+                not_integer,
+                format!("{}: {} is not an integer", expr, operand),
+            );
+            values.push(val);
+        }
+
+        if let Some(cc) = comparison(op) {
+            return self.compile_comparison(expr, cc, &values);
+        }
+
+        let result = match (op, values.as_slice()) {
+            ("+", []) => return Ok(self.make_integer(0)),
+            ("*", []) => return Ok(self.make_integer(1)),
+            ("-", []) | ("/", []) => {
+                return Err(ErrorKind::SyntaxError(format!(
+                    "{} needs at least one operand: {}",
+                    op, expr
+                ))
+                .into())
+            }
+            ("-", &[x]) => {
+                let zero = self.builder.ins().iconst(types::I64, 0);
+                self.builder.ins().isub(zero, x)
+            }
+            ("/", &[x]) => {
+                let one = self.builder.ins().iconst(types::I64, 1);
+                self.compile_division(expr, one, x)
+            }
+            (_, &[first, ref rest @ ..]) => {
+                let mut acc = first;
+                for &x in rest {
+                    acc = match op {
+                        "+" => self.builder.ins().iadd(acc, x),
+                        "-" => self.builder.ins().isub(acc, x),
+                        "*" => self.builder.ins().imul(acc, x),
+                        "/" => self.compile_division(expr, acc, x),
+                        _ => unreachable!(),
+                    };
+                }
+                acc
+            }
+            _ => unreachable!(),
+        };
+
+        Ok(self.cast_integer(result))
+    }
+
+    /// `(< a b c)` holds if `(< a b)` and `(< b c)` hold.
+    fn compile_comparison(
+        &mut self,
+        expr: &Object,
+        cc: IntCC,
+        values: &[Value],
+    ) -> Result<(Value, Value)> {
+        if values.len() < 2 {
+            return Err(
+                ErrorKind::SyntaxError(format!("needs at least two operands: {}", expr)).into(),
+            );
+        }
+        let mut result = self.builder.ins().icmp(cc, values[0], values[1]);
+        for pair in values[1..].windows(2) {
+            let holds = self.builder.ins().icmp(cc, pair[0], pair[1]);
+            result = self.builder.ins().band(result, holds);
+        }
+        Ok(self.cast_boolean(result))
+    }
+
+    /// Division traps on a zero divisor and on overflow, so both are checked first.
+    fn compile_division(&mut self, expr: &Object, dividend: Value, divisor: Value) -> Value {
+        let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, divisor, 0);
+        self.fail_if(is_zero, format!("{}: division by zero", expr));
+
+        let is_min = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::Equal, dividend, i64::MIN);
+        let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, divisor, -1);
+        let overflows = self.builder.ins().band(is_min, is_minus_one);
+        self.fail_if(overflows, format!("{}: integer overflow", expr));
+
+        self.builder.ins().sdiv(dividend, divisor)
+    }
+
+    fn compile_definition(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        try_switch! {expr,
+            [(_ ?name:symbol ?value)] => {
+                let name = name.as_symbol().unwrap();
+                let (val_tag, val_val) = if self.special_form(value) == Some("lambda") {
+                    // name the function after the variable
+                    self.compile_lambda(value, name.name())?
+                } else {
+                    self.compile_expression(value)?
+                };
+
+                let cell = self.global_cell(name);
+                self.store_global(cell, val_tag, val_val);
+                Ok(self.make_undef())
+            },
+        }
+    }
+
+    fn compile_assignment(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        try_switch! {expr,
+            [(_ ?name:symbol ?value)] => {
+                let name = name.as_symbol().unwrap();
+                let (val_tag, val_val) = self.compile_expression(value)?;
+
+                if let Some(&(tag_var, val_var)) = self.locals.get(&name) {
+                    self.builder.def_var(tag_var, val_tag);
+                    self.builder.def_var(val_var, val_val);
+                } else {
+                    let cell = self.bound_global_cell(name);
+                    self.store_global(cell, val_tag, val_val);
+                }
+                Ok(self.make_undef())
+            },
+        }
+    }
+
+    fn store_global(&mut self, cell: Value, tag: Value, val: Value) {
+        let flags = MemFlags::new();
+        self.builder.ins().store(flags, tag, cell, CELL_TAG_OFFSET);
+        self.builder
+            .ins()
+            .store(flags, val, cell, CELL_VALUE_OFFSET);
+    }
+
+    fn compile_lambda(&mut self, expr: &Object, name: &str) -> Result<(Value, Value)> {
+        let (func_id, arity) = try_switch! {expr,
+            [(_ ?params ?body ...)] if !body.is_empty() => {
+                self.jit
+                    .compile_function(params, &body, name)
+                    .map(|func_id| (func_id, params.list_parts().0.len()))
+            },
+        }?;
+        let func_ref = self
+            .jit
+            .module
+            .declare_func_in_func(func_id, self.builder.func);
+        let addr = self.builder.ins().func_addr(types::I64, func_ref); // TODO: automatically detect pointer size of target
+        let func = self
+            .builder
+            .ins()
+            .bor_imm(addr, (arity as i64) << ARITY_SHIFT);
+        Ok(self.cast_function(func))
+    }
+
+    /// Check that the operator of an application is a procedure that takes `nargs` arguments,
+    /// and return the address of its code.
+    fn procedure_address(
+        &mut self,
+        expr: &Object,
+        (tag, func): (Value, Value),
+        nargs: usize,
+    ) -> Value {
+        let not_function = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::NotEqual, tag, Tag::Function as i64);
+        self.fail_if(not_function, format!("{}: not a procedure", expr));
+
+        let arity = self.builder.ins().ushr_imm(func, ARITY_SHIFT);
+        let wrong_arity = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::NotEqual, arity, nargs as i64);
+        self.fail_if(wrong_arity, format!("{}: wrong number of arguments", expr));
+
+        self.builder.ins().band_imm(func, ADDRESS_MASK)
+    }
+
+    fn compile_application(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        let (operator, operands) = application_parts(expr)?;
+        let signature = make_dynamic_signature(&mut self.jit.module, operands.len());
+        let sig = self.builder.func.import_signature(signature);
+
+        let env = self.use_variable("env");
+
+        let proc = self.compile_expression(operator)?;
+        let args = self.compile_args(env, &operands)?;
+        let addr = self.procedure_address(expr, proc, operands.len());
+
+        let call = self.builder.ins().call_indirect(sig, addr, &args);
+        let results = self.builder.inst_results(call).to_vec();
+
+        // the callee may have returned a pending tail call
+        let mut sig = self.jit.module.make_signature();
+        sig.params.push(AbiParam::new(types::I64));
+        sig.params.push(AbiParam::new(types::I8));
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I8));
+        sig.returns.push(AbiParam::new(types::I64));
+
+        let trampoline_decl = self
+            .jit
+            .module
+            .declare_function("trampoline", Linkage::Import, &sig)
+            .map_err(codegen_error)?;
+        let trampoline = self
+            .jit
+            .module
+            .declare_func_in_func(trampoline_decl, self.builder.func);
+        let call = self
+            .builder
+            .ins()
+            .call(trampoline, &[env, results[0], results[1]]);
+        let (tag, val) = {
+            let results = self.builder.inst_results(call);
+            (results[0], results[1])
+        };
+        self.propagate_error(tag, val);
+        Ok((tag, val))
+    }
+
+    /// Instead of calling the procedure, pass the arguments to the trampoline and return the
+    /// procedure as a pending tail call.
+    fn compile_tail_call(&mut self, expr: &Object) -> Result<(Value, Value)> {
+        let (operator, operands) = application_parts(expr)?;
+        if operands.len() > MAX_TAIL_CALL_ARITY {
+            return Err(ErrorKind::CodegenError(format!(
+                "tail calls with more than {} arguments are not supported: {}",
+                MAX_TAIL_CALL_ARITY, expr
+            ))
+            .into());
+        }
+        let env = self.use_variable("env");
+
+        let proc = self.compile_expression(operator)?;
+        let args = self.compile_args(env, &operands)?;
+        let addr = self.procedure_address(expr, proc, operands.len());
+
+        let mut sig = self.jit.module.make_signature();
+        sig.params.push(AbiParam::new(types::I8));
+        sig.params.push(AbiParam::new(types::I64));
+
+        let push_decl = self
+            .jit
+            .module
+            .declare_function("push_tail_call_argument", Linkage::Import, &sig)
+            .map_err(codegen_error)?;
+        let push = self
+            .jit
+            .module
+            .declare_func_in_func(push_decl, self.builder.func);
+        for arg in args[1..].chunks(2) {
+            self.builder.ins().call(push, arg);
+        }
+
+        let tag = self.builder.ins().iconst(types::I8, Tag::TailCall as i64);
+        Ok((tag, addr))
+    }
+
+    fn compile_args(&mut self, env: Value, args: &[&Object]) -> Result<Vec<Value>> {
+        let mut compiled_args = vec![];
+        compiled_args.push(env);
+        for op in args {
+            let (tag, val) = self.compile_expression(op)?;
+            compiled_args.push(tag);
+            compiled_args.push(val);
+        }
+        Ok(compiled_args)
+    }
+
+    fn make_undef(&mut self) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Undef as i64);
+        let val = self.builder.ins().iconst(types::I64, 0);
+        (tag, val)
+    }
+
+    fn make_integer(&mut self, value: i64) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Integer as i64);
+        let val = self.builder.ins().iconst(types::I64, value);
+        (tag, val)
+    }
+
+    fn cast_integer(&mut self, val: Value) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Integer as i64);
+        (tag, val)
+    }
+
+    fn make_boolean(&mut self, value: bool) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Boolean as i64);
+        let val = self.builder.ins().iconst(types::I64, value as i64);
+        (tag, val)
+    }
+
+    fn cast_boolean(&mut self, condition: Value) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Boolean as i64);
+        let val = self.builder.ins().bint(types::I64, condition);
+        (tag, val)
+    }
+
+    fn make_float(&mut self, value: f64) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Float as i64);
+        let val = self
+            .builder
+            .ins()
+            .iconst(types::I64, value.to_bits() as i64);
+        (tag, val)
+    }
+
+    fn make_symbol(&mut self, name: Symbol) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Symbol as i64);
+        let val = self.global_cell(name);
+        (tag, val)
+    }
+
+    fn cast_function(&mut self, func: Value) -> (Value, Value) {
+        let tag = self.builder.ins().iconst(types::I8, Tag::Function as i64);
+        (tag, func)
+    }
+}
+
+fn make_dynamic_signature(module: &mut Module<SimpleJITBackend>, nargs: usize) -> Signature {
+    let mut signature = module.make_signature();
+
+    // every function takes as first argument the current environment
+    signature.params.push(AbiParam::new(types::I64));
+
+    for _ in 0..nargs {
+        signature.params.push(AbiParam::new(types::I8));
+        signature.params.push(AbiParam::new(types::I64));
+    }
+    signature.returns.push(AbiParam::new(types::I8));
+    signature.returns.push(AbiParam::new(types::I64));
+    signature
+}
+
+fn is_self_evaluating(expr: &Object) -> bool {
+    expr.is_number() || expr.is_string() || matches!(expr.as_value(), TaggedValue::Boolean(_))
+}
+
+fn is_variable(expr: &Object) -> bool {
+    expr.is_symbol()
+}
+
+const SPECIAL_FORMS: [&str; 14] = [
+    "quote", "define", "set!", "lambda", "if", "let", "begin", "+", "-", "*", "/", "=", "<", ">",
+];
+
+fn comparison(op: &str) -> Option<IntCC> {
+    match op {
+        "=" => Some(IntCC::Equal),
+        "<" => Some(IntCC::SignedLessThan),
+        ">" => Some(IntCC::SignedGreaterThan),
+        _ => None,
+    }
+}
+
+fn application_parts(expr: &Object) -> Result<(&Object, Vec<&Object>)> {
+    try_switch! {expr,
+        [(?operator ?operands ...)] => Ok((operator, operands)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+
+    fn eval(compiler: &mut Compiler, source: &str) -> Object {
+        compiler.eval(&parse_datum(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut compiler = Compiler::new();
+        assert_eq!(eval(&mut compiler, "(+ 1 (* 2 3))"), Object::integer(7));
+        assert_eq!(eval(&mut compiler, "(/ (- 10 4) 2)"), Object::integer(3));
+    }
+
+    #[test]
+    fn arithmetic_takes_any_number_of_operands() {
+        let mut compiler = Compiler::new();
+        assert_eq!(eval(&mut compiler, "(+)"), Object::integer(0));
+        assert_eq!(eval(&mut compiler, "(*)"), Object::integer(1));
+        assert_eq!(eval(&mut compiler, "(+ 1)"), Object::integer(1));
+        assert_eq!(eval(&mut compiler, "(- 1)"), Object::integer(-1));
+        assert_eq!(eval(&mut compiler, "(/ 1)"), Object::integer(1));
+        assert_eq!(eval(&mut compiler, "(+ 1 2 3)"), Object::integer(6));
+        assert_eq!(eval(&mut compiler, "(- 10 1 2)"), Object::integer(7));
+        assert_eq!(eval(&mut compiler, "(/ 100 5 2)"), Object::integer(10));
+    }
+
+    #[test]
+    fn parameters_shadow_operators() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define sub (lambda (a b) (- a b)))");
+        assert_eq!(
+            eval(&mut compiler, "((lambda (+) (+ 1 2)) sub)"),
+            Object::integer(-1)
+        );
+    }
+
+    #[test]
+    fn lambda_bodies_are_sequences() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define x 0)");
+        eval(&mut compiler, "(define f (lambda (y) (set! x y) (+ x 1)))");
+        assert_eq!(eval(&mut compiler, "(f 41)"), Object::integer(42));
+        assert_eq!(eval(&mut compiler, "x"), Object::integer(41));
+    }
+
+    #[test]
+    fn definitions_are_visible_to_later_forms() {
+        let mut compiler = Compiler::new();
+        assert_eq!(eval(&mut compiler, "(define x 42)"), Object::undef());
+        assert_eq!(eval(&mut compiler, "(+ x 1)"), Object::integer(43));
+    }
+
+    #[test]
+    fn procedures() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define f (lambda () (* 6 7)))");
+        assert_eq!(eval(&mut compiler, "(f)"), Object::integer(42));
+    }
+
+    #[test]
+    fn parameters() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define square (lambda (x) (* x x)))");
+        eval(&mut compiler, "(define sub (lambda (x y) (- x y)))");
+        assert_eq!(
+            eval(&mut compiler, "(sub (square 7) 7)"),
+            Object::integer(42)
+        );
+    }
+
+    #[test]
+    fn functions_get_unique_names() {
+        let mut compiler = Compiler::new();
+        let result = eval(&mut compiler, "((lambda (a) a) ((lambda (b) (+ b 1)) 41))");
+        assert_eq!(result, Object::integer(42));
+    }
+
+    #[test]
+    fn later_forms_link_against_earlier_definitions() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define twice (lambda (x) (* 2 x)))");
+        eval(
+            &mut compiler,
+            "(define quad (lambda (x) (twice (twice x))))",
+        );
+        assert_eq!(eval(&mut compiler, "(quad 5)"), Object::integer(20));
+    }
+
+    #[test]
+    fn globals_can_be_referenced_before_they_are_defined() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define g (lambda () (f)))");
+        eval(&mut compiler, "(define f (lambda () 1))");
+        assert_eq!(eval(&mut compiler, "(g)"), Object::integer(1));
+    }
+
+    #[test]
+    fn redefinition_updates_existing_code() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define f (lambda () 1))");
+        eval(&mut compiler, "(define g (lambda () (f)))");
+        assert_eq!(eval(&mut compiler, "(g)"), Object::integer(1));
+        eval(&mut compiler, "(define f (lambda () 2))");
+        assert_eq!(eval(&mut compiler, "(g)"), Object::integer(2));
+    }
+
+    #[test]
+    fn compiled_forms_can_run_repeatedly() {
+        let mut compiler = Compiler::new();
+        let form = compiler.compile(&parse_datum("(- 50 8)").unwrap()).unwrap();
+        assert_eq!(form.call().unwrap(), Tagged::new(Tag::Integer, 42));
+        assert_eq!(form.eval().unwrap(), Object::integer(42));
+    }
+
+    #[test]
+    fn quoted_symbols() {
+        let mut compiler = Compiler::new();
+        assert_eq!(eval(&mut compiler, "'foo"), Object::symbol("foo"));
+        eval(&mut compiler, "(define s 'bar)");
+        assert_eq!(eval(&mut compiler, "s"), Object::symbol("bar"));
+    }
+
+    #[test]
+    fn assignment() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define x 1)");
+        eval(&mut compiler, "(define get-x (lambda () x))");
+        eval(&mut compiler, "(set! x 2)");
+        assert_eq!(eval(&mut compiler, "(get-x)"), Object::integer(2));
+    }
+
+    #[test]
+    fn uninterned_symbols_are_distinct_variables() {
+        // (lambda (x) (let ((x' 1)) x)), where x' is a different symbol named x
+        let (x, renamed) = (Symbol::uninterned("x"), Symbol::uninterned("x"));
+        let binding = list!(list!(Object::from(renamed), 1));
+        let body = list!(let, @binding, Object::from(x));
+        let lambda = list!(lambda, list!(Object::from(x)), @body);
+        let mut compiler = Compiler::new();
+        let result = compiler.eval(&list!(@lambda, 2)).unwrap();
+        assert_eq!(result, Object::integer(2));
+    }
+
+    fn error_message(compiler: &mut Compiler, source: &str) -> String {
+        match compiler
+            .eval(&parse_datum(source).unwrap())
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::RuntimeError(msg) => msg.clone(),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn unbound_variables_are_errors() {
+        let mut compiler = Compiler::new();
+        assert_eq!(
+            error_message(&mut compiler, "(+ y 1)"),
+            "unbound variable y"
+        );
+        assert_eq!(
+            error_message(&mut compiler, "(set! y 1)"),
+            "unbound variable y"
+        );
+        assert!(!compiler.globals().is_bound(Symbol::new("y")));
+    }
+
+    #[test]
+    fn calling_a_non_procedure_is_an_error() {
+        let mut compiler = Compiler::new();
+        assert_eq!(error_message(&mut compiler, "(5)"), "(5): not a procedure");
+        eval(&mut compiler, "(define g (lambda () (5)))");
+        assert_eq!(error_message(&mut compiler, "(g)"), "(5): not a procedure");
+        eval(&mut compiler, "(define h (lambda () (1 2)))");
+        assert_eq!(
+            error_message(&mut compiler, "(+ (h) 1)"),
+            "(1 2): not a procedure"
+        );
+    }
+
+    #[test]
+    fn calls_with_the_wrong_number_of_arguments_are_errors() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define f (lambda (x) x))");
+        assert_eq!(
+            error_message(&mut compiler, "(f 1 2)"),
+            "(f 1 2): wrong number of arguments"
+        );
+        assert_eq!(
+            error_message(&mut compiler, "(f)"),
+            "(f): wrong number of arguments"
+        );
+        eval(&mut compiler, "(define g (lambda () (f)))");
+        assert_eq!(
+            error_message(&mut compiler, "(g)"),
+            "(f): wrong number of arguments"
+        );
+        assert_eq!(eval(&mut compiler, "(f 1)"), Object::integer(1));
+    }
+
+    #[test]
+    fn arithmetic_on_non_integers_is_an_error() {
+        let mut compiler = Compiler::new();
+        assert_eq!(
+            error_message(&mut compiler, "(+ 1 1.5)"),
+            "(+ 1 1.5): 1.5 is not an integer"
+        );
+        assert_eq!(
+            error_message(&mut compiler, "(- 'a)"),
+            "(- (quote a)): (quote a) is not an integer"
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        let mut compiler = Compiler::new();
+        assert_eq!(
+            error_message(&mut compiler, "(/ 1 0)"),
+            "(/ 1 0): division by zero"
+        );
+        assert_eq!(
+            error_message(&mut compiler, "(/ 0)"),
+            "(/ 0): division by zero"
+        );
+        eval(&mut compiler, "(define min (- -9223372036854775807 1))");
+        assert_eq!(
+            error_message(&mut compiler, "(/ min -1)"),
+            "(/ min -1): integer overflow"
+        );
+        assert_eq!(eval(&mut compiler, "(/ min 1)"), Object::integer(i64::MIN));
+    }
+
+    #[test]
+    fn errors_propagate_through_calls() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define f (lambda () (+ z 1)))");
+        eval(&mut compiler, "(define g (lambda () (f)))");
+        assert_eq!(
+            error_message(&mut compiler, "(+ (f) 1)"),
+            "unbound variable z"
+        );
+        assert_eq!(
+            error_message(&mut compiler, "(* (g) 2)"),
+            "unbound variable z"
+        );
+        eval(&mut compiler, "(define z 41)");
+        assert_eq!(eval(&mut compiler, "(g)"), Object::integer(42));
+    }
+
+    #[test]
+    fn globals_are_accessible_from_rust() {
+        let mut compiler = Compiler::new();
+        let answer = Symbol::new("answer");
+        compiler
+            .globals_mut()
+            .define(answer, &Object::integer(41))
+            .unwrap();
+        eval(&mut compiler, "(set! answer (+ answer 1))");
+        assert_eq!(
+            compiler.globals().lookup(answer).unwrap(),
+            Object::integer(42)
+        );
+
+        eval(&mut compiler, "(define seven 7)");
+        let mut names: Vec<_> = compiler.globals().names().map(|s| s.name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["answer", "seven"]);
+    }
+
+    #[test]
+    fn conditionals() {
+        let mut compiler = Compiler::new();
+        assert_eq!(eval(&mut compiler, "(if #t 1 2)"), Object::integer(1));
+        assert_eq!(eval(&mut compiler, "(if #f 1 2)"), Object::integer(2));
+        assert_eq!(eval(&mut compiler, "(if 0 1 2)"), Object::integer(1));
+        assert_eq!(eval(&mut compiler, "(if 'x 1 2)"), Object::integer(1));
+        assert_eq!(eval(&mut compiler, "(if #f 1)"), Object::undef());
+        assert_eq!(eval(&mut compiler, "(< 1 2 3)"), Object::boolean(true));
+        assert_eq!(eval(&mut compiler, "(< 1 3 2)"), Object::boolean(false));
+        assert_eq!(eval(&mut compiler, "(> 3 2)"), Object::boolean(true));
+        assert_eq!(eval(&mut compiler, "(= 2 2 2)"), Object::boolean(true));
+        eval(
+            &mut compiler,
+            "(define fib (lambda (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+        );
+        assert_eq!(eval(&mut compiler, "(fib 20)"), Object::integer(6765));
+    }
+
+    #[test]
+    fn local_bindings() {
+        let mut compiler = Compiler::new();
+        assert_eq!(
+            eval(&mut compiler, "(let ((x 1) (y 2)) (+ x y))"),
+            Object::integer(3)
+        );
+        assert_eq!(
+            eval(&mut compiler, "(let ((x 1)) (let ((x 2) (y x)) (* x y)))"),
+            Object::integer(2)
+        );
+        eval(
+            &mut compiler,
+            "(define f (lambda (x) (let ((x (+ x 1))) (set! x (* x 2)) x)))",
+        );
+        assert_eq!(eval(&mut compiler, "(f 20)"), Object::integer(42));
+        assert_eq!(
+            eval(&mut compiler, "(begin (define y 1) (set! y 2) y)"),
+            Object::integer(2)
+        );
+    }
+
+    #[test]
+    fn tail_recursive_loop_runs_in_constant_stack_space() {
+        let mut compiler = Compiler::new();
+        eval(
+            &mut compiler,
+            "(define loop (lambda (n) (if (= n 0) 'done (loop (- n 1)))))",
+        );
+        assert_eq!(
+            eval(&mut compiler, "(loop 10000000)"),
+            Object::symbol("done")
+        );
+    }
+
+    #[test]
+    fn tail_calls_take_at_most_four_arguments() {
+        let mut compiler = Compiler::new();
+        eval(&mut compiler, "(define f (lambda (a b c d e) e))");
+        assert_eq!(
+            eval(&mut compiler, "(+ (f 1 2 3 4 5) 0)"),
+            Object::integer(5)
+        );
+        match compiler
+            .compile(&parse_datum("(define h (lambda () (f 1 2 3 4 5)))").unwrap())
+            .map(|_| ())
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::CodegenError(_) => {}
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn malformed_forms_are_syntax_errors() {
+        let mut compiler = Compiler::new();
+        for source in &[
+            "(define)",
+            "(define x)",
+            "(define 1 2)",
+            "(set! x)",
+            "(lambda (x))",
+            "(lambda)",
+            "(quote)",
+            "(quote 1)",
+            "(if)",
+            "(if #t)",
+            "(if #t 1 2 3)",
+            "(let ((x 1)))",
+            "(let ((1 2)) 3)",
+            "(begin)",
+            "(< 1)",
+            "(-)",
+            "(/)",
+        ] {
+            match compiler.compile(&parse_datum(source).unwrap()) {
+                Err(e) => match e.kind() {
+                    ErrorKind::SyntaxError(_) => {}
+                    kind => panic!("unexpected error for {}: {:?}", source, kind),
+                },
+                Ok(_) => panic!("{} compiled", source),
+            }
+        }
+        // the failed forms leave the compiler usable
+        assert_eq!(eval(&mut compiler, "(+ 1 2)"), Object::integer(3));
+    }
+
+    #[test]
+    fn unsupported_expressions_are_errors() {
+        let mut compiler = Compiler::new();
+        assert!(compiler.compile(&parse_datum("\"text\"").unwrap()).is_err());
+    }
+}
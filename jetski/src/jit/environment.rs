@@ -0,0 +1,234 @@
+//! The global environment of compiled code
+//! Every global variable has a cell, which compiled code reads and writes directly. The cell of
+//! a variable is created the first time it is referenced, and it stays unbound until a value is
+//! defined. Cells are boxed and owned by the environment, which lives as long as the compiled code
+//! of its `Compiler`, so the address of a cell identifies the variable's symbol for as long as the
+//! code can run, just like `Symbol::id`. Compiled code also represents symbols by the address of
+//! their cell.
+
+use super::{Tag, Tagged};
+use crate::error::{ErrorKind, Result};
+use crate::object::TaggedValue;
+use crate::runtime::Symbol;
+use crate::Object;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// The storage of a global variable.
+#[repr(C)]
+pub(super) struct GlobalCell {
+    tag: Cell<i8>,
+    val: Cell<i64>,
+    pub name: Symbol,
+}
+
+// offsets of the fields of `GlobalCell`, as accessed by compiled code
+pub(super) const CELL_TAG_OFFSET: i32 = 0;
+pub(super) const CELL_VALUE_OFFSET: i32 = 8;
+
+impl GlobalCell {
+    /// The cell at an address, as obtained from `GlobalEnvironment::cell`. The environment that
+    /// owns the cell must still be alive.
+    pub unsafe fn from_address<'a>(addr: i64) -> &'a GlobalCell {
+        &*(addr as *const GlobalCell)
+    }
+
+    pub fn address(&self) -> i64 {
+        self as *const _ as i64
+    }
+
+    fn is_bound(&self) -> bool {
+        self.tag.get() != Tag::Unbound as i8
+    }
+
+    fn get(&self) -> Tagged {
+        // only `set` and compiled code write the tag, and both write valid tags
+        let tag = Tag::try_from(self.tag.get()).expect("invalid tag in global cell");
+        Tagged::new(tag, self.val.get())
+    }
+
+    fn set(&self, value: Tagged) {
+        self.tag.set(value.tag as i8);
+        self.val.set(value.val);
+    }
+}
+
+/// The global variables of compiled code.
+#[derive(Default)]
+pub struct GlobalEnvironment {
+    cells: HashMap<Symbol, Box<GlobalCell>>,
+}
+
+impl GlobalEnvironment {
+    pub fn new() -> Self {
+        GlobalEnvironment::default()
+    }
+
+    /// Bind a global variable, replacing its previous value.
+    pub fn define(&mut self, name: Symbol, value: &Object) -> Result<()> {
+        let value = self.encode(value)?;
+        self.cell(name).set(value);
+        Ok(())
+    }
+
+    /// Assign a new value to a bound global variable.
+    pub fn set(&mut self, name: Symbol, value: &Object) -> Result<()> {
+        self.bound_cell(name)?;
+        self.define(name, value)
+    }
+
+    /// The value of a global variable.
+    pub fn lookup(&self, name: Symbol) -> Result<Object> {
+        Object::try_from(self.bound_cell(name)?.get())
+    }
+
+    pub fn is_bound(&self, name: Symbol) -> bool {
+        self.bound_cell(name).is_ok()
+    }
+
+    /// The bound global variables, in no particular order.
+    pub fn names<'a>(&'a self) -> impl Iterator<Item = Symbol> + 'a {
+        self.cells
+            .values()
+            .filter(|cell| cell.is_bound())
+            .map(|cell| cell.name)
+    }
+
+    /// The cell of a global variable, which is created unbound if the variable has never been
+    /// referenced before.
+    pub(super) fn cell(&mut self, name: Symbol) -> &GlobalCell {
+        self.cells.entry(name).or_insert_with(|| {
+            Box::new(GlobalCell {
+                tag: Cell::new(Tag::Unbound as i8),
+                val: Cell::new(0),
+                name,
+            })
+        })
+    }
+
+    fn bound_cell(&self, name: Symbol) -> Result<&GlobalCell> {
+        match self.cells.get(&name) {
+            Some(cell) if cell.is_bound() => Ok(cell),
+            _ => Err(ErrorKind::RuntimeError(unbound_variable(name)).into()),
+        }
+    }
+
+    /// The representation of an object in compiled code.
+    fn encode(&mut self, value: &Object) -> Result<Tagged> {
+        let (tag, val) = match value.as_value() {
+            TaggedValue::Undef => (Tag::Undef, 0),
+            TaggedValue::Nil => (Tag::Null, 0),
+            TaggedValue::Integer(i) => (Tag::Integer, *i),
+            TaggedValue::Float(x) => (Tag::Float, x.to_bits() as i64),
+            TaggedValue::Boolean(b) => (Tag::Boolean, *b as i64),
+            TaggedValue::Symbol(s) => (Tag::Symbol, self.cell(*s).address()),
+            TaggedValue::Function(f) => (Tag::Function, *f as i64),
+            _ => {
+                return Err(ErrorKind::RuntimeError(format!(
+                    "{} can't be represented in compiled code",
+                    value
+                ))
+                .into())
+            }
+        };
+        Ok(Tagged::new(tag, val))
+    }
+}
+
+/// The message of the error raised when an unbound variable is referenced.
+pub(super) fn unbound_variable(name: Symbol) -> String {
+    format!("unbound variable {}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_layout_matches_the_offsets_used_by_compiled_code() {
+        let mut env = GlobalEnvironment::new();
+        let cell = env.cell(Symbol::new("x"));
+        let base = cell.address();
+        assert_eq!(&cell.tag as *const _ as i64 - base, CELL_TAG_OFFSET as i64);
+        assert_eq!(
+            &cell.val as *const _ as i64 - base,
+            CELL_VALUE_OFFSET as i64
+        );
+    }
+
+    #[test]
+    fn define_and_lookup() {
+        let mut env = GlobalEnvironment::new();
+        let x = Symbol::new("x");
+        env.define(x, &Object::integer(42)).unwrap();
+        assert_eq!(env.lookup(x).unwrap(), Object::integer(42));
+        env.define(x, &Object::float(1.5)).unwrap();
+        assert_eq!(env.lookup(x).unwrap(), Object::float(1.5));
+    }
+
+    #[test]
+    fn symbols_are_represented_by_their_cell() {
+        let mut env = GlobalEnvironment::new();
+        env.define(Symbol::new("x"), &Object::symbol("foo"))
+            .unwrap();
+        assert_eq!(env.lookup(Symbol::new("x")).unwrap(), Object::symbol("foo"));
+        // referencing a symbol does not bind it
+        assert!(!env.is_bound(Symbol::new("foo")));
+    }
+
+    #[test]
+    fn unbound_variables_are_errors() {
+        let mut env = GlobalEnvironment::new();
+        let x = Symbol::new("x");
+        env.cell(x);
+        for result in [
+            env.lookup(x),
+            env.set(x, &Object::nil()).map(|_| Object::nil()),
+        ] {
+            match result.unwrap_err().kind() {
+                ErrorKind::RuntimeError(msg) => assert_eq!(msg, "unbound variable x"),
+                kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+        assert!(!env.is_bound(x));
+    }
+
+    #[test]
+    fn cells_keep_their_address() {
+        let mut env = GlobalEnvironment::new();
+        let x = env.cell(Symbol::new("x")).address();
+        for i in 0..100 {
+            env.cell(Symbol::new(format!("v{}", i)));
+        }
+        assert_eq!(env.cell(Symbol::new("x")).address(), x);
+    }
+
+    #[test]
+    fn set_replaces_the_value() {
+        let mut env = GlobalEnvironment::new();
+        let x = Symbol::new("x");
+        env.define(x, &Object::integer(1)).unwrap();
+        env.set(x, &Object::integer(2)).unwrap();
+        assert_eq!(env.lookup(x).unwrap(), Object::integer(2));
+    }
+
+    #[test]
+    fn names_lists_bound_variables() {
+        let mut env = GlobalEnvironment::new();
+        env.define(Symbol::new("a"), &Object::integer(1)).unwrap();
+        env.define(Symbol::new("b"), &Object::nil()).unwrap();
+        env.cell(Symbol::new("c"));
+        let mut names: Vec<_> = env.names().map(|s| s.name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn only_immediate_values_can_be_defined() {
+        let mut env = GlobalEnvironment::new();
+        assert!(env
+            .define(Symbol::new("x"), &Object::string("text".to_string()))
+            .is_err());
+    }
+}
@@ -1,44 +1,176 @@
+//! Just-in-time compilation with Cranelift
+//! `Compiler` compiles toplevel forms to machine code. Compiled code represents values as a
+//! pair of a `Tag` and a 64 bit payload, which converts to an `Object`.
+
+use crate::error::{Error, ErrorKind, Result};
 use crate::Object;
 use cranelift::prelude::*;
+use cranelift_simplejit::SimpleJITBuilder;
+use std::convert::TryFrom;
+use target_lexicon::Triple;
+
+mod compiler;
+mod environment;
+pub mod trampoline;
+
+pub use compiler::{CompiledForm, Compiler};
+pub use environment::GlobalEnvironment;
 
-#[derive(Debug)]
+/// The type of a value in compiled code. The payload depends on the tag: the integer, the bits
+/// of the float, 1 for `#t` and 0 for `#f`, the address of the symbol's global cell, or the
+/// address of the function with its number of parameters in the upper 16 bits, which addresses
+/// don't use.
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Tag {
     Undef,
     Null,
     Integer,
     Float,
+    Boolean,
     Symbol,
     Function,
+    /// A pending tail call of the function at the address in the payload; see `trampoline`.
+    TailCall,
+    /// The contents of the cell of a global variable that has no value.
+    Unbound,
+    /// Compiled code failed; the payload identifies the error message recorded by the `Compiler`.
+    Error,
 }
 
-impl From<(Tag, i64)> for Object {
-    fn from((tag, val): (Tag, i64)) -> Object {
-        match tag {
+/// The tags as compiled code stores them, in declaration order.
+const TAGS: [Tag; 10] = [
+    Tag::Undef,
+    Tag::Null,
+    Tag::Integer,
+    Tag::Float,
+    Tag::Boolean,
+    Tag::Symbol,
+    Tag::Function,
+    Tag::TailCall,
+    Tag::Unbound,
+    Tag::Error,
+];
+
+impl TryFrom<i8> for Tag {
+    type Error = Error;
+
+    fn try_from(tag: i8) -> Result<Tag> {
+        usize::try_from(tag)
+            .ok()
+            .and_then(|index| TAGS.get(index))
+            .copied()
+            .ok_or_else(|| ErrorKind::RuntimeError(format!("invalid tag {}", tag)).into())
+    }
+}
+
+/// A tagged value as returned by compiled procedures. Cranelift returns the tag and the payload
+/// in two registers, which is how the System V ABI returns this struct from an `extern "C"`
+/// function, so Rust calls compiled procedures through `extern "C"` function pointers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct Tagged {
+    pub tag: Tag,
+    pub val: i64,
+}
+
+impl Tagged {
+    pub fn new(tag: Tag, val: i64) -> Self {
+        Tagged { tag, val }
+    }
+}
+
+/// Only values convert to objects; the other tags are internal to compiled code.
+impl TryFrom<Tagged> for Object {
+    type Error = Error;
+
+    fn try_from(Tagged { tag, val }: Tagged) -> Result<Object> {
+        Ok(match tag {
             Tag::Undef => Object::undef(),
             Tag::Null => Object::nil(),
             Tag::Integer => Object::integer(val),
-            Tag::Float => Object::float(unsafe { std::mem::transmute::<i64, f64>(val) }),
-            Tag::Symbol => {
-                Object::symbol(&unsafe { std::mem::transmute::<_, char>(val as u32).to_string() })
-            }
+            Tag::Float => Object::float(f64::from_bits(val as u64)),
+            Tag::Boolean => Object::boolean(val != 0),
+            Tag::Symbol => Object::from(unsafe { environment::GlobalCell::from_address(val) }.name),
             Tag::Function => Object::function(val as *const _),
-            _ => unimplemented!("Convert {:?} to object", (tag, val)),
+            Tag::TailCall | Tag::Unbound | Tag::Error => {
+                return Err(
+                    ErrorKind::RuntimeError(format!("{:?} is not a value", (tag, val))).into(),
+                )
+            }
+        })
+    }
+}
+
+/// A JIT builder for the host machine.
+/// `SimpleJITBuilder::new` detects the CPU features with a version of `raw-cpuid` that panics on
+/// recent x86 CPUs, so we look them up with the standard library instead.
+pub(crate) fn host_jit_builder() -> SimpleJITBuilder {
+    let mut isa_builder = isa::lookup(Triple::host()).expect("host machine is not supported");
+    #[cfg(target_arch = "x86_64")]
+    {
+        let features = [
+            ("has_sse3", is_x86_feature_detected!("sse3")),
+            ("has_sse41", is_x86_feature_detected!("sse4.1")),
+            ("has_sse42", is_x86_feature_detected!("sse4.2")),
+            ("has_popcnt", is_x86_feature_detected!("popcnt")),
+            ("has_avx", is_x86_feature_detected!("avx")),
+            ("has_bmi1", is_x86_feature_detected!("bmi1")),
+            ("has_bmi2", is_x86_feature_detected!("bmi2")),
+            ("has_lzcnt", is_x86_feature_detected!("lzcnt")),
+        ];
+        for &(name, detected) in features.iter() {
+            if detected {
+                isa_builder.enable(name).unwrap();
+            }
+        }
+    }
+    let isa = isa_builder.finish(settings::Flags::new(settings::builder()));
+    SimpleJITBuilder::with_isa(isa)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_convert_to_objects() {
+        let convert = |tag, val| Object::try_from(Tagged::new(tag, val)).unwrap();
+        assert_eq!(convert(Tag::Null, 0), Object::nil());
+        assert_eq!(convert(Tag::Integer, -3), Object::integer(-3));
+        assert_eq!(
+            convert(Tag::Float, 2.5f64.to_bits() as i64),
+            Object::float(2.5)
+        );
+    }
+
+    #[test]
+    fn tags_convert_from_their_representation() {
+        for &tag in &TAGS {
+            assert_eq!(Tag::try_from(tag as i8).unwrap(), tag);
+        }
+        assert!(Tag::try_from(TAGS.len() as i8).is_err());
+        assert!(Tag::try_from(-1).is_err());
+    }
+
+    #[test]
+    fn internal_tags_do_not_convert() {
+        for &tag in &[Tag::TailCall, Tag::Unbound, Tag::Error] {
+            assert!(Object::try_from(Tagged::new(tag, 0)).is_err());
         }
     }
 }
 
 #[cfg(test)]
 mod learning_tests {
-    use cranelift::codegen::{write, write_function};
+    use cranelift::codegen::write_function;
     use cranelift::prelude::*;
     use cranelift_module::{Linkage, Module};
-    use cranelift_simplejit::{SimpleJITBackend, SimpleJITBuilder};
-    use std::sync::mpsc::TrySendError::Full;
+    use cranelift_simplejit::SimpleJITBackend;
 
     #[test]
     fn it_works() {
-        let mut module: Module<SimpleJITBackend> = Module::new(SimpleJITBuilder::new());
+        let mut module: Module<SimpleJITBackend> = Module::new(super::host_jit_builder());
         let mut ctx = module.make_context();
         let mut func_ctx = FunctionBuilderContext::new();
 
@@ -66,7 +198,7 @@ mod learning_tests {
             bcx.finalize();
 
             let mut s = String::new();
-            write_function(&mut s, &bcx.func, None).unwrap();
+            write_function(&mut s, bcx.func, None).unwrap();
             println!("{}", s);
         }
         module.define_function(func_a, &mut ctx).unwrap();
@@ -76,14 +208,14 @@ mod learning_tests {
 
         let code_a = module.get_finalized_function(func_a);
 
-        let ptr_a = unsafe { std::mem::transmute::<_, fn(u32) -> u32>(code_a) };
+        let ptr_a = unsafe { std::mem::transmute::<*const u8, fn(u32) -> u32>(code_a) };
 
         assert_eq!(ptr_a(5), 42);
     }
 
     #[test]
     fn multi_return() {
-        let mut module: Module<SimpleJITBackend> = Module::new(SimpleJITBuilder::new());
+        let mut module: Module<SimpleJITBackend> = Module::new(super::host_jit_builder());
         let mut ctx = module.make_context();
         let mut func_ctx = FunctionBuilderContext::new();
 
@@ -110,7 +242,7 @@ mod learning_tests {
             bcx.finalize();
 
             let mut s = String::new();
-            write_function(&mut s, &bcx.func, None).unwrap();
+            write_function(&mut s, bcx.func, None).unwrap();
             println!("{}", s);
         }
         module.define_function(func_a, &mut ctx).unwrap();
@@ -120,7 +252,7 @@ mod learning_tests {
 
         let code_a = module.get_finalized_function(func_a);
 
-        let ptr_a = unsafe { std::mem::transmute::<_, fn() -> (u32, u32)>(code_a) };
+        let ptr_a = unsafe { std::mem::transmute::<*const u8, fn() -> (u32, u32)>(code_a) };
 
         assert_eq!(ptr_a(), (3, 7));
     }
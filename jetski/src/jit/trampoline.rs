@@ -0,0 +1,137 @@
+//! Proper tail calls by trampolining
+//! Compiled procedures return a tagged value. Instead of calling a procedure in tail position,
+//! which would grow the native stack, a procedure registers the arguments with
+//! `push_tail_call_argument` and returns `(Tag::TailCall, procedure)`. Every other call site
+//! passes the result to `trampoline`, which performs pending tail calls in a loop until a proper
+//! value is returned. Chains of tail calls thus run in constant stack space.
+//!
+//! Both functions are exported to generated code.
+
+use super::{Tag, Tagged};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+
+/// The largest number of arguments that can be passed in a tail call.
+pub const MAX_TAIL_CALL_ARITY: usize = 4;
+
+thread_local! {
+    static ARGUMENTS: RefCell<Vec<(i8, i64)>> = RefCell::new(Vec::with_capacity(MAX_TAIL_CALL_ARITY));
+}
+
+/// Register the next argument of a pending tail call.
+pub extern "C" fn push_tail_call_argument(tag: i8, val: i64) {
+    ARGUMENTS.with(|args| args.borrow_mut().push((tag, val)));
+}
+
+/// Resolve the result of a call: perform pending tail calls until a value is returned.
+pub extern "C" fn trampoline(env: i64, mut tag: i8, mut val: i64) -> Tagged {
+    while tag == Tag::TailCall as i8 {
+        let args = ARGUMENTS.with(|args| args.replace(Vec::with_capacity(MAX_TAIL_CALL_ARITY)));
+        let result = unsafe { call(env, val, &args) };
+        tag = result.tag as i8;
+        val = result.val;
+    }
+    // a panic can't unwind into compiled code, so an invalid tag aborts
+    let tag = Tag::try_from(tag).expect("compiled code returned an invalid tag");
+    Tagged::new(tag, val)
+}
+
+/// Call a compiled procedure, which takes the environment and a tag and a value per argument.
+/// The compiler rejects tail calls with more than `MAX_TAIL_CALL_ARITY` arguments.
+unsafe fn call(env: i64, func: i64, args: &[(i8, i64)]) -> Tagged {
+    use std::mem::transmute;
+    type Procedure0 = extern "C" fn(i64) -> Tagged;
+    type Procedure1 = extern "C" fn(i64, i8, i64) -> Tagged;
+    type Procedure2 = extern "C" fn(i64, i8, i64, i8, i64) -> Tagged;
+    type Procedure3 = extern "C" fn(i64, i8, i64, i8, i64, i8, i64) -> Tagged;
+    type Procedure4 = extern "C" fn(i64, i8, i64, i8, i64, i8, i64, i8, i64) -> Tagged;
+    match *args {
+        [] => transmute::<i64, Procedure0>(func)(env),
+        [(t0, v0)] => transmute::<i64, Procedure1>(func)(env, t0, v0),
+        [(t0, v0), (t1, v1)] => transmute::<i64, Procedure2>(func)(env, t0, v0, t1, v1),
+        [(t0, v0), (t1, v1), (t2, v2)] => {
+            transmute::<i64, Procedure3>(func)(env, t0, v0, t1, v1, t2, v2)
+        }
+        [(t0, v0), (t1, v1), (t2, v2), (t3, v3)] => {
+            transmute::<i64, Procedure4>(func)(env, t0, v0, t1, v1, t2, v2, t3, v3)
+        }
+        _ => unreachable!(
+            "tail call with {} arguments, at most {} are supported",
+            args.len(),
+            MAX_TAIL_CALL_ARITY
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Procedure1 = extern "C" fn(i64, i8, i64) -> Tagged;
+
+    // These procedures follow the calling convention of generated code.
+
+    extern "C" fn count_down(_env: i64, _tag: i8, n: i64) -> Tagged {
+        if n == 0 {
+            Tagged::new(Tag::Integer, 42)
+        } else {
+            push_tail_call_argument(Tag::Integer as i8, n - 1);
+            Tagged::new(Tag::TailCall, count_down as Procedure1 as usize as i64)
+        }
+    }
+
+    extern "C" fn is_even(_env: i64, _tag: i8, n: i64) -> Tagged {
+        if n == 0 {
+            Tagged::new(Tag::Integer, 1)
+        } else {
+            push_tail_call_argument(Tag::Integer as i8, n - 1);
+            Tagged::new(Tag::TailCall, is_odd as Procedure1 as usize as i64)
+        }
+    }
+
+    extern "C" fn is_odd(_env: i64, _tag: i8, n: i64) -> Tagged {
+        if n == 0 {
+            Tagged::new(Tag::Integer, 0)
+        } else {
+            push_tail_call_argument(Tag::Integer as i8, n - 1);
+            Tagged::new(Tag::TailCall, is_even as Procedure1 as usize as i64)
+        }
+    }
+
+    fn call(f: Procedure1, n: i64) -> (Tag, i64) {
+        let result = f(0, Tag::Integer as i8, n);
+        let result = trampoline(0, result.tag as i8, result.val);
+        (result.tag, result.val)
+    }
+
+    /// Run on a thread with a small stack, which a growing stack would overflow.
+    fn with_small_stack(f: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn tail_recursive_loop_runs_in_constant_stack_space() {
+        with_small_stack(|| assert_eq!(call(count_down, 10_000_000), (Tag::Integer, 42)));
+    }
+
+    #[test]
+    fn mutual_tail_recursion_runs_in_constant_stack_space() {
+        with_small_stack(|| {
+            assert_eq!(call(is_even, 10_000_001), (Tag::Integer, 0));
+            assert_eq!(call(is_odd, 10_000_001), (Tag::Integer, 1));
+        });
+    }
+
+    #[test]
+    fn values_are_returned_unchanged() {
+        assert_eq!(
+            trampoline(0, Tag::Float as i8, 7),
+            Tagged::new(Tag::Float, 7)
+        );
+    }
+}
@@ -2,13 +2,18 @@
 
 extern crate pest;
 
+// allows the `datum!` expansion to refer to `::jetski` from within this crate
+extern crate self as jetski;
+
 #[macro_use]
 extern crate pest_derive;
 #[macro_use]
 pub mod syntax;
 
 pub mod core_scheme;
+pub mod differential;
 mod error;
+pub mod eval;
 pub mod jit;
 mod object;
 pub mod parser;
@@ -16,6 +21,7 @@ pub mod runtime;
 pub mod transformations;
 
 pub use error::*;
+pub use jetski_macros::datum;
 pub use object::Object;
 
 // TODO: I'm not yet sure where this trait should live...
@@ -24,6 +30,16 @@ pub trait SchemeExpression {
 
     fn symbol_name(&self) -> Option<&'static str>;
 
+    fn is_integer(&self) -> bool {
+        false
+    }
+    fn is_float(&self) -> bool {
+        false
+    }
+    fn is_string(&self) -> bool {
+        false
+    }
+
     fn car(&self) -> Option<&Self>;
     fn cdr(&self) -> Option<&Self>;
     fn decons(&self) -> Option<(&Self, &Self)> {
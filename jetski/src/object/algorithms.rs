@@ -1,6 +1,8 @@
 use super::{Object, TaggedValue};
 use crate::error::{ErrorKind, Result};
 use crate::SchemeExpression;
+#[cfg(test)]
+use {crate::runtime::Symbol, std::collections::HashMap};
 
 impl Object {
     pub fn get_ref(&self, idx: usize) -> Option<&Object> {
@@ -21,13 +23,55 @@ impl Object {
         Some(len)
     }
 
+    /// Split a (possibly improper) list into its elements and the final cdr.
+    pub fn list_parts(&self) -> (Vec<&Object>, &Object) {
+        let mut items = vec![];
+        let mut cursor = self;
+        while let TaggedValue::Pair(car, cdr) = cursor.as_value() {
+            items.push(&**car);
+            cursor = cdr;
+        }
+        (items, cursor)
+    }
+
+    /// Structural equality, except that uninterned symbols only need to correspond one to one
+    /// and have the same names. This compares generated code with expected code that was read
+    /// from text, where the uninterned symbols are necessarily different ones.
+    #[cfg(test)]
+    pub fn same_up_to_uninterned(&self, other: &Object) -> bool {
+        fn same(
+            a: &Object,
+            b: &Object,
+            pairs: &mut HashMap<Symbol, Symbol>,
+            reverse: &mut HashMap<Symbol, Symbol>,
+        ) -> bool {
+            use TaggedValue::*;
+            match (a.as_value(), b.as_value()) {
+                (Symbol(x), Symbol(y)) if !x.is_interned() && !y.is_interned() => {
+                    x.name() == y.name()
+                        && *pairs.entry(*x).or_insert(*y) == *y
+                        && *reverse.entry(*y).or_insert(*x) == *x
+                }
+                (Pair(a_car, a_cdr), Pair(b_car, b_cdr)) => {
+                    same(a_car, b_car, pairs, reverse) && same(a_cdr, b_cdr, pairs, reverse)
+                }
+                (Vector(xs), Vector(ys)) => {
+                    xs.len() == ys.len()
+                        && xs.iter().zip(ys).all(|(x, y)| same(x, y, pairs, reverse))
+                }
+                _ => a == b,
+            }
+        }
+        same(self, other, &mut HashMap::new(), &mut HashMap::new())
+    }
+
     pub fn map<F: FnMut(&Self) -> Result<Self>>(&self, mut op: F) -> Result<Self> {
         if self.is_nil() {
             Ok(Object::nil())
         } else {
             self.car()
                 .ok_or_else(|| ErrorKind::NotAPair(self.clone()).into())
-                .and_then(|car| op(car))
+                .and_then(&mut op)
                 .and_then(|new_car| Ok(Object::cons(new_car, self.cdr().unwrap().map(op)?)))
         }
     }
@@ -10,6 +10,10 @@ impl Object {
         Object::new(TaggedValue::Nil)
     }
 
+    pub fn boolean(value: bool) -> Self {
+        Object::new(TaggedValue::Boolean(value))
+    }
+
     pub fn integer(value: i64) -> Self {
         Object::new(TaggedValue::Integer(value))
     }
@@ -33,6 +37,10 @@ impl Object {
     pub fn cons(car: Object, cdr: Object) -> Self {
         Object::new(TaggedValue::Pair(Box::new(car), Box::new(cdr)))
     }
+
+    pub fn vector(items: Vec<Object>) -> Self {
+        Object::new(TaggedValue::Vector(items))
+    }
 }
 
 pub struct ListBuilder {
@@ -44,7 +52,7 @@ impl ListBuilder {
     pub fn new() -> Self {
         let mut builder = ListBuilder {
             partial_list: Box::new(Object::nil()),
-            cursor: 0 as *mut _,
+            cursor: std::ptr::null_mut(),
         };
         builder.cursor = builder.partial_list.as_mut();
         builder
@@ -1,6 +1,5 @@
 use super::{Object, TaggedValue};
 use crate::runtime::Symbol;
-use crate::SchemeExpression;
 
 macro_rules! impl_from {
     ($T:ty, $as:ty, $constructor:path) => {
@@ -20,10 +19,17 @@ impl_from!(u32, i64, Object::integer);
 impl_from!(u16, i64, Object::integer);
 impl_from!(u8, i64, Object::integer);
 
+impl_from!(bool, bool, Object::boolean);
+
 impl_from!(f64, f64, Object::float);
 impl_from!(f32, f64, Object::float);
 
-impl_from!(Symbol, Symbol, Object::symbol);
+// Converting a symbol must preserve its identity, which matters for uninterned symbols.
+impl From<Symbol> for Object {
+    fn from(s: Symbol) -> Object {
+        Object::new(TaggedValue::Symbol(s))
+    }
+}
 
 impl Object {
     pub fn list_to_vec(&self) -> Option<Vec<Object>> {
@@ -12,6 +12,8 @@ impl std::fmt::Display for Object {
         match &self.content {
             Nil => write!(f, "'()"),
             Undef => write!(f, "<undefined>"),
+            Boolean(true) => write!(f, "#t"),
+            Boolean(false) => write!(f, "#f"),
             Integer(x) => write!(f, "{}", x),
             Float(x) => write!(f, "{}", x),
             Symbol(s) => write!(f, "{}", s),
@@ -30,6 +32,16 @@ impl std::fmt::Display for Object {
                 }
                 write!(f, ")")
             }
+            Vector(items) => {
+                write!(f, "#(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
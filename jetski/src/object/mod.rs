@@ -31,10 +31,12 @@ impl Object {
 pub enum TaggedValue {
     Undef,
     Nil,
+    Boolean(bool),
     Integer(i64),
     Float(f64),
     Symbol(Symbol),
     String(String),
     Pair(Box<Object>, Box<Object>),
+    Vector(Vec<Object>),
     Function(*const u8),
 }
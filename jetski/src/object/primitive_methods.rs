@@ -2,24 +2,18 @@ use super::{Object, Symbol, TaggedValue};
 
 impl Object {
     pub fn is_null(&self) -> bool {
-        match self.content {
-            TaggedValue::Nil => true,
-            _ => false,
-        }
+        matches!(self.content, TaggedValue::Nil)
     }
 
     pub fn is_number(&self) -> bool {
-        match self.content {
-            TaggedValue::Integer(_) | TaggedValue::Float(_) => true,
-            _ => false,
-        }
+        matches!(
+            self.content,
+            TaggedValue::Integer(_) | TaggedValue::Float(_)
+        )
     }
 
     pub fn is_integer(&self) -> bool {
-        match self.content {
-            TaggedValue::Integer(_) => true,
-            _ => false,
-        }
+        matches!(self.content, TaggedValue::Integer(_))
     }
 
     pub fn try_as_integer(&self) -> Option<i64> {
@@ -30,10 +24,7 @@ impl Object {
     }
 
     pub fn is_float(&self) -> bool {
-        match self.content {
-            TaggedValue::Float(_) => true,
-            _ => false,
-        }
+        matches!(self.content, TaggedValue::Float(_))
     }
 
     pub fn try_as_float(&self) -> Option<f64> {
@@ -55,16 +46,21 @@ impl Object {
     }
 
     pub fn is_string(&self) -> bool {
-        match self.content {
-            TaggedValue::String(_) => true,
-            _ => false,
-        }
+        matches!(self.content, TaggedValue::String(_))
     }
 
     pub fn is_list(&self) -> bool {
+        matches!(self.content, TaggedValue::Pair(_, _))
+    }
+
+    pub fn is_vector(&self) -> bool {
+        self.as_vector().is_some()
+    }
+
+    pub fn as_vector(&self) -> Option<&[Object]> {
         match self.content {
-            TaggedValue::Pair(_, _) => true,
-            _ => false,
+            TaggedValue::Vector(ref items) => Some(items),
+            _ => None,
         }
     }
 }
@@ -12,6 +12,18 @@ impl crate::SchemeExpression for Object {
         }
     }
 
+    fn is_integer(&self) -> bool {
+        Object::is_integer(self)
+    }
+
+    fn is_float(&self) -> bool {
+        Object::is_float(self)
+    }
+
+    fn is_string(&self) -> bool {
+        Object::is_string(self)
+    }
+
     fn car(&self) -> Option<&Self> {
         match self.content {
             TaggedValue::Pair(ref a, _) => Some(a),
@@ -1,42 +1,86 @@
 use crate::error::{ErrorKind, Result};
 use crate::object::{ListBuilder, Object};
+use crate::runtime::Symbol;
 use pest::{iterators::Pair, Parser};
+use std::collections::HashMap;
 
 #[derive(Parser)]
 #[grammar = "r7rs.pest"]
 pub struct R7rsGrammar;
 
+/// Read a datum. Uninterned symbols are written `#:name`, as they are printed; every occurrence
+/// of the same name within the datum reads as the same symbol.
 pub fn parse_datum(input: &str) -> Result<Object> {
     let mut datum = R7rsGrammar::parse(Rule::datum, input)?;
-    walk_datum(datum.next().unwrap())
+    walk_datum(datum.next().unwrap(), &mut HashMap::new())
 }
 
-fn walk_datum(pair: Pair<Rule>) -> Result<Object> {
+/// Assert that generated code is the expected code, which is read from text. Uninterned symbols
+/// of the expected code stand for the uninterned symbols of the same names in the generated code,
+/// and `<undefined>`, which has no read syntax, stands for the undefined value it prints as.
+#[cfg(test)]
+#[track_caller]
+pub fn assert_same_code(actual: &Object, expected: &str) {
+    use crate::SchemeExpression;
+    fn read_undefined(obj: &Object) -> Object {
+        match (obj.car(), obj.cdr()) {
+            (Some(car), Some(cdr)) => Object::cons(read_undefined(car), read_undefined(cdr)),
+            _ if obj.symbol_name() == Some("<undefined>") => Object::undef(),
+            _ => obj.clone(),
+        }
+    }
+    let expected = read_undefined(&parse_datum(expected).unwrap());
+    assert!(
+        actual.same_up_to_uninterned(&expected),
+        "assertion failed\n  actual: {}\nexpected: {}",
+        actual,
+        expected
+    );
+}
+
+/// The uninterned symbols read so far, by name.
+type Uninterned<'i> = HashMap<&'i str, Symbol>;
+
+fn walk_datum<'i>(pair: Pair<'i, Rule>, uninterned: &mut Uninterned<'i>) -> Result<Object> {
     match pair.as_rule() {
-        Rule::list => walk_list(pair),
+        Rule::list => walk_list(pair, uninterned),
+        Rule::vector => walk_vector(pair, uninterned),
+        Rule::boolean => walk_boolean(pair),
         Rule::number => walk_number(pair),
         Rule::symbol => walk_symbol(pair),
+        Rule::uninterned_symbol => walk_uninterned_symbol(pair, uninterned),
         Rule::string_content => walk_string(pair),
-        Rule::abbreviation => walk_abbreviation(pair),
+        Rule::abbreviation => walk_abbreviation(pair, uninterned),
         _ => unimplemented!("{:?}", pair),
     }
 }
 
-fn walk_list(pair: Pair<Rule>) -> Result<Object> {
+fn walk_list<'i>(pair: Pair<'i, Rule>, uninterned: &mut Uninterned<'i>) -> Result<Object> {
     let mut parse_list = pair.into_inner();
     let mut list_builder = ListBuilder::new();
     while let Some(list_item) = parse_list.next() {
         if list_item.as_rule() == Rule::dot {
-            let item = walk_datum(parse_list.next().unwrap())?;
+            let item = walk_datum(parse_list.next().unwrap(), uninterned)?;
             list_builder.set_cdr(item);
         } else {
-            let item = walk_datum(list_item)?;
+            let item = walk_datum(list_item, uninterned)?;
             list_builder.append(item);
         }
     }
     Ok(list_builder.build())
 }
 
+fn walk_vector<'i>(pair: Pair<'i, Rule>, uninterned: &mut Uninterned<'i>) -> Result<Object> {
+    pair.into_inner()
+        .map(|item| walk_datum(item, uninterned))
+        .collect::<Result<_>>()
+        .map(Object::vector)
+}
+
+fn walk_boolean(pair: Pair<Rule>) -> Result<Object> {
+    Ok(Object::boolean(pair.as_str().starts_with("#t")))
+}
+
 fn walk_number(pair: Pair<Rule>) -> Result<Object> {
     let number = pair.into_inner().next().unwrap();
     match number.as_rule() {
@@ -78,11 +122,22 @@ fn walk_symbol(pair: Pair<Rule>) -> Result<Object> {
     }
 }
 
+fn walk_uninterned_symbol<'i>(
+    pair: Pair<'i, Rule>,
+    uninterned: &mut Uninterned<'i>,
+) -> Result<Object> {
+    let name = pair.into_inner().next().unwrap().as_str();
+    let symbol = *uninterned
+        .entry(name)
+        .or_insert_with(|| Symbol::uninterned(name));
+    Ok(symbol.into())
+}
+
 fn walk_string(pair: Pair<Rule>) -> Result<Object> {
     Ok(Object::string(pair.as_str().to_owned()))
 }
 
-fn walk_abbreviation(pair: Pair<Rule>) -> Result<Object> {
+fn walk_abbreviation<'i>(pair: Pair<'i, Rule>, uninterned: &mut Uninterned<'i>) -> Result<Object> {
     let mut inner = pair.into_inner();
     let prefix = inner.next().unwrap();
     let datum = inner.next().unwrap();
@@ -90,7 +145,7 @@ fn walk_abbreviation(pair: Pair<Rule>) -> Result<Object> {
     match prefix.as_str() {
         "'" => Ok(Object::cons(
             Object::symbol("quote"),
-            Object::cons(walk_datum(datum)?, Object::nil()),
+            Object::cons(walk_datum(datum, uninterned)?, Object::nil()),
         )),
         _ => unimplemented!("{:?}", prefix),
     }
@@ -99,6 +154,7 @@ fn walk_abbreviation(pair: Pair<Rule>) -> Result<Object> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::datum;
 
     #[test]
     fn it_works() {
@@ -115,4 +171,47 @@ mod tests {
         println!("{:?}", parse_datum("'(1 2 3)").unwrap());
         panic!()
     }
+
+    #[test]
+    fn uninterned_symbols() {
+        let datum = parse_datum("(#:x #:x #:|a b| x)").unwrap();
+        let items = datum.list_parts().0;
+        assert!(!items[0].as_symbol().unwrap().is_interned());
+        assert_eq!(items[0], items[1]);
+        assert_eq!(items[2].as_symbol().unwrap().name(), "a b");
+        assert_ne!(items[0], items[3]);
+        assert_ne!(parse_datum("#:x").unwrap(), parse_datum("#:x").unwrap());
+    }
+
+    #[test]
+    fn code_is_the_same_up_to_uninterned_symbols() {
+        let (x, other_x) = (Symbol::uninterned("x"), Symbol::uninterned("x"));
+        let lambda = |param: Symbol, body: Symbol| list!(lambda, @list!(@Object::from(param)), @Object::from(body));
+        assert_same_code(&lambda(x, x), "(lambda (#:x) #:x)");
+        let expected = parse_datum("(lambda (#:x) #:x)").unwrap();
+        assert!(!lambda(x, other_x).same_up_to_uninterned(&expected));
+        let interned = Symbol::new("x");
+        assert!(!lambda(interned, interned).same_up_to_uninterned(&expected));
+        let expected = parse_datum("(lambda (#:y) #:y)").unwrap();
+        assert!(!lambda(x, x).same_up_to_uninterned(&expected));
+    }
+
+    #[test]
+    fn datum_macro_matches_parser() {
+        assert_eq!(
+            datum!("(#e1 |x y| #i2 \"foo\" bar #b10 4.7 . 5)"),
+            parse_datum("(#e1 |x y| #i2 \"foo\" bar #b10 4.7 . 5)").unwrap()
+        );
+        assert_eq!(
+            datum!("(define (two-sqr x) (* 2 x x))"),
+            parse_datum("(define (two-sqr x) (* 2 x x))").unwrap()
+        );
+        assert_eq!(datum!("'(1 2 3)"), parse_datum("'(1 2 3)").unwrap());
+        assert_eq!(datum!("()"), Object::nil());
+        assert_eq!(datum!("#(1 (a) #())"), parse_datum("#(1 (a) #())").unwrap());
+        assert_eq!(
+            datum!("(#t #true #f #false)"),
+            list!(@true, @true, @false, @false)
+        );
+    }
 }
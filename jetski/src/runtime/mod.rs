@@ -1,3 +1,5 @@
+mod number;
 mod symbol;
 
-pub use symbol::Symbol;
+pub use number::compare_integer_float;
+pub use symbol::{GensymCounter, Symbol};
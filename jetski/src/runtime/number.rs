@@ -0,0 +1,57 @@
+use std::cmp::Ordering;
+
+/// 2^63, the smallest float that is greater than every `i64`.
+const TWO_TO_63: f64 = 9_223_372_036_854_775_808.0;
+
+/// Compare an exact integer with a float without rounding the integer, which would make large
+/// integers equal to their float neighbours. `None` if the float is NaN.
+pub fn compare_integer_float(i: i64, x: f64) -> Option<Ordering> {
+    if x.is_nan() {
+        None
+    } else if x >= TWO_TO_63 {
+        Some(Ordering::Less)
+    } else if x < -TWO_TO_63 {
+        Some(Ordering::Greater)
+    } else {
+        // the integer part of `x` is in range now, and converts exactly
+        let int_part = x.trunc();
+        match i.cmp(&(int_part as i64)) {
+            Ordering::Equal => 0.0.partial_cmp(&(x - int_part)),
+            ord => Some(ord),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_are_not_rounded() {
+        assert_eq!(
+            compare_integer_float(i64::MAX, TWO_TO_63),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare_integer_float(i64::MIN, -TWO_TO_63),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            compare_integer_float(9_007_199_254_740_993, 9_007_199_254_740_992.0),
+            Some(Ordering::Greater)
+        );
+    }
+
+    #[test]
+    fn fractions_and_special_values() {
+        assert_eq!(compare_integer_float(1, 1.5), Some(Ordering::Less));
+        assert_eq!(compare_integer_float(-1, -1.5), Some(Ordering::Greater));
+        assert_eq!(compare_integer_float(-2, -1.5), Some(Ordering::Less));
+        assert_eq!(compare_integer_float(0, -0.0), Some(Ordering::Equal));
+        assert_eq!(
+            compare_integer_float(0, f64::NEG_INFINITY),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(compare_integer_float(0, f64::NAN), None);
+    }
+}
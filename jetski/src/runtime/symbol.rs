@@ -1,13 +1,17 @@
 use lazy_static::lazy_static;
+use std::cell::Cell;
 use std::collections::BTreeSet;
 use std::hash::{Hash, Hasher};
-use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 
 lazy_static! {
     static ref STATIC_NAMES: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
 }
 
+/// Numbers that distinguish uninterned symbols. Zero is reserved for interned symbols.
+static NEXT_UNINTERNED: AtomicUsize = AtomicUsize::new(1);
+
 fn static_name<T: AsRef<str> + ToString>(name: T) -> &'static str {
     let mut container = STATIC_NAMES.lock().unwrap();
     let s = match container.get(name.as_ref()) {
@@ -30,12 +34,15 @@ fn static_name<T: AsRef<str> + ToString>(name: T) -> &'static str {
 #[derive(Copy, Clone)]
 pub struct Symbol {
     name: &'static str,
+    /// Zero for interned symbols, and a number unique to the symbol otherwise.
+    uninterned: usize,
 }
 
 impl Symbol {
     pub fn new<T: AsRef<str> + ToString>(name: T) -> Self {
         Symbol {
             name: static_name(name),
+            uninterned: 0,
         }
     }
 
@@ -43,8 +50,53 @@ impl Symbol {
         self.name
     }
 
-    pub fn id(&self) -> usize {
-        self.name as *const _ as *const u8 as usize
+    /// Identifies the symbol: the address of its name, and the number of an uninterned symbol.
+    pub fn id(&self) -> (usize, usize) {
+        (self.name.as_ptr() as usize, self.uninterned)
+    }
+
+    /// Generate a fresh symbol.
+    ///
+    /// The symbol is uninterned, so it differs from every other symbol, even from symbols with
+    /// the same name. Its name is `base` followed by a dot and the next number from `counter`.
+    pub fn gensym<T: AsRef<str>>(base: T, counter: &GensymCounter) -> Self {
+        Symbol::uninterned(format!("{}.{}", base.as_ref(), counter.next()))
+    }
+
+    /// Create a symbol that differs from every other symbol, even from symbols with the same name.
+    /// The name itself is shared with the interned symbol of that name.
+    ///
+    /// Uninterned symbols print as `#:` followed by their name. The reader reads that notation
+    /// back as uninterned symbols, but never as the symbols that were printed.
+    pub fn uninterned<T: AsRef<str> + ToString>(name: T) -> Self {
+        Symbol {
+            name: static_name(name),
+            uninterned: NEXT_UNINTERNED.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn is_interned(&self) -> bool {
+        self.uninterned == 0
+    }
+}
+
+/// Numbering of generated symbols.
+///
+/// Each compilation should use its own counter, so that generated names are deterministic.
+#[derive(Debug, Default)]
+pub struct GensymCounter {
+    next: Cell<usize>,
+}
+
+impl GensymCounter {
+    pub fn new() -> Self {
+        GensymCounter::default()
+    }
+
+    fn next(&self) -> usize {
+        let n = self.next.get();
+        self.next.set(n + 1);
+        n
     }
 }
 
@@ -72,20 +124,22 @@ impl Hash for Symbol {
     fn hash<H: Hasher>(&self, state: &mut H) {
         // Since symbols are supposed to be identifiable by pointer
         // we can hash the address rather than the whole string.
-        let id = self.name as *const _ as *const u8 as usize;
-        id.hash(state);
+        self.id().hash(state);
     }
 }
 
 impl std::fmt::Display for Symbol {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if !self.is_interned() {
+            write!(f, "#:")?;
+        }
         write!(f, "{}", self.name)
     }
 }
 
 impl std::fmt::Debug for Symbol {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+        write!(f, "{}", self)
     }
 }
 
@@ -147,6 +201,45 @@ mod tests {
         assert_ne!(a1, b1);
     }
 
+    #[test]
+    fn gensyms_are_fresh() {
+        let counter = GensymCounter::new();
+        let x = Symbol::new("x.0");
+        let g0 = Symbol::gensym("x", &counter);
+        let g1 = Symbol::gensym("x", &counter);
+
+        assert_eq!(g0.name(), "x.0");
+        assert_eq!(g1.name(), "x.1");
+        assert_ne!(g0, x);
+        assert_ne!(g0, g1);
+        let copy = g0;
+        assert_eq!(g0, copy);
+        assert!(x.is_interned());
+        assert!(!g0.is_interned());
+        assert_eq!(x.to_string(), "x.0");
+        assert_eq!(g0.to_string(), "#:x.0");
+    }
+
+    #[test]
+    fn uninterned_symbols_share_names() {
+        let a = Symbol::uninterned("shared");
+        let b = Symbol::uninterned("shared");
+
+        assert_eq!(a.name().as_ptr(), Symbol::new("shared").name().as_ptr());
+        assert_eq!(a.name().as_ptr(), b.name().as_ptr());
+        assert_ne!(a, b);
+        assert_ne!(a, Symbol::new("shared"));
+    }
+
+    #[test]
+    fn gensym_numbering_is_per_counter() {
+        let a = Symbol::gensym("t", &GensymCounter::new());
+        let b = Symbol::gensym("t", &GensymCounter::new());
+
+        assert_eq!(a.name(), b.name());
+        assert_ne!(a, b);
+    }
+
     #[test]
     fn symbol_order_is_consistent() {
         let a1 = Symbol::new("A");
@@ -1,6 +1,4 @@
 #[macro_use]
-mod expression_matcher;
-#[macro_use]
 mod scheme;
 #[macro_use]
 mod scheme_matcher;
@@ -50,7 +50,6 @@ macro_rules! primitive {
 #[cfg(test)]
 mod tests {
     use crate::object::Object;
-    use crate::SchemeExpression;
 
     #[test]
     fn cons_symbols() {
@@ -14,6 +14,22 @@ macro_rules! switch {
             .unwrap_or_else(|| switch!($exp, $($rest)*))
     };
 
+    ($exp:expr, [$($template:tt)*] if $guard:expr => $action:expr) => {
+        switch!($exp, [$($template)*] if $guard => $action,)
+    };
+
+    ($exp:expr, [$($template:tt)*] if $guard:expr => $action:expr,) => {
+        scheme_match!($exp, { if $guard { Some($action) } else { None } }, $($template)*)
+            .unwrap_or(None)
+            .expect("Last clause in switch must never fail to match")
+    };
+
+    ($exp:expr, [$($template:tt)*] if $guard:expr => $action:expr, $($rest:tt)*) => {
+        scheme_match!($exp, { if $guard { Some($action) } else { None } }, $($template)*)
+            .unwrap_or(None)
+            .unwrap_or_else(|| switch!($exp, $($rest)*))
+    };
+
     ($exp:expr, $predicate:expr => $action:expr,) => {
         if $predicate($exp) {
             $action
@@ -31,8 +47,181 @@ macro_rules! switch {
     };
 }
 
+/// Like `switch!`, but every action must evaluate to a `Result`, and an input that matches no
+/// clause produces a syntax error listing the expected shapes instead of panicking.
+/// Actions are expanded inline, so `return` and `?` leave the enclosing function. For this to
+/// work, only the `?x` pattern variables of a template are visible to its action.
+#[macro_export]
+macro_rules! try_switch {
+    (@clauses $exp:expr, [$($expected:expr),*], [$($template:tt)*] => $action:expr) => {
+        try_switch!(@clauses $exp, [$($expected),*], [$($template)*] => $action,)
+    };
+
+    (@clauses $exp:expr, [$($expected:expr),*], [$($template:tt)*] => $action:expr, $($rest:tt)*) => {
+        try_switch!(@binders [] [$($template)*] $exp, [$($expected),*], [$($template)*], [],
+                    $action, stringify!($($template)*), $($rest)*)
+    };
+
+    (@clauses $exp:expr, [$($expected:expr),*], [$($template:tt)*] if $guard:expr => $action:expr) => {
+        try_switch!(@clauses $exp, [$($expected),*], [$($template)*] if $guard => $action,)
+    };
+
+    (@clauses $exp:expr, [$($expected:expr),*], [$($template:tt)*] if $guard:expr => $action:expr, $($rest:tt)*) => {
+        try_switch!(@binders [] [$($template)*] $exp, [$($expected),*], [$($template)*], [$guard],
+                    $action, concat!(stringify!($($template)*), " if ", stringify!($guard)), $($rest)*)
+    };
+
+    (@clauses $exp:expr, [$($expected:expr),*], $predicate:expr => $action:expr) => {
+        try_switch!(@clauses $exp, [$($expected),*], $predicate => $action,)
+    };
+
+    (@clauses $exp:expr, [$($expected:expr),*], $predicate:expr => $action:expr, $($rest:tt)*) => {
+        if $predicate($exp) {
+            $action
+        } else {
+            try_switch!(@clauses $exp, [$($expected,)* stringify!($predicate)], $($rest)*)
+        }
+    };
+
+    (@clauses $exp:expr, [$($expected:expr),*],) => {
+        Err($crate::Error::from($crate::ErrorKind::SyntaxError(format!(
+            "{:?} does not match any of: {}",
+            $exp,
+            [$($expected),*].join(", ")
+        ))))
+    };
+
+    // collect the pattern variables of a template, then match it and pass the bindings out
+    (@binders [$($found:ident)*] [(? or $($alt:tt)*) $($more:tt)*] $($args:tt)*) => {
+        try_switch!(@binders [$($found)*] [$($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [? $var:ident $($more:tt)*] $($args:tt)*) => {
+        try_switch!(@binders [$($found)* $var] [$($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [($($inner:tt)*) $($more:tt)*] $($args:tt)*) => {
+        try_switch!(@binders [$($found)*] [$($inner)* $($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [$other:tt $($more:tt)*] $($args:tt)*) => {
+        try_switch!(@binders [$($found)*] [$($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [] $exp:expr, [$($expected:expr),*], [$($template:tt)*],
+     [$($guard:expr)?], $action:expr, $description:expr, $($rest:tt)*) => {
+        match scheme_match!($exp, {
+            $(if !($guard) { None } else)? { Some(scheme_ellipsis!(@tuple [$($found)*])) }
+        }, $($template)*).unwrap_or(None) {
+            // variables used only by the guard are bound again here
+            #[allow(unused_variables)]
+            Some(scheme_ellipsis!(@tuple [$($found)*])) => $action,
+            None => try_switch!(@clauses $exp, [$($expected,)* $description], $($rest)*),
+        }
+    };
+
+    ($exp:expr, $($clauses:tt)*) => {
+        try_switch!(@clauses $exp, [], $($clauses)*)
+    };
+}
+
+/// Match an expression against a Scheme-shaped pattern.
+///
+/// Evaluates to `Some(action)` if the pattern matches and `None` otherwise.
+/// Pattern syntax:
+///  - `_` matches anything
+///  - `?x` matches anything and binds it to `x`
+///  - `?x:symbol` matches only the given type (`symbol`, `integer`, `float`, `number`, `string`,
+///    `pair` or `null`) and binds it to `x`
+///  - `foo` and `42` match the symbol `foo` and the value `42`
+///  - `(?or lambda λ)` matches any one of several symbols (or values). The `?` keeps `(or ...)`
+///    an ordinary list pattern, so `(or ?x ...)` still matches Scheme's `or` form. In turn, `or`
+///    can't be used as the name of a pattern variable.
+///  - `(a b . c)` matches lists and dotted lists
+///  - `(a ... b)` matches zero or more elements, binding each pattern variable in `a` to a `Vec`
+///
+/// Rust patterns can be used as well, which is useful for matching enum variants:
+///  - `{Int(x)}` matches if the expression matches the Rust pattern `Int(x)`
+///  - `[a, Int(x), ..rest]` matches a list whose elements match the Rust patterns `a` and `Int(x)`,
+///    binding the remaining list to `rest`. `[a, b]` matches a two-element list and `[a, ..]`
+///    ignores the rest of the list. Elements can also be nested `[...]` or Scheme-shaped `(...)`.
 #[macro_export]
 macro_rules! scheme_match {
+    // Rust pattern mode: elements of [...] lists
+    (@rust $exp:expr, $action:block, [$($inner:tt)*]) => {
+        scheme_match!($exp, $action, [$($inner)*])
+    };
+
+    (@rust $exp:expr, $action:block, ($($inner:tt)*)) => {
+        scheme_match!($exp, $action, ($($inner)*))
+    };
+
+    (@rust $exp:expr, $action:block, $atom:pat) => {
+        match $exp {
+            $atom => Some($action),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    };
+
+    ($exp:expr, $action:block, (? or $($alt:ident)+)) => {{
+        let _alt = $exp;
+        if $(scheme_match!(_alt, {}, $alt).is_some())||+ {
+            Some($action)
+        } else {
+            None
+        }
+    }};
+
+    ($exp:expr, $action:block, (? or $($alt:literal)+)) => {{
+        let _alt = $exp;
+        if $(scheme_match!(_alt, {}, $alt).is_some())||+ {
+            Some($action)
+        } else {
+            None
+        }
+    }};
+
+    ($exp:expr, $action:block, (? $var:ident : $ty:ident ... . $($tail:tt)*)) => {
+        scheme_ellipsis!(@binders [] [? $var] $exp, $action, [? $var : $ty], [$($tail)*])
+    };
+
+    ($exp:expr, $action:block, (? $var:ident : $ty:ident ... $($rest:tt)*)) => {
+        scheme_ellipsis!(@binders [] [? $var] $exp, $action, [? $var : $ty], [($($rest)*)])
+    };
+
+    ($exp:expr, $action:block, (? $var:ident : $ty:ident . $($cdr:tt)*)) => {
+        $exp.decons()
+            .and_then(|(_car, _cdr)| scheme_match!(_car, {
+                scheme_match!(_cdr, $action, $($cdr)*)
+            }, ? $var : $ty)).unwrap_or(None)
+    };
+
+    ($exp:expr, $action:block, (? $var:ident : $ty:ident $($rest:tt)*)) => {
+        $exp.decons()
+            .and_then(|(_car, _cdr)| {
+                scheme_match!(_car, {
+                    scheme_match!(_cdr, $action, ($($rest)*))
+                }, ? $var : $ty).unwrap_or(None)
+            })
+    };
+
+    ($exp:expr, $action:block, (? $var:ident ... . $($tail:tt)*)) => {
+        scheme_ellipsis!(@binders [] [? $var] $exp, $action, [? $var], [$($tail)*])
+    };
+
+    ($exp:expr, $action:block, (? $var:ident ... $($rest:tt)*)) => {
+        scheme_ellipsis!(@binders [] [? $var] $exp, $action, [? $var], [($($rest)*)])
+    };
+
+    ($exp:expr, $action:block, ($sub:tt ... . $($tail:tt)*)) => {
+        scheme_ellipsis!(@binders [] [$sub] $exp, $action, [$sub], [$($tail)*])
+    };
+
+    ($exp:expr, $action:block, ($sub:tt ... $($rest:tt)*)) => {
+        scheme_ellipsis!(@binders [] [$sub] $exp, $action, [$sub], [($($rest)*)])
+    };
+
     ($exp:expr, $action:block, ($single:tt)) => {
         $exp.decons()
             .filter(|(_, cdr)| cdr.is_nil())
@@ -77,6 +266,72 @@ macro_rules! scheme_match {
             })
     };
 
+    ($exp:expr, $action:block, []) => {
+        scheme_match!($exp, $action, ())
+    };
+
+    ($exp:expr, $action:block, [$single:tt]) => {
+        $exp.cdr()
+            .filter(|cdr| cdr.is_nil())
+            .and_then(|_| $exp.car())
+            .and_then(|car| scheme_match!(@rust car, $action, $single))
+    };
+
+    ($exp:expr, $action:block, [$single:tt, ..]) => {
+        $exp.car().and_then(|car| {
+            scheme_match!(@rust car, $action, $single)
+        })
+    };
+
+    ($exp:expr, $action:block, [$single:tt, .. $($tail:tt)*]) => {
+        $exp.decons().map(|(car, cdr)|{
+            scheme_match!(@rust car, {
+                scheme_match!(@rust cdr, $action, $($tail)*)
+            }, $single).unwrap_or(None)
+        }).unwrap_or(None)
+    };
+
+    ($exp:expr, $action:block, [$single_variant:tt $single_value:tt, ..]) => {
+        $exp.car().and_then(|car| {
+            scheme_match!(@rust car, $action, $single_variant $single_value)
+        })
+    };
+
+    ($exp:expr, $action:block, [$single_variant:tt $single_value:tt, .. $($tail:tt)*]) => {
+        $exp.decons().map(|(car, cdr)|{
+            scheme_match!(@rust car, {
+                scheme_match!(@rust cdr, $action, $($tail)*)
+            }, $single_variant $single_value).unwrap_or(None)
+        }).unwrap_or(None)
+    };
+
+    ($exp:expr, $action:block, [$single_variant:tt $single_value:tt]) => {
+        $exp.cdr()
+            .filter(|cdr| cdr.is_nil())
+            .and_then(|_| $exp.car())
+            .and_then(|car| scheme_match!(@rust car, $action, $single_variant $single_value))
+    };
+
+    ($exp:expr, $action:block, [$first:tt, $($rest:tt)*]) => {
+        $exp.car()
+            .and_then(|car| scheme_match!(@rust car, {
+                scheme_match!($exp.cdr().unwrap(), $action, [$($rest)*])
+            }, $first))
+            .unwrap_or(None)
+    };
+
+    ($exp:expr, $action:block, [$first_variant:tt $first_value:tt, $($rest:tt)*]) => {
+        $exp.car()
+            .and_then(|car| scheme_match!(@rust car, {
+                scheme_match!($exp.cdr().unwrap(), $action, [$($rest)*])
+            }, $first_variant $first_value))
+            .unwrap_or(None)
+    };
+
+    ($exp:expr, $action:block, {$atom:pat}) => {
+        scheme_match!(@rust $exp, $action, $atom)
+    };
+
     ($exp:expr, $action:block, ()) => {
         if $exp.is_nil() {
             Some($action)
@@ -89,6 +344,16 @@ macro_rules! scheme_match {
         Some($action)
     };
 
+    ($exp:expr, $action:block, ? $var:ident : $ty:ident) => {{
+        let _typed = $exp;
+        if scheme_type!(_typed, $ty) {
+            let $var = _typed;
+            Some($action)
+        } else {
+            None
+        }
+    }};
+
     ($exp:expr, $action:block, ? $var:ident) => {
         {
             let $var = $exp;
@@ -113,6 +378,106 @@ macro_rules! scheme_match {
     };
 }
 
+/// Implementation detail of `scheme_match!`: checks the type of a type-restricted pattern variable.
+#[macro_export]
+macro_rules! scheme_type {
+    ($exp:expr, symbol) => {
+        $exp.symbol_name().is_some()
+    };
+
+    ($exp:expr, integer) => {
+        $exp.is_integer()
+    };
+
+    ($exp:expr, float) => {
+        $exp.is_float()
+    };
+
+    ($exp:expr, number) => {
+        $exp.is_integer() || $exp.is_float()
+    };
+
+    ($exp:expr, string) => {
+        $exp.is_string()
+    };
+
+    ($exp:expr, pair) => {
+        $exp.car().is_some()
+    };
+
+    ($exp:expr, null) => {
+        $exp.is_nil()
+    };
+}
+
+/// Implementation detail of `scheme_match!`: matches a sub-pattern followed by an ellipsis.
+///
+/// The ellipsis consumes as many list elements as possible, as long as each matches the
+/// sub-pattern and the remaining list matches the rest of the pattern. Every pattern variable
+/// in the sub-pattern is bound to a `Vec` holding one entry per consumed element.
+#[macro_export]
+macro_rules! scheme_ellipsis {
+    // collect the names of all pattern variables in the sub-pattern
+    (@binders [$($found:ident)*] [(? or $($alt:tt)*) $($more:tt)*] $($args:tt)*) => {
+        scheme_ellipsis!(@binders [$($found)*] [$($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [? $var:ident $($more:tt)*] $($args:tt)*) => {
+        scheme_ellipsis!(@binders [$($found)* $var] [$($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [($($inner:tt)*) $($more:tt)*] $($args:tt)*) => {
+        scheme_ellipsis!(@binders [$($found)*] [$($inner)* $($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [$other:tt $($more:tt)*] $($args:tt)*) => {
+        scheme_ellipsis!(@binders [$($found)*] [$($more)*] $($args)*)
+    };
+
+    (@binders [$($found:ident)*] [] $exp:expr, $action:block, [$($sub:tt)*], [$($rest:tt)*]) => {{
+        let mut _cursor = $exp;
+        let mut _cursors = vec![_cursor];
+        let mut _matches = vec![];
+        while let Some((_car, _cdr)) = _cursor.decons() {
+            match scheme_match!(_car, { scheme_ellipsis!(@tuple [$($found)*]) }, $($sub)*) {
+                Some(m) => _matches.push(m),
+                None => break,
+            }
+            _cursor = _cdr;
+            _cursors.push(_cursor);
+        }
+        #[allow(unused_variables)]
+        let _n_matched = (0..=_matches.len())
+            .rev()
+            .find(|&i| scheme_match!(_cursors[i], {}, $($rest)*).is_some());
+        _n_matched.and_then(|i| {
+            _matches.truncate(i);
+            let _tail = _cursors[i];
+            scheme_ellipsis!(@unzip _matches, [$($found)*], {
+                scheme_match!(_tail, $action, $($rest)*)
+            })
+        })
+    }};
+
+    // the bindings of one sub-match are stored as nested pairs: (a, (b, (c, ())))
+    (@tuple []) => {
+        ()
+    };
+
+    (@tuple [$first:ident $($rest:ident)*]) => {
+        ($first, scheme_ellipsis!(@tuple [$($rest)*]))
+    };
+
+    (@unzip $matches:ident, [], $body:block) => {
+        $body
+    };
+
+    (@unzip $matches:ident, [$first:ident $($rest:ident)*], $body:block) => {{
+        let ($first, _rest): (Vec<_>, Vec<_>) = $matches.into_iter().unzip();
+        scheme_ellipsis!(@unzip _rest, [$($rest)*], $body)
+    }};
+}
+
 #[cfg(test)]
 mod tests {
     use crate::SchemeExpression;
@@ -160,6 +525,10 @@ mod tests {
         fn is_nil(&self) -> bool {
             *self == Nil
         }
+
+        fn is_integer(&self) -> bool {
+            matches!(self, Int(_))
+        }
     }
 
     impl PartialEq<i64> for Expr {
@@ -260,14 +629,14 @@ mod tests {
     fn switch_one_clause() {
         assert_eq!(
             switch! { &Int(5),
-                [?x] => { x }
+                [?x] => x
             },
             &Int(5)
         );
 
         assert_eq!(
             switch! { &Nil,
-                [_] => { 42 }
+                [_] => 42
             },
             42
         );
@@ -304,4 +673,430 @@ mod tests {
             2
         )
     }
+
+    fn list(items: Vec<Expr>) -> Expr {
+        items
+            .into_iter()
+            .rev()
+            .fold(Nil, |cdr, car| Expr::cons(car, cdr))
+    }
+
+    #[test]
+    fn ellipsis_match() {
+        let list = list(vec![Int(1), Int(2), Int(3)]);
+        assert_eq!(
+            Some(vec![&Int(1), &Int(2), &Int(3)]),
+            scheme_match!(&list, { x }, (?x ...))
+        );
+        assert_eq!(Some(()), scheme_match!(&list, {}, (_ ...)));
+        assert_eq!(Some(vec![]), scheme_match!(&Nil, { x }, (?x ...)));
+    }
+
+    #[test]
+    fn ellipsis_mismatch() {
+        let list = list(vec![Int(1), Int(2), Int(3)]);
+        assert_eq!(None, scheme_match!(&list, {}, (1 ...)));
+        assert_eq!(None, scheme_match!(&Int(1), {}, (_ ...)));
+    }
+
+    #[test]
+    fn ellipsis_with_prefix_and_suffix() {
+        let list = list(vec![Int(0), Int(1), Int(2), Int(3)]);
+        assert_eq!(
+            Some((&Int(0), vec![&Int(1), &Int(2)], &Int(3))),
+            scheme_match!(&list, { (a, b, c) }, (?a ?b ... ?c))
+        );
+        assert_eq!(
+            Some((vec![], &Int(2), &Int(3))),
+            scheme_match!(&list, { (a, b, c) }, (0 1 ?a ... ?b ?c))
+        );
+        assert_eq!(None, scheme_match!(&list, {}, (_ _ _ _ _ ...  _)));
+    }
+
+    #[test]
+    fn ellipsis_with_dotted_tail() {
+        let pair = Expr::cons(Int(1), Expr::cons(Int(2), Int(3)));
+        assert_eq!(
+            Some((vec![&Int(1), &Int(2)], &Int(3))),
+            scheme_match!(&pair, { (x, y) }, (?x ... . ?y))
+        );
+
+        let list = list(vec![Int(1), Int(2)]);
+        assert_eq!(
+            Some((vec![&Int(1), &Int(2)], &Nil)),
+            scheme_match!(&list, { (x, y) }, (?x ... . ?y))
+        );
+    }
+
+    #[test]
+    fn ellipsis_of_lists() {
+        // (let ((a 1) (b 2)) a b)
+        let list = list(vec![
+            Symbol("let"),
+            list(vec![
+                list(vec![Symbol("a"), Int(1)]),
+                list(vec![Symbol("b"), Int(2)]),
+            ]),
+            Symbol("a"),
+            Symbol("b"),
+        ]);
+        assert_eq!(
+            Some((
+                vec![&Symbol("a"), &Symbol("b")],
+                vec![&Int(1), &Int(2)],
+                vec![&Symbol("a"), &Symbol("b")]
+            )),
+            scheme_match!(&list, { (name, init, body) }, (let ((?name ?init) ...) ?body ...))
+        );
+        assert_eq!(None, scheme_match!(&list, {}, (let ((_ 1) ...) _ ...)));
+    }
+
+    #[test]
+    fn nested_ellipsis() {
+        let list = list(vec![
+            list(vec![Int(1), Int(2)]),
+            list(vec![]),
+            list(vec![Int(3)]),
+        ]);
+        assert_eq!(
+            Some(vec![vec![&Int(1), &Int(2)], vec![], vec![&Int(3)]]),
+            scheme_match!(&list, { x }, ((?x ...) ...))
+        );
+    }
+
+    #[test]
+    fn switch_ellipsis() {
+        let list = list(vec![Symbol("and"), Int(1), Int(2)]);
+        assert_eq!(
+            switch! { &list,
+                [(or ?x ...)] => x.len() + 10,
+                [(and ?x ...)] => x.len(),
+                [_] => 0,
+            },
+            2
+        );
+    }
+
+    #[test]
+    fn try_switch_match() {
+        let result: crate::Result<_> = try_switch! { &Int(5),
+            [4] => Ok(&Nil),
+            [?x] => Ok(x),
+        };
+        assert_eq!(result.unwrap(), &Int(5));
+
+        let result: crate::Result<_> = try_switch! { &Int(4),
+            |_| false => Ok(1),
+            |_| true => Ok(2),
+        };
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[test]
+    fn try_switch_error() {
+        let result: crate::Result<()> = try_switch! { &Int(5),
+            [1] => Ok(()),
+            [(foo ?x)] => Ok(()),
+            |_| false => Ok(()),
+        };
+        match result.unwrap_err().kind() {
+            crate::ErrorKind::SyntaxError(msg) => {
+                assert!(
+                    msg.starts_with("Int(5) does not match any of: 1, (foo ?x), |_| false"),
+                    "{}",
+                    msg
+                )
+            }
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn try_switch_actions_leave_the_enclosing_function() {
+        fn describe(x: &Expr) -> crate::Result<&'static str> {
+            let n = try_switch! { x,
+                [(?_op ?n)] if n == &Nil => return Ok("nil operand"),
+                [(?_op ?n)] => Ok(n),
+                [?n] => Ok(n),
+            }?;
+            Ok(if n == &Int(0) { "zero" } else { "other" })
+        }
+        let call = list(vec![Symbol("f"), Nil]);
+        assert_eq!(describe(&call).unwrap(), "nil operand");
+        assert_eq!(describe(&Int(0)).unwrap(), "zero");
+        assert_eq!(describe(&Int(1)).unwrap(), "other");
+    }
+
+    #[test]
+    fn typed_binder() {
+        assert_eq!(Some(&Int(1)), scheme_match!(&Int(1), { x }, ?x:integer));
+        assert_eq!(Some(&Int(1)), scheme_match!(&Int(1), { x }, ?x:number));
+        assert_eq!(None, scheme_match!(&Int(1), { x }, ?x:symbol));
+        assert_eq!(
+            Some(&Symbol("a")),
+            scheme_match!(&Symbol("a"), { x }, ?x:symbol)
+        );
+        assert_eq!(Some(&Nil), scheme_match!(&Nil, { x }, ?x:null));
+        assert_eq!(None, scheme_match!(&Nil, { x }, ?x:pair));
+    }
+
+    #[test]
+    fn typed_binder_in_list() {
+        let list = list(vec![Symbol("f"), Int(1), Int(2)]);
+        assert_eq!(
+            Some((&Symbol("f"), vec![&Int(1), &Int(2)])),
+            scheme_match!(&list, { (f, args) }, (?f:symbol ?args:integer ...))
+        );
+        assert_eq!(
+            Some((&Symbol("f"), &Int(1))),
+            scheme_match!(&list, { (f, x) }, (?f:symbol ?x:integer _))
+        );
+        assert_eq!(
+            Some(&Symbol("f")),
+            scheme_match!(&list, { f }, (?f:symbol . ?_args:pair))
+        );
+        assert_eq!(None, scheme_match!(&list, {}, (?_f:integer . _)));
+        assert_eq!(None, scheme_match!(&list, {}, (_ ?_x:symbol ...)));
+    }
+
+    #[test]
+    fn alternatives() {
+        let lambda = list(vec![Symbol("lambda"), Nil, Int(1)]);
+        let lambda2 = list(vec![Symbol("λ"), Nil, Int(1)]);
+        let other = list(vec![Symbol("let"), Nil, Int(1)]);
+        assert_eq!(Some(()), scheme_match!(&lambda, {}, ((?or lambda λ) () _)));
+        assert_eq!(Some(()), scheme_match!(&lambda2, {}, ((?or lambda λ) () _)));
+        assert_eq!(None, scheme_match!(&other, {}, ((?or lambda λ) () _)));
+        assert_eq!(Some(()), scheme_match!(&Int(3), {}, (?or 1 2 3)));
+        assert_eq!(None, scheme_match!(&Int(4), {}, (?or 1 2 3)));
+    }
+
+    #[test]
+    fn alternatives_next_to_pattern_variables() {
+        let lambda = list(vec![Symbol("λ"), Int(1), Int(2)]);
+        assert_eq!(
+            Some(vec![&Int(1), &Int(2)]),
+            scheme_match!(&lambda, { x }, ((?or lambda λ) ?x ...))
+        );
+        let result: crate::Result<_> = try_switch! { &lambda,
+            [((?or lambda λ) ?x . _)] => Ok(x),
+        };
+        assert_eq!(result.unwrap(), &Int(1));
+    }
+
+    #[test]
+    fn or_form_is_a_literal_pattern() {
+        let list = list(vec![Symbol("or"), Symbol("a"), Symbol("b")]);
+        assert_eq!(
+            Some(vec![&Symbol("a"), &Symbol("b")]),
+            scheme_match!(&list, { x }, (or ?x ...))
+        );
+        assert_eq!(Some(()), scheme_match!(&list, {}, (or a b)));
+        assert_eq!(None, scheme_match!(&Symbol("a"), {}, (or a b)));
+    }
+
+    #[test]
+    fn switch_guards() {
+        let is_even = |x: &Expr| if let Int(i) = x { i % 2 == 0 } else { false };
+        let classify = |x: &Expr| {
+            switch! { x,
+                [?n] if is_even(n) => "even",
+                [?n:integer] if !is_even(n) => "odd",
+                [_] => "other",
+            }
+        };
+        assert_eq!(classify(&Int(2)), "even");
+        assert_eq!(classify(&Int(3)), "odd");
+        assert_eq!(classify(&Nil), "other");
+    }
+
+    #[test]
+    fn try_switch_guards() {
+        let result: crate::Result<()> = try_switch! { &Int(5),
+            [?n] if n == &6 => Ok(()),
+        };
+        match result.unwrap_err().kind() {
+            crate::ErrorKind::SyntaxError(msg) => {
+                assert!(
+                    msg.starts_with("Int(5) does not match any of: ?n if n == &6"),
+                    "{}",
+                    msg
+                )
+            }
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    mod rust_patterns {
+        use super::Expr;
+        use super::Expr::*;
+        use crate::SchemeExpression;
+
+        #[test]
+        fn simple_match_constant() {
+            assert_eq!(
+                Some(()),
+                scheme_match!(&Symbol("xyz"), {}, { Symbol("xyz") })
+            );
+            assert_eq!(Some(()), scheme_match!(&Int(42), {}, { Int(42) }));
+        }
+
+        #[test]
+        fn simple_mismatch_constant() {
+            assert_eq!(None, scheme_match!(&Symbol("abc"), {}, { Symbol("xyz") }));
+            assert_eq!(None, scheme_match!(&Symbol("abc"), {}, { Int(1) }));
+            assert_eq!(None, scheme_match!(&Int(42), {}, { Symbol("xyz") }));
+            assert_eq!(None, scheme_match!(&Int(42), {}, { Int(2) }));
+        }
+
+        #[test]
+        fn simple_match_any() {
+            assert_eq!(Some(()), scheme_match!(&Symbol("xyz"), {}, { _ }));
+        }
+
+        #[test]
+        fn simple_match_any_bound() {
+            assert_eq!(
+                Some(&Symbol("y")),
+                scheme_match!(&Symbol("y"), { x }, { x })
+            );
+            assert_eq!(Some(&Int(42)), scheme_match!(&Int(42), { x }, { x }));
+        }
+
+        #[test]
+        fn simple_match_variant_bound() {
+            assert_eq!(
+                Some("y"),
+                scheme_match!(&Symbol("y"), { *x }, { Symbol(x) })
+            );
+            assert_eq!(Some(42), scheme_match!(&Int(42), { *y }, { Int(y) }));
+        }
+
+        #[test]
+        fn unarylist_mismatch() {
+            assert_eq!(None, scheme_match!(&Symbol("abc"), {}, [_]));
+            let pair = Expr::cons(Int(1), Int(2));
+            assert_eq!(None, scheme_match!(&pair, {}, [_]));
+        }
+
+        #[test]
+        fn unarylist_match_any() {
+            let pair = Expr::cons(Int(5), Nil);
+            assert_eq!(Some(()), scheme_match!(&pair, {}, [_]));
+        }
+
+        #[test]
+        fn unarylist_match_any_bound() {
+            let pair = Expr::cons(Int(5), Nil);
+            assert_eq!(Some(&Int(5)), scheme_match!(&pair, { x }, [x]));
+        }
+
+        #[test]
+        fn unarylist_match_variant_bound() {
+            let pair = Expr::cons(Symbol("hello"), Nil);
+            assert_eq!(Some("hello"), scheme_match!(&pair, { *x }, [Symbol(x)]));
+        }
+
+        #[test]
+        fn list_mismatch() {
+            assert_eq!(None, scheme_match!(&Symbol("abc"), {}, [_, _]));
+            let pair = Expr::cons(Int(1), Nil);
+            assert_eq!(None, scheme_match!(&pair, {}, [_, _]));
+        }
+
+        #[test]
+        fn list_match_any() {
+            let list = Expr::cons(Int(1), Expr::cons(Int(2), Expr::cons(Int(3), Nil)));
+            assert_eq!(Some(()), scheme_match!(&list, {}, [_, _, _]));
+        }
+
+        #[test]
+        fn list_match_any_bound() {
+            let list = Expr::cons(Int(1), Expr::cons(Int(2), Expr::cons(Int(3), Nil)));
+            assert_eq!(
+                Some((&Int(1), &Int(2), &Int(3))),
+                scheme_match!(&list, { (x, y, z) }, [x, y, z])
+            );
+        }
+
+        #[test]
+        fn list_match_variant_bound() {
+            let list = Expr::cons(Int(1), Expr::cons(Symbol("two"), Expr::cons(Int(3), Nil)));
+            assert_eq!(
+                Some((1, "two", 3)),
+                scheme_match!(&list, { (*x, *y, *z) }, [Int(x), Symbol(y), Int(z)])
+            );
+        }
+
+        #[test]
+        fn nested_list() {
+            let list = Expr::cons(
+                Int(1),
+                Expr::cons(
+                    Expr::cons(Symbol("2a"), Expr::cons(Symbol("2b"), Nil)),
+                    Expr::cons(Int(3), Nil),
+                ),
+            );
+            assert_eq!(
+                Some((&Int(1), "2b")),
+                scheme_match!(&list, { (x, *y) }, [x, [Symbol("2a"), Symbol(y)], _])
+            );
+        }
+
+        #[test]
+        fn list_match_tail() {
+            let list = Expr::cons(
+                Int(0),
+                Expr::cons(Int(1), Expr::cons(Int(2), Expr::cons(Int(3), Nil))),
+            );
+            assert_eq!(
+                Some(&Expr::cons(Int(2), Expr::cons(Int(3), Nil))),
+                scheme_match!(&list, { rest }, [Int(0), Int(1), ..rest])
+            );
+            assert_eq!(
+                Some(&Expr::cons(Int(2), Expr::cons(Int(3), Nil))),
+                scheme_match!(&list, { rest }, [_, _, ..rest])
+            );
+        }
+
+        #[test]
+        fn list_match_ignore_tail() {
+            let list = Expr::cons(
+                Int(0),
+                Expr::cons(Int(1), Expr::cons(Int(2), Expr::cons(Int(3), Nil))),
+            );
+            assert_eq!(Some(1), scheme_match!(&list, { *x }, [Int(0), Int(x), ..]));
+            assert_eq!(Some(&Int(1)), scheme_match!(&list, { one }, [_, one, ..]));
+        }
+
+        #[test]
+        fn pair_match() {
+            let list = Expr::cons(Int(0), Int(1));
+            assert_eq!(
+                Some((0, 1)),
+                scheme_match!(&list, { (*a, *b) }, [Int(a), ..Int(b)])
+            );
+            assert_eq!(
+                Some((&Int(0), &Int(1))),
+                scheme_match!(&list, { (a, b) }, [a, ..b])
+            );
+        }
+
+        #[test]
+        fn mixed_syntax() {
+            let list = super::list(vec![
+                Symbol("define"),
+                super::list(vec![Symbol("f"), Symbol("x")]),
+                Int(42),
+            ]);
+            assert_eq!(
+                Some(("f", &Symbol("x"), 42)),
+                scheme_match!(&list, { (*f, x, *y) }, (define [Symbol(f), x] {Int(y)}))
+            );
+            assert_eq!(
+                Some((vec![&Symbol("x")], &Int(42))),
+                scheme_match!(&list, { (x, y) }, [Symbol("define"), (f ?x ...), y])
+            );
+        }
+    }
 }
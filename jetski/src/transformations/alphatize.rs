@@ -4,126 +4,649 @@
 use super::SourceTransformer;
 use crate::error::{ErrorKind, Result};
 use crate::object::{ListBuilder, Object};
-use crate::runtime::Symbol;
+use crate::runtime::{GensymCounter, Symbol};
 use crate::SchemeExpression;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet};
 
-struct Alphatizer {
-    symbol_counter: usize,
+#[derive(Default)]
+pub struct Alphatizer {
+    gensym: GensymCounter,
+    globals: HashSet<Symbol>,
 }
 
 impl SourceTransformer for Alphatizer {
     fn transform(&mut self, input: &Object) -> Result<Object> {
-        let global_scope = Scope::new();
-        self.transform_recursive(input, &global_scope)
+        let mut global_scope = Scope::new();
+        for &name in &self.globals {
+            global_scope.insert(name, name);
+        }
+        let result = self.transform_toplevel(input, &mut global_scope);
+        self.globals.extend(global_scope.into_bindings().keys());
+        result
     }
 }
 
 impl Alphatizer {
     pub fn new() -> Self {
-        Alphatizer { symbol_counter: 0 }
+        Alphatizer {
+            gensym: GensymCounter::new(),
+            globals: HashSet::new(),
+        }
     }
 
-    fn transform_recursive(&mut self, input: &Object, scope: &Scope) -> Result<Object> {
-        switch! {input,
-            [(lambda ?params . ?body)] => {
-                let mut inner_scope = scope.extend();
-                let inserted = inner_scope.insert_vars(params, self);
-                let new_params = self.transform_varlist(params, &inner_scope);
-                let new_body = self.transform_sequence(body, &inner_scope);
-                inserted
-                    .and(new_params)
-                    .and_then(|p| new_body.map(|b| (p, b)))
-                    .map(|(p, b)| list!(lambda, @p, . @b))
-            },
-            [(quote . _)] => Ok(input.clone()),
+    /// Global variables keep their names, so that later inputs can refer to them.
+    fn transform_toplevel(&mut self, input: &Object, scope: &mut Scope<Symbol>) -> Result<Object> {
+        match special_form(input, scope) {
+            Some("begin") => {
+                let mut body = ListBuilder::new();
+                for form in input.cdr().unwrap().list_parts().0 {
+                    body.append(self.transform_toplevel(form, scope)?);
+                }
+                Ok(Object::cons(input.car().unwrap().clone(), body.build()))
+            }
+            Some("define") => {
+                if let Some(name) = definition_name(input, scope) {
+                    scope.insert(name, name);
+                }
+                self.transform_define(input, scope)
+            }
+            _ => self.transform_recursive(input, scope),
+        }
+    }
+
+    fn transform_recursive(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        match special_form(input, scope) {
+            Some("quote") => return Ok(input.clone()),
+            Some("quasiquote") => return self.transform_quasiquote(input, scope),
+            Some("case") => return self.transform_case(input, scope),
+            Some("lambda") => return self.transform_lambda(input, scope),
+            Some("define") => return self.transform_define(input, scope),
+            Some("let") => return self.transform_let(input, scope),
+            Some("let*") => return self.transform_let_star(input, scope),
+            Some("letrec") | Some("letrec*") => return self.transform_letrec(input, scope),
+            Some("let-values") => return self.transform_let_values(input, scope),
+            Some("let*-values") => return self.transform_let_star_values(input, scope),
+            Some("case-lambda") => return self.transform_case_lambda(input, scope),
+            Some("guard") => return self.transform_guard(input, scope),
+            Some("do") => return self.transform_do(input, scope),
+            _ => {}
+        }
+
+        try_switch! {input,
             [(?first . ?tail)] => {
                 self.transform_recursive(first, scope)
                     .and_then(|f| self.transform_sequence(tail, scope)
                         .map(|t| cons!(@f, @t)))
             },
             [?x] => if let Some(s) = x.as_symbol() {
-                Ok(scope.lookup(s).into())
+                Ok(scope.rename(s).into())
             } else {
                 Ok(input.clone())
             },
         }
     }
 
-    fn transform_sequence(&mut self, exps: &Object, scope: &Scope) -> Result<Object> {
+    fn transform_quasiquote(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?template)] => {
+                let template = self.transform_template(template, 1, scope)?;
+                Ok(list!(@input.car().unwrap().clone(), @template))
+            },
+        }
+    }
+
+    /// Templates are quoted, except for the expressions unquoted at the nesting depth of the
+    /// outermost quasiquote.
+    fn transform_template(
+        &mut self,
+        template: &Object,
+        depth: usize,
+        scope: &Scope<Symbol>,
+    ) -> Result<Object> {
+        let keyword = template.car().and_then(Object::as_symbol);
+        let depth = match keyword.as_ref().map(Symbol::name) {
+            Some("unquote") | Some("unquote-splicing") if depth == 1 => {
+                return self.transform_unquote(template, scope)
+            }
+            Some("unquote") | Some("unquote-splicing") => depth - 1,
+            Some("quasiquote") => depth + 1,
+            _ => depth,
+        };
+        match (template.car(), template.cdr()) {
+            (Some(car), Some(cdr)) => Ok(Object::cons(
+                self.transform_template(car, depth, scope)?,
+                self.transform_template(cdr, depth, scope)?,
+            )),
+            _ => Ok(template.clone()),
+        }
+    }
+
+    fn transform_unquote(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?expr)] => {
+                let expr = self.transform_recursive(expr, scope)?;
+                Ok(list!(@input.car().unwrap().clone(), @expr))
+            },
+        }
+    }
+
+    /// The data of the clauses are quoted; `else` is kept as it is.
+    fn transform_case(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?key ?clauses ...)] => {
+                let key = self.transform_recursive(key, scope)?;
+                let mut result = ListBuilder::new();
+                for clause in clauses {
+                    result.append(self.transform_case_clause(clause, scope)?);
+                }
+                Ok(list!(@input.car().unwrap().clone(), @key, . @result.build()))
+            },
+        }
+    }
+
+    fn transform_case_clause(&mut self, clause: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {clause,
+            [(?data . ?body)] => Ok(Object::cons(data.clone(), self.transform_sequence(body, scope)?)),
+        }
+    }
+
+    fn transform_lambda(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(lambda ?params . ?body)] => {
+                let (params, body) = self.transform_function(params, body, scope)?;
+                Ok(list!(lambda, @params, . @body))
+            },
+        }
+    }
+
+    /// Internal definitions have been inserted into the scope by `transform_body`.
+    fn transform_define(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(define (?name:symbol . ?params) . ?body)] => {
+                let name = scope.rename(name.as_symbol().unwrap());
+                let (params, body) = self.transform_function(params, body, scope)?;
+                let signature = Object::cons(name.into(), params);
+                Ok(list!(define, @signature, . @body))
+            },
+            [(define ?name:symbol ?value)] => {
+                let name = scope.rename(name.as_symbol().unwrap());
+                let value = self.transform_recursive(value, scope)?;
+                Ok(list!(define, @Object::from(name), @value))
+            },
+        }
+    }
+
+    fn transform_let(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?name:symbol ((?vars ?inits) ...) . ?body)] => {
+                let inits = self.transform_all(&inits, scope)?;
+                let mut loop_scope = scope.extend();
+                let name = self.insert_var(name, &mut loop_scope)?;
+                let mut inner_scope = loop_scope.extend();
+                let vars = self.insert_all(&vars, &mut inner_scope)?;
+                let body = self.transform_body(body, &inner_scope)?;
+                let bindings = make_bindings(vars, inits);
+                Ok(list!(@input.car().unwrap().clone(), @Object::from(name), @bindings, . @body))
+            },
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let inits = self.transform_all(&inits, scope)?;
+                let mut inner_scope = scope.extend();
+                let vars = self.insert_all(&vars, &mut inner_scope)?;
+                let body = self.transform_body(body, &inner_scope)?;
+                let bindings = make_bindings(vars, inits);
+                Ok(list!(@input.car().unwrap().clone(), @bindings, . @body))
+            },
+        }
+    }
+
+    fn transform_let_star(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let mut bindings = ListBuilder::new();
+                let body = self.transform_sequential(&vars, &inits, body, scope, &mut bindings)?;
+                Ok(list!(@input.car().unwrap().clone(), @bindings.build(), . @body))
+            },
+        }
+    }
+
+    /// Every variable of `let*` is in scope of the following initializers.
+    fn transform_sequential(
+        &mut self,
+        vars: &[&Object],
+        inits: &[&Object],
+        body: &Object,
+        scope: &Scope<Symbol>,
+        bindings: &mut ListBuilder,
+    ) -> Result<Object> {
+        if vars.is_empty() {
+            return self.transform_body(body, scope);
+        }
+        let init = self.transform_recursive(inits[0], scope)?;
+        let mut inner_scope = scope.extend();
+        let var = self.insert_var(vars[0], &mut inner_scope)?;
+        bindings.append(list!(@Object::from(var), @init));
+        self.transform_sequential(&vars[1..], &inits[1..], body, &inner_scope, bindings)
+    }
+
+    fn transform_letrec(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let mut inner_scope = scope.extend();
+                let vars = self.insert_all(&vars, &mut inner_scope)?;
+                let inits = self.transform_all(&inits, &inner_scope)?;
+                let body = self.transform_body(body, &inner_scope)?;
+                let bindings = make_bindings(vars, inits);
+                Ok(list!(@input.car().unwrap().clone(), @bindings, . @body))
+            },
+        }
+    }
+
+    /// The formals of `let-values` are parameter lists, which may be improper or a single
+    /// symbol.
+    fn transform_let_values(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ((?formals ?inits) ...) . ?body)] => {
+                let inits = self.transform_all(&inits, scope)?;
+                let mut inner_scope = scope.extend();
+                for f in &formals {
+                    self.insert_vars(f, &mut inner_scope)?;
+                }
+                let mut bindings = ListBuilder::new();
+                for (f, init) in formals.into_iter().zip(inits) {
+                    bindings.append(list!(@self.transform_varlist(f, &inner_scope)?, @init));
+                }
+                let body = self.transform_body(body, &inner_scope)?;
+                Ok(list!(@input.car().unwrap().clone(), @bindings.build(), . @body))
+            },
+        }
+    }
+
+    fn transform_let_star_values(
+        &mut self,
+        input: &Object,
+        scope: &Scope<Symbol>,
+    ) -> Result<Object> {
+        try_switch! {input,
+            [(_ ((?formals ?inits) ...) . ?body)] => {
+                let mut bindings = ListBuilder::new();
+                let body = self.transform_sequential_values(&formals, &inits, body, scope, &mut bindings)?;
+                Ok(list!(@input.car().unwrap().clone(), @bindings.build(), . @body))
+            },
+        }
+    }
+
+    /// Like `transform_sequential`, but every binding binds a parameter list.
+    fn transform_sequential_values(
+        &mut self,
+        formals: &[&Object],
+        inits: &[&Object],
+        body: &Object,
+        scope: &Scope<Symbol>,
+        bindings: &mut ListBuilder,
+    ) -> Result<Object> {
+        if formals.is_empty() {
+            return self.transform_body(body, scope);
+        }
+        let init = self.transform_recursive(inits[0], scope)?;
+        let mut inner_scope = scope.extend();
+        self.insert_vars(formals[0], &mut inner_scope)?;
+        let renamed = self.transform_varlist(formals[0], &inner_scope)?;
+        bindings.append(list!(@renamed, @init));
+        self.transform_sequential_values(&formals[1..], &inits[1..], body, &inner_scope, bindings)
+    }
+
+    /// Every clause is renamed like a lambda.
+    fn transform_case_lambda(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ (?params . ?bodies) ...)] => {
+                let mut clauses = ListBuilder::new();
+                for (params, body) in params.into_iter().zip(bodies) {
+                    let (params, body) = self.transform_function(params, body, scope)?;
+                    clauses.append(Object::cons(params, body));
+                }
+                Ok(Object::cons(input.car().unwrap().clone(), clauses.build()))
+            },
+        }
+    }
+
+    /// The condition variable is in scope of the clauses, but not of the body.
+    fn transform_guard(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ (?var:symbol . ?clauses) . ?body)] => {
+                let body = self.transform_body(body, scope)?;
+                let mut clause_scope = scope.extend();
+                let var = self.insert_var(var, &mut clause_scope)?;
+                let clauses = clauses.map(|clause| self.transform_sequence(clause, &clause_scope))?;
+                let spec = Object::cons(var.into(), clauses);
+                Ok(list!(@input.car().unwrap().clone(), @spec, . @body))
+            },
+        }
+    }
+
+    fn transform_do(&mut self, input: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        try_switch! {input,
+            [(_ (?specs ...) (?test . ?exprs) . ?commands)] => {
+                let mut vars = vec![];
+                let mut inits = vec![];
+                let mut steps = vec![];
+                for spec in specs {
+                    match spec.list_parts() {
+                        (parts, tail) if tail.is_nil() && (parts.len() == 2 || parts.len() == 3) => {
+                            vars.push(parts[0]);
+                            inits.push(parts[1]);
+                            steps.push(parts.get(2).cloned());
+                        }
+                        _ => {
+                            return Err(ErrorKind::SyntaxError(format!(
+                                "invalid do binding: {:?}",
+                                spec
+                            ))
+                            .into())
+                        }
+                    }
+                }
+
+                let inits = self.transform_all(&inits, scope)?;
+                let mut inner_scope = scope.extend();
+                let vars = self.insert_all(&vars, &mut inner_scope)?;
+                let mut bindings = ListBuilder::new();
+                for ((var, init), step) in vars.into_iter().zip(inits).zip(steps) {
+                    let mut binding = ListBuilder::new();
+                    binding.append(var.into());
+                    binding.append(init);
+                    if let Some(step) = step {
+                        binding.append(self.transform_recursive(step, &inner_scope)?);
+                    }
+                    bindings.append(binding.build());
+                }
+                let clause = Object::cons(
+                    self.transform_recursive(test, &inner_scope)?,
+                    self.transform_sequence(exprs, &inner_scope)?,
+                );
+                let commands = self.transform_sequence(commands, &inner_scope)?;
+                Ok(list!(@input.car().unwrap().clone(), @bindings.build(), @clause, . @commands))
+            },
+        }
+    }
+
+    /// Transform the parameters and body of a lambda or a function definition.
+    fn transform_function(
+        &mut self,
+        params: &Object,
+        body: &Object,
+        scope: &Scope<Symbol>,
+    ) -> Result<(Object, Object)> {
+        let mut inner_scope = scope.extend();
+        self.insert_vars(params, &mut inner_scope)?;
+        let params = self.transform_varlist(params, &inner_scope)?;
+        let body = self.transform_body(body, &inner_scope)?;
+        Ok((params, body))
+    }
+
+    /// Internal definitions are visible in the whole body, but not outside of it.
+    fn transform_body(&mut self, body: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        let mut body_scope = scope.extend();
+        for form in body.list_parts().0 {
+            if let Some(name) = definition_name(form, scope) {
+                self.insert_var(&name.into(), &mut body_scope)?;
+            }
+        }
+        self.transform_sequence(body, &body_scope)
+    }
+
+    fn transform_sequence(&mut self, exps: &Object, scope: &Scope<Symbol>) -> Result<Object> {
         exps.map(|x| self.transform_recursive(x, scope))
     }
 
-    fn transform_varlist(&mut self, vars: &Object, scope: &Scope) -> Result<Object> {
-        vars.map(|x| Ok(scope.lookup(x.as_symbol().unwrap()).into()))
+    fn transform_all(&mut self, exps: &[&Object], scope: &Scope<Symbol>) -> Result<Vec<Object>> {
+        exps.iter()
+            .map(|x| self.transform_recursive(x, scope))
+            .collect()
+    }
+
+    fn transform_varlist(&mut self, vars: &Object, scope: &Scope<Symbol>) -> Result<Object> {
+        let (vars, rest) = vars.list_parts();
+        let mut list = ListBuilder::new();
+        for var in vars {
+            list.append(scope.rename(var.as_symbol().unwrap()).into());
+        }
+        if let Some(rest) = rest.as_symbol() {
+            list.set_cdr(scope.rename(rest).into());
+        }
+        Ok(list.build())
+    }
+
+    /// Insert the parameters of a (possibly improper) parameter list.
+    fn insert_vars(&mut self, vars: &Object, scope: &mut Scope<Symbol>) -> Result<()> {
+        let (vars, rest) = vars.list_parts();
+        for var in vars.into_iter().chain(Some(rest).filter(|r| !r.is_nil())) {
+            self.insert_var(var, scope)?;
+        }
+        Ok(())
+    }
+
+    fn insert_all(&mut self, vars: &[&Object], scope: &mut Scope<Symbol>) -> Result<Vec<Symbol>> {
+        vars.iter().map(|var| self.insert_var(var, scope)).collect()
+    }
+
+    fn insert_var(&mut self, var: &Object, scope: &mut Scope<Symbol>) -> Result<Symbol> {
+        let var = var.as_symbol().ok_or_else(|| {
+            ErrorKind::SyntaxError(format!("variable name is not a symbol: {:?}", var))
+        })?;
+        let new_name = Symbol::gensym(var.name(), &self.gensym);
+        if scope.insert(var, new_name).is_some() {
+            return Err(
+                ErrorKind::SyntaxError(format!("duplicate variable binding: {:?}", var)).into(),
+            );
+        }
+        Ok(new_name)
+    }
+}
+
+/// Validation hook for the output of alphatization: every local variable is bound exactly once,
+/// under a generated name. Global definitions keep their names and are not checked.
+pub fn unique_names(input: &Object) -> std::result::Result<(), String> {
+    check_bindings(input, true, &mut HashSet::new())
+}
+
+fn check_bindings(
+    input: &Object,
+    toplevel: bool,
+    seen: &mut HashSet<Symbol>,
+) -> std::result::Result<(), String> {
+    // local variables are uninterned, so interned keywords are never shadowed here
+    let keyword = input
+        .car()
+        .and_then(Object::as_symbol)
+        .filter(Symbol::is_interned)
+        .map(|keyword| keyword.name());
+
+    match keyword {
+        // templates are data, except for unquoted parts, which are not checked
+        Some("quote") | Some("quasiquote") => return Ok(()),
+        Some("lambda") => check_varlist(input.get_ref(1), seen)?,
+        Some("define") => {
+            let target = input.get_ref(1);
+            let name = target.map(|t| t.car().unwrap_or(t));
+            if !toplevel {
+                check_binding(name, seen)?;
+            }
+            if let Some(params) = target.and_then(Object::cdr) {
+                check_varlist(Some(params), seen)?;
+            }
+        }
+        Some("let-values") | Some("let*-values") => {
+            let bindings = input.get_ref(1).map(|b| b.list_parts().0);
+            for binding in bindings.unwrap_or_default() {
+                check_varlist(binding.car(), seen)?;
+            }
+        }
+        Some("case-lambda") => {
+            for clause in input.list_parts().0.into_iter().skip(1) {
+                check_varlist(clause.car(), seen)?;
+            }
+        }
+        Some("guard") => check_binding(input.get_ref(1).and_then(Object::car), seen)?,
+        Some("let") | Some("let*") | Some("letrec") | Some("letrec*") | Some("do") => {
+            let mut bindings = input.get_ref(1);
+            if bindings.and_then(Object::as_symbol).is_some() {
+                check_binding(bindings, seen)?;
+                bindings = input.get_ref(2);
+            }
+            for binding in bindings.map(|b| b.list_parts().0).unwrap_or_default() {
+                check_binding(binding.car(), seen)?;
+            }
+        }
+        _ => {}
+    }
+
+    let toplevel = toplevel && keyword == Some("begin");
+    for x in input.list_parts().0 {
+        check_bindings(x, toplevel, seen)?;
     }
+    Ok(())
+}
 
-    fn make_unique_symbol(&mut self, s: Symbol) -> Symbol {
-        let id = self.symbol_counter;
-        self.symbol_counter += 1;
-        Symbol::new(format!("{}{}", s.name(), id))
+fn check_varlist(
+    vars: Option<&Object>,
+    seen: &mut HashSet<Symbol>,
+) -> std::result::Result<(), String> {
+    let (vars, rest) = match vars {
+        Some(vars) => vars.list_parts(),
+        None => return Ok(()),
+    };
+    for var in vars.into_iter().chain(Some(rest).filter(|r| !r.is_nil())) {
+        check_binding(Some(var), seen)?;
     }
+    Ok(())
 }
 
-struct Scope<'a> {
-    rename: HashMap<Symbol, Symbol>,
-    parent: Option<&'a Scope<'a>>,
+fn check_binding(
+    var: Option<&Object>,
+    seen: &mut HashSet<Symbol>,
+) -> std::result::Result<(), String> {
+    match var.and_then(Object::as_symbol) {
+        Some(s) if !s.is_interned() && seen.insert(s) => Ok(()),
+        Some(s) if !s.is_interned() => Err(format!("variable is bound more than once: {}", s)),
+        _ => Err(format!("variable binding was not renamed: {:?}", var)),
+    }
 }
 
-impl<'a> Scope<'a> {
+/// The keyword of a special form, unless it is shadowed by a variable.
+fn special_form(input: &Object, scope: &Scope<Symbol>) -> Option<&'static str> {
+    input
+        .car()
+        .and_then(Object::as_symbol)
+        .filter(|&keyword| scope.lookup(keyword).is_none())
+        .map(|keyword| keyword.name())
+}
+
+/// The variable defined by `(define name value)` or `(define (name . params) . body)`.
+fn definition_name(input: &Object, scope: &Scope<Symbol>) -> Option<Symbol> {
+    if special_form(input, scope) != Some("define") {
+        return None;
+    }
+    let target = input.get_ref(1)?;
+    target.car().unwrap_or(target).as_symbol()
+}
+
+fn make_bindings(vars: Vec<Symbol>, inits: Vec<Object>) -> Object {
+    let mut bindings = ListBuilder::new();
+    for (var, init) in vars.into_iter().zip(inits) {
+        bindings.append(list!(@Object::from(var), @init));
+    }
+    bindings.build()
+}
+
+/// Lexical scope that maps names to what they are bound to.
+/// Alphatization binds each variable to its new name; other transforms bind richer information.
+pub(super) struct Scope<'a, T> {
+    bindings: HashMap<Symbol, T>,
+    parent: Option<&'a Scope<'a, T>>,
+    depth: usize,
+}
+
+impl<'a, T> Scope<'a, T> {
     pub fn new() -> Self {
         Scope {
-            rename: HashMap::new(),
+            bindings: HashMap::new(),
             parent: None,
+            depth: 0,
         }
     }
 
-    pub fn extend(&'a self) -> Scope<'a> {
+    pub fn extend(&'a self) -> Scope<'a, T> {
         Scope {
-            rename: HashMap::new(),
+            bindings: HashMap::new(),
             parent: Some(self),
+            depth: self.depth + 1,
         }
     }
 
-    pub fn lookup(&self, var: Symbol) -> Symbol {
-        match self.rename.get(&var) {
-            Some(x) => *x,
-            None => self.parent.map(|p| p.lookup(var)).unwrap_or(var),
+    pub fn lookup(&self, name: Symbol) -> Option<&T> {
+        match self.bindings.get(&name) {
+            Some(x) => Some(x),
+            None => self.parent.and_then(|p| p.lookup(name)),
         }
     }
 
-    pub fn insert_vars(&mut self, vars: &Object, a: &mut Alphatizer) -> Result<()> {
-        let mut cursor = vars;
-        while let Some(var) = cursor.car() {
-            let var = var.as_symbol().ok_or_else(|| {
-                ErrorKind::SyntaxError(format!("function parameter is not a symbol: {:?}", var))
-            })?;
-            if self.rename.insert(var, a.make_unique_symbol(var)).is_some() {
-                return Err(ErrorKind::SyntaxError(format!(
-                    "duplicate function parameter: {:?}",
-                    var
-                ))
-                .into());
-            }
-            cursor = cursor.cdr().unwrap();
+    /// Look up a name in this scope only, ignoring the enclosing scopes.
+    pub fn lookup_local(&self, name: Symbol) -> Option<&T> {
+        self.bindings.get(&name)
+    }
+
+    pub fn insert(&mut self, name: Symbol, value: T) -> Option<T> {
+        self.bindings.insert(name, value)
+    }
+
+    /// Number of enclosing scopes; the global scope has depth 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The enclosing scope (or this scope itself) with the given depth.
+    pub fn ancestor(&self, depth: usize) -> &Self {
+        assert!(depth <= self.depth);
+        let mut scope = self;
+        while scope.depth > depth {
+            scope = scope.parent.unwrap();
         }
-        Ok(())
+        scope
+    }
+
+    pub fn into_bindings(self) -> HashMap<Symbol, T> {
+        self.bindings
+    }
+
+    /// Copies of the bindings of this scope and its ancestors, indexed by depth.
+    pub fn frames(&self) -> Vec<HashMap<Symbol, T>>
+    where
+        T: Clone,
+    {
+        let mut frames = self.parent.map(Scope::frames).unwrap_or_default();
+        frames.push(self.bindings.clone());
+        frames
+    }
+}
+
+impl<'a> Scope<'a, Symbol> {
+    pub fn rename(&self, var: Symbol) -> Symbol {
+        self.lookup(var).copied().unwrap_or(var)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::parse_datum;
+    use crate::parser::{assert_same_code, parse_datum};
 
     macro_rules! assert_source_eq {
         ($transformer:expr, $actual:expr, $expected:expr) => {
-            assert_eq!(
-                $transformer
+            assert_same_code(
+                &$transformer
                     .transform(&parse_datum($actual).unwrap())
                     .unwrap(),
-                parse_datum($expected).unwrap()
+                $expected,
             )
         };
     }
@@ -167,7 +690,7 @@ mod tests {
         assert_source_eq!(
             alphatizer,
             "(lambda (x y) (sqrt (+ (* x x) (* y y))))",
-            "(lambda (x0 y1) (sqrt (+ (* x0 x0) (* y1 y1))))"
+            "(lambda (#:x.0 #:y.1) (sqrt (+ (* #:x.0 #:x.0) (* #:y.1 #:y.1))))"
         );
     }
 
@@ -177,7 +700,7 @@ mod tests {
         assert_source_eq!(
             alphatizer,
             "((lambda (x y) (+ x y)) y x)",
-            "((lambda (x0 y1) (+ x0 y1)) y x)"
+            "((lambda (#:x.0 #:y.1) (+ #:x.0 #:y.1)) y x)"
         );
     }
 
@@ -187,7 +710,7 @@ mod tests {
         assert_source_eq!(
             alphatizer,
             "(lambda (x y) ((lambda (x) (* x y) (+ x y)) y))",
-            "(lambda (x0 y1) ((lambda (x2) (* x2 y1) (+ x2 y1)) y1))"
+            "(lambda (#:x.0 #:y.1) ((lambda (#:x.2) (* #:x.2 #:y.1) (+ #:x.2 #:y.1)) #:y.1))"
         );
     }
 
@@ -197,7 +720,31 @@ mod tests {
         assert_source_eq!(
             alphatizer,
             "(lambda (x y) '(x y z))",
-            "(lambda (x0 y1) '(x y z))"
+            "(lambda (#:x.0 #:y.1) '(x y z))"
+        );
+    }
+
+    #[test]
+    fn alphatize_preserve_case_data() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (a) (case a ((a b) a) (else (list 'a a))))",
+            "(lambda (#:a.0) (case #:a.0 ((a b) #:a.0) (else (list 'a #:a.0))))"
+        );
+    }
+
+    #[test]
+    fn alphatize_only_unquoted_template_parts() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (x y)
+                (quasiquote (x (unquote x) (unquote-splicing y) (x unquote x)
+                             (quasiquote (x (unquote x) (unquote (unquote y)))))))",
+            "(lambda (#:x.0 #:y.1)
+                (quasiquote (x (unquote #:x.0) (unquote-splicing #:y.1) (x unquote #:x.0)
+                             (quasiquote (x (unquote x) (unquote (unquote #:y.1)))))))"
         );
     }
 
@@ -215,9 +762,231 @@ mod tests {
             "(begin
                 (define x 3)
                 (define y 4)
-                (define sqr (lambda (x0) (* x0 x0)))
-                (define sqrs (lambda (x1 y2) (+ (sqr x1) (sqr y2))))
+                (define sqr (lambda (#:x.0) (* #:x.0 #:x.0)))
+                (define sqrs (lambda (#:x.1 #:y.2) (+ (sqr #:x.1) (sqr #:y.2))))
                 (sqrs x y))"
         );
     }
+
+    #[test]
+    fn renamed_variables_do_not_collide_with_user_variables() {
+        let mut alphatizer = Alphatizer::new();
+        let output = alphatizer
+            .transform(&parse_datum("(lambda (x) (+ x x.0))").unwrap())
+            .unwrap();
+        let body = output.get_ref(2).unwrap();
+        let renamed = body.get_ref(1).unwrap();
+        let user_var = body.get_ref(2).unwrap();
+        assert_eq!(output.to_string(), "(lambda (#:x.0) (+ #:x.0 x.0))");
+        assert_eq!(output.get_ref(1).unwrap().car(), Some(renamed));
+        assert_ne!(renamed, user_var);
+    }
+
+    #[test]
+    fn alphatize_variadic_lambda() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (x . rest) (cons x rest))",
+            "(lambda (#:x.0 . #:rest.1) (cons #:x.0 #:rest.1))"
+        );
+        assert_source_eq!(
+            alphatizer,
+            "(lambda args args)",
+            "(lambda #:args.2 #:args.2)"
+        );
+    }
+
+    #[test]
+    fn alphatize_internal_definitions() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (x)
+                (define (f y) (g y))
+                (define (g z) (* x z))
+                (f 1))",
+            "(lambda (#:x.0)
+                (define (#:f.1 #:y.3) (#:g.2 #:y.3))
+                (define (#:g.2 #:z.4) (* #:x.0 #:z.4))
+                (#:f.1 1))"
+        );
+        assert_source_eq!(
+            alphatizer,
+            "(begin (define (h) (define k 1) k) k)",
+            "(begin (define (h) (define #:k.5 1) #:k.5) k)"
+        );
+    }
+
+    #[test]
+    fn alphatize_let() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(let ((x 1) (y x)) (let ((x y)) x))",
+            "(let ((#:x.0 1) (#:y.1 x)) (let ((#:x.2 #:y.1)) #:x.2))"
+        );
+    }
+
+    #[test]
+    fn alphatize_let_star() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(let* ((x 1) (x (+ x 1))) x)",
+            "(let* ((#:x.0 1) (#:x.1 (+ #:x.0 1))) #:x.1)"
+        );
+    }
+
+    #[test]
+    fn alphatize_letrec() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(letrec ((even? (lambda (n) (odd? n))) (odd? (lambda (n) (even? n)))) (even? x))",
+            "(letrec ((#:even?.0 (lambda (#:n.2) (#:odd?.1 #:n.2))) (#:odd?.1 (lambda (#:n.3) (#:even?.0 #:n.3)))) (#:even?.0 x))"
+        );
+    }
+
+    #[test]
+    fn alphatize_named_let() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(let loop ((i 0)) (if (< i n) (loop (+ i 1)) i))",
+            "(let #:loop.0 ((#:i.1 0)) (if (< #:i.1 n) (#:loop.0 (+ #:i.1 1)) #:i.1))"
+        );
+    }
+
+    #[test]
+    fn alphatize_do() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(do ((i 0 (+ i 1)) (acc '() (cons i acc))) ((= i n) acc) (display i))",
+            "(do ((#:i.0 0 (+ #:i.0 1)) (#:acc.1 '() (cons #:i.0 #:acc.1))) ((= #:i.0 n) #:acc.1) (display #:i.0))"
+        );
+    }
+
+    #[test]
+    fn alphatize_let_values() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (a) (let-values (((a) (values 2)) ((b . c) (values a 3))) (list a b c)))",
+            "(lambda (#:a.0) (let-values (((#:a.1) (values 2)) ((#:b.2 . #:c.3) (values #:a.0 3))) (list #:a.1 #:b.2 #:c.3)))"
+        );
+        assert_source_eq!(
+            alphatizer,
+            "(let-values ((all (values 1 2))) all)",
+            "(let-values ((#:all.4 (values 1 2))) #:all.4)"
+        );
+    }
+
+    #[test]
+    fn alphatize_let_star_values() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(let*-values (((a b) (values 1 2)) ((a) (values b))) a)",
+            "(let*-values (((#:a.0 #:b.1) (values 1 2)) ((#:a.2) (values #:b.1))) #:a.2)"
+        );
+    }
+
+    #[test]
+    fn alphatize_case_lambda() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (x) (case-lambda ((x y) y) ((y) x) (rest rest)))",
+            "(lambda (#:x.0) (case-lambda ((#:x.1 #:y.2) #:y.2) ((#:y.3) #:x.0) (#:rest.4 #:rest.4)))"
+        );
+    }
+
+    #[test]
+    fn alphatize_guard() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (e) (guard (e ((symbol? e) e) (else (raise e))) (f e)))",
+            "(lambda (#:e.0) (guard (#:e.1 ((symbol? #:e.1) #:e.1) (else (raise #:e.1))) (f #:e.0)))"
+        );
+    }
+
+    #[test]
+    fn alphatize_shadowed_special_forms() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (if quote) (if (quote x) 1 2))",
+            "(lambda (#:if.0 #:quote.1) (#:if.0 (#:quote.1 x) 1 2))"
+        );
+        assert_source_eq!(
+            alphatizer,
+            "(lambda (lambda) (lambda (x) x))",
+            "(lambda (#:lambda.2) (#:lambda.2 (x) x))"
+        );
+    }
+
+    #[test]
+    fn global_definitions_shadow_special_forms_in_later_inputs() {
+        let mut alphatizer = Alphatizer::new();
+        assert_source_eq!(alphatizer, "(lambda (x) x)", "(lambda (#:x.0) #:x.0)");
+        assert_source_eq!(alphatizer, "(define lambda list)", "(define lambda list)");
+        assert_source_eq!(alphatizer, "(lambda (x) x)", "(lambda (x) x)");
+    }
+
+    #[test]
+    fn alphatized_names_are_unique() {
+        let mut alphatizer = Alphatizer::new();
+        let source = parse_datum(
+            "(begin
+                (define (f x) (define y x) (let loop ((i 0)) (loop y)))
+                (lambda (x . rest) (do ((i 0 (+ i 1))) ((= i x) rest))))",
+        )
+        .unwrap();
+        assert_eq!(
+            unique_names(&alphatizer.transform(&source).unwrap()),
+            Ok(())
+        );
+
+        let source = parse_datum(
+            "(lambda (a x)
+                (let-values (((a) (values 2)) ((b . c) (values 1 2))) a)
+                (let*-values ((all (values 1)) ((a) (values 2))) a)
+                (case-lambda ((x y) y) (rest rest))
+                (guard (a (#t a)) x))",
+        )
+        .unwrap();
+        assert_eq!(
+            unique_names(&alphatizer.transform(&source).unwrap()),
+            Ok(())
+        );
+        for source in &[
+            "(let-values (((a) (values 1))) a)",
+            "(let*-values ((all (values 1))) all)",
+            "(case-lambda ((x) x))",
+            "(guard (e (#t e)) 1)",
+        ] {
+            assert!(unique_names(&parse_datum(source).unwrap()).is_err());
+        }
+
+        let x = Object::from(Symbol::gensym("x", &GensymCounter::new()));
+        let shadowing = list!(lambda, @list!(@x.clone()), @list!(lambda, @list!(@x.clone()), @x));
+        assert!(unique_names(&shadowing).is_err());
+        assert!(unique_names(&parse_datum("(lambda (x) x)").unwrap()).is_err());
+    }
+
+    #[test]
+    fn duplicate_bindings_are_errors() {
+        let mut alphatizer = Alphatizer::new();
+        for source in &[
+            "(lambda (x x) x)",
+            "(let ((x 1) (x 2)) x)",
+            "(lambda () (define x 1) (define x 2) x)",
+        ] {
+            let source = parse_datum(source).unwrap();
+            assert!(alphatizer.transform(&source).is_err());
+        }
+    }
 }
@@ -0,0 +1,443 @@
+//! Continuation-passing style source transform
+//! Every procedure gets its continuation as an additional last argument, and every call
+//! becomes a tail call. Primitive operations are wrapped as `(cps op)`, and assignments become
+//! `(set-then! var value next)`.
+//!
+//! The input consists of `lambda`, `quote`, `if`, `set!`, `begin`, `let`, `letrec` and
+//! applications. Definitions and the other derived forms are reported as syntax errors.
+//!
+//! Primitives are recognized by name, so the input should be alphatized to make sure
+//! they are not shadowed. Continuation variables introduced by the transform are
+//! uninterned, so they can never be captured by user variables.
+
+use super::SourceTransformer;
+use crate::error::{ErrorKind, Result};
+use crate::object::{ListBuilder, Object};
+use crate::runtime::{GensymCounter, Symbol};
+use crate::SchemeExpression;
+
+type Cont<'a, T> = Box<dyn FnOnce(T) -> Result<Object> + 'a>;
+
+pub struct CpsTransformer {
+    gensym: GensymCounter,
+    halt: Object,
+}
+
+impl Default for CpsTransformer {
+    fn default() -> Self {
+        CpsTransformer::new()
+    }
+}
+
+impl SourceTransformer for CpsTransformer {
+    fn transform(&mut self, input: &Object) -> Result<Object> {
+        self.tc(input, self.halt.clone())
+    }
+}
+
+impl CpsTransformer {
+    /// Transform programs that pass their result to `halt`.
+    pub fn new() -> Self {
+        CpsTransformer::with_continuation(Object::symbol("halt"))
+    }
+
+    /// Transform programs that pass their result to the given continuation.
+    pub fn with_continuation(halt: Object) -> Self {
+        CpsTransformer {
+            gensym: GensymCounter::new(),
+            halt,
+        }
+    }
+
+    /// Transform `expr` so that it passes its value to the continuation expression `c`.
+    fn tc(&self, expr: &Object, c: Object) -> Result<Object> {
+        if is_atomic(expr) {
+            return Ok(list!(@c, @self.m(expr)?));
+        }
+
+        match keyword(expr) {
+            Some("begin") => self.tc_sequence(body_of(expr)?, c),
+            Some("if") => try_switch! {expr,
+                [(_ ?test ?yes)] => {
+                    let expr = list!(@expr.car().unwrap().clone(), @test.clone(), @yes.clone(), @Object::undef());
+                    self.tc(&expr, c)
+                },
+                [(_ ?test ?yes ?no)] => {
+                    let k = self.gensym("k");
+                    let (yes, no) = (yes.clone(), no.clone());
+                    let body = self.tk(test, Box::new(move |aexp| {
+                        Ok(list!(@Object::symbol("if"), @aexp, @self.tc(&yes, k.into())?, @self.tc(&no, k.into())?))
+                    }))?;
+                    Ok(list!(@list!(lambda, @list!(@k), @body), @c))
+                },
+            },
+            Some("set!") => try_switch! {expr,
+                [(_ ?var:symbol ?value)] => {
+                    let var = var.clone();
+                    self.tk(value, Box::new(move |aexp| {
+                        Ok(list!(@set_then(), @var, @aexp, @list!(@c, @Object::undef())))
+                    }))
+                },
+            },
+            Some("let") => try_switch! {expr,
+                [(_ ((?vars:symbol ?inits) ...) . ?body)] => {
+                    let mut params = ListBuilder::new();
+                    vars.into_iter().for_each(|var| params.append(var.clone()));
+                    let mut call = ListBuilder::new();
+                    call.append(list!(lambda, @params.build(), . @body.clone()));
+                    inits.into_iter().for_each(|init| call.append(init.clone()));
+                    self.tc(&call.build(), c)
+                },
+            },
+            Some("letrec") => self.tc_letrec(expr, c),
+            Some(kw) if UNSUPPORTED_FORMS.contains(&kw) => Err(ErrorKind::SyntaxError(format!(
+                "{} is not supported by the CPS transform: {:?}",
+                kw, expr
+            ))
+            .into()),
+            Some(op) if is_primitive(op) => {
+                let op = expr.car().unwrap().clone();
+                self.tsk(
+                    args_of(expr)?,
+                    Box::new(move |args| {
+                        let mut call = ListBuilder::new();
+                        call.append(list!(cps, @op));
+                        args.into_iter().for_each(|arg| call.append(arg));
+                        call.append(c);
+                        Ok(call.build())
+                    }),
+                )
+            }
+            _ => {
+                let args = args_of(expr)?;
+                self.tk(
+                    expr.car().unwrap(),
+                    Box::new(move |f| {
+                        self.tsk(
+                            args,
+                            Box::new(move |args| {
+                                let mut call = ListBuilder::new();
+                                call.append(f);
+                                args.into_iter().for_each(|arg| call.append(arg));
+                                call.append(c);
+                                Ok(call.build())
+                            }),
+                        )
+                    }),
+                )
+            }
+        }
+    }
+
+    /// Transform `expr` so that its value is passed to the meta-continuation `k`, which
+    /// generates the code that uses the value.
+    fn tk<'a>(&'a self, expr: &Object, k: Cont<'a, Object>) -> Result<Object> {
+        if is_atomic(expr) {
+            return k(self.m(expr)?);
+        }
+
+        match keyword(expr) {
+            Some("begin") => self.tk_sequence(body_of(expr)?, k),
+            Some("set!") => try_switch! {expr,
+                [(_ ?var:symbol ?value)] => {
+                    let var = var.clone();
+                    self.tk(value, Box::new(move |aexp| {
+                        Ok(list!(@set_then(), @var, @aexp, @k(Object::undef())?))
+                    }))
+                },
+            },
+            _ => {
+                let rv = self.gensym("rv");
+                let cont = list!(lambda, @list!(@rv), @k(rv.into())?);
+                self.tc(expr, cont)
+            }
+        }
+    }
+
+    fn tsk<'a>(&'a self, mut exprs: Vec<Object>, k: Cont<'a, Vec<Object>>) -> Result<Object> {
+        if exprs.is_empty() {
+            return k(vec![]);
+        }
+        let first = exprs.remove(0);
+        self.tk(
+            &first,
+            Box::new(move |head| {
+                self.tsk(
+                    exprs,
+                    Box::new(move |mut tail| {
+                        tail.insert(0, head);
+                        k(tail)
+                    }),
+                )
+            }),
+        )
+    }
+
+    fn tc_sequence(&self, mut exprs: Vec<Object>, c: Object) -> Result<Object> {
+        match exprs.len() {
+            0 => Ok(list!(@c, @Object::undef())),
+            1 => self.tc(&exprs[0], c),
+            _ => {
+                let first = exprs.remove(0);
+                self.tk(&first, Box::new(move |_| self.tc_sequence(exprs, c)))
+            }
+        }
+    }
+
+    fn tk_sequence<'a>(&'a self, mut exprs: Vec<Object>, k: Cont<'a, Object>) -> Result<Object> {
+        match exprs.len() {
+            0 => k(Object::undef()),
+            1 => self.tk(&exprs[0], k),
+            _ => {
+                let first = exprs.remove(0);
+                self.tk(&first, Box::new(move |_| self.tk_sequence(exprs, k)))
+            }
+        }
+    }
+
+    /// The prototype moved the continuation into the scope of the `letrec`, where its free
+    /// variables could be captured. Unless the continuation is a generated variable, it is
+    /// now bound outside the `letrec`.
+    fn tc_letrec(&self, expr: &Object, c: Object) -> Result<Object> {
+        if c.as_symbol().is_none_or(|s| s.is_interned()) {
+            let k = self.gensym("k");
+            let body = self.tc_letrec(expr, k.into())?;
+            return Ok(list!(@list!(lambda, @list!(@k), @body), @c));
+        }
+
+        try_switch! {expr,
+            [(_ ((?vars:symbol ?inits) ...) . ?body)] => {
+                // non-atomic initializers are assigned at the beginning of the body
+                let mut bindings = ListBuilder::new();
+                let mut body_exprs = vec![];
+                for (var, init) in vars.into_iter().zip(inits) {
+                    if is_atomic(init) {
+                        bindings.append(list!(@var.clone(), @self.m(init)?));
+                    } else {
+                        bindings.append(list!(@var.clone(), @Object::undef()));
+                        body_exprs.push(list!(@Object::symbol("set!"), @var.clone(), @init.clone()));
+                    }
+                }
+                body_exprs.extend(body.list_parts().0.into_iter().cloned());
+                let body = self.tc_sequence(body_exprs, c)?;
+                Ok(list!(letrec, @bindings.build(), @body))
+            },
+        }
+    }
+
+    /// Transform an atomic expression.
+    fn m(&self, expr: &Object) -> Result<Object> {
+        if keyword(expr) != Some("lambda") {
+            return Ok(expr.clone());
+        }
+        try_switch! {expr,
+            [(_ (?params:symbol ...) . ?body)] => {
+                let k = self.gensym("k");
+                let mut new_params = ListBuilder::new();
+                params.into_iter().for_each(|p| new_params.append(p.clone()));
+                new_params.append(k.into());
+                let body = self.tc_sequence(body.list_parts().0.into_iter().cloned().collect(), k.into())?;
+                Ok(list!(lambda, @new_params.build(), @body))
+            },
+            [(_ ?params . _)] => Err(ErrorKind::SyntaxError(format!(
+                "variadic lambda is not supported by the CPS transform: {:?}",
+                params
+            ))
+            .into()),
+        }
+    }
+
+    fn gensym(&self, base: &str) -> Symbol {
+        Symbol::gensym(base, &self.gensym)
+    }
+}
+
+/// Special forms that must be desugared before the transform, and definitions.
+const UNSUPPORTED_FORMS: &[&str] = &[
+    "define",
+    "define-syntax",
+    "define-record-type",
+    "let*",
+    "letrec*",
+    "let-syntax",
+    "letrec-syntax",
+    "cond",
+    "case",
+    "and",
+    "or",
+    "when",
+    "unless",
+    "do",
+    "delay",
+    "delay-force",
+    "quasiquote",
+    "parameterize",
+    "guard",
+    "case-lambda",
+];
+
+fn is_atomic(expr: &Object) -> bool {
+    match keyword(expr) {
+        Some("lambda") | Some("quote") => true,
+        _ => !expr.is_list(),
+    }
+}
+
+fn is_primitive(name: &str) -> bool {
+    ["+", "-", "*", "/"].contains(&name)
+}
+
+fn keyword(expr: &Object) -> Option<&'static str> {
+    expr.car().and_then(Object::symbol_name)
+}
+
+fn body_of(expr: &Object) -> Result<Vec<Object>> {
+    args_of(expr)
+}
+
+fn args_of(expr: &Object) -> Result<Vec<Object>> {
+    let (args, tail) = expr.cdr().unwrap().list_parts();
+    if tail.is_nil() {
+        Ok(args.into_iter().cloned().collect())
+    } else {
+        Err(ErrorKind::SyntaxError(format!("improper list in expression: {:?}", expr)).into())
+    }
+}
+
+fn set_then() -> Object {
+    Object::symbol("set-then!")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{assert_same_code, parse_datum};
+
+    macro_rules! assert_cps_eq {
+        ($halt:expr, $actual:expr, $expected:expr) => {
+            assert_same_code(
+                &CpsTransformer::with_continuation(Object::symbol($halt))
+                    .transform(&parse_datum($actual).unwrap())
+                    .unwrap(),
+                $expected,
+            )
+        };
+    }
+
+    #[test]
+    fn application() {
+        assert_cps_eq!("halt", "(g a)", "(g a halt)");
+    }
+
+    #[test]
+    fn sequence() {
+        assert_cps_eq!(
+            "halt",
+            r#"(begin (print "A") (print "B") (print "C"))"#,
+            r#"(print "A" (lambda (#:rv.0) (print "B" (lambda (#:rv.1) (print "C" halt)))))"#
+        );
+    }
+
+    #[test]
+    fn lambda_takes_continuation() {
+        assert_cps_eq!(
+            "halt",
+            r#"(lambda (x) (begin (print "A") (print "B") (print "C")))"#,
+            r#"(halt (lambda (x #:k.0) (print "A" (lambda (#:rv.1) (print "B" (lambda (#:rv.2) (print "C" #:k.0)))))))"#
+        );
+    }
+
+    #[test]
+    fn lambda_with_multiple_body_expressions() {
+        assert_cps_eq!(
+            "print",
+            "((lambda (x y) (print x) (print y)) 5 3)",
+            "((lambda (x y #:k.0) (print x (lambda (#:rv.1) (print y #:k.0)))) 5 3 print)"
+        );
+    }
+
+    #[test]
+    fn primitives_are_wrapped() {
+        assert_cps_eq!("print", "((lambda (x y) (sqrt (+ (* x x) (* y y)))) 2 3)", "((lambda (x y #:k.0) ((cps *) x x (lambda (#:rv.2) ((cps *) y y (lambda (#:rv.3) ((cps +) #:rv.2 #:rv.3 (lambda (#:rv.1) (sqrt #:rv.1 #:k.0)))))))) 2 3 print)");
+    }
+
+    #[test]
+    fn letrec() {
+        assert_cps_eq!("print", "(letrec ((a 42) (b (+ 1 2))) (+ a b))", "((lambda (#:k.0) (letrec ((a 42) (b <undefined>)) ((cps +) 1 2 (lambda (#:rv.1) (set-then! b #:rv.1 ((cps +) a b #:k.0)))))) print)");
+    }
+
+    #[test]
+    fn letrec_does_not_capture_continuation() {
+        assert_cps_eq!("halt", "(lambda (x) (print x (letrec ((x 3)) x)))", "(halt (lambda (x #:k.0) ((lambda (#:k.2) (letrec ((x 3)) (#:k.2 x))) (lambda (#:rv.1) (print x #:rv.1 #:k.0)))))");
+    }
+
+    #[test]
+    fn if_in_tail_position() {
+        assert_cps_eq!(
+            "halt",
+            r#"(lambda (x) (if (even? x) (print "even") (print "odd")))"#,
+            r#"(halt (lambda (x #:k.0) ((lambda (#:k.1) (even? x (lambda (#:rv.2) (if #:rv.2 (print "even" #:k.1) (print "odd" #:k.1))))) #:k.0)))"#
+        );
+    }
+
+    #[test]
+    fn if_as_argument() {
+        assert_cps_eq!(
+            "halt",
+            r#"(lambda (x) (print (if (even? x) "even" "odd")))"#,
+            r#"(halt (lambda (x #:k.0) ((lambda (#:k.2) (even? x (lambda (#:rv.3) (if #:rv.3 (#:k.2 "even") (#:k.2 "odd"))))) (lambda (#:rv.1) (print #:rv.1 #:k.0)))))"#
+        );
+    }
+
+    #[test]
+    fn one_armed_if() {
+        assert_cps_eq!(
+            "halt",
+            "(if x (f 1))",
+            "((lambda (#:k.0) (if x (f 1 #:k.0) (#:k.0 <undefined>))) halt)"
+        );
+    }
+
+    #[test]
+    fn let_binds_by_application() {
+        assert_cps_eq!(
+            "halt",
+            "(let ((x (f 1)) (y 2)) (g x y))",
+            "(f 1 (lambda (#:rv.1) ((lambda (x y #:k.0) (g x y #:k.0)) #:rv.1 2 halt)))"
+        );
+    }
+
+    fn syntax_error(source: &str) -> String {
+        match CpsTransformer::new().transform(&parse_datum(source).unwrap()) {
+            Err(e) => match e.kind() {
+                ErrorKind::SyntaxError(msg) => msg.clone(),
+                kind => panic!("unexpected error: {:?}", kind),
+            },
+            Ok(x) => panic!("expected syntax error, got {}", x),
+        }
+    }
+
+    #[test]
+    fn unsupported_forms_are_syntax_errors() {
+        assert!(syntax_error("(define x (f 1))").starts_with("define is not supported"));
+        assert!(syntax_error("(print (cond (x 1)))").starts_with("cond is not supported"));
+        assert!(syntax_error("(let loop ((i 0)) (loop i))").contains("does not match any of"));
+        assert_eq!(
+            syntax_error("(f (lambda args args))"),
+            "variadic lambda is not supported by the CPS transform: args"
+        );
+        assert_eq!(
+            syntax_error("(lambda (a . b) a)"),
+            "variadic lambda is not supported by the CPS transform: (a . b)"
+        );
+    }
+
+    #[test]
+    fn assignment() {
+        assert_cps_eq!(
+            "halt",
+            "(begin (set! x (f 1)) x)",
+            "(f 1 (lambda (#:rv.0) (set-then! x #:rv.0 (halt x))))"
+        );
+    }
+}
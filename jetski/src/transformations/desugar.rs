@@ -0,0 +1,805 @@
+//! Desugaring of derived expressions
+//! Rewrites the derived expression types of R7RS section 7.3 into the core forms `lambda`, `if`,
+//! `set!`, `quote`, `define` and `begin`, along the lines of the sample definitions in the
+//! report. Derived forms are rewritten repeatedly until only core forms remain. Quasiquote
+//! templates become procedure calls that build the data.
+//!
+//! Keywords are recognized by name, so the input should be alphatized (or expanded) to make
+//! sure user variables do not shadow them. Temporaries introduced by the rewrites are
+//! uninterned, so they can never capture user variables.
+
+use super::SourceTransformer;
+use crate::error::{ErrorKind, Result};
+use crate::object::{ListBuilder, Object};
+use crate::runtime::{GensymCounter, Symbol};
+use crate::SchemeExpression;
+
+#[derive(Debug, Default)]
+pub struct Desugar {
+    gensym: GensymCounter,
+}
+
+impl SourceTransformer for Desugar {
+    fn transform(&mut self, input: &Object) -> Result<Object> {
+        self.desugar(input)
+    }
+}
+
+impl Desugar {
+    pub fn new() -> Self {
+        Desugar {
+            gensym: GensymCounter::new(),
+        }
+    }
+
+    fn desugar(&self, expr: &Object) -> Result<Object> {
+        if !expr.is_list() {
+            return Ok(expr.clone());
+        }
+
+        let derived = match expr.car().unwrap().symbol_name() {
+            Some("quote") => return Ok(expr.clone()),
+            Some("lambda") => return self.desugar_lambda(expr),
+            Some("define") => return self.desugar_define(expr),
+            Some("let") => self.rewrite_let(expr)?,
+            Some("let*") => self.rewrite_let_star(expr)?,
+            Some("letrec") => self.rewrite_letrec(expr)?,
+            Some("letrec*") => self.rewrite_letrec_star(expr)?,
+            Some("let-values") => self.rewrite_let_values(expr)?,
+            Some("let*-values") => self.rewrite_let_star_values(expr)?,
+            Some("and") => self.rewrite_and(expr)?,
+            Some("or") => self.rewrite_or(expr)?,
+            Some("when") => self.rewrite_when(expr)?,
+            Some("unless") => self.rewrite_unless(expr)?,
+            Some("cond") => self.rewrite_cond(expr)?,
+            Some("case") => self.rewrite_case(expr)?,
+            Some("do") => self.rewrite_do(expr)?,
+            Some("case-lambda") => self.rewrite_case_lambda(expr)?,
+            Some("guard") => self.rewrite_guard(expr)?,
+            Some("parameterize") => self.rewrite_parameterize(expr)?,
+            Some("delay") => self.rewrite_delay(expr)?,
+            Some("delay-force") => self.rewrite_delay_force(expr)?,
+            Some("quasiquote") => self.rewrite_quasiquote(expr)?,
+            // `if`, `set!`, `begin` and procedure applications only need their subexpressions
+            // desugared.
+            _ => return self.desugar_all(expr),
+        };
+        self.desugar(&derived)
+    }
+
+    fn desugar_all(&self, exprs: &Object) -> Result<Object> {
+        exprs.map(|x| self.desugar(x))
+    }
+
+    fn desugar_lambda(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?params . ?body)] => {
+                Ok(cons(sym("lambda"), cons(params.clone(), self.desugar_all(body)?)))
+            },
+        }
+    }
+
+    /// Procedure definitions are rewritten as variable definitions.
+    fn desugar_define(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ (?name . ?params) . ?body)] => {
+                let value = cons(sym("lambda"), cons(params.clone(), body.clone()));
+                Ok(list!(define, @name.clone(), @self.desugar(&value)?))
+            },
+            [(_ ?name:symbol ?value)] => Ok(list!(define, @name.clone(), @self.desugar(value)?)),
+        }
+    }
+
+    fn rewrite_let(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?name:symbol ((?vars ?inits) ...) . ?body)] => {
+                let proc = make_lambda(make_list(cloned(&vars)), body.clone());
+                let bindings = list!(@list!(@name.clone(), @proc));
+                let func = cons(sym("letrec"), list!(@bindings, @name.clone()));
+                Ok(cons(func, make_list(cloned(&inits))))
+            },
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let proc = make_lambda(make_list(cloned(&vars)), body.clone());
+                Ok(cons(proc, make_list(cloned(&inits))))
+            },
+        }
+    }
+
+    fn rewrite_let_star(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ () . ?body)] => Ok(make_let(vec![], body.clone())),
+            [(_ (?first . ?rest) . ?body)] => {
+                let inner = cons(sym("let*"), cons(rest.clone(), body.clone()));
+                Ok(make_let(vec![first.clone()], list!(@inner)))
+            },
+        }
+    }
+
+    /// All initializers are evaluated before any variable is assigned.
+    fn rewrite_letrec(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let temps: Vec<_> = vars.iter().map(|v| self.temporary(v)).collect::<Result<_>>()?;
+                let assignments = vars
+                    .iter()
+                    .zip(&temps)
+                    .map(|(&var, temp)| list!(@sym("set!"), @var.clone(), @temp.clone()))
+                    .collect();
+                let temp_bindings = temps
+                    .into_iter()
+                    .zip(inits)
+                    .map(|(temp, init)| list!(@temp, @init.clone()))
+                    .collect();
+                let mut sequence = vec![make_let(temp_bindings, make_list(assignments))];
+                sequence.push(make_let(vec![], body.clone()));
+                Ok(make_let(undefined_bindings(&vars), make_list(sequence)))
+            },
+        }
+    }
+
+    /// Variables are assigned in order, each right after its initializer was evaluated.
+    fn rewrite_letrec_star(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let mut sequence: Vec<_> = vars
+                    .iter()
+                    .zip(&inits)
+                    .map(|(&var, &init)| list!(@sym("set!"), @var.clone(), @init.clone()))
+                    .collect();
+                sequence.push(make_let(vec![], body.clone()));
+                Ok(make_let(undefined_bindings(&vars), make_list(sequence)))
+            },
+        }
+    }
+
+    /// The formals of all bindings are bound to temporaries first, so that no initializer is
+    /// evaluated in the scope of the variables.
+    fn rewrite_let_values(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ((?formals ?inits) ...) . ?body)] => {
+                let mut bindings = vec![];
+                let mut temp_formals = vec![];
+                for &f in &formals {
+                    temp_formals.push(self.temporary_formals(f, &mut bindings)?);
+                }
+                let mut result = make_let(bindings, body.clone());
+                for (temps, &init) in temp_formals.into_iter().zip(&inits).rev() {
+                    result = list!(
+                        @sym("call-with-values"),
+                        @make_lambda(Object::nil(), list!(@init.clone())),
+                        @make_lambda(temps, list!(@result))
+                    );
+                }
+                Ok(result)
+            },
+        }
+    }
+
+    fn rewrite_let_star_values(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ () . ?body)] => Ok(make_let(vec![], body.clone())),
+            [(_ (?first . ?rest) . ?body)] => {
+                let inner = cons(sym("let*-values"), cons(rest.clone(), body.clone()));
+                Ok(list!(@sym("let-values"), @list!(@first.clone()), @inner))
+            },
+        }
+    }
+
+    fn rewrite_and(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_)] => Ok(Object::boolean(true)),
+            [(_ ?test)] => Ok(test.clone()),
+            [(_ ?test . ?rest)] => {
+                let rest = cons(sym("and"), rest.clone());
+                Ok(list!(@sym("if"), @test.clone(), @rest, @Object::boolean(false)))
+            },
+        }
+    }
+
+    fn rewrite_or(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_)] => Ok(Object::boolean(false)),
+            [(_ ?test)] => Ok(test.clone()),
+            [(_ ?test . ?rest)] => {
+                let x = self.gensym("x");
+                let rest = cons(sym("or"), rest.clone());
+                let body = list!(@sym("if"), @x.clone(), @x.clone(), @rest);
+                Ok(make_let(vec![list!(@x, @test.clone())], list!(@body)))
+            },
+        }
+    }
+
+    fn rewrite_when(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?test . ?body)] => {
+                Ok(list!(@sym("if"), @test.clone(), @cons(sym("begin"), body.clone())))
+            },
+        }
+    }
+
+    fn rewrite_unless(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?test . ?body)] => {
+                let test = list!(not, @test.clone());
+                Ok(list!(@sym("if"), @test, @cons(sym("begin"), body.clone())))
+            },
+        }
+    }
+
+    fn rewrite_cond(&self, expr: &Object) -> Result<Object> {
+        let clauses = proper_list(expr.cdr().unwrap())?;
+        let (clause, rest) = match clauses.split_first() {
+            None => return Ok(unspecified()),
+            Some((clause, rest)) => (*clause, make_list(cloned(rest))),
+        };
+        let alternative = if rest.is_nil() {
+            None
+        } else {
+            Some(cons(sym("cond"), rest))
+        };
+
+        let parts = proper_list(clause)?;
+        match parts.as_slice() {
+            [keyword, body @ ..] if is_keyword(keyword, "else") => {
+                if alternative.is_some() {
+                    return Err(syntax_error("else clause must be last", expr));
+                }
+                Ok(cons(sym("begin"), make_list(cloned(body))))
+            }
+            [test] => Ok(match alternative {
+                None => (*test).clone(),
+                Some(alternative) => list!(or, @(*test).clone(), @alternative),
+            }),
+            [test, arrow, receiver] if is_keyword(arrow, "=>") => {
+                let t = self.gensym("t");
+                let call = list!(@(*receiver).clone(), @t.clone());
+                let body = make_if(t.clone(), call, alternative);
+                Ok(make_let(vec![list!(@t, @(*test).clone())], list!(@body)))
+            }
+            [test, body @ ..] => {
+                let consequence = cons(sym("begin"), make_list(cloned(body)));
+                Ok(make_if((*test).clone(), consequence, alternative))
+            }
+            [] => Err(syntax_error("empty cond clause", expr)),
+        }
+    }
+
+    fn rewrite_case(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?key . ?clauses)] => {
+                let k = self.gensym("key");
+                let body = self.case_clauses(&k, &proper_list(clauses)?, expr)?;
+                Ok(make_let(vec![list!(@k, @key.clone())], list!(@body)))
+            },
+        }
+    }
+
+    fn case_clauses(&self, key: &Object, clauses: &[&Object], expr: &Object) -> Result<Object> {
+        let (clause, rest) = match clauses.split_first() {
+            None => return Ok(unspecified()),
+            Some(x) => x,
+        };
+        let alternative = if rest.is_empty() {
+            None
+        } else {
+            Some(self.case_clauses(key, rest, expr)?)
+        };
+
+        let parts = proper_list(clause)?;
+        let consequence = match &parts[1..] {
+            [arrow, receiver] if is_keyword(arrow, "=>") => {
+                list!(@(*receiver).clone(), @key.clone())
+            }
+            body => cons(sym("begin"), make_list(cloned(body))),
+        };
+        match parts.first() {
+            Some(keyword) if is_keyword(keyword, "else") => {
+                if alternative.is_some() {
+                    return Err(syntax_error("else clause must be last", expr));
+                }
+                Ok(consequence)
+            }
+            Some(data) => {
+                let test = list!(memv, @key.clone(), @list!(quote, @(*data).clone()));
+                Ok(make_if(test, consequence, alternative))
+            }
+            None => Err(syntax_error("empty case clause", expr)),
+        }
+    }
+
+    fn rewrite_do(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?specs (?test . ?exprs) . ?commands)] => {
+                let mut vars = vec![];
+                let mut inits = vec![];
+                let mut steps = vec![];
+                for spec in proper_list(specs)? {
+                    let parts = proper_list(spec)?;
+                    match parts.as_slice() {
+                        [var, _] => steps.push((*var).clone()),
+                        [_, _, step] => steps.push((*step).clone()),
+                        _ => return Err(syntax_error("invalid do binding", spec)),
+                    }
+                    vars.push(parts[0].clone());
+                    inits.push(parts[1].clone());
+                }
+
+                let result = if exprs.is_nil() {
+                    unspecified()
+                } else {
+                    cons(sym("begin"), exprs.clone())
+                };
+
+                let loop_var = self.gensym("loop");
+                let mut iteration = proper_list(commands)?.into_iter().cloned().collect::<Vec<_>>();
+                iteration.push(cons(loop_var.clone(), make_list(steps)));
+                let body = list!(
+                    @sym("if"),
+                    @test.clone(),
+                    @result,
+                    @cons(sym("begin"), make_list(iteration))
+                );
+                let proc = make_lambda(make_list(vars), list!(@body));
+                let bindings = list!(@list!(@loop_var.clone(), @proc));
+                Ok(list!(letrec, @bindings, @cons(loop_var, make_list(inits))))
+            },
+        }
+    }
+
+    fn rewrite_case_lambda(&self, expr: &Object) -> Result<Object> {
+        let args = self.gensym("args");
+        let len = self.gensym("len");
+        let mut body = list!(error, @Object::string("no matching case-lambda clause".to_string()));
+
+        for clause in proper_list(expr.cdr().unwrap())?.into_iter().rev() {
+            let (formals, clause_body) = match clause.decons() {
+                Some(x) => x,
+                None => return Err(syntax_error("invalid case-lambda clause", clause)),
+            };
+            let (required, rest) = formals.list_parts();
+            let n = Object::integer(required.len() as i64);
+            let proc = make_lambda(formals.clone(), clause_body.clone());
+            let call = list!(apply, @proc, @args.clone());
+            body = if rest.is_nil() {
+                make_if(list!(@sym("="), @len.clone(), @n), call, Some(body))
+            } else if required.is_empty() {
+                call
+            } else {
+                make_if(list!(@sym(">="), @len.clone(), @n), call, Some(body))
+            };
+        }
+
+        let body = make_let(
+            vec![list!(@len, @list!(length, @args.clone()))],
+            list!(@body),
+        );
+        Ok(make_lambda(args, list!(@body)))
+    }
+
+    fn rewrite_guard(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ (?var:symbol . ?clauses) . ?body)] => {
+                let guard_k = self.gensym("guard-k");
+                let handler_k = self.gensym("handler-k");
+                let condition = self.gensym("condition");
+                let args = self.gensym("args");
+
+                let mut clauses = proper_list(clauses)?.into_iter().cloned().collect::<Vec<_>>();
+                let has_else = clauses
+                    .last()
+                    .and_then(|clause| clause.car())
+                    .is_some_and(|keyword| is_keyword(keyword, "else"));
+                if !has_else {
+                    let raise = list!(@sym("raise-continuable"), @condition.clone());
+                    let reraise = list!(@handler_k.clone(), @make_lambda(Object::nil(), list!(@raise)));
+                    clauses.push(list!(@sym("else"), @reraise));
+                }
+                let handler_body = make_let(
+                    vec![list!(@var.clone(), @condition.clone())],
+                    list!(@cons(sym("cond"), make_list(clauses))),
+                );
+
+                let handler = make_lambda(
+                    list!(@condition),
+                    list!(@list!(@call_cc(make_lambda(
+                        list!(@handler_k),
+                        list!(@list!(
+                            @guard_k.clone(),
+                            @make_lambda(Object::nil(), list!(@handler_body))
+                        )),
+                    ))))
+                );
+
+                let on_return = list!(
+                    @guard_k.clone(),
+                    @make_lambda(Object::nil(), list!(@cons(sym("apply"), list!(values, @args.clone()))))
+                );
+                let thunk = make_lambda(
+                    Object::nil(),
+                    list!(@list!(
+                        @sym("call-with-values"),
+                        @make_lambda(Object::nil(), body.clone()),
+                        @make_lambda(args, list!(@on_return))
+                    )),
+                );
+
+                let install = list!(@sym("with-exception-handler"), @handler, @thunk);
+                Ok(list!(@call_cc(make_lambda(list!(@guard_k), list!(@install)))))
+            },
+        }
+    }
+
+    /// Parameter objects follow the protocol of the R7RS sample implementation: applied to
+    /// `'<param-convert>` they return their converter, and `(p '<param-set!> value)` sets their
+    /// value without conversion.
+    fn rewrite_parameterize(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ((?params ?values) ...) . ?body)] => {
+                let mut param_bindings = vec![];
+                let mut old_bindings = vec![];
+                let mut new_bindings = vec![];
+                let mut before = vec![];
+                let mut after = vec![];
+                for (&param, &value) in params.iter().zip(&values) {
+                    let p = self.gensym("p");
+                    let old = self.gensym("old");
+                    let new = self.gensym("new");
+                    let converter = list!(@p.clone(), @list!(quote, @sym("<param-convert>")));
+                    param_bindings.push(list!(@p.clone(), @param.clone()));
+                    old_bindings.push(list!(@old.clone(), @list!(@p.clone())));
+                    new_bindings.push(list!(@new.clone(), @list!(@converter, @value.clone())));
+                    before.push(list!(@p.clone(), @list!(quote, @sym("<param-set!>")), @new));
+                    after.push(list!(@p, @list!(quote, @sym("<param-set!>")), @old));
+                }
+
+                let wind = list!(
+                    @sym("dynamic-wind"),
+                    @make_lambda(Object::nil(), make_list(before)),
+                    @make_lambda(Object::nil(), body.clone()),
+                    @make_lambda(Object::nil(), make_list(after))
+                );
+                old_bindings.extend(new_bindings);
+                let body = make_let(old_bindings, list!(@wind));
+                Ok(make_let(param_bindings, list!(@body)))
+            },
+        }
+    }
+
+    /// Promises are created with the two-argument `make-promise` of the R7RS sample
+    /// implementation, which takes a flag that tells whether the promise is already done.
+    fn rewrite_delay_force(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?expression)] => {
+                let thunk = make_lambda(Object::nil(), list!(@expression.clone()));
+                Ok(list!(@sym("make-promise"), @Object::boolean(false), @thunk))
+            },
+        }
+    }
+
+    fn rewrite_delay(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?expression)] => {
+                let promise = list!(@sym("make-promise"), @Object::boolean(true), @expression.clone());
+                Ok(list!(@sym("delay-force"), @promise))
+            },
+        }
+    }
+
+    /// Templates are built with `cons`, `append` and `list->vector`. Only the operands of
+    /// `unquote` and `unquote-splicing` at the depth of the outermost quasiquote are evaluated;
+    /// the other parts of the template are quoted.
+    fn rewrite_quasiquote(&self, expr: &Object) -> Result<Object> {
+        try_switch! {expr,
+            [(_ ?template)] => self.rewrite_template(template, 1),
+        }
+    }
+
+    fn rewrite_template(&self, template: &Object, depth: usize) -> Result<Object> {
+        if !contains_unquote(template) {
+            return Ok(list!(quote, @template.clone()));
+        }
+        if let Some(items) = template.as_vector() {
+            let items = make_list(items.to_vec());
+            return Ok(list!(@sym("list->vector"), @self.rewrite_template(&items, depth)?));
+        }
+        let keyword = template.car().and_then(Object::symbol_name);
+        match keyword {
+            Some("unquote") if depth == 1 => try_switch! {template,
+                [(_ ?expr)] => Ok(expr.clone()),
+            },
+            Some("unquote") => self.rewrite_nested_template(template, depth - 1),
+            Some("quasiquote") => self.rewrite_nested_template(template, depth + 1),
+            _ => {
+                let (car, cdr) = template.decons().unwrap();
+                match car.car().and_then(Object::symbol_name) {
+                    Some("unquote-splicing") if depth == 1 => try_switch! {car,
+                        [(_ ?expr)] => Ok(list!(@sym("append"), @expr.clone(),
+                                                @self.rewrite_template(cdr, depth)?)),
+                    },
+                    Some("unquote-splicing") => Ok(list!(
+                        @sym("cons"),
+                        @self.rewrite_nested_template(car, depth - 1)?,
+                        @self.rewrite_template(cdr, depth)?
+                    )),
+                    _ => Ok(list!(
+                        @sym("cons"),
+                        @self.rewrite_template(car, depth)?,
+                        @self.rewrite_template(cdr, depth)?
+                    )),
+                }
+            }
+        }
+    }
+
+    /// A nested `(keyword template)` form is kept as data, with its template at `depth`.
+    fn rewrite_nested_template(&self, template: &Object, depth: usize) -> Result<Object> {
+        try_switch! {template,
+            [(?keyword ?inner)] => Ok(list!(
+                @sym("list"),
+                @list!(quote, @keyword.clone()),
+                @self.rewrite_template(inner, depth)?
+            )),
+        }
+    }
+
+    /// Copy a formals list, replacing each variable by a fresh temporary.
+    fn temporary_formals(&self, formals: &Object, bindings: &mut Vec<Object>) -> Result<Object> {
+        if formals.is_nil() {
+            return Ok(Object::nil());
+        }
+        match formals.decons() {
+            Some((var, rest)) => {
+                let temp = self.temporary(var)?;
+                bindings.push(list!(@var.clone(), @temp.clone()));
+                Ok(cons(temp, self.temporary_formals(rest, bindings)?))
+            }
+            None => {
+                let temp = self.temporary(formals)?;
+                bindings.push(list!(@formals.clone(), @temp.clone()));
+                Ok(temp)
+            }
+        }
+    }
+
+    fn temporary(&self, var: &Object) -> Result<Object> {
+        var.as_symbol()
+            .map(|s| Symbol::gensym(s.name(), &self.gensym).into())
+            .ok_or_else(|| syntax_error("expected symbol", var))
+    }
+
+    fn gensym(&self, base: &str) -> Object {
+        Symbol::gensym(base, &self.gensym).into()
+    }
+}
+
+fn sym(name: &str) -> Object {
+    Object::symbol(name)
+}
+
+fn cons(car: Object, cdr: Object) -> Object {
+    Object::cons(car, cdr)
+}
+
+fn make_list(items: Vec<Object>) -> Object {
+    let mut list = ListBuilder::new();
+    items.into_iter().for_each(|item| list.append(item));
+    list.build()
+}
+
+fn cloned(items: &[&Object]) -> Vec<Object> {
+    items.iter().map(|&x| x.clone()).collect()
+}
+
+fn proper_list(list: &Object) -> Result<Vec<&Object>> {
+    let (items, tail) = list.list_parts();
+    if tail.is_nil() {
+        Ok(items)
+    } else {
+        Err(syntax_error("expected proper list", list))
+    }
+}
+
+fn make_lambda(params: Object, body: Object) -> Object {
+    cons(sym("lambda"), cons(params, body))
+}
+
+fn make_let(bindings: Vec<Object>, body: Object) -> Object {
+    cons(sym("let"), cons(make_list(bindings), body))
+}
+
+fn make_if(test: Object, consequence: Object, alternative: Option<Object>) -> Object {
+    match alternative {
+        Some(alternative) => list!(@sym("if"), @test, @consequence, @alternative),
+        None => list!(@sym("if"), @test, @consequence),
+    }
+}
+
+fn call_cc(proc: Object) -> Object {
+    list!(@sym("call/cc"), @proc)
+}
+
+/// The idiomatic unspecified value, `(if #f #f)`.
+fn unspecified() -> Object {
+    list!(@sym("if"), @Object::boolean(false), @Object::boolean(false))
+}
+
+fn undefined_bindings(vars: &[&Object]) -> Vec<Object> {
+    vars.iter()
+        .map(|&var| list!(@var.clone(), @unspecified()))
+        .collect()
+}
+
+/// Whether a quasiquote template contains any unquoted parts, at any depth.
+fn contains_unquote(template: &Object) -> bool {
+    if let Some(items) = template.as_vector() {
+        return items.iter().any(contains_unquote);
+    }
+    match template.decons() {
+        Some((car, cdr)) => {
+            is_keyword(car, "unquote")
+                || is_keyword(car, "unquote-splicing")
+                || contains_unquote(car)
+                || contains_unquote(cdr)
+        }
+        None => false,
+    }
+}
+
+fn is_keyword(expr: &Object, name: &str) -> bool {
+    expr.symbol_name() == Some(name)
+}
+
+fn syntax_error(msg: &str, expr: &Object) -> crate::error::Error {
+    ErrorKind::SyntaxError(format!("{}: {}", msg, expr)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{assert_same_code, parse_datum};
+
+    macro_rules! assert_desugars {
+        ($actual:expr, $expected:expr) => {
+            assert_same_code(
+                &Desugar::new()
+                    .transform(&parse_datum($actual).unwrap())
+                    .unwrap(),
+                $expected,
+            )
+        };
+    }
+
+    #[test]
+    fn quasiquote_templates_are_built_from_lists() {
+        assert_desugars!("(quasiquote (when 1 2))", "'(when 1 2)");
+        assert_desugars!(
+            "(quasiquote (a (unquote (when x y)) (unquote-splicing l) b))",
+            "(cons 'a (cons (if x (begin y)) (append l '(b))))"
+        );
+        assert_desugars!("(quasiquote (1 . (unquote x)))", "(cons '1 x)");
+        assert_desugars!(
+            "(quasiquote #(1 (unquote x)))",
+            "(list->vector (cons '1 (cons x '())))"
+        );
+    }
+
+    #[test]
+    fn nested_quasiquote_keeps_inner_unquotes() {
+        assert_desugars!(
+            "(quasiquote (1 (quasiquote (2 (unquote (3 (unquote x)))))))",
+            "(cons '1 (cons (list 'quasiquote (cons '2 (cons (list 'unquote (cons '3 (cons x '()))) '()))) '()))"
+        );
+    }
+
+    #[test]
+    fn core_forms_are_unchanged() {
+        assert_desugars!(
+            "(begin (define x '(let a)) (set! x (lambda (y) (if y 1 2))))",
+            "(begin (define x '(let a)) (set! x (lambda (y) (if y 1 2))))"
+        );
+    }
+
+    #[test]
+    fn procedure_definitions() {
+        assert_desugars!(
+            "(define (f x . y) (g x y))",
+            "(define f (lambda (x . y) (g x y)))"
+        );
+    }
+
+    #[test]
+    fn let_forms() {
+        assert_desugars!(
+            "(let ((x 1) (y 2)) (+ x y))",
+            "((lambda (x y) (+ x y)) 1 2)"
+        );
+        assert_desugars!(
+            "(let* ((x 1) (y x)) y)",
+            "((lambda (x) ((lambda (y) ((lambda () y))) x)) 1)"
+        );
+        assert_desugars!("(let loop ((i 0)) (loop (+ i 1)))", "(((lambda (loop) ((lambda (#:loop.0) (set! loop #:loop.0)) (lambda (i) (loop (+ i 1)))) ((lambda () loop))) (if #f #f)) 0)");
+    }
+
+    #[test]
+    fn letrec_forms() {
+        assert_desugars!("(letrec ((even? (lambda (n) (odd? n))) (odd? (lambda (n) (even? n)))) (even? 1))", "((lambda (even? odd?) ((lambda (#:even?.0 #:odd?.1) (set! even? #:even?.0) (set! odd? #:odd?.1)) (lambda (n) (odd? n)) (lambda (n) (even? n))) ((lambda () (even? 1)))) (if #f #f) (if #f #f))");
+        assert_desugars!(
+            "(letrec* ((a 1) (b a)) b)",
+            "((lambda (a b) (set! a 1) (set! b a) ((lambda () b))) (if #f #f) (if #f #f))"
+        );
+    }
+
+    #[test]
+    fn let_values_forms() {
+        assert_desugars!("(let-values (((a b) (values 1 2)) ((c . d) (f))) (list a b c d))", "(call-with-values (lambda () (values 1 2)) (lambda (#:a.0 #:b.1) (call-with-values (lambda () (f)) (lambda (#:c.2 . #:d.3) ((lambda (a b c d) (list a b c d)) #:a.0 #:b.1 #:c.2 #:d.3)))))");
+        assert_desugars!("(let*-values (((a) (f)) (b (g a))) b)", "(call-with-values (lambda () (f)) (lambda (#:a.0) ((lambda (a) (call-with-values (lambda () (g a)) (lambda #:b.1 ((lambda (b) ((lambda () b))) #:b.1)))) #:a.0)))");
+    }
+
+    #[test]
+    fn boolean_operators() {
+        assert_desugars!("(and)", "#t");
+        assert_desugars!("(and a b c)", "(if a (if b c #f) #f)");
+        assert_desugars!("(or)", "#f");
+        assert_desugars!("(or a b)", "((lambda (#:x.0) (if #:x.0 #:x.0 b)) a)");
+    }
+
+    #[test]
+    fn when_and_unless() {
+        assert_desugars!("(when a b c)", "(if a (begin b c))");
+        assert_desugars!("(unless a b c)", "(if (not a) (begin b c))");
+    }
+
+    #[test]
+    fn cond_clauses() {
+        assert_desugars!("(cond (a 1) (b) (c => f) (else 2 3))", "(if a (begin 1) ((lambda (#:x.0) (if #:x.0 #:x.0 ((lambda (#:t.1) (if #:t.1 (f #:t.1) (begin 2 3))) c))) b))");
+        assert_desugars!("(cond (a 1))", "(if a (begin 1))");
+    }
+
+    #[test]
+    fn case_clauses() {
+        assert_desugars!("(case (f) ((1 2) 'low) ((3) => g) (else 'high))", "((lambda (#:key.0) (if (memv #:key.0 (quote (1 2))) (begin (quote low)) (if (memv #:key.0 (quote (3))) (g #:key.0) (begin (quote high))))) (f))");
+    }
+
+    #[test]
+    fn do_loops() {
+        assert_desugars!("(do ((i 0 (+ i 1)) (acc '())) ((= i 3) acc) (set! acc (cons i acc)))", "((lambda (#:loop.0) ((lambda (#:loop.0.1) (set! #:loop.0 #:loop.0.1)) (lambda (i acc) (if (= i 3) (begin acc) (begin (set! acc (cons i acc)) (#:loop.0 (+ i 1) acc))))) ((lambda () (#:loop.0 0 (quote ()))))) (if #f #f))");
+    }
+
+    #[test]
+    fn case_lambda_dispatches_on_argument_count() {
+        assert_desugars!("(case-lambda ((x) x) ((x y . z) y) (all all))", "(lambda #:args.0 ((lambda (#:len.1) (if (= #:len.1 1) (apply (lambda (x) x) #:args.0) (if (>= #:len.1 2) (apply (lambda (x y . z) y) #:args.0) (apply (lambda all all) #:args.0)))) (length #:args.0)))");
+    }
+
+    #[test]
+    fn guard_installs_exception_handler() {
+        assert_desugars!("(guard (e ((symbol? e) e)) (raise 'oops))", "((call/cc (lambda (#:guard-k.0) (with-exception-handler (lambda (#:condition.2) ((call/cc (lambda (#:handler-k.1) (#:guard-k.0 (lambda () ((lambda (e) (if (symbol? e) (begin e) (begin (#:handler-k.1 (lambda () (raise-continuable #:condition.2)))))) #:condition.2))))))) (lambda () (call-with-values (lambda () (raise (quote oops))) (lambda #:args.3 (#:guard-k.0 (lambda () (apply values #:args.3))))))))))");
+    }
+
+    #[test]
+    fn parameterize_uses_dynamic_wind() {
+        assert_desugars!("(parameterize ((p 1)) (p))", "((lambda (#:p.0) ((lambda (#:old.1 #:new.2) (dynamic-wind (lambda () (#:p.0 (quote <param-set!>) #:new.2)) (lambda () (p)) (lambda () (#:p.0 (quote <param-set!>) #:old.1)))) (#:p.0) ((#:p.0 (quote <param-convert>)) 1))) p)");
+    }
+
+    #[test]
+    fn promises() {
+        assert_desugars!(
+            "(delay (f))",
+            "(make-promise #f (lambda () (make-promise #t (f))))"
+        );
+    }
+
+    #[test]
+    fn derived_forms_are_desugared_recursively() {
+        assert_desugars!(
+            "(lambda (x) (when (and x) (cond (else x))))",
+            "(lambda (x) (if x (begin (begin x))))"
+        );
+    }
+
+    #[test]
+    fn else_must_be_last_clause() {
+        assert!(Desugar::new()
+            .transform(&parse_datum("(cond (else 1) (a 2))").unwrap())
+            .is_err());
+    }
+}
@@ -0,0 +1,1402 @@
+//! Macro expansion source transform
+//! This transform expands all uses of macros defined with `define-syntax`, `let-syntax` and
+//! `letrec-syntax`. The output contains no macro definitions or uses.
+//!
+//! Expansion is hygienic. Every identifier a macro inserts into its output is replaced by a
+//! fresh alias that remembers the original name and the scope the macro was defined in. An
+//! alias that is not bound by the expansion itself means whatever the original name means in
+//! that scope. Local variables are renamed to unique names, like the `Alphatizer` does, so
+//! bindings at the macro use can never capture identifiers inserted by the macro.
+//! Aliases do not appear in the output; quoted aliases turn back into their original names.
+//!
+//! Macros are defined in Scheme with `syntax-rules`, or as explicit and implicit renaming macros
+//! with `er-macro-transformer` and `ir-macro-transformer`. The procedures of renaming macros
+//! run at expansion time in an `Interpreter` that only knows the primitives, not the
+//! definitions of the program. Renaming macros can also be written in Rust and registered with
+//! `MacroExpander::define_er_macro` and `MacroExpander::define_ir_macro`.
+
+use super::alphatize::Scope;
+use super::syntax_rules::{Hygiene, SyntaxRules};
+use super::SourceTransformer;
+use crate::error::{ErrorKind, Result};
+use crate::eval::{Interpreter, Value};
+use crate::object::{ListBuilder, Object, TaggedValue};
+use crate::runtime::{GensymCounter, Symbol};
+use crate::SchemeExpression;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Signature of macro transformers implemented in Rust.
+///
+/// A transformer is called with the macro use, a function that turns names into identifiers,
+/// and a function that tells if two identifiers mean the same thing at the macro use.
+/// Explicit renaming macros get `rename`, which makes identifiers that mean what the name means
+/// where the macro is defined. Implicit renaming macros get `inject`, which makes identifiers
+/// that mean what the name means at the macro use; all other identifiers they insert are renamed.
+pub type MacroFn = dyn Fn(
+    &Object,
+    &mut dyn FnMut(&str) -> Object,
+    &dyn Fn(&Object, &Object) -> bool,
+) -> Result<Object>;
+
+enum Transformer {
+    SyntaxRules(SyntaxRules),
+    ExplicitRenaming(MacroProcedure),
+    ImplicitRenaming(MacroProcedure),
+}
+
+/// The procedure of a renaming macro.
+enum MacroProcedure {
+    Rust(Box<MacroFn>),
+    /// A procedure of the expander's interpreter, which is called with the macro use and
+    /// native procedures in place of the functions passed to a `MacroFn`.
+    Scheme(Value),
+}
+
+#[derive(Default)]
+pub struct MacroExpander {
+    global_macros: HashMap<Symbol, Rc<Transformer>>,
+    aliases: Aliases,
+    gensym: GensymCounter,
+    interpreter: RefCell<Interpreter>,
+}
+
+impl SourceTransformer for MacroExpander {
+    fn transform(&mut self, input: &Object) -> Result<Object> {
+        let mut global_scope = Scope::new();
+        for (&name, transformer) in &self.global_macros {
+            global_scope.insert(name, Binding::Macro(transformer.clone(), 0));
+        }
+
+        let result = self.expand_toplevel(input, &mut global_scope);
+
+        // aliases never escape an expansion
+        self.aliases.clear();
+
+        // macros defined at the top level remain available to later inputs
+        for (name, binding) in global_scope.into_bindings() {
+            match binding {
+                Binding::Macro(transformer, _) => self.global_macros.insert(name, transformer),
+                _ => self.global_macros.remove(&name),
+            };
+        }
+        result
+    }
+}
+
+impl MacroExpander {
+    pub fn new() -> Self {
+        MacroExpander::default()
+    }
+
+    /// Define a global explicit renaming macro.
+    pub fn define_er_macro<F>(&mut self, name: &str, transformer: F)
+    where
+        F: Fn(
+                &Object,
+                &mut dyn FnMut(&str) -> Object,
+                &dyn Fn(&Object, &Object) -> bool,
+            ) -> Result<Object>
+            + 'static,
+    {
+        self.global_macros.insert(
+            Symbol::new(name),
+            Rc::new(Transformer::ExplicitRenaming(MacroProcedure::Rust(
+                Box::new(transformer),
+            ))),
+        );
+    }
+
+    /// Define a global implicit renaming macro.
+    pub fn define_ir_macro<F>(&mut self, name: &str, transformer: F)
+    where
+        F: Fn(
+                &Object,
+                &mut dyn FnMut(&str) -> Object,
+                &dyn Fn(&Object, &Object) -> bool,
+            ) -> Result<Object>
+            + 'static,
+    {
+        self.global_macros.insert(
+            Symbol::new(name),
+            Rc::new(Transformer::ImplicitRenaming(MacroProcedure::Rust(
+                Box::new(transformer),
+            ))),
+        );
+    }
+
+    fn expand_toplevel(&mut self, input: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        self.expand_toplevel_form(input, scope)
+            .map(|output| output.unwrap_or_else(Object::undef))
+    }
+
+    /// Expand a top-level form; syntax definitions produce no output.
+    fn expand_toplevel_form(
+        &mut self,
+        input: &Object,
+        scope: &mut Scope<Binding>,
+    ) -> Result<Option<Object>> {
+        match self.resolve_head(input, scope) {
+            Some(Binding::Free(kw)) if kw.name() == "begin" => {
+                let mut body = ListBuilder::new();
+                for form in input.cdr().unwrap().list_parts().0 {
+                    if let Some(output) = self.expand_toplevel_form(form, scope)? {
+                        body.append(output);
+                    }
+                }
+                Ok(Some(Object::cons(Object::from(kw), body.build())))
+            }
+            Some(Binding::Free(kw)) if kw.name() == "define-syntax" => {
+                self.define_syntax(input, scope)?;
+                Ok(None)
+            }
+            Some(Binding::Free(kw)) if kw.name() == "define" => {
+                self.expand_define(input, scope, true).map(Some)
+            }
+            Some(Binding::Macro(transformer, depth)) => {
+                let output = self.apply_macro(&transformer, depth, input, scope)?;
+                self.expand_toplevel_form(&output, scope)
+            }
+            _ => self.expand(input, scope).map(Some),
+        }
+    }
+
+    fn expand(&mut self, input: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        if let Some(name) = input.as_symbol() {
+            return self.expand_reference(name, scope);
+        }
+
+        match self.resolve_head(input, scope) {
+            Some(Binding::Macro(transformer, depth)) => {
+                let output = self.apply_macro(&transformer, depth, input, scope)?;
+                self.expand(&output, scope)
+            }
+            Some(Binding::Free(kw)) => self.expand_special_form(kw, input, scope),
+            Some(Binding::Variable(_)) => self.expand_sequence(input, scope),
+            None if input.is_list() => self.expand_sequence(input, scope),
+            None => Ok(self.strip_aliases(input)),
+        }
+    }
+
+    fn expand_special_form(
+        &mut self,
+        keyword: Symbol,
+        input: &Object,
+        scope: &mut Scope<Binding>,
+    ) -> Result<Object> {
+        match keyword.name() {
+            "quote" => Ok(self.strip_aliases(input)),
+            "quasiquote" => self.expand_quasiquote(input, scope),
+            "case" => self.expand_case(input, scope),
+            "lambda" => self.expand_lambda(input, scope),
+            "define" => self.expand_define(input, scope, false),
+            "let" | "let*" | "letrec" | "letrec*" => self.expand_let(keyword, input, scope),
+            "do" => self.expand_do(input, scope),
+            "let-syntax" => self.expand_let_syntax(input, scope, false),
+            "letrec-syntax" => self.expand_let_syntax(input, scope, true),
+            "define-syntax" => Err(ErrorKind::SyntaxError(format!(
+                "define-syntax is only allowed at the beginning of a body: {:?}",
+                input
+            ))
+            .into()),
+            _ => self.expand_sequence(input, scope),
+        }
+    }
+
+    fn expand_reference(&mut self, name: Symbol, scope: &Scope<Binding>) -> Result<Object> {
+        match self.resolve(name, scope) {
+            Binding::Variable(name) | Binding::Free(name) => Ok(Object::from(name)),
+            Binding::Macro(_, _) => Err(ErrorKind::SyntaxError(format!(
+                "invalid use of macro keyword: {}",
+                self.original_name(name)
+            ))
+            .into()),
+        }
+    }
+
+    fn expand_quasiquote(&mut self, input: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?template)] => {
+                let template = self.expand_template(template, 1, scope)?;
+                Ok(list!(quasiquote, @template))
+            },
+        }
+    }
+
+    /// Templates are quoted, except for the expressions unquoted at the nesting depth of the
+    /// outermost quasiquote.
+    fn expand_template(
+        &mut self,
+        template: &Object,
+        depth: usize,
+        scope: &mut Scope<Binding>,
+    ) -> Result<Object> {
+        let keyword = match self.resolve_head(template, scope) {
+            Some(Binding::Free(kw)) => kw.name(),
+            _ => "",
+        };
+        let depth = match keyword {
+            "unquote" | "unquote-splicing" if depth == 1 => {
+                return self.expand_unquote(keyword, template, scope)
+            }
+            "unquote" | "unquote-splicing" => depth - 1,
+            "quasiquote" => depth + 1,
+            _ => depth,
+        };
+        match (template.car(), template.cdr()) {
+            (Some(car), Some(cdr)) => Ok(Object::cons(
+                self.expand_template(car, depth, scope)?,
+                self.expand_template(cdr, depth, scope)?,
+            )),
+            _ => Ok(self.strip_aliases(template)),
+        }
+    }
+
+    fn expand_unquote(
+        &mut self,
+        keyword: &str,
+        input: &Object,
+        scope: &mut Scope<Binding>,
+    ) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?expr)] => {
+                let expr = self.expand(expr, scope)?;
+                Ok(list!(@Object::symbol(keyword), @expr))
+            },
+        }
+    }
+
+    /// The data of the clauses are quoted.
+    fn expand_case(&mut self, input: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?key ?clauses ...)] => {
+                let key = self.expand(key, scope)?;
+                let mut result = ListBuilder::new();
+                for clause in clauses {
+                    result.append(self.expand_case_clause(clause, scope)?);
+                }
+                Ok(list!(case, @key, . @result.build()))
+            },
+        }
+    }
+
+    fn expand_case_clause(
+        &mut self,
+        clause: &Object,
+        scope: &mut Scope<Binding>,
+    ) -> Result<Object> {
+        try_switch! {clause,
+            [(?data . ?body)] => {
+                let body = self.expand_sequence(body, scope)?;
+                Ok(Object::cons(self.strip_aliases(data), body))
+            },
+        }
+    }
+
+    fn expand_lambda(&mut self, input: &Object, scope: &Scope<Binding>) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?params . ?body)] => {
+                let mut inner_scope = scope.extend();
+                let params = self.bind_variables(params, &mut inner_scope)?;
+                let body = self.expand_body(body, &mut inner_scope)?;
+                Ok(list!(lambda, @params, . @body))
+            },
+        }
+    }
+
+    fn expand_define(
+        &mut self,
+        input: &Object,
+        scope: &mut Scope<Binding>,
+        toplevel: bool,
+    ) -> Result<Object> {
+        try_switch! {input,
+            [(_ (?name . ?params) . ?body)] => {
+                let name = self.define_variable(name, scope, toplevel)?;
+                let mut inner_scope = scope.extend();
+                let params = self.bind_variables(params, &mut inner_scope)?;
+                let body = self.expand_body(body, &mut inner_scope)?;
+                let signature = Object::cons(name, params);
+                Ok(list!(define, @signature, . @body))
+            },
+            [(_ ?name ?value)] => {
+                let name = self.define_variable(name, scope, toplevel)?;
+                let value = self.expand(value, scope)?;
+                Ok(list!(define, @name, @value))
+            },
+        }
+    }
+
+    fn expand_let(
+        &mut self,
+        keyword: Symbol,
+        input: &Object,
+        scope: &mut Scope<Binding>,
+    ) -> Result<Object> {
+        try_switch! {input,
+            [(_ ?name:symbol ((?vars ?inits) ...) . ?body)] if keyword.name() == "let" => {
+                let inits = self.expand_all(&inits, scope)?;
+                let mut loop_scope = scope.extend();
+                let name = self.bind_variable(name, &mut loop_scope, false)?;
+                let mut inner_scope = loop_scope.extend();
+                let mut bindings = ListBuilder::new();
+                for (var, init) in vars.into_iter().zip(inits) {
+                    let var = self.bind_variable(var, &mut inner_scope, false)?;
+                    bindings.append(list!(@var, @init));
+                }
+                let body = self.expand_body(body, &mut inner_scope)?;
+                Ok(list!(@Object::from(keyword), @name, @bindings.build(), . @body))
+            },
+            [(_ ((?vars ?inits) ...) . ?body)] => {
+                let outer_inits = if keyword.name() == "let" {
+                    Some(self.expand_all(&inits, scope)?)
+                } else {
+                    None
+                };
+                let mut inner_scope = scope.extend();
+                let mut bindings = ListBuilder::new();
+                match (keyword.name(), outer_inits) {
+                    (_, Some(inits)) => {
+                        for (var, init) in vars.into_iter().zip(inits) {
+                            let var = self.bind_variable(var, &mut inner_scope, false)?;
+                            bindings.append(list!(@var, @init));
+                        }
+                    }
+                    ("let*", None) => {
+                        for (var, init) in vars.into_iter().zip(inits) {
+                            let init = self.expand(init, &mut inner_scope)?;
+                            // later variables shadow earlier ones of the same name
+                            let var = self.rebind_variable(var, &mut inner_scope, false)?;
+                            bindings.append(list!(@var, @init));
+                        }
+                    }
+                    (_, None) => {
+                        let vars = vars
+                            .into_iter()
+                            .map(|var| self.bind_variable(var, &mut inner_scope, false))
+                            .collect::<Result<Vec<_>>>()?;
+                        for (var, init) in vars.into_iter().zip(inits) {
+                            let init = self.expand(init, &mut inner_scope)?;
+                            bindings.append(list!(@var, @init));
+                        }
+                    }
+                }
+                let body = self.expand_body(body, &mut inner_scope)?;
+                Ok(list!(@Object::from(keyword), @bindings.build(), . @body))
+            },
+        }
+    }
+
+    fn expand_do(&mut self, input: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        try_switch! {input,
+            [(_ (?specs ...) (?test . ?exprs) . ?commands)] => {
+                let mut specs_parts = vec![];
+                for spec in specs {
+                    match spec.list_parts() {
+                        (parts, tail) if tail.is_nil() && (parts.len() == 2 || parts.len() == 3) => {
+                            specs_parts.push(parts)
+                        }
+                        _ => {
+                            return Err(ErrorKind::SyntaxError(format!(
+                                "invalid do binding: {:?}",
+                                spec
+                            ))
+                            .into())
+                        }
+                    }
+                }
+
+                let inits = specs_parts
+                    .iter()
+                    .map(|parts| self.expand(parts[1], scope))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut inner_scope = scope.extend();
+                let vars = specs_parts
+                    .iter()
+                    .map(|parts| self.bind_variable(parts[0], &mut inner_scope, false))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let mut bindings = ListBuilder::new();
+                for ((var, init), parts) in vars.into_iter().zip(inits).zip(&specs_parts) {
+                    let mut binding = ListBuilder::new();
+                    binding.append(var);
+                    binding.append(init);
+                    if let Some(step) = parts.get(2) {
+                        binding.append(self.expand(step, &mut inner_scope)?);
+                    }
+                    bindings.append(binding.build());
+                }
+                let test = self.expand(test, &mut inner_scope)?;
+                let exprs = self.expand_sequence(exprs, &mut inner_scope)?;
+                let commands = self.expand_sequence(commands, &mut inner_scope)?;
+                let clause = Object::cons(test, exprs);
+                Ok(list!(@Object::symbol("do"), @bindings.build(), @clause, . @commands))
+            },
+        }
+    }
+
+    fn expand_let_syntax(
+        &mut self,
+        input: &Object,
+        scope: &Scope<Binding>,
+        recursive: bool,
+    ) -> Result<Object> {
+        try_switch! {input,
+            [(_ ((?names:symbol ?specs) ...) . ?body)] => {
+                let mut inner_scope = scope.extend();
+                for (name, spec) in names.into_iter().zip(specs) {
+                    // let-syntax macros are defined in the outer scope, letrec-syntax macros in
+                    // the inner scope where they can refer to each other
+                    let (transformer, depth) = if recursive {
+                        (self.parse_transformer(spec, &inner_scope)?, inner_scope.depth())
+                    } else {
+                        (self.parse_transformer(spec, scope)?, scope.depth())
+                    };
+                    inner_scope.insert(
+                        name.as_symbol().unwrap(),
+                        Binding::Macro(Rc::new(transformer), depth),
+                    );
+                }
+                let body = self.expand_body(body, &mut inner_scope)?;
+                if body.cdr().map(Object::is_null).unwrap_or(false) {
+                    Ok(body.car().unwrap().clone())
+                } else {
+                    let thunk = list!(lambda, @Object::nil(), . @body);
+                    Ok(list!(@thunk))
+                }
+            },
+        }
+    }
+
+    /// Expand a lambda body or a `begin` sequence.
+    /// Macro definitions at the beginning of the body and internal definitions are visible in
+    /// the whole body.
+    fn expand_body(&mut self, body: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        let mut cursor = body;
+        while let Some(form) = cursor
+            .car()
+            .filter(|form| self.is_form(form, "define-syntax", scope))
+        {
+            self.define_syntax(form, scope)?;
+            cursor = cursor.cdr().unwrap();
+        }
+
+        // internal definitions may shadow parameters, but not each other
+        let mut body_scope = scope.extend();
+        for form in cursor.list_parts().0 {
+            if self.is_form(form, "define", &body_scope) {
+                let target = form.get_ref(1);
+                let name = target.map(|t| t.car().unwrap_or(t));
+                if let Some(name) = name.filter(|name| name.is_symbol()) {
+                    self.bind_variable(name, &mut body_scope, false)?;
+                }
+            }
+        }
+
+        self.expand_sequence(cursor, &mut body_scope)
+    }
+
+    fn expand_sequence(&mut self, exps: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        let (items, tail) = exps.list_parts();
+        let mut list = ListBuilder::new();
+        for item in items {
+            list.append(self.expand(item, scope)?);
+        }
+        list.set_cdr(self.strip_aliases(tail));
+        Ok(list.build())
+    }
+
+    fn expand_all(&mut self, exps: &[&Object], scope: &mut Scope<Binding>) -> Result<Vec<Object>> {
+        exps.iter().map(|x| self.expand(x, scope)).collect()
+    }
+
+    fn define_syntax(&mut self, input: &Object, scope: &mut Scope<Binding>) -> Result<()> {
+        try_switch! {input,
+            [(_ ?name:symbol ?spec)] => {
+                let transformer = self.parse_transformer(spec, scope)?;
+                let depth = scope.depth();
+                scope.insert(name.as_symbol().unwrap(), Binding::Macro(Rc::new(transformer), depth));
+                Ok(())
+            },
+        }
+    }
+
+    fn parse_transformer(&self, spec: &Object, scope: &Scope<Binding>) -> Result<Transformer> {
+        let keyword = match self.resolve_head(spec, scope) {
+            Some(Binding::Free(kw)) => kw.name(),
+            _ => "",
+        };
+        match keyword {
+            "syntax-rules" => {
+                // the keyword may be an alias if the macro was defined by another macro
+                let spec = Object::cons(Object::symbol(keyword), spec.cdr().unwrap().clone());
+                SyntaxRules::parse(&spec).map(Transformer::SyntaxRules)
+            }
+            "er-macro-transformer" | "ir-macro-transformer" => try_switch! {spec,
+                [(_ ?procedure)] => {
+                    let procedure = self.strip_aliases(procedure);
+                    let procedure = self.interpreter.borrow_mut().eval_datum(&procedure)?;
+                    if !procedure.is_procedure() {
+                        return Err(ErrorKind::SyntaxError(format!(
+                            "macro transformer is not a procedure: {}",
+                            procedure
+                        ))
+                        .into());
+                    }
+                    let procedure = MacroProcedure::Scheme(procedure);
+                    Ok(if keyword == "er-macro-transformer" {
+                        Transformer::ExplicitRenaming(procedure)
+                    } else {
+                        Transformer::ImplicitRenaming(procedure)
+                    })
+                },
+            },
+            _ => {
+                Err(ErrorKind::SyntaxError(format!("invalid macro transformer: {:?}", spec)).into())
+            }
+        }
+    }
+
+    fn apply_macro(
+        &self,
+        transformer: &Transformer,
+        depth: usize,
+        input: &Object,
+        scope: &Scope<Binding>,
+    ) -> Result<Object> {
+        match transformer {
+            Transformer::SyntaxRules(rules) => {
+                rules.expand(input, &mut Renamer::new(self, scope, depth))
+            }
+            Transformer::ExplicitRenaming(procedure) => {
+                let use_depth = scope.depth();
+                let call = Rc::new(MacroCall::new(self, scope, depth));
+                let compare = {
+                    let call = call.clone();
+                    move |a: &Object, b: &Object| call.same_meaning(a, use_depth, b, use_depth)
+                };
+                self.call_procedure(procedure, input, "rename", &call, compare)
+            }
+            Transformer::ImplicitRenaming(procedure) => {
+                // Identifiers from the macro use are wrapped in aliases that resolve at the macro
+                // use, so they can be told apart from the identifiers the transformer inserts.
+                let use_depth = scope.depth();
+                let call = Rc::new(MacroCall::new(self, scope, use_depth));
+                let input = map_symbols(input, &mut |name| call.identifier(name));
+
+                let compare = {
+                    let call = call.clone();
+                    move |a: &Object, b: &Object| {
+                        let depth_of = |x: &Object| match x.as_symbol() {
+                            Some(s) if !call.is_identifier(s) => depth,
+                            _ => use_depth,
+                        };
+                        call.same_meaning(a, depth_of(a), b, depth_of(b))
+                    }
+                };
+                let output = self.call_procedure(procedure, &input, "inject", &call, compare)?;
+
+                let mut renamer = Renamer::new(self, scope, depth);
+                Ok(map_symbols(&output, &mut |name| {
+                    if call.is_identifier(name) {
+                        name
+                    } else {
+                        renamer.rename(name)
+                    }
+                }))
+            }
+        }
+    }
+
+    /// Call the procedure of a renaming macro, which makes identifiers with `call` and compares
+    /// them with `compare`. Procedures written in Scheme get the former as the native procedure
+    /// `name`.
+    fn call_procedure<F>(
+        &self,
+        procedure: &MacroProcedure,
+        input: &Object,
+        name: &str,
+        call: &Rc<MacroCall>,
+        compare: F,
+    ) -> Result<Object>
+    where
+        F: Fn(&Object, &Object) -> bool + 'static,
+    {
+        match procedure {
+            MacroProcedure::Rust(transformer) => transformer(
+                input,
+                &mut |name: &str| Object::from(call.identifier(Symbol::new(name))),
+                &compare,
+            ),
+            MacroProcedure::Scheme(procedure) => {
+                let call = call.clone();
+                let make_identifier = Value::native(name, move |args| match args {
+                    [Value::Symbol(name)] => Ok(Value::Symbol(call.identifier(*name))),
+                    _ => Err("expected a symbol".to_string()),
+                });
+                let compare = Value::native("compare", move |args| match args {
+                    [a, b] => match (a.to_object(), b.to_object()) {
+                        (Some(a), Some(b)) => Ok(Value::Boolean(compare(&a, &b))),
+                        _ => Err("expected two identifiers".to_string()),
+                    },
+                    _ => Err("expected two identifiers".to_string()),
+                });
+                let args = vec![Value::from(input), make_identifier, compare];
+                let output = self
+                    .interpreter
+                    .borrow_mut()
+                    .apply_procedure(procedure.clone(), args)?;
+                output.to_object().ok_or_else(|| {
+                    ErrorKind::SyntaxError(format!(
+                        "macro transformer did not return code: {}",
+                        output
+                    ))
+                    .into()
+                })
+            }
+        }
+    }
+
+    /// Find out what an identifier means in the given scope.
+    fn resolve(&self, name: Symbol, scope: &Scope<Binding>) -> Binding {
+        if let Some(binding) = scope.lookup(name) {
+            return binding.clone();
+        }
+        match self.aliases.get(name) {
+            Some(alias) => self.resolve(alias.original, scope.ancestor(alias.depth)),
+            None => Binding::Free(name),
+        }
+    }
+
+    fn resolve_head(&self, x: &Object, scope: &Scope<Binding>) -> Option<Binding> {
+        x.car()
+            .and_then(Object::as_symbol)
+            .map(|name| self.resolve(name, scope))
+    }
+
+    fn is_form(&self, x: &Object, keyword: &str, scope: &Scope<Binding>) -> bool {
+        match self.resolve_head(x, scope) {
+            Some(Binding::Free(kw)) => kw.name() == keyword,
+            _ => false,
+        }
+    }
+
+    /// Bind all variables of a (possibly improper) lambda parameter list.
+    fn bind_variables(&mut self, params: &Object, scope: &mut Scope<Binding>) -> Result<Object> {
+        let (vars, rest) = params.list_parts();
+        let mut list = ListBuilder::new();
+        for var in vars {
+            list.append(self.bind_variable(var, scope, false)?);
+        }
+        if !rest.is_null() {
+            list.set_cdr(self.bind_variable(rest, scope, false)?);
+        }
+        Ok(list.build())
+    }
+
+    /// Bind a variable and return its name in the output. Local variables must be bound only
+    /// once per scope; global variables can be redefined.
+    fn bind_variable(
+        &mut self,
+        var: &Object,
+        scope: &mut Scope<Binding>,
+        toplevel: bool,
+    ) -> Result<Object> {
+        let bound = var.as_symbol().and_then(|name| scope.lookup_local(name));
+        if !toplevel && bound.is_some() {
+            return Err(ErrorKind::SyntaxError(format!(
+                "duplicate variable binding: {:?}",
+                self.strip_aliases(var)
+            ))
+            .into());
+        }
+        self.rebind_variable(var, scope, toplevel)
+    }
+
+    /// Bind a variable, replacing a binding in the same scope, and return its name in the output.
+    /// Global variables keep their name unless they were inserted by a macro.
+    fn rebind_variable(
+        &mut self,
+        var: &Object,
+        scope: &mut Scope<Binding>,
+        toplevel: bool,
+    ) -> Result<Object> {
+        let name = var.as_symbol().ok_or_else(|| {
+            ErrorKind::SyntaxError(format!("variable name is not a symbol: {:?}", var))
+        })?;
+        let output = if toplevel && self.aliases.get(name).is_none() {
+            name
+        } else {
+            Symbol::gensym(self.original_name(name), &self.gensym)
+        };
+        scope.insert(name, Binding::Variable(output));
+        Ok(Object::from(output))
+    }
+
+    fn define_variable(
+        &mut self,
+        var: &Object,
+        scope: &mut Scope<Binding>,
+        toplevel: bool,
+    ) -> Result<Object> {
+        // internal definitions have been bound before their body was expanded
+        match var.as_symbol().and_then(|name| scope.lookup_local(name)) {
+            Some(Binding::Variable(output)) if !toplevel => Ok(Object::from(*output)),
+            _ => self.bind_variable(var, scope, toplevel),
+        }
+    }
+
+    fn original_name(&self, mut name: Symbol) -> Symbol {
+        while let Some(alias) = self.aliases.get(name) {
+            name = alias.original;
+        }
+        name
+    }
+
+    fn strip_aliases(&self, x: &Object) -> Object {
+        map_symbols(x, &mut |name| self.original_name(name))
+    }
+}
+
+#[derive(Clone)]
+enum Binding {
+    /// A macro and the depth of the scope it was defined in
+    Macro(Rc<Transformer>, usize),
+    /// A variable and its name in the output
+    Variable(Symbol),
+    /// An unbound identifier, such as a global variable or a special form
+    Free(Symbol),
+}
+
+fn same_binding(a: &Binding, b: &Binding) -> bool {
+    match (a, b) {
+        (Binding::Macro(a, _), Binding::Macro(b, _)) => Rc::ptr_eq(a, b),
+        (Binding::Variable(a), Binding::Variable(b)) => a == b,
+        (Binding::Free(a), Binding::Free(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// An identifier inserted by a macro that was defined in the scope at `depth`.
+#[derive(Copy, Clone)]
+struct Alias {
+    original: Symbol,
+    depth: usize,
+}
+
+/// The aliases of the current expansion. They are shared with the native procedures passed to
+/// macro transformers written in Scheme.
+#[derive(Default, Clone)]
+struct Aliases(Rc<RefCell<HashMap<Symbol, Alias>>>);
+
+impl Aliases {
+    fn make(&self, name: Symbol, depth: usize) -> Symbol {
+        // aliases are uninterned, so they never clash with names in the source
+        let alias = Symbol::uninterned(name);
+        self.0.borrow_mut().insert(
+            alias,
+            Alias {
+                original: name,
+                depth,
+            },
+        );
+        alias
+    }
+
+    fn get(&self, name: Symbol) -> Option<Alias> {
+        self.0.borrow().get(&name).copied()
+    }
+
+    fn clear(&self) {
+        self.0.borrow_mut().clear()
+    }
+}
+
+/// One use of a renaming macro. The scope of the macro use is copied, so that the procedures
+/// passed to a transformer written in Scheme can own it.
+struct MacroCall {
+    /// The bindings of the scope of the macro use and its ancestors, indexed by depth
+    frames: Vec<HashMap<Symbol, Binding>>,
+    aliases: Aliases,
+    /// The depth of the scope in which the identifiers made by the transformer resolve
+    depth: usize,
+    identifiers: RefCell<HashMap<Symbol, Symbol>>,
+}
+
+impl MacroCall {
+    fn new(expander: &MacroExpander, scope: &Scope<Binding>, depth: usize) -> Self {
+        MacroCall {
+            frames: scope.frames(),
+            aliases: expander.aliases.clone(),
+            depth,
+            identifiers: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The identifier for a name, which is the same for every use of the name in the call.
+    fn identifier(&self, name: Symbol) -> Symbol {
+        let (aliases, depth) = (&self.aliases, self.depth);
+        *self
+            .identifiers
+            .borrow_mut()
+            .entry(name)
+            .or_insert_with(|| aliases.make(name, depth))
+    }
+
+    fn is_identifier(&self, name: Symbol) -> bool {
+        self.identifiers.borrow().values().any(|&id| id == name)
+    }
+
+    /// Find out what an identifier means in the scope at `depth`, like `MacroExpander::resolve`.
+    fn resolve(&self, name: Symbol, depth: usize) -> Binding {
+        let bound = self.frames[..=depth]
+            .iter()
+            .rev()
+            .find_map(|frame| frame.get(&name));
+        if let Some(binding) = bound {
+            return binding.clone();
+        }
+        match self.aliases.get(name) {
+            Some(alias) => self.resolve(alias.original, alias.depth),
+            None => Binding::Free(name),
+        }
+    }
+
+    fn same_meaning(&self, a: &Object, a_depth: usize, b: &Object, b_depth: usize) -> bool {
+        match (a.as_symbol(), b.as_symbol()) {
+            (Some(a), Some(b)) => {
+                same_binding(&self.resolve(a, a_depth), &self.resolve(b, b_depth))
+            }
+            _ => a == b,
+        }
+    }
+}
+
+/// Renames the identifiers inserted by one macro expansion.
+struct Renamer<'e, 's, 'a> {
+    expander: &'e MacroExpander,
+    scope: &'s Scope<'a, Binding>,
+    depth: usize,
+    renamed: HashMap<Symbol, Symbol>,
+}
+
+impl<'e, 's, 'a> Renamer<'e, 's, 'a> {
+    fn new(expander: &'e MacroExpander, scope: &'s Scope<'a, Binding>, depth: usize) -> Self {
+        Renamer {
+            expander,
+            scope,
+            depth,
+            renamed: HashMap::new(),
+        }
+    }
+}
+
+impl<'e, 's, 'a> Hygiene for Renamer<'e, 's, 'a> {
+    fn rename(&mut self, name: Symbol) -> Symbol {
+        let (expander, depth) = (self.expander, self.depth);
+        *self
+            .renamed
+            .entry(name)
+            .or_insert_with(|| expander.aliases.make(name, depth))
+    }
+
+    fn matches_literal(&self, input: Symbol, literal: Symbol) -> bool {
+        same_binding(
+            &self.expander.resolve(input, self.scope),
+            &self
+                .expander
+                .resolve(literal, self.scope.ancestor(self.depth)),
+        )
+    }
+}
+
+fn map_symbols(x: &Object, f: &mut dyn FnMut(Symbol) -> Symbol) -> Object {
+    match x.as_value() {
+        TaggedValue::Symbol(s) => Object::from(f(*s)),
+        TaggedValue::Pair(car, cdr) => Object::cons(map_symbols(car, f), map_symbols(cdr, f)),
+        TaggedValue::Vector(items) => {
+            Object::vector(items.iter().map(|x| map_symbols(x, f)).collect())
+        }
+        _ => x.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{assert_same_code, parse_datum};
+
+    macro_rules! assert_source_eq {
+        ($transformer:expr, $actual:expr, $expected:expr) => {
+            assert_same_code(
+                &$transformer
+                    .transform(&parse_datum($actual).unwrap())
+                    .unwrap(),
+                $expected,
+            )
+        };
+    }
+
+    #[test]
+    fn code_without_macros_only_renames_locals() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(define (f x . y) (if x (g 'x) (lambda z z)))",
+            "(define (f #:x.0 . #:y.1) (if #:x.0 (g 'x) (lambda #:z.2 #:z.2)))"
+        );
+    }
+
+    #[test]
+    fn binding_forms_rename_locals() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(let loop ((i 0) (acc '()))
+               (let* ((x i) (x (+ x 1)))
+                 (letrec ((even? (lambda (n) (odd? n))) (odd? (lambda (n) (even? n))))
+                   (loop x (cons i acc)))))",
+            "(let #:loop.0 ((#:i.1 0) (#:acc.2 '()))
+               (let* ((#:x.3 #:i.1) (#:x.4 (+ #:x.3 1)))
+                 (letrec ((#:even?.5 (lambda (#:n.7) (#:odd?.6 #:n.7))) (#:odd?.6 (lambda (#:n.8) (#:even?.5 #:n.8))))
+                   (#:loop.0 #:x.4 (cons #:i.1 #:acc.2)))))"
+        );
+        assert_source_eq!(
+            expander,
+            "(do ((i 0 (+ i 1)) (v x)) ((= i n) v) (f v i))",
+            "(do ((#:i.9 0 (+ #:i.9 1)) (#:v.10 x)) ((= #:i.9 n) #:v.10) (f #:v.10 #:i.9))"
+        );
+    }
+
+    #[test]
+    fn internal_definitions_are_visible_in_the_whole_body() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(define (f)
+               (define (even? n) (odd? n))
+               (define (odd? n) (even? n))
+               (even? 1))",
+            "(define (f)
+               (define (#:even?.0 #:n.2) (#:odd?.1 #:n.2))
+               (define (#:odd?.1 #:n.3) (#:even?.0 #:n.3))
+               (#:even?.0 1))"
+        );
+    }
+
+    #[test]
+    fn toplevel_define_syntax() {
+        let mut expander = MacroExpander::new();
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax swap!
+                       (syntax-rules ()
+                         ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(swap! x y)",
+            "(let ((#:tmp.0 x)) (set! x y) (set! y #:tmp.0))"
+        );
+    }
+
+    #[test]
+    fn recursive_macro() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(begin
+               (define-syntax my-or
+                 (syntax-rules ()
+                   ((_) 0)
+                   ((_ e) e)
+                   ((_ e r ...) (if e e (my-or r ...)))))
+               (my-or a b c))",
+            "(begin (if a a (if b b c)))"
+        );
+    }
+
+    #[test]
+    fn macro_in_lambda_body() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(lambda (x)
+               (define-syntax twice (syntax-rules () ((_ e) (begin e e))))
+               (twice (f x)))",
+            "(lambda (#:x.0) (begin (f #:x.0) (f #:x.0)))"
+        );
+        assert_source_eq!(expander, "(twice 1)", "(twice 1)");
+    }
+
+    #[test]
+    fn let_syntax() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(let-syntax ((inc (syntax-rules () ((_ x) (+ x 1))))) (inc (inc 1)))",
+            "(+ (+ 1 1) 1)"
+        );
+        assert_source_eq!(
+            expander,
+            "(let-syntax ((inc (syntax-rules () ((_ x) (+ x 1))))) (f) (inc 2))",
+            "((lambda () (f) (+ 2 1)))"
+        );
+    }
+
+    #[test]
+    fn letrec_syntax() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(letrec-syntax
+                 ((my-and (syntax-rules ()
+                            ((_) 1)
+                            ((_ e) e)
+                            ((_ e r ...) (if e (my-and r ...) 0)))))
+               (my-and a b c))",
+            "(if a (if b c 0) 0)"
+        );
+    }
+
+    #[test]
+    fn variables_shadow_macros() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(let-syntax ((inc (syntax-rules () ((_ x) (+ x 1)))))
+               (lambda (inc) (inc 2)))",
+            "(lambda (#:inc.0) (#:inc.0 2))"
+        );
+    }
+
+    #[test]
+    fn macros_do_not_capture_user_variables() {
+        let mut expander = MacroExpander::new();
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax my-or
+                       (syntax-rules ()
+                         ((_) false)
+                         ((_ e) e)
+                         ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(lambda (t) (my-or x t))",
+            "(lambda (#:t.0) (let ((#:t.1 x)) (if #:t.1 #:t.1 #:t.0)))"
+        );
+
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax swap!
+                       (syntax-rules ()
+                         ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(let ((tmp 5) (other 6)) (swap! tmp other))",
+            "(let ((#:tmp.2 5) (#:other.3 6)) (let ((#:tmp.4 #:tmp.2)) (set! #:tmp.2 #:other.3) (set! #:other.3 #:tmp.4)))"
+        );
+    }
+
+    #[test]
+    fn user_variables_do_not_capture_macro_identifiers() {
+        let mut expander = MacroExpander::new();
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax my-or
+                       (syntax-rules ()
+                         ((_ a b) (let ((t a)) (if t t b)))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(let ((if list)) (my-or x if))",
+            "(let ((#:if.0 list)) (let ((#:t.1 x)) (if #:t.1 #:t.1 #:if.0)))"
+        );
+        assert_source_eq!(
+            expander,
+            "(lambda (x)
+               (let-syntax ((get-x (syntax-rules () ((_) x))))
+                 (lambda (x) (get-x))))",
+            "(lambda (#:x.2) (lambda (#:x.3) #:x.2))"
+        );
+    }
+
+    #[test]
+    fn literals_match_by_binding() {
+        let mut expander = MacroExpander::new();
+        let define = parse_datum(
+            "(define-syntax my-if
+               (syntax-rules (then else) ((_ c then a else b) (if c a b))))",
+        )
+        .unwrap();
+        expander.transform(&define).unwrap();
+        assert_source_eq!(expander, "(my-if x then 1 else 2)", "(if x 1 2)");
+        let source = parse_datum("(lambda (then) (my-if x then 1 else 2))").unwrap();
+        assert!(expander.transform(&source).is_err());
+    }
+
+    #[test]
+    fn macros_defining_macros() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(begin
+               (define-syntax def-const
+                 (syntax-rules ()
+                   ((_ name value) (define-syntax name (syntax-rules () ((_) value))))))
+               (def-const answer 42)
+               (answer))",
+            "(begin 42)"
+        );
+    }
+
+    #[test]
+    fn quoted_aliases_are_stripped() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(let-syntax ((q (syntax-rules () ((_ x) '(x tmp #(tmp)))))) (q 1))",
+            "'(1 tmp #(tmp))"
+        );
+    }
+
+    #[test]
+    fn explicit_renaming_macros() {
+        let mut expander = MacroExpander::new();
+        expander.define_er_macro("my-or", |form, rename, _compare| {
+            let a = form.get_ref(1).unwrap().clone();
+            let b = form.get_ref(2).unwrap().clone();
+            let t = rename("t");
+            let binding = list!(@t.clone(), @a);
+            let test = list!(@rename("if"), @t.clone(), @t, @b);
+            Ok(list!(@rename("let"), @list!(@binding), @test))
+        });
+        assert_source_eq!(
+            expander,
+            "(lambda (t if) (my-or t if))",
+            "(lambda (#:t.0 #:if.1) (let ((#:t.2 #:t.0)) (if #:t.2 #:t.2 #:if.1)))"
+        );
+    }
+
+    #[test]
+    fn explicit_renaming_compare() {
+        let mut expander = MacroExpander::new();
+        expander.define_er_macro("is-else?", |form, rename, compare| {
+            let x = form.get_ref(1).unwrap();
+            Ok(Object::integer(compare(x, &rename("else")) as i64))
+        });
+        assert_source_eq!(expander, "(is-else? else)", "1");
+        assert_source_eq!(
+            expander,
+            "(lambda (else) (is-else? else))",
+            "(lambda (#:else.0) 0)"
+        );
+    }
+
+    #[test]
+    fn implicit_renaming_macros() {
+        let mut expander = MacroExpander::new();
+        expander.define_ir_macro("swap!", |form, _inject, _compare| {
+            let a = form.get_ref(1).unwrap().clone();
+            let b = form.get_ref(2).unwrap().clone();
+            let set = Object::symbol("set!");
+            let binding = list!(tmp, @a.clone());
+            let set_a = list!(@set.clone(), @a, @b.clone());
+            let set_b = list!(@set, @b, tmp);
+            Ok(list!(let, @list!(@binding), @set_a, @set_b))
+        });
+        assert_source_eq!(
+            expander,
+            "(lambda (tmp x) (swap! tmp x))",
+            "(lambda (#:tmp.0 #:x.1) (let ((#:tmp.2 #:tmp.0)) (set! #:tmp.0 #:x.1) (set! #:x.1 #:tmp.2)))"
+        );
+
+        expander.define_ir_macro("loop", |form, inject, _compare| {
+            let body = form.cdr().unwrap().clone();
+            let exit = inject("exit");
+            let handler = list!(lambda, @list!(@exit), . @body);
+            Ok(list!(@Object::symbol("call/cc"), @handler))
+        });
+        assert_source_eq!(
+            expander,
+            "(loop (exit 1))",
+            "(call/cc (lambda (#:exit.3) (#:exit.3 1)))"
+        );
+    }
+
+    #[test]
+    fn explicit_renaming_macros_in_scheme() {
+        let mut expander = MacroExpander::new();
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax my-or
+                       (er-macro-transformer
+                         (lambda (form rename compare)
+                           (let ((t (rename 't)))
+                             (list (rename 'let) (list (list t (cadr form)))
+                                   (list (rename 'if) t t (list-ref form 2)))))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(lambda (t if) (my-or t if))",
+            "(lambda (#:t.0 #:if.1) (let ((#:t.2 #:t.0)) (if #:t.2 #:t.2 #:if.1)))"
+        );
+
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax is-else?
+                       (er-macro-transformer
+                         (lambda (form rename compare)
+                           (if (compare (cadr form) (rename 'else)) 1 0))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(expander, "(is-else? else)", "1");
+        assert_source_eq!(
+            expander,
+            "(lambda (else) (is-else? else))",
+            "(lambda (#:else.3) 0)"
+        );
+    }
+
+    #[test]
+    fn implicit_renaming_macros_in_scheme() {
+        let mut expander = MacroExpander::new();
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax swap!
+                       (ir-macro-transformer
+                         (lambda (form inject compare)
+                           (let ((a (cadr form)) (b (list-ref form 2)))
+                             (list 'let (list (list 'tmp a))
+                                   (list 'set! a b)
+                                   (list 'set! b 'tmp))))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(lambda (tmp x) (swap! tmp x))",
+            "(lambda (#:tmp.0 #:x.1) (let ((#:tmp.2 #:tmp.0)) (set! #:tmp.0 #:x.1) (set! #:x.1 #:tmp.2)))"
+        );
+
+        expander
+            .transform(
+                &parse_datum(
+                    "(define-syntax loop
+                       (ir-macro-transformer
+                         (lambda (form inject compare)
+                           (list 'call/cc (cons 'lambda (cons (list (inject 'exit)) (cdr form)))))))",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_source_eq!(
+            expander,
+            "(loop (exit 1))",
+            "(call/cc (lambda (#:exit.3) (#:exit.3 1)))"
+        );
+    }
+
+    #[test]
+    fn invalid_procedural_transformers() {
+        let mut expander = MacroExpander::new();
+        for source in &[
+            "(define-syntax foo (er-macro-transformer 42))",
+            "(define-syntax foo (ir-macro-transformer))",
+            "(define-syntax foo (er-macro-transformer (lambda (form rename compare) car)))
+             (foo)",
+        ] {
+            let source = parse_datum(&format!("(begin {})", source)).unwrap();
+            assert!(expander.transform(&source).is_err());
+        }
+    }
+
+    #[test]
+    fn quoted_macro_uses_are_not_expanded() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(let-syntax ((inc (syntax-rules () ((_ x) (+ x 1))))) '(inc 2))",
+            "'(inc 2)"
+        );
+    }
+
+    #[test]
+    fn case_data_and_templates_are_quoted() {
+        let mut expander = MacroExpander::new();
+        assert_source_eq!(
+            expander,
+            "(lambda (a) (case 3 ((a) 1) (else a)))",
+            "(lambda (#:a.0) (case 3 ((a) 1) (else #:a.0)))"
+        );
+        assert_source_eq!(
+            expander,
+            "(lambda (x) (quasiquote (x (unquote x) (quasiquote (x (unquote x))))))",
+            "(lambda (#:x.1) (quasiquote (x (unquote #:x.1) (quasiquote (x (unquote x))))))"
+        );
+    }
+
+    #[test]
+    fn duplicate_bindings_are_errors() {
+        let mut expander = MacroExpander::new();
+        for source in &[
+            "(lambda (x x) x)",
+            "(let ((x 1) (x 2)) x)",
+            "(lambda () (define a 1) (define a 2) a)",
+        ] {
+            let err = expander
+                .transform(&parse_datum(source).unwrap())
+                .unwrap_err();
+            match err.kind() {
+                ErrorKind::SyntaxError(msg) => assert!(msg.starts_with("duplicate variable")),
+                kind => panic!("unexpected error: {:?}", kind),
+            }
+        }
+        for source in &[
+            "(begin (define x 1) (define x 2))",
+            "(lambda (x) (define x 1) x)",
+        ] {
+            assert!(expander.transform(&parse_datum(source).unwrap()).is_ok());
+        }
+    }
+
+    #[test]
+    fn expansion_errors() {
+        let mut expander = MacroExpander::new();
+        let source =
+            parse_datum("(let-syntax ((inc (syntax-rules () ((_ x) (+ x 1))))) (inc 1 2))")
+                .unwrap();
+        assert!(expander.transform(&source).is_err());
+
+        let source = parse_datum("(f (define-syntax foo (syntax-rules ())))").unwrap();
+        assert!(expander.transform(&source).is_err());
+    }
+}
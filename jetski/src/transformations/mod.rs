@@ -1,4 +1,9 @@
 pub mod alphatize;
+pub mod cps;
+pub mod desugar;
+pub mod expand;
+pub mod pipeline;
+mod syntax_rules;
 
 use crate::error::Result;
 use crate::Object;
@@ -0,0 +1,302 @@
+//! Pass pipeline
+//! A `Pipeline` runs a sequence of named source transformers. It measures the time spent in
+//! each pass, can print the program after selected passes, and runs validation hooks that check
+//! the invariants a pass is supposed to establish. Passes are referred to by name, and naming a
+//! pass that does not exist, or adding two passes of the same name, is an error.
+//!
+//! A pipeline is a source transformer itself, so pipelines can be nested.
+
+use super::SourceTransformer;
+use crate::error::{ErrorKind, Result};
+use crate::Object;
+use std::collections::HashSet;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Checks an invariant of the program and describes the violation if it does not hold.
+pub type Validator = Box<dyn Fn(&Object) -> std::result::Result<(), String>>;
+
+struct Pass {
+    name: String,
+    transformer: Box<dyn SourceTransformer>,
+    validators: Vec<Validator>,
+    time: Duration,
+}
+
+pub struct Pipeline {
+    passes: Vec<Pass>,
+    print_after: HashSet<String>,
+    print_after_all: bool,
+    output: Box<dyn Write>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new()
+    }
+}
+
+impl SourceTransformer for Pipeline {
+    fn transform(&mut self, source: &Object) -> Result<Object> {
+        let mut program = source.clone();
+        for pass in &mut self.passes {
+            let start = Instant::now();
+            program = pass.transformer.transform(&program)?;
+            pass.time += start.elapsed();
+
+            if self.print_after_all || self.print_after.contains(&pass.name) {
+                writeln!(self.output, ";; after {}\n{}", pass.name, program)?;
+            }
+
+            for validate in &pass.validators {
+                validate(&program).map_err(|msg| {
+                    ErrorKind::ValidationError(format!("after {}: {}", pass.name, msg))
+                })?;
+            }
+        }
+        Ok(program)
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline {
+            passes: vec![],
+            print_after: HashSet::new(),
+            print_after_all: false,
+            output: Box::new(std::io::stderr()),
+        }
+    }
+
+    /// Append a pass to the pipeline. Pass names must be unique.
+    pub fn with_pass(
+        mut self,
+        name: &str,
+        transformer: impl SourceTransformer + 'static,
+    ) -> Result<Self> {
+        if self.find_pass(name).is_some() {
+            return Err(
+                ErrorKind::ValidationError(format!("duplicate pass name: {}", name)).into(),
+            );
+        }
+        self.passes.push(Pass {
+            name: name.to_string(),
+            transformer: Box::new(transformer),
+            validators: vec![],
+            time: Duration::default(),
+        });
+        Ok(self)
+    }
+
+    /// Check the output of the named pass.
+    pub fn validate_after(
+        mut self,
+        name: &str,
+        validator: impl Fn(&Object) -> std::result::Result<(), String> + 'static,
+    ) -> Result<Self> {
+        let idx = self.expect_pass(name)?;
+        self.passes[idx].validators.push(Box::new(validator));
+        Ok(self)
+    }
+
+    /// Print the program after the named pass, like `-print-after=<name>`.
+    pub fn print_after(mut self, name: &str) -> Result<Self> {
+        self.expect_pass(name)?;
+        self.print_after.insert(name.to_string());
+        Ok(self)
+    }
+
+    /// Print the program after every pass, including passes that are appended later.
+    pub fn print_after_all(mut self) -> Self {
+        self.print_after_all = true;
+        self
+    }
+
+    /// Where the program is printed; the default is stderr.
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|p| p.name.as_str())
+    }
+
+    /// Total time spent in each pass, over all programs transformed so far.
+    pub fn timings(&self) -> Vec<(&str, Duration)> {
+        self.passes
+            .iter()
+            .map(|p| (p.name.as_str(), p.time))
+            .collect()
+    }
+
+    fn find_pass(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|p| p.name == name)
+    }
+
+    fn expect_pass(&self, name: &str) -> Result<usize> {
+        self.find_pass(name)
+            .ok_or_else(|| ErrorKind::ValidationError(format!("unknown pass: {}", name)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+    use crate::transformations::alphatize::{unique_names, Alphatizer};
+    use crate::transformations::desugar::Desugar;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn source() -> Object {
+        parse_datum("(let ((x 1)) (and x y))").unwrap()
+    }
+
+    fn front_end() -> Pipeline {
+        Pipeline::new()
+            .with_pass("alphatize", Alphatizer::new())
+            .and_then(|pipeline| pipeline.with_pass("desugar", Desugar::new()))
+            .unwrap()
+    }
+
+    #[test]
+    fn passes_run_in_order() {
+        let mut pipeline = front_end();
+        let expected = Desugar::new()
+            .transform(&Alphatizer::new().transform(&source()).unwrap())
+            .unwrap();
+        assert_eq!(
+            pipeline.transform(&source()).unwrap().to_string(),
+            expected.to_string()
+        );
+    }
+
+    #[test]
+    fn timings_are_recorded_per_pass() {
+        let mut pipeline = front_end();
+        pipeline.transform(&source()).unwrap();
+        let names: Vec<_> = pipeline.timings().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["alphatize", "desugar"]);
+        assert_eq!(pipeline.pass_names().collect::<Vec<_>>(), names);
+    }
+
+    #[test]
+    fn print_after_named_pass() {
+        let output = SharedBuffer::default();
+        let mut pipeline = front_end()
+            .print_after("alphatize")
+            .unwrap()
+            .with_output(output.clone());
+        pipeline.transform(&source()).unwrap();
+        assert_eq!(
+            output.contents(),
+            ";; after alphatize\n(let ((#:x.0 1)) (and #:x.0 y))\n"
+        );
+    }
+
+    #[test]
+    fn print_after_all_includes_later_passes() {
+        let output = SharedBuffer::default();
+        let mut pipeline = Pipeline::new()
+            .print_after_all()
+            .with_pass("alphatize", Alphatizer::new())
+            .unwrap()
+            .with_pass("desugar", Desugar::new())
+            .unwrap()
+            .with_output(output.clone());
+        pipeline.transform(&source()).unwrap();
+        let contents = output.contents();
+        assert!(contents.contains(";; after alphatize\n"));
+        assert!(contents.contains(";; after desugar\n"));
+    }
+
+    struct BrokenOutput;
+
+    impl Write for BrokenOutput {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn print_failures_are_errors() {
+        let mut pipeline = Pipeline::new()
+            .with_pass("alphatize", Alphatizer::new())
+            .unwrap()
+            .print_after("alphatize")
+            .unwrap()
+            .with_output(BrokenOutput);
+        match pipeline.transform(&source()).unwrap_err().kind() {
+            ErrorKind::IoError(e) => assert_eq!(e.to_string(), "broken"),
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn validation_failures_name_the_pass() {
+        let mut pipeline = Pipeline::new()
+            .with_pass("desugar", Desugar::new())
+            .unwrap()
+            .validate_after("desugar", unique_names)
+            .unwrap();
+        match pipeline.transform(&source()).unwrap_err().kind() {
+            ErrorKind::ValidationError(msg) => assert!(msg.starts_with("after desugar: ")),
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+
+        let mut pipeline = Pipeline::new()
+            .with_pass("alphatize", Alphatizer::new())
+            .unwrap()
+            .validate_after("alphatize", unique_names)
+            .unwrap();
+        assert!(pipeline.transform(&source()).is_ok());
+    }
+
+    fn configuration_error(pipeline: Result<Pipeline>) -> String {
+        match pipeline.err().expect("no error").kind() {
+            ErrorKind::ValidationError(msg) => msg.clone(),
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn unknown_pass_names_are_rejected() {
+        let pipeline = || Pipeline::new().with_pass("alphatize", Alphatizer::new());
+        assert_eq!(
+            configuration_error(pipeline().and_then(|p| p.print_after("cps"))),
+            "unknown pass: cps"
+        );
+        assert_eq!(
+            configuration_error(pipeline().and_then(|p| p.validate_after("cps", unique_names))),
+            "unknown pass: cps"
+        );
+        assert_eq!(
+            configuration_error(pipeline().and_then(|p| p.with_pass("alphatize", Desugar::new()))),
+            "duplicate pass name: alphatize"
+        );
+    }
+}
@@ -0,0 +1,501 @@
+//! Pattern matching and template transcription for `syntax-rules` macros.
+
+use crate::error::{ErrorKind, Result};
+use crate::object::{ListBuilder, Object, TaggedValue};
+use crate::runtime::Symbol;
+use crate::SchemeExpression;
+use std::collections::HashMap;
+
+/// A macro transformer defined with `syntax-rules`.
+#[derive(Debug)]
+pub struct SyntaxRules {
+    ellipsis: Option<Symbol>,
+    literals: Vec<Symbol>,
+    rules: Vec<(Object, Object)>,
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Object),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<Symbol, Binding>;
+
+/// Connects macro transcription to the expander's notion of identifiers.
+pub trait Hygiene {
+    /// Rename an identifier that is inserted by a template.
+    fn rename(&mut self, name: Symbol) -> Symbol;
+
+    /// Does an identifier in the macro use mean the same as a literal of the macro definition?
+    fn matches_literal(&self, input: Symbol, literal: Symbol) -> bool;
+}
+
+impl SyntaxRules {
+    /// Parse a `(syntax-rules ...)` transformer specification.
+    pub fn parse(spec: &Object) -> Result<Self> {
+        try_switch! {spec,
+            [(?kw:symbol ?ellipsis:symbol (?literals ...) (?patterns ?templates) ...)]
+                if is_keyword(kw, "syntax-rules") => {
+                SyntaxRules::new(Some(ellipsis), &literals, &patterns, &templates)
+            },
+            [(?kw:symbol (?literals ...) (?patterns ?templates) ...)]
+                if is_keyword(kw, "syntax-rules") => {
+                SyntaxRules::new(None, &literals, &patterns, &templates)
+            },
+        }
+    }
+
+    fn new(
+        ellipsis: Option<&Object>,
+        literals: &[&Object],
+        patterns: &[&Object],
+        templates: &[&Object],
+    ) -> Result<Self> {
+        let ellipsis = ellipsis
+            .map(|e| e.as_symbol().unwrap())
+            .unwrap_or_else(|| Symbol::new("..."));
+        let literals = literals
+            .iter()
+            .map(|lit| {
+                lit.as_symbol().ok_or_else(|| {
+                    ErrorKind::SyntaxError(format!(
+                        "syntax-rules literal is not a symbol: {:?}",
+                        lit
+                    ))
+                    .into()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let rules = patterns
+            .iter()
+            .zip(templates)
+            .map(|(&p, &t)| {
+                if p.is_list() {
+                    Ok((p.clone(), t.clone()))
+                } else {
+                    Err(
+                        ErrorKind::SyntaxError(format!("invalid syntax-rules pattern: {:?}", p))
+                            .into(),
+                    )
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(SyntaxRules {
+            // listing the ellipsis as a literal disables its special meaning
+            ellipsis: if literals.contains(&ellipsis) {
+                None
+            } else {
+                Some(ellipsis)
+            },
+            literals,
+            rules,
+        })
+    }
+
+    /// Expand one use of the macro. The keyword position of the patterns is ignored.
+    pub fn expand(&self, form: &Object, hygiene: &mut dyn Hygiene) -> Result<Object> {
+        for (pattern, template) in &self.rules {
+            let mut bindings = Bindings::new();
+            if self.match_pattern(
+                pattern.cdr().unwrap(),
+                form.cdr().unwrap(),
+                &mut bindings,
+                hygiene,
+            ) {
+                return self.transcribe(template, &bindings, self.ellipsis, hygiene);
+            }
+        }
+        Err(ErrorKind::SyntaxError(format!("no syntax rule matches {:?}", form)).into())
+    }
+
+    fn is_ellipsis(&self, x: &Object) -> bool {
+        self.ellipsis.is_some() && x.as_symbol() == self.ellipsis
+    }
+
+    fn match_pattern(
+        &self,
+        pattern: &Object,
+        input: &Object,
+        bindings: &mut Bindings,
+        hygiene: &dyn Hygiene,
+    ) -> bool {
+        match pattern.as_value() {
+            TaggedValue::Symbol(s) if s.name() == "_" => true,
+            TaggedValue::Symbol(s) if self.literals.contains(s) => input
+                .as_symbol()
+                .is_some_and(|x| hygiene.matches_literal(x, *s)),
+            TaggedValue::Symbol(s) => {
+                bindings.insert(*s, Binding::One(input.clone()));
+                true
+            }
+            TaggedValue::Pair(_, _) => {
+                let (items, tail) = pattern.list_parts();
+                match items.iter().position(|p| self.is_ellipsis(p)) {
+                    Some(idx) if idx > 0 => {
+                        let (input_items, input_tail) = input.list_parts();
+                        self.match_ellipsis(&items, idx, &input_items, bindings, hygiene)
+                            && self.match_pattern(tail, input_tail, bindings, hygiene)
+                    }
+                    _ => input.decons().is_some_and(|(input_car, input_cdr)| {
+                        self.match_pattern(pattern.car().unwrap(), input_car, bindings, hygiene)
+                            && self.match_pattern(
+                                pattern.cdr().unwrap(),
+                                input_cdr,
+                                bindings,
+                                hygiene,
+                            )
+                    }),
+                }
+            }
+            TaggedValue::Vector(items) => input.as_vector().is_some_and(|input_items| {
+                let items: Vec<_> = items.iter().collect();
+                let input_items: Vec<_> = input_items.iter().collect();
+                match items.iter().position(|p| self.is_ellipsis(p)) {
+                    Some(idx) if idx > 0 => {
+                        self.match_ellipsis(&items, idx, &input_items, bindings, hygiene)
+                    }
+                    _ => {
+                        items.len() == input_items.len()
+                            && items
+                                .iter()
+                                .zip(&input_items)
+                                .all(|(p, x)| self.match_pattern(p, x, bindings, hygiene))
+                    }
+                }
+            }),
+            _ => pattern == input,
+        }
+    }
+
+    /// Match a sequence of patterns, where `patterns[ellipsis_idx - 1]` is followed by an ellipsis.
+    fn match_ellipsis(
+        &self,
+        patterns: &[&Object],
+        ellipsis_idx: usize,
+        input: &[&Object],
+        bindings: &mut Bindings,
+        hygiene: &dyn Hygiene,
+    ) -> bool {
+        let before = &patterns[..ellipsis_idx - 1];
+        let repeated = patterns[ellipsis_idx - 1];
+        let after = &patterns[ellipsis_idx + 1..];
+
+        if input.len() < before.len() + after.len() {
+            return false;
+        }
+        let n_repeated = input.len() - before.len() - after.len();
+
+        let before_ok = before
+            .iter()
+            .zip(input)
+            .all(|(p, x)| self.match_pattern(p, x, bindings, hygiene));
+        let after_ok = after
+            .iter()
+            .zip(&input[before.len() + n_repeated..])
+            .all(|(p, x)| self.match_pattern(p, x, bindings, hygiene));
+        if !before_ok || !after_ok {
+            return false;
+        }
+
+        let mut sequences: HashMap<Symbol, Vec<Binding>> = self
+            .pattern_variables(repeated)
+            .into_iter()
+            .map(|var| (var, vec![]))
+            .collect();
+        for x in &input[before.len()..before.len() + n_repeated] {
+            let mut sub_bindings = Bindings::new();
+            if !self.match_pattern(repeated, x, &mut sub_bindings, hygiene) {
+                return false;
+            }
+            for (var, binding) in sub_bindings {
+                sequences.get_mut(&var).unwrap().push(binding);
+            }
+        }
+        bindings.extend(
+            sequences
+                .into_iter()
+                .map(|(var, seq)| (var, Binding::Many(seq))),
+        );
+        true
+    }
+
+    fn pattern_variables(&self, pattern: &Object) -> Vec<Symbol> {
+        let mut vars = vec![];
+        collect_symbols(pattern, &mut vars);
+        vars.retain(|s| s.name() != "_" && !self.literals.contains(s) && Some(*s) != self.ellipsis);
+        vars
+    }
+
+    fn transcribe(
+        &self,
+        template: &Object,
+        bindings: &Bindings,
+        ellipsis: Option<Symbol>,
+        hygiene: &mut dyn Hygiene,
+    ) -> Result<Object> {
+        match template.as_value() {
+            TaggedValue::Symbol(s) => match bindings.get(s) {
+                Some(Binding::One(x)) => Ok(x.clone()),
+                Some(Binding::Many(_)) => Err(ErrorKind::SyntaxError(format!(
+                    "pattern variable used without ellipsis: {}",
+                    s
+                ))
+                .into()),
+                // `_` and `...` keep their meaning in macros defined by macros
+                None if s.name() == "_" || s.name() == "..." => Ok(template.clone()),
+                None => Ok(Object::from(hygiene.rename(*s))),
+            },
+            TaggedValue::Pair(_, _) => {
+                let (items, tail) = template.list_parts();
+                if ellipsis.is_some() && items[0].as_symbol() == ellipsis {
+                    // (... template) escapes the ellipsis
+                    return match items.get(1) {
+                        Some(escaped) if items.len() == 2 && tail.is_nil() => {
+                            self.transcribe(escaped, bindings, None, hygiene)
+                        }
+                        _ => Err(ErrorKind::SyntaxError(format!(
+                            "invalid ellipsis escape: {:?}",
+                            template
+                        ))
+                        .into()),
+                    };
+                }
+                let mut list = ListBuilder::new();
+                for item in self.transcribe_items(&items, bindings, ellipsis, hygiene)? {
+                    list.append(item);
+                }
+                list.set_cdr(self.transcribe(tail, bindings, ellipsis, hygiene)?);
+                Ok(list.build())
+            }
+            TaggedValue::Vector(items) => {
+                let items: Vec<_> = items.iter().collect();
+                self.transcribe_items(&items, bindings, ellipsis, hygiene)
+                    .map(Object::vector)
+            }
+            _ => Ok(template.clone()),
+        }
+    }
+
+    fn transcribe_items(
+        &self,
+        items: &[&Object],
+        bindings: &Bindings,
+        ellipsis: Option<Symbol>,
+        hygiene: &mut dyn Hygiene,
+    ) -> Result<Vec<Object>> {
+        let mut output = vec![];
+        let mut idx = 0;
+        while idx < items.len() {
+            let depth = items[idx + 1..]
+                .iter()
+                .take_while(|x| ellipsis.is_some() && x.as_symbol() == ellipsis)
+                .count();
+            output
+                .extend(self.transcribe_ellipsis(items[idx], depth, bindings, ellipsis, hygiene)?);
+            idx += 1 + depth;
+        }
+        Ok(output)
+    }
+
+    /// Transcribe a template that is followed by `depth` ellipses.
+    fn transcribe_ellipsis(
+        &self,
+        template: &Object,
+        depth: usize,
+        bindings: &Bindings,
+        ellipsis: Option<Symbol>,
+        hygiene: &mut dyn Hygiene,
+    ) -> Result<Vec<Object>> {
+        if depth == 0 {
+            return Ok(vec![self.transcribe(template, bindings, ellipsis, hygiene)?]);
+        }
+
+        let mut vars = vec![];
+        collect_symbols(template, &mut vars);
+        let sequences: Vec<_> = vars
+            .into_iter()
+            .filter_map(|var| match bindings.get(&var) {
+                Some(Binding::Many(seq)) => Some((var, seq)),
+                _ => None,
+            })
+            .collect();
+
+        let n = match sequences.first() {
+            Some((_, seq)) => seq.len(),
+            None => {
+                return Err(ErrorKind::SyntaxError(format!(
+                    "no pattern variable in ellipsis template: {:?}",
+                    template
+                ))
+                .into())
+            }
+        };
+        if sequences.iter().any(|(_, seq)| seq.len() != n) {
+            return Err(ErrorKind::SyntaxError(format!(
+                "pattern variables of different lengths in ellipsis template: {:?}",
+                template
+            ))
+            .into());
+        }
+
+        let mut output = vec![];
+        for i in 0..n {
+            let mut inner_bindings = bindings.clone();
+            for (var, seq) in &sequences {
+                inner_bindings.insert(*var, seq[i].clone());
+            }
+            output.extend(self.transcribe_ellipsis(
+                template,
+                depth - 1,
+                &inner_bindings,
+                ellipsis,
+                hygiene,
+            )?);
+        }
+        Ok(output)
+    }
+}
+
+pub fn is_keyword(x: &Object, name: &str) -> bool {
+    x.symbol_name() == Some(name)
+}
+
+fn collect_symbols(x: &Object, symbols: &mut Vec<Symbol>) {
+    match x.as_value() {
+        TaggedValue::Symbol(s) if !symbols.contains(s) => symbols.push(*s),
+        TaggedValue::Pair(car, cdr) => {
+            collect_symbols(car, symbols);
+            collect_symbols(cdr, symbols);
+        }
+        TaggedValue::Vector(items) => items.iter().for_each(|x| collect_symbols(x, symbols)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_datum;
+
+    impl Hygiene for () {
+        fn rename(&mut self, name: Symbol) -> Symbol {
+            name
+        }
+
+        fn matches_literal(&self, input: Symbol, literal: Symbol) -> bool {
+            input == literal
+        }
+    }
+
+    fn expand(spec: &str, form: &str) -> Result<Object> {
+        SyntaxRules::parse(&parse_datum(spec).unwrap())?
+            .expand(&parse_datum(form).unwrap(), &mut ())
+    }
+
+    macro_rules! assert_expands {
+        ($spec:expr, $form:expr, $expected:expr) => {
+            assert_eq!(
+                expand($spec, $form).unwrap(),
+                parse_datum($expected).unwrap()
+            )
+        };
+    }
+
+    #[test]
+    fn simple_substitution() {
+        assert_expands!(
+            "(syntax-rules () ((_ a b) (b a)))",
+            "(flip 1 (f x))",
+            "((f x) 1)"
+        );
+    }
+
+    #[test]
+    fn first_matching_rule_is_used() {
+        let spec = "(syntax-rules () ((_) false) ((_ e) e) ((_ e r ...) (if e e (my-or r ...))))";
+        assert_expands!(spec, "(my-or)", "false");
+        assert_expands!(spec, "(my-or x)", "x");
+        assert_expands!(spec, "(my-or x y z)", "(if x x (my-or y z))");
+    }
+
+    #[test]
+    fn literals() {
+        let spec = "(syntax-rules (then else) ((_ c then a else b) (if c a b)))";
+        assert_expands!(spec, "(my-if x then 1 else 2)", "(if x 1 2)");
+        assert!(expand(spec, "(my-if x than 1 else 2)").is_err());
+    }
+
+    #[test]
+    fn ellipsis() {
+        assert_expands!(
+            "(syntax-rules () ((_ ((n v) ...) body ...) ((lambda (n ...) body ...) v ...)))",
+            "(my-let ((a 1) (b 2)) (f a) b)",
+            "((lambda (a b) (f a) b) 1 2)"
+        );
+    }
+
+    #[test]
+    fn ellipsis_followed_by_patterns() {
+        assert_expands!(
+            "(syntax-rules () ((_ a ... b c) (list c b a ...)))",
+            "(rev-last 1 2 3 4)",
+            "(list 4 3 1 2)"
+        );
+    }
+
+    #[test]
+    fn nested_ellipsis() {
+        assert_expands!(
+            "(syntax-rules () ((_ (a b ...) ...) (list (a b ...) ... (b ... ...))))",
+            "(m (f 1 2) (g) (h 3))",
+            "(list (f 1 2) (g) (h 3) (1 2 3))"
+        );
+    }
+
+    #[test]
+    fn dotted_patterns() {
+        let spec = "(syntax-rules () ((_ a . rest) (quote rest)))";
+        assert_expands!(spec, "(m 1 2 3)", "'(2 3)");
+        assert_expands!(spec, "(m 1 . 2)", "'2");
+        assert_expands!(
+            "(syntax-rules () ((_ a ... . rest) (quote (rest a ...))))",
+            "(m 1 2 . 3)",
+            "'(3 1 2)"
+        );
+    }
+
+    #[test]
+    fn vector_patterns() {
+        let spec = "(syntax-rules () ((_ #(a b ...)) (list a #(b ...))))";
+        assert_expands!(spec, "(m #(1 2 3))", "(list 1 #(2 3))");
+        assert!(expand(spec, "(m (1 2 3))").is_err());
+    }
+
+    #[test]
+    fn custom_ellipsis() {
+        assert_expands!(
+            "(syntax-rules ::: () ((_ x :::) (list (x ...) :::)))",
+            "(m 1 2)",
+            "(list (1 ...) (2 ...))"
+        );
+    }
+
+    #[test]
+    fn escaped_ellipsis() {
+        assert_expands!(
+            "(syntax-rules () ((_ x) (quote (x (... ...)))))",
+            "(m 1)",
+            "'(1 ...)"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(expand("(syntax-rules () ((_ x) x))", "(m)").is_err());
+        assert!(expand("(syntax-rules () ((_ x ...) x))", "(m 1)").is_err());
+        assert!(expand("(syntax-rules () ((_ x) (x ...)))", "(m 1)").is_err());
+        assert!(expand("(syntax-rules (1) ((_ x) x))", "(m 1)").is_err());
+        assert!(expand("(not-syntax-rules () ((_ x) x))", "(m 1)").is_err());
+    }
+}